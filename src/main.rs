@@ -3,4 +3,4 @@ mod util;
 // runs raytracer
 fn main() {
     util::tracing::run();
-}
\ No newline at end of file
+}
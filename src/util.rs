@@ -2,4 +2,24 @@
 pub mod geometry;
 pub mod tracing;
 pub mod materials;
-pub mod texture;
\ No newline at end of file
+pub mod texture;
+pub mod color;
+pub mod material_library;
+pub mod lights;
+pub mod background;
+pub mod sampler;
+pub mod sampling;
+pub mod animation;
+pub mod output;
+pub mod aov;
+pub mod denoise;
+pub mod stats;
+pub mod cancel;
+pub mod simd;
+pub mod cryptomatte;
+pub mod fog;
+pub mod bake;
+pub mod batch;
+pub mod preview;
+pub mod progress;
+pub mod description;
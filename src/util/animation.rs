@@ -0,0 +1,220 @@
+// ANIMATION - Moves the camera along a path of keyframes and renders a sequence of frames
+
+#![allow(dead_code)]
+
+use cgmath::*;
+use image::ImageFormat;
+use rayon::prelude::*;
+use std::ops::Range;
+use std::sync::Arc;
+use ::tracing::{info_span, warn};
+
+use super::tracing::*;
+use super::sampler::*;
+
+// where the camera is and what it's looking at, at one point in time
+#[derive(Debug, Clone, Copy)]
+pub struct CameraKeyframe {
+    pub time: Float,          // in seconds
+    pub position: Vec3,
+    pub target: Vec3,       // point the camera looks at
+    pub vfov: Float,          // vertical field of view in degrees
+    pub focus_dist: Float,
+}
+
+// CAMERA PATH - keyframes (in order of time) that the camera moves smoothly through
+// positions and targets follow a Catmull-Rom spline through the keyframes, so the camera doesn't jerk as it passes
+// each one, while the field of view and focus distance change linearly. before the first keyframe and after the last
+// the camera holds still
+#[derive(Debug, Clone)]
+pub struct CameraPath {
+    pub keyframes: Vec<CameraKeyframe>,
+}
+impl CameraPath {
+    // the camera's keyframe values at any time
+    pub fn at(&self, time: Float) -> CameraKeyframe {
+        let keys = &self.keyframes;
+        assert!(!keys.is_empty(), "camera path has no keyframes");
+        if time <= keys[0].time { return CameraKeyframe { time, ..keys[0] } }
+        if time >= keys[keys.len()-1].time { return CameraKeyframe { time, ..keys[keys.len()-1] } }
+        // keyframes on either side of the time, and their neighbors for the spline's tangents
+        let i = keys.partition_point(|key| key.time <= time) - 1;
+        let (k0, k1, k2, k3) = (keys[i.saturating_sub(1)], keys[i], keys[i+1], keys[usize::min(i+2, keys.len()-1)]);
+        let t = (time - k1.time) / (k2.time - k1.time);
+        CameraKeyframe {
+            time,
+            position: catmull_rom(k0.position, k1.position, k2.position, k3.position, t),
+            target: catmull_rom(k0.target, k1.target, k2.target, k3.target, t),
+            vfov: k1.vfov + t*(k2.vfov - k1.vfov),
+            focus_dist: k1.focus_dist + t*(k2.focus_dist - k1.focus_dist),
+        }
+    }
+    // moves a camera to where the path has it at a time (keeping its up vector and all other settings)
+    // its shutter interval is moved along too, so motion blur is measured from the start of each frame
+    pub fn apply(&self, camera: &Camera, time: Float) -> Camera {
+        let key = self.at(time);
+        let view_dir = (key.target - key.position).normalize();
+        Camera {
+            eyepoint: key.position,
+            view_dir,
+            up: view_dir.cross(camera.up).normalize().cross(view_dir),
+            focal_length: 0.5*camera.image_plane_size().y / (0.5*key.vfov.to_radians()).tan(),
+            focus_dist: key.focus_dist,
+            shutter_open: time + camera.shutter_open,
+            shutter_close: time + camera.shutter_close,
+            ..camera.clone()
+        }
+    }
+}
+// uniform Catmull-Rom spline between p1 and p2 (t from 0 to 1), shaped by the points before and after them
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: Float) -> Vec3 {
+    let (t2, t3) = (t*t, t*t*t);
+    0.5*((2.0*p1) + (p2 - p0)*t + (2.0*p0 - 5.0*p1 + 4.0*p2 - p3)*t2 + (3.0*p1 - p0 - 3.0*p2 + p3)*t3)
+}
+
+// TEMPORAL ACCUMULATION - carries each pixel's samples over from frame to frame, so slowly changing shots can be
+// rendered with far fewer samples a frame. each pixel is followed back to the previous frame by finding what its center
+// ray hits and where the previous frame's camera saw that point (its motion vector), and the previous frame's image
+// there (filtered between the pixels around it) is averaged in with the new samples, up to max_history frames' worth.
+// history is thrown away where it doesn't belong to the same surface, which shows as a jump in depth or normal:
+// where the point was hidden or off screen in the previous frame, or where something moved (only the camera's motion
+// is followed, and rays scattered inside volumes land somewhere new each frame). pixels that lose their history are
+// as noisy as the frame's own samples, until they catch up.
+// needs a perspective camera without stereo
+#[derive(Debug, Clone, Copy)]
+pub struct TemporalAccumulation {
+    pub max_history: u32,         // most frames a pixel's average spans (more is smoother, but lags further behind changes in lighting)
+    pub depth_tolerance: Float,   // how far the previous frame's depth can be from where the point should be, relative to it
+    pub normal_tolerance: Float,  // and how far its normal can be turned, in degrees
+}
+impl Default for TemporalAccumulation {
+    fn default() -> TemporalAccumulation {
+        TemporalAccumulation { max_history: 8, depth_tolerance: 0.05, normal_tolerance: 25.0 }
+    }
+}
+
+// what a pixel's center ray first hits
+#[derive(Debug, Clone, Copy)]
+enum Surface {
+    Background(Vec3),   // the ray's direction (the background is infinitely far away, so only the camera turning moves it)
+    Hit(Vec3, Vec3),    // hit point and normal
+}
+
+// a rendered frame, kept to accumulate the next one onto
+struct FrameHistory {
+    camera: Camera,
+    image: HdrImage,
+    surfaces: Vec<Surface>,
+    frames: Vec<u32>,   // how many frames each pixel's average spans
+}
+impl FrameHistory {
+    // the frame's image with each pixel averaged with the previous frame's where it can be followed back to it
+    fn accumulate(scene: &Scene, mut image: HdrImage, previous: Option<&FrameHistory>, settings: &TemporalAccumulation) -> FrameHistory {
+        let camera = &scene.camera;
+        let (width, height) = camera.image_size();
+        let surfaces: Vec<Surface> = (0..width*height).into_par_iter().map(|i| {
+            let point = vec2(((i % width) as Float + 0.5) / width as Float, ((i / width) as Float + 0.5) / height as Float);
+            let ray = camera.center_ray(point);
+            match scene.intersect_ray_kind(&ray, 0.001, camera.max_trace_dist, RayKind::Camera) {
+                Some(hit) => Surface::Hit(hit.hitpoint, hit.normal),
+                None => Surface::Background(ray.direction),
+            }
+        }).collect();
+        let mut frames = vec![1; surfaces.len()];
+        if let Some(previous) = previous.filter(|previous| previous.image.dimensions() == image.dimensions()) {
+            let max_history = u32::max(1, settings.max_history);
+            for (i, surface) in surfaces.iter().enumerate() {
+                if let Some((history, history_frames)) = previous.reproject(surface, settings) {
+                    let (x, y) = (i as u32 % width, i as u32 / width);
+                    frames[i] = u32::min(history_frames + 1, max_history);
+                    let color = pixel_color(image.get_pixel(x, y));
+                    image.put_pixel(x, y, color_pixel(history + (color - history) / frames[i] as Float));
+                }
+            }
+        }
+        FrameHistory { camera: camera.clone(), image, surfaces, frames }
+    }
+    // the frame's color where a surface seen in the next frame was, and how many frames it spans, if the surface was
+    // there (bilinearly filtered, leaving out the pixels around it that saw something else)
+    fn reproject(&self, surface: &Surface, settings: &TemporalAccumulation) -> Option<(Color, u32)> {
+        let direction = match *surface {
+            Surface::Background(direction) => direction,
+            Surface::Hit(point, _) => point - self.camera.eyepoint,
+        };
+        let point = self.camera.center_ray_point(direction)?;
+        let (width, height) = self.camera.image_size();
+        let (px, py) = (point.x*width as Float - 0.5, point.y*height as Float - 0.5);
+        let (x0, y0) = (px.floor(), py.floor());
+        let (fx, fy) = (px - x0, py - y0);
+        let (mut color, mut weight, mut frames) = (Color::zero(), 0.0, u32::MAX);
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let (x, y) = (x0 as i64 + dx, y0 as i64 + dy);
+            if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 { continue }
+            let w = (if dx == 0 { 1.0 - fx } else { fx }) * (if dy == 0 { 1.0 - fy } else { fy });
+            let i = (y*width as i64 + x) as usize;
+            if w <= 0.0 || !self.same_surface(&self.surfaces[i], surface, settings) { continue }
+            color += w*pixel_color(self.image.get_pixel(x as u32, y as u32));
+            weight += w;
+            frames = u32::min(frames, self.frames[i]);
+        }
+        if weight > 0.0 { Some((color / weight, frames)) } else { None }
+    }
+    // whether what a pixel of the frame saw could be a surface seen in the next frame: both the background, or hits at
+    // about the depth the new one is from this frame's camera, facing about the same way
+    fn same_surface(&self, old: &Surface, new: &Surface, settings: &TemporalAccumulation) -> bool {
+        match (*old, *new) {
+            (Surface::Background(_), Surface::Background(_)) => true,
+            (Surface::Hit(old_point, old_normal), Surface::Hit(new_point, new_normal)) => {
+                let expected = (new_point - self.camera.eyepoint).magnitude();
+                let found = (old_point - self.camera.eyepoint).magnitude();
+                (found - expected).abs() <= settings.depth_tolerance*expected
+                    && old_normal.dot(new_normal) >= settings.normal_tolerance.to_radians().cos()
+            }
+            _ => false,
+        }
+    }
+}
+
+// renders each frame in the range with the camera moved along the path, at fps frames per second,
+// to files named like "<file_prefix>_0042.png". the scene's camera is left at the last frame rendered
+// (a camera with autofocus refocuses every frame, overriding the path's focus distances)
+pub fn render_frames(scene: &mut Scene, path: &CameraPath, frames: Range<u32>, fps: Float, file_prefix: &str) {
+    render_frames_with(scene, path, frames, fps, file_prefix, None);
+}
+// same, with temporal accumulation carrying pixels' samples over between frames, so each frame can take fewer
+// (the camera's aa_sample_count a frame, e.g. a quarter of what a single image would need)
+pub fn render_frames_accumulated(scene: &mut Scene, path: &CameraPath, frames: Range<u32>, fps: Float, file_prefix: &str, settings: &TemporalAccumulation) {
+    render_frames_with(scene, path, frames, fps, file_prefix, Some(settings));
+}
+fn render_frames_with(scene: &mut Scene, path: &CameraPath, frames: Range<u32>, fps: Float, file_prefix: &str, accumulation: Option<&TemporalAccumulation>) {
+    let accumulation = accumulation.filter(|_| {
+        let supported = scene.camera.stereo.is_none() && matches!(scene.camera.projection_mode, CameraProjectionMode::Perspective);
+        if !supported { warn!("temporal accumulation needs a perspective camera without stereo, rendering frames separately instead"); }
+        supported
+    });
+    let mut history: Option<FrameHistory> = None;
+    // (checkpoints are for single images, so frames don't use one. a cancelled frame is saved as far as it got, and the
+    // frames after it are skipped)
+    let camera = Camera { checkpoint_file: None, ..scene.camera.clone() };
+    for (i, frame) in frames.enumerate() {
+        let _span = info_span!("frame", frame).entered();
+        scene.camera = path.apply(&camera, frame as Float / fps);
+        scene.autofocus();
+        let file_name = format!("{}_{:04}.png", file_prefix, frame);
+        match accumulation {
+            None => scene.render_to_image().save_with_format(&file_name, ImageFormat::Png).unwrap(),
+            Some(settings) => {
+                // each frame carries on its pixels' sample sequences, so averaging them doesn't just repeat the same noise
+                let skip = i as u32*camera.aa_sample_count;
+                scene.camera.sampler = Arc::new(SkippedSampler { sampler: camera.sampler.clone(), skip });
+                let frame_history = FrameHistory::accumulate(scene, scene.render_to_hdr(), history.as_ref(), settings);
+                scene.display_image(&frame_history.image).save_with_format(&file_name, ImageFormat::Png).unwrap();
+                history = Some(frame_history);
+            }
+        }
+        if scene.camera.cancel.is_cancelled() {
+            warn!(frame, "animation cancelled");
+            break;
+        }
+    }
+}
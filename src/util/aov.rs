@@ -0,0 +1,99 @@
+// AOV - Arbitrary output variables: images of what the camera rays first hit, rendered alongside the image
+//
+// Each is averaged over a pixel's samples, just like the image. Rays that hit nothing leave zeros.
+
+#![allow(dead_code)]
+
+use cgmath::*;
+use serde::{Serialize, Deserialize};
+
+use super::tracing::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Aov {
+    Depth,          // distance in front of the camera (along the view direction), in scene units
+    WorldNormal,    // shading normal in world space
+    CameraNormal,   // shading normal in camera space (x right, y up, z towards the camera)
+    Albedo,         // color of the surface without any lighting
+    Uv,             // texture coordinates (red u, green v)
+    ObjectId,       // which of the scene's objects was hit, numbered from 1 in the order they're listed (0 = nothing).
+                    // averaged like everything else, so pixels on the edge between objects get ids in between
+    Direct,         // light reaching the camera after one bounce
+    Indirect,       // light reaching the camera after more than one bounce
+    Alpha,          // how much of the pixel is covered, for laying the image over another with premultiplied alpha: 1 where
+                    // camera rays hit something, 0 where they see the background, and on shadow catchers, how much of the
+                    // lights' light is blocked there (see ShadowCatcher)
+}
+impl Aov {
+    // name of the aov's exr layer, or what's added to the file name when it's written to its own image
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::WorldNormal => "normal",
+            Aov::CameraNormal => "camera_normal",
+            Aov::Albedo => "albedo",
+            Aov::Uv => "uv",
+            Aov::ObjectId => "object_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Alpha => "alpha",
+        }
+    }
+    // value of the aov for one camera ray, given what it hit, the direct and indirect light it brought back, and its
+    // alpha (single values, like depth, are copied to all three channels)
+    pub fn value(&self, camera: &Camera, ray: &Ray, hit: &Option<RayHit>, direct: Color, indirect: Color, alpha: Float) -> Color {
+        match (self, hit) {
+            (Aov::Direct, _) => direct,
+            (Aov::Indirect, _) => indirect,
+            (Aov::Alpha, _) => vec3(alpha, alpha, alpha),
+            (_, None) => Color::zero(),
+            (Aov::Depth, Some(hit)) => {
+                let depth = hit.distance*ray.direction.dot(camera.view_dir.normalize());
+                vec3(depth, depth, depth)
+            }
+            (Aov::WorldNormal, Some(hit)) => hit.normal,
+            (Aov::CameraNormal, Some(hit)) => camera.to_camera_space(hit.normal),
+            (Aov::Albedo, Some(hit)) => hit.material.albedo(hit),
+            (Aov::Uv, Some(hit)) => hit.tex_coords.map_or(Color::zero(), |uv| uv.extend(0.0)),
+            (Aov::ObjectId, Some(hit)) => vec3(hit.object_id as Float, hit.object_id as Float, hit.object_id as Float),
+        }
+    }
+    // whether the aov holds light, which is exposed and vignetted like the image
+    pub fn is_light(&self) -> bool {
+        matches!(self, Aov::Direct | Aov::Indirect)
+    }
+    // the aov turned into something that can be looked at in an 8 or 16-bit image, for formats that can't store it
+    // as is: depth runs from black up close to white at the furthest point, normals map each axis from -1..1 to 0..1,
+    // and each object gets its own color. light and colors are left alone, since they're displayed like the image.
+    // values are raised to the gamma so they come out as they are once the image is gamma corrected
+    pub fn visualize(&self, img: &HdrImage, gamma: Float) -> HdrImage {
+        if matches!(self, Aov::Albedo | Aov::Direct | Aov::Indirect) { return img.clone() }
+        let max_depth = img.pixels().map(|p| float(p[0])).fold(0.0, Float::max);
+        HdrImage::from_fn(img.width(), img.height(), |x, y| {
+            let p = img.get_pixel(x, y);
+            let p = pixel_color(p);
+            let color = match self {
+                Aov::Depth if max_depth > 0.0 => p / max_depth,
+                Aov::WorldNormal | Aov::CameraNormal => 0.5*p + vec3(0.5, 0.5, 0.5),
+                Aov::ObjectId => id_color(p.x.round() as u32),
+                _ => p,
+            }.map(|c| c.clamp(0.0, 1.0).powf(gamma));
+            color_pixel(color)
+        })
+    }
+}
+
+// distinct looking color for an object id (black for no object), spreading hues apart by the golden ratio
+fn id_color(id: u32) -> Color {
+    if id == 0 { return Color::zero() }
+    let hue = (id as Float * 0.618034).fract()*6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    match hue as u32 {
+        0 => vec3(1.0, x, 0.0),
+        1 => vec3(x, 1.0, 0.0),
+        2 => vec3(0.0, 1.0, x),
+        3 => vec3(0.0, x, 1.0),
+        4 => vec3(x, 0.0, 1.0),
+        _ => vec3(1.0, 0.0, x),
+    }
+}
@@ -0,0 +1,72 @@
+// BACKGROUND - Implements what rays see when they leave the scene without hitting anything
+
+#![allow(dead_code)]
+
+use cgmath::*;
+use super::tracing::consts::PI;
+use std::sync::Arc;
+
+use super::tracing::*;
+use super::texture::*;
+
+// Trait for backgrounds; anything that gives a color for each direction a ray can escape in
+pub trait Background {
+    fn color(&self, direction: Vec3) -> Color;
+}
+
+// SOLID COLOR - the same in every direction (black for a void)
+pub struct SolidColor {
+    pub color: Color,
+}
+impl Background for SolidColor {
+    fn color(&self, _direction: Vec3) -> Color {
+        self.color
+    }
+}
+
+// VERTICAL GRADIENT - blends from one color straight down to another straight up
+pub struct VerticalGradient {
+    pub bottom: Color,
+    pub top: Color,
+}
+impl VerticalGradient {
+    // white to light blue sky from ray tracing in one weekend
+    pub fn sky() -> VerticalGradient {
+        VerticalGradient { bottom: vec3(1.0, 1.0, 1.0), top: vec3(0.5, 0.7, 1.0) }
+    }
+}
+impl Background for VerticalGradient {
+    fn color(&self, direction: Vec3) -> Color {
+        let t = 0.5*(direction.normalize().y + 1.0);
+        (1.0-t)*self.bottom + t*self.top
+    }
+}
+
+// IMAGE - a texture wrapped around the scene as an equirectangular (latitude-longitude) panorama
+// (unlike an EnvironmentLight, it's only seen, it doesn't light anything)
+pub struct ImageBackground {
+    pub texture: Arc<dyn Texture + Send + Sync>,
+    pub intensity: Float,     // scales the texture's colors
+    pub rotation: Float,      // turns the panorama around the vertical axis, in degrees
+}
+impl ImageBackground {
+    pub fn load_from_file(file_name: &str, intensity: Float) -> Option<ImageBackground> {
+        let texture = ImageTexture::load_from_file(file_name, ColorSpace::Srgb)?.with_wrap(WrapMode::Repeat);
+        Some(ImageBackground { texture: Arc::new(texture), intensity, rotation: 0.0 })
+    }
+}
+impl Background for ImageBackground {
+    fn color(&self, direction: Vec3) -> Color {
+        let uv = equirectangular_uv(Matrix3::from_angle_y(Deg(-self.rotation)) * direction.normalize());
+        // (textures put v = 0 at the bottom of the image)
+        self.intensity*self.texture.sample(vec2(uv.x, 1.0 - uv.y), Vec3::zero())
+    }
+}
+
+// position of a unit direction on an equirectangular image: u goes once around the horizon starting from -z,
+// v goes from straight up (0) to straight down (1)
+pub fn equirectangular_uv(direction: Vec3) -> Vec2 {
+    let phi = Float::atan2(direction.x, -direction.z);
+    let u = if phi < 0.0 { phi/(2.0*PI) + 1.0 } else { phi/(2.0*PI) };
+    vec2(u, direction.y.clamp(-1.0, 1.0).acos()/PI)
+}
@@ -0,0 +1,265 @@
+// BAKE - Bakes the light falling on a mesh into a texture (a lightmap or ambient occlusion map) for real-time engines
+//
+// The mesh's triangles are rasterized in uv space to find the point on the mesh under the center of each texel, so it
+// needs a uv layout where no two triangles overlap, like any lightmap. Rays are then path traced out over the
+// hemisphere around each point's normal, either gathering the light arriving there, or finding how much of the
+// hemisphere nearby geometry hides. Texels the layout doesn't cover are filled in from their neighbors a few texels
+// out (dilation), so bilinear filtering and mipmaps in the engine don't bleed black in along the seams.
+//
+// Light probes are baked at points in space instead: rays are path traced out in every direction from each one, and
+// the light they bring back is boiled down to a few colors an engine can light moving objects from, written to a text
+// file with a line per probe.
+
+#![allow(dead_code)]
+
+use cgmath::*;
+use image::ImageBuffer;
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+use ::tracing::{info_span, warn};
+
+use super::tracing::*;
+use super::tracing::consts::PI;
+use super::geometry::*;
+use super::materials::*;
+use super::sampler::*;
+use super::sampling::*;
+
+// what a baked texel holds
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum BakeMode {
+    Irradiance,     // light arriving at the surface, as the light a white diffuse surface would reflect there (so an engine multiplies it by the albedo)
+    AmbientOcclusion { distance: Float },   // how open the hemisphere is (cosine weighted), 0 where it's hidden within distance all round
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BakeSettings {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,       // rays per texel
+    pub mode: BakeMode,
+    pub dilation: u32,      // how many texels out from the uv layout empty texels are filled in
+}
+impl Default for BakeSettings {
+    fn default() -> Self {
+        Self {
+            width: 512,
+            height: 512,
+            samples: 64,
+            mode: BakeMode::Irradiance,
+            dilation: 4,
+        }
+    }
+}
+
+// bakes a mesh that's part of the scene (or, for one that isn't, the light that would fall on it in its place)
+// the mesh must have texture coordinates
+pub fn bake(scene: &Scene, mesh: &StaticMesh, settings: &BakeSettings) -> Option<HdrImage> {
+    if !mesh.has_texcoords() {
+        warn!("can't bake a mesh without texture coordinates");
+        return None;
+    }
+    let _span = info_span!("bake", width = settings.width, height = settings.height, samples = settings.samples).entered();
+    let points = rasterize(mesh, settings.width, settings.height);
+    let mut texels: Vec<Option<Color>> = points.par_iter().enumerate().map(|(i, point)| {
+        let texel = (i as u32 % settings.width, i as u32 / settings.width);
+        point.map(|(position, normal)| bake_texel(scene, texel, position, normal, settings))
+    }).collect();
+    dilate(&mut texels, settings.width, settings.height, settings.dilation);
+    Some(ImageBuffer::from_fn(settings.width, settings.height, |x, y| {
+        color_pixel(texels[(y*settings.width + x) as usize].unwrap_or_else(Color::zero))
+    }))
+}
+
+// point on the mesh (and its normal) under the center of each texel, if the uv layout covers it
+fn rasterize(mesh: &StaticMesh, width: u32, height: u32) -> Vec<Option<(Vec3, Vec3)>> {
+    let mut points = vec![None; (width*height) as usize];
+    for idx in 0..mesh.triangle_count() {
+        let corners = mesh.world_triangle(idx);
+        // corners in texel space (rows go down the image, like texture lookups)
+        let [a, b, c] = corners.map(|(_, _, uv)| vec2(uv.x*width as Float, (1.0 - uv.y)*height as Float));
+        let area = (b - a).perp_dot(c - a);
+        if area.abs() < 1e-12 { continue }
+        let x_range = (Float::min(a.x, Float::min(b.x, c.x)).floor().max(0.0) as u32)..(Float::max(a.x, Float::max(b.x, c.x)).ceil().min(width as Float) as u32);
+        let y_range = (Float::min(a.y, Float::min(b.y, c.y)).floor().max(0.0) as u32)..(Float::max(a.y, Float::max(b.y, c.y)).ceil().min(height as Float) as u32);
+        for y in y_range {
+            for x in x_range.clone() {
+                // barycentric coordinates of the texel's center
+                let p = vec2(x as Float + 0.5, y as Float + 0.5);
+                let wa = (c - b).perp_dot(p - b) / area;
+                let wb = (a - c).perp_dot(p - c) / area;
+                let wc = 1.0 - wa - wb;
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 { continue }
+                let position = wa*corners[0].0 + wb*corners[1].0 + wc*corners[2].0;
+                let normal = (wa*corners[0].1 + wb*corners[1].1 + wc*corners[2].1).normalize();
+                points[(y*width + x) as usize] = Some((position, normal));
+            }
+        }
+    }
+    points
+}
+
+// average of a texel's samples
+fn bake_texel(scene: &Scene, texel: (u32, u32), position: Vec3, normal: Vec3, settings: &BakeSettings) -> Color {
+    let time = scene.camera.shutter_open;
+    // irradiance is the light a white diffuse surface would reflect towards a ray coming straight down onto the point
+    let white = Arc::new(Lambertian { albedo: vec3(1.0,1.0,1.0), ..Default::default() });
+    let ray = Ray { origin: position + normal, direction: -normal, time };
+    let frame = Frame::new(normal);
+    let mut sum = Color::zero();
+    for i in 0..settings.samples {
+        start_sample(&scene.camera.sampler, texel, i, 0);
+        sum += match settings.mode {
+            BakeMode::Irradiance => scene.shade_first_hit(&ray, RayHit::new(1.0, normal, white.clone(), &ray)),
+            BakeMode::AmbientOcclusion { distance } => {
+                let occlusion_ray = Ray { origin: position, direction: frame.to_world(sample_cosine_hemisphere(rand_vec2())), time };
+                if scene.intersect_any(&occlusion_ray, 0.001, distance) { Color::zero() } else { vec3(1.0,1.0,1.0) }
+            }
+        };
+    }
+    sum / settings.samples as Float
+}
+
+// fills empty texels next to filled ones with the average of those neighbors, one ring of texels per pass
+fn dilate(texels: &mut [Option<Color>], width: u32, height: u32, passes: u32) {
+    for _ in 0..passes {
+        let previous = texels.to_vec();
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                if previous[(y*width as i64 + x) as usize].is_some() { continue }
+                let (mut sum, mut count) = (Color::zero(), 0);
+                for (dx, dy) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy))) {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 { continue }
+                    if let Some(color) = previous[(ny*width as i64 + nx) as usize] {
+                        sum += color;
+                        count += 1;
+                    }
+                }
+                if count > 0 {
+                    texels[(y*width as i64 + x) as usize] = Some(sum / count as Float);
+                }
+            }
+        }
+    }
+}
+
+// LIGHT PROBES
+// how the light arriving at a probe from every direction is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProbeFormat {
+    // 9 coefficients of the light's projection onto the real spherical harmonics up to band 2, in the usual order
+    // (l,m) = (0,0), (1,-1), (1,0), (1,1), (2,-2), (2,-1), (2,0), (2,1), (2,2). they hold radiance, so for irradiance an
+    // engine scales the bands by pi, 2pi/3, and pi/4
+    SphericalHarmonics,
+    // 6 colors, the light from +x, -x, +y, -y, +z, and -z (Valve's ambient cube). a direction n gets the colors on its
+    // side of each axis, weighted by n.x^2, n.y^2, and n.z^2
+    AmbientCube,
+}
+impl ProbeFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProbeFormat::SphericalHarmonics => "sh2",
+            ProbeFormat::AmbientCube => "ambient_cube",
+        }
+    }
+    pub fn coefficient_count(&self) -> usize {
+        match self {
+            ProbeFormat::SphericalHarmonics => 9,
+            ProbeFormat::AmbientCube => 6,
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ProbeSettings {
+    pub samples: u32,       // rays per probe
+    pub format: ProbeFormat,
+}
+impl Default for ProbeSettings {
+    fn default() -> Self {
+        Self {
+            samples: 1024,
+            format: ProbeFormat::SphericalHarmonics,
+        }
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Probe {
+    pub position: Vec3,
+    pub coefficients: Vec<Color>,   // in the order of the settings' format
+}
+
+// bakes a probe at each of the given points (e.g. a grid through the scene's empty space)
+pub fn bake_probes(scene: &Scene, positions: &[Vec3], settings: &ProbeSettings) -> Vec<Probe> {
+    let _span = info_span!("bake_probes", probes = positions.len(), samples = settings.samples).entered();
+    let probes = positions.par_iter().enumerate().map(|(i, &position)| Probe {
+        position,
+        coefficients: bake_probe(scene, i as u32, position, settings),
+    }).collect();
+    probes
+}
+
+// coefficients for one probe, from rays spread evenly over the sphere
+fn bake_probe(scene: &Scene, index: u32, position: Vec3, settings: &ProbeSettings) -> Vec<Color> {
+    let mut coefficients = vec![Color::zero(); settings.format.coefficient_count()];
+    let mut cube_weights = [0.0; 6];
+    for i in 0..settings.samples {
+        start_sample(&scene.camera.sampler, (index, 0), i, 0);
+        let direction = sample_uniform_sphere(rand_vec2());
+        let light = scene.incoming_light(&Ray { origin: position, direction, time: scene.camera.shutter_open });
+        match settings.format {
+            ProbeFormat::SphericalHarmonics => {
+                for (coefficient, basis) in coefficients.iter_mut().zip(sh_basis(direction)) {
+                    *coefficient += basis*light / (uniform_sphere_pdf()*settings.samples as Float);
+                }
+            }
+            ProbeFormat::AmbientCube => {
+                // each direction counts towards the face on its side of each axis, as much as it would be lit by it
+                for axis in 0..3 {
+                    let face = 2*axis + if direction[axis] < 0.0 { 1 } else { 0 };
+                    let weight = direction[axis]*direction[axis];
+                    coefficients[face] += weight*light;
+                    cube_weights[face] += weight;
+                }
+            }
+        }
+    }
+    if settings.format == ProbeFormat::AmbientCube {
+        for (coefficient, weight) in coefficients.iter_mut().zip(cube_weights) {
+            if weight > 0.0 { *coefficient /= weight }
+        }
+    }
+    coefficients
+}
+
+// real spherical harmonics up to band 2 for a unit direction, in ProbeFormat::SphericalHarmonics's order
+fn sh_basis(d: Vec3) -> [Float; 9] {
+    let c1 = Float::sqrt(3.0/(4.0*PI));
+    let c2 = Float::sqrt(15.0/(4.0*PI));
+    [
+        0.5*Float::sqrt(1.0/PI),
+        c1*d.y,
+        c1*d.z,
+        c1*d.x,
+        c2*d.x*d.y,
+        c2*d.y*d.z,
+        0.25*Float::sqrt(5.0/PI)*(3.0*d.z*d.z - 1.0),
+        c2*d.x*d.z,
+        0.5*c2*(d.x*d.x - d.y*d.y),
+    ]
+}
+
+// writes probes to a text file: a header line "probes <format name> <probe count> <coefficients per probe>", then a
+// line per probe with its position followed by the red, green, and blue of each of its coefficients
+pub fn write_probes(file_name: &str, probes: &[Probe], format: ProbeFormat) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(file_name)?);
+    writeln!(file, "probes {} {} {}", format.name(), probes.len(), format.coefficient_count())?;
+    for probe in probes {
+        let mut values = vec![probe.position.x, probe.position.y, probe.position.z];
+        values.extend(probe.coefficients.iter().flat_map(|c| [c.x, c.y, c.z]));
+        writeln!(file, "{}", values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" "))?;
+    }
+    file.flush()
+}
@@ -0,0 +1,162 @@
+// BATCH - Renders a queue of jobs listed in a manifest file one after another, reporting how each one went
+//
+// Each line of the manifest (other than blank lines and # comments) is a job: the name of a scene (see
+// scene_by_name), any number of settings to change as key=value, and the file to write the render to, e.g.
+//     default samples=400 width=800 height=600 seed=7 renders/hero.exr
+// A job that can't be rendered (an unknown scene or setting, a folder that doesn't exist, or a render that panics)
+// is reported and the queue carries on with the next one. ctrl-c stops the job that's rendering, writing what it
+// finished, and skips the rest. Each job already renders on every core, so jobs aren't rendered side by side.
+
+#![allow(dead_code)]
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::time::Instant;
+use ::tracing::{info, info_span, warn};
+
+use super::tracing::*;
+use super::cancel::*;
+
+// one line of a manifest
+#[derive(Debug, Clone)]
+pub struct BatchJob {
+    pub line: usize,        // in the manifest, numbered from 1
+    pub scene: String,
+    pub settings: Vec<(String, String)>,
+    pub output: String,
+}
+#[derive(Debug, Clone)]
+pub enum JobStatus {
+    Done(f32),          // seconds it took
+    Failed(String),     // why
+    Skipped,            // the batch was cancelled first
+}
+
+// the jobs in a manifest, or None (after logging why) if it can't be read or a line isn't a job
+pub fn read_manifest(file_name: &str) -> Option<Vec<BatchJob>> {
+    let text = match std::fs::read_to_string(file_name) {
+        Ok(text) => text,
+        Err(err) => {
+            warn!(manifest = %file_name, "couldn't read manifest: {}", err);
+            return None;
+        }
+    };
+    let mut jobs = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() { continue }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() < 2 {
+            warn!(manifest = %file_name, line = i+1, "manifest line should be a scene, any settings, and an output file: {}", line);
+            return None;
+        }
+        let mut settings = Vec::new();
+        for word in &words[1..words.len()-1] {
+            match word.split_once('=') {
+                Some((key, value)) => settings.push((key.to_string(), value.to_string())),
+                None => {
+                    warn!(manifest = %file_name, line = i+1, "manifest line has a setting without a value: {}", word);
+                    return None;
+                }
+            }
+        }
+        jobs.push(BatchJob { line: i+1, scene: words[0].to_string(), settings, output: words[words.len()-1].to_string() });
+    }
+    Some(jobs)
+}
+
+// renders every job in a manifest (seed is used by jobs that don't set their own, and progress_json turns on json
+// progress for every job)
+pub fn render_manifest(file_name: &str, seed: u64, progress_json: bool) {
+    if let Some(jobs) = read_manifest(file_name) {
+        render_batch(&jobs, seed, progress_json);
+    }
+}
+
+// renders jobs in order, logging each one's status as it finishes and a summary at the end
+pub fn render_batch(jobs: &[BatchJob], seed: u64, progress_json: bool) -> Vec<JobStatus> {
+    let cancel = CancelToken::new();
+    cancel_on_ctrl_c(&cancel);
+    let mut statuses = Vec::with_capacity(jobs.len());
+    for (i, job) in jobs.iter().enumerate() {
+        if cancel.is_cancelled() {
+            statuses.push(JobStatus::Skipped);
+            continue;
+        }
+        let _span = info_span!("job", job = i+1, of = jobs.len(), line = job.line, scene = %job.scene, output = %job.output).entered();
+        let start = Instant::now();
+        let status = match render_job(job, seed, progress_json, &cancel) {
+            Ok(()) => JobStatus::Done(start.elapsed().as_secs_f32()),
+            Err(reason) => JobStatus::Failed(reason),
+        };
+        match &status {
+            JobStatus::Done(seconds) => info!(seconds, "job done"),
+            JobStatus::Failed(reason) => warn!("job failed: {}", reason),
+            JobStatus::Skipped => (),
+        }
+        statuses.push(status);
+    }
+
+    let count = |f: fn(&JobStatus) -> bool| statuses.iter().filter(|&status| f(status)).count();
+    info!(done = count(|s| matches!(s, JobStatus::Done(_))), failed = count(|s| matches!(s, JobStatus::Failed(_))),
+        skipped = count(|s| matches!(s, JobStatus::Skipped)), "batch finished");
+    for (job, status) in jobs.iter().zip(&statuses) {
+        if let JobStatus::Failed(reason) = status {
+            warn!(line = job.line, output = %job.output, "job failed: {}", reason);
+        }
+    }
+    statuses
+}
+
+// builds, renders, and saves one job's scene
+fn render_job(job: &BatchJob, seed: u64, progress_json: bool, cancel: &CancelToken) -> Result<(), String> {
+    let seed = match job.settings.iter().find(|(key, _)| key == "seed") {
+        Some((_, value)) => parse(value, "seed")?,
+        None => seed,
+    };
+    if let Some(folder) = Path::new(&job.output).parent() {
+        if !folder.as_os_str().is_empty() && !folder.is_dir() {
+            return Err(format!("folder {} doesn't exist", folder.display()));
+        }
+    }
+    // (a panic partway through, e.g. from an asset that's missing, only fails this job)
+    catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+        let mut scene = scene_by_name(&job.scene, seed).ok_or_else(|| format!("no scene named {}", job.scene))?;
+        // (each job keeps its own checkpoint if the scene has one, so a stopped batch carries on with the right one)
+        scene.camera.checkpoint_file = scene.camera.checkpoint_file.as_ref().map(|_| format!("{}.checkpoint", job.output));
+        for (key, value) in &job.settings {
+            apply_setting(&mut scene.camera, key, value)?;
+        }
+        scene.camera.cancel = cancel.clone();
+        scene.camera.progress_json |= progress_json;
+        scene.autofocus();
+        let (img, aovs) = scene.render_with_aovs();
+        let mattes = scene.render_cryptomattes();
+        scene.save_image_with_aovs(&img, &aovs, &mattes, &job.output);
+        scene.render_cameras(&job.output);
+        Ok(())
+    })).unwrap_or_else(|panic| {
+        let message = panic.downcast_ref::<&str>().map(|s| s.to_string()).or_else(|| panic.downcast_ref::<String>().cloned());
+        Err(format!("render panicked: {}", message.unwrap_or_default()))
+    })
+}
+
+// changes one of the camera's settings:
+// samples, width, height, path_depth, exposure (compensation, in stops), checkpoint (a file to save the job's progress
+// in if it's stopped, and carry on from), or seed (handled when the scene is built)
+fn apply_setting(camera: &mut Camera, key: &str, value: &str) -> Result<(), String> {
+    match key {
+        "samples" => camera.aa_sample_count = parse(value, key)?,
+        "width" => camera.screen_width = parse(value, key)?,
+        "height" => camera.screen_height = parse(value, key)?,
+        "path_depth" => camera.path_depth = parse(value, key)?,
+        "exposure" => camera.exposure_compensation = parse(value, key)?,
+        "checkpoint" => camera.checkpoint_file = Some(value.to_string()),
+        "seed" => (),
+        _ => return Err(format!("unknown setting {}", key)),
+    }
+    Ok(())
+}
+fn parse<T: std::str::FromStr>(value: &str, key: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} can't be {}", key, value))
+}
@@ -0,0 +1,49 @@
+// CANCEL - Stops renders early, from another thread or with ctrl-c, keeping what's been rendered so far
+
+#![allow(dead_code)]
+
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use ::tracing::warn;
+
+// shared flag a render checks as it goes, and stops at the next tile or pass once it's set
+// clones share the flag, so keep a clone to cancel a render running on another thread
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// token ctrl-c cancels
+static CTRL_C_TOKEN: OnceLock<CancelToken> = OnceLock::new();
+
+// makes ctrl-c cancel the token instead of killing the program (pressing it again still quits straight away)
+// only one token can be hooked up, and only on unix-like systems
+pub fn cancel_on_ctrl_c(token: &CancelToken) {
+    if CTRL_C_TOKEN.set(token.clone()).is_err() {
+        warn!("ctrl-c already cancels another token");
+        return;
+    }
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(libc::SIGINT, on_ctrl_c as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+#[cfg(unix)]
+extern "C" fn on_ctrl_c(_signal: libc::c_int) {
+    // (only does what's safe in a signal handler, the render reports that it was cancelled itself)
+    if let Some(token) = CTRL_C_TOKEN.get() {
+        if token.is_cancelled() {
+            unsafe { libc::_exit(130) }
+        }
+        token.cancel();
+    }
+}
@@ -0,0 +1,120 @@
+// COLOR - Implements color science helpers (light source colors, color space conversions, tone mapping)
+
+#![allow(dead_code)]
+
+use cgmath::*;
+use serde::{Serialize, Deserialize};
+
+use super::tracing::*;
+use super::materials::luminance;
+
+// gaussian with different widths on either side of the peak, used by the color matching fits
+fn piecewise_gaussian(x: Float, mu: Float, sigma_low: Float, sigma_high: Float) -> Float {
+    let sigma = if x < mu { sigma_low } else { sigma_high };
+    Float::exp(-0.5*((x-mu)/sigma).powi(2))
+}
+// CIE 1931 color matching functions at a wavelength in nm
+// analytic multi-lobe fit from Wyman et al. 2013, "Simple Analytic Approximations to the CIE XYZ Color Matching Functions"
+pub fn cie_xyz(lambda: Float) -> Vec3 {
+    vec3(
+        1.056*piecewise_gaussian(lambda, 599.8, 37.9, 31.0) + 0.362*piecewise_gaussian(lambda, 442.0, 16.0, 26.7) - 0.065*piecewise_gaussian(lambda, 501.1, 20.4, 26.2),
+        0.821*piecewise_gaussian(lambda, 568.8, 46.9, 40.5) + 0.286*piecewise_gaussian(lambda, 530.9, 16.3, 31.1),
+        1.217*piecewise_gaussian(lambda, 437.0, 11.8, 36.0) + 0.681*piecewise_gaussian(lambda, 459.0, 26.0, 13.8),
+    )
+}
+// converts CIE XYZ to linear sRGB (D65 white point)
+pub fn xyz_to_linear_srgb(xyz: Vec3) -> Color {
+    vec3(
+        3.2406*xyz.x - 1.5372*xyz.y - 0.4986*xyz.z,
+        -0.9689*xyz.x + 1.8758*xyz.y + 0.0415*xyz.z,
+        0.0557*xyz.x - 0.2040*xyz.y + 1.0570*xyz.z,
+    )
+}
+// sRGB transfer function, from encoded values (as stored in most 8-bit images) to linear light and back
+pub fn srgb_to_linear(c: Float) -> Float {
+    if c <= 0.04045 { c/12.92 } else { ((c + 0.055)/1.055).powf(2.4) }
+}
+pub fn linear_to_srgb(c: Float) -> Float {
+    if c <= 0.0031308 { 12.92*c } else { 1.055*c.powf(1.0/2.4) - 0.055 }
+}
+// spectral radiance of a blackbody at a wavelength in nm (Planck's law, unscaled)
+pub fn planck(lambda: Float, kelvin: Float) -> Float {
+    const C2: f64 = 1.4387769e7;    // second radiation constant hc/k, in nm*K
+    let lambda = to_f64(lambda);
+    (1.0 / (lambda.powi(5) * ((C2 / (lambda * to_f64(kelvin))).exp() - 1.0))) as Float
+}
+// color of a blackbody at a temperature in Kelvin, normalized to unit luminance
+// (e.g. 1900K candle, 2700K incandescent bulb, 5500K noon sun, 6500K overcast daylight)
+pub fn blackbody(kelvin: Float) -> Color {
+    let mut xyz = Vec3::zero();
+    let mut lambda = 380.0;
+    while lambda <= 780.0 {
+        xyz += planck(lambda, kelvin)*cie_xyz(lambda);
+        lambda += 5.0;
+    }
+    // hot blackbodies fall slightly outside of srgb, so negative components are clipped
+    xyz_to_linear_srgb(xyz/xyz.y).map(|c| Float::max(c, 0.0))
+}
+// emitted light of a given color temperature and brightness, for use as a material's emission
+pub fn blackbody_emission(kelvin: Float, intensity: Float) -> Color {
+    intensity*blackbody(kelvin)
+}
+
+// TONE MAPPING
+// how linear radiance is squeezed into the 0 to 1 range a display can show (before gamma correction)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ToneMapping {
+    Clamp,      // channels above 1 are clipped, which is exact for anything not too bright
+    Reinhard,   // luminance L becomes L/(1+L), keeping each color's hue and saturation (Reinhard et al. 2002)
+    Aces,       // Narkowicz's fit of the ACES filmic curve, with contrast and highlights that roll off per channel
+    Filmic,     // Hable's curve from Uncharted 2, with a white point of 11.2
+    AgX,        // Sobotka's AgX, which desaturates highlights towards white without shifting their hue
+}
+impl ToneMapping {
+    // maps a color to displayable linear values from 0 to 1
+    pub fn apply(&self, color: Color) -> Color {
+        let color = color.map(|c| Float::max(c, 0.0));
+        let mapped = match self {
+            ToneMapping::Clamp => color,
+            ToneMapping::Reinhard => {
+                let l = luminance(color);
+                if l > 0.0 { color / (1.0 + l) } else { color }
+            }
+            // the fit maps 1 to about 0.8, so radiance is scaled down by 0.6 to keep midtones where they were
+            ToneMapping::Aces => color.map(|c| {
+                let c = 0.6*c;
+                (c*(2.51*c + 0.03)) / (c*(2.43*c + 0.59) + 0.14)
+            }),
+            ToneMapping::Filmic => {
+                let curve = |x: Float| (x*(0.15*x + 0.05) + 0.004) / (x*(0.15*x + 0.5) + 0.06) - 0.02/0.30;
+                color.map(|c| curve(2.0*c) / curve(11.2))
+            }
+            ToneMapping::AgX => agx(color),
+        };
+        mapped.map(|c| c.clamp(0.0, 1.0))
+    }
+}
+// AgX base look, from Wrensch's fit of Blender's implementation: colors are pulled slightly towards gray in a wider
+// space, log encoded between -12.47 and +4.03 stops around middle gray, put through a sigmoid, and pulled back out
+fn agx(color: Color) -> Color {
+    // (column major)
+    let inset = Matrix3::new(
+        0.8424791, 0.04232824, 0.04237565,
+        0.0784336, 0.8784686, 0.0784336,
+        0.07922375, 0.07916613, 0.879143,
+    );
+    let outset = Matrix3::new(
+        1.196879, -0.05289685, -0.05297164,
+        -0.09802088, 1.151903, -0.09804345,
+        -0.09902974, -0.09896118, 1.151074,
+    );
+    const MIN_EV: Float = -12.47393;
+    const MAX_EV: Float = 4.026069;
+    let encoded = (inset*color).map(|c| (Float::max(c, 1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV));
+    let sigmoid = encoded.map(|x| {
+        let (x2, x4) = (x*x, x*x*x*x);
+        15.5*x4*x2 - 40.14*x4*x + 31.96*x4 - 6.868*x2*x + 0.4298*x2 + 0.1191*x - 0.00232
+    });
+    // the sigmoid's output is display encoded, so it's decoded back to linear for the gamma correction after
+    (outset*sigmoid).map(|c| Float::max(c, 0.0).powf(2.2))
+}
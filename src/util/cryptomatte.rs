@@ -0,0 +1,140 @@
+// CRYPTOMATTE - ID mattes of what the camera sees, in the format compositing software reads
+//
+// Follows the Cryptomatte spec (Friedman and Jones 2015, https://github.com/Psyop/Cryptomatte). Every object (or
+// material) is named, and each pixel lists the names its camera rays first hit, most coverage first, along with how much
+// of the pixel each covers. Names are stored as their MurmurHash3 turned into a float, with a manifest in the file's
+// header mapping the names back to hashes, so any object can be picked out with antialiased edges without rendering a
+// mask for it.
+
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+
+use super::tracing::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Cryptomatte {
+    Object,     // which of the scene's objects was hit (see Scene::object_name)
+    Material,   // which material was hit (see Material::name)
+}
+impl Cryptomatte {
+    // name of the matte's exr layer, which its channels are named after
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cryptomatte::Object => "CryptoObject",
+            Cryptomatte::Material => "CryptoMaterial",
+        }
+    }
+}
+// ids kept for each pixel (two to a layer of red, green, blue, and alpha), which is plenty for all but the busiest pixels
+pub const CRYPTOMATTE_RANKS: usize = 6;
+
+// one matte: the hashed names covering each pixel (row by row) and how much of it they cover, most first
+pub struct CryptomatteImage {
+    pub kind: Cryptomatte,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Vec<(u32, Float)>>,
+    pub manifest: BTreeMap<String, u32>,    // every name in the matte, and its hash
+}
+impl CryptomatteImage {
+    // the matte from each pixel's names, with how much each of them counts (weights for one name are added up, and
+    // a pixel's are scaled to add up to 1, counting whatever had no name as uncovered)
+    pub fn from_names(kind: Cryptomatte, width: u32, height: u32, names: Vec<(Vec<(String, Float)>, Float)>) -> CryptomatteImage {
+        let mut manifest = BTreeMap::new();
+        let pixels = names.into_iter().map(|(names, total)| {
+            let mut ids: Vec<(u32, Float)> = Vec::new();
+            for (name, weight) in names {
+                let id = *manifest.entry(name).or_insert_with_key(|name| cryptomatte_hash(name));
+                match ids.iter_mut().find(|(other, _)| *other == id) {
+                    Some((_, coverage)) => *coverage += weight,
+                    None => ids.push((id, weight)),
+                }
+            }
+            ids.iter_mut().for_each(|(_, coverage)| *coverage /= Float::max(total, Float::MIN_POSITIVE));
+            ids.sort_by(|a, b| b.1.total_cmp(&a.1));
+            ids.truncate(CRYPTOMATTE_RANKS);
+            ids
+        }).collect();
+        CryptomatteImage { kind, width, height, pixels, manifest }
+    }
+    // channels of the matte's layers, as (name, values): each layer's red and blue hold ids, and its green and alpha
+    // hold their coverage
+    pub fn channels(&self) -> Vec<(String, Vec<f32>)> {
+        let mut channels = Vec::new();
+        for layer in 0..CRYPTOMATTE_RANKS.div_ceil(2) {
+            for (c, channel) in ["R", "G", "B", "A"].iter().enumerate() {
+                let rank = 2*layer + c/2;
+                let values = self.pixels.iter().map(|ids| match ids.get(rank) {
+                    Some(&(id, _)) if c % 2 == 0 => cryptomatte_id_to_float(id),
+                    Some(&(_, coverage)) => to_f32(coverage),
+                    None => 0.0,
+                }).collect();
+                channels.push((format!("{}{:02}.{}", self.kind.name(), layer, channel), values));
+            }
+        }
+        channels
+    }
+    // header attributes the spec asks for, as (name, value)
+    pub fn metadata(&self) -> Vec<(String, String)> {
+        // each matte's attributes are keyed by the start of its name's hash
+        let key = format!("cryptomatte/{}", &format!("{:08x}", murmur3_32(self.kind.name().as_bytes(), 0))[..7]);
+        let entries: Vec<String> = self.manifest.iter().map(|(name, id)| format!("\"{}\":\"{:08x}\"", json_escape(name), id)).collect();
+        vec![
+            (format!("{}/name", key), self.kind.name().to_string()),
+            (format!("{}/hash", key), "MurmurHash3_32".to_string()),
+            (format!("{}/conversion", key), "uint32_to_float32".to_string()),
+            (format!("{}/manifest", key), format!("{{{}}}", entries.join(","))),
+        ]
+    }
+}
+
+// hash of a name, as the spec takes it
+pub fn cryptomatte_hash(name: &str) -> u32 {
+    murmur3_32(name.as_bytes(), 0)
+}
+// a hash stored as a float, with its bits as they are, except hashes that would be infinite, nan, or denormal have a bit
+// of their exponent flipped so every image format and compositor keeps them intact
+pub fn cryptomatte_id_to_float(id: u32) -> f32 {
+    let exponent = (id >> 23) & 255;
+    f32::from_bits(if exponent == 0 || exponent == 255 { id ^ (1 << 23) } else { id })
+}
+
+// MurmurHash3, 32-bit version (Appleby 2011)
+pub fn murmur3_32(data: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+    let mix = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    let mut h = seed;
+    let mut blocks = data.chunks_exact(4);
+    for block in &mut blocks {
+        h ^= mix(u32::from_le_bytes([block[0], block[1], block[2], block[3]]));
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let k = tail.iter().rev().fold(0, |k, &byte| (k << 8) | byte as u32);
+        h ^= mix(k);
+    }
+    h ^= data.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^ (h >> 16)
+}
+
+// a string with the characters json needs escaped escaped, to go between quotes
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
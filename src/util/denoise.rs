@@ -0,0 +1,95 @@
+// DENOISE - Built in denoiser for cleaning up noisy renders, without any libraries
+//
+// A joint (cross) bilateral filter: each pixel becomes a weighted average of its neighbours, where neighbours count
+// less the further away they are and the more their normal, albedo, and depth differ from the pixel's. Those come
+// from the AOVs, which are close to noise free after a few samples, so the filter blurs away noise within surfaces
+// while keeping their edges sharp. Lighting is filtered with the albedo divided out and multiplied back afterwards,
+// so textures aren't blurred along with the noise.
+
+#![allow(dead_code)]
+
+use cgmath::*;
+use image::Rgb;
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use super::tracing::*;
+use super::aov::*;
+
+// how strongly to filter. sigmas are the differences at which a neighbour's weight falls to about 60%
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Denoiser {
+    pub radius: u32,        // neighbours up to this many pixels away in each direction are averaged
+    pub spatial_sigma: Float, // in pixels
+    pub color_sigma: Float,   // in (albedo divided out) lighting, after compressing highlights into 0..1
+    pub normal_sigma: Float,  // in distance between unit normals
+    pub albedo_sigma: Float,  // in distance between albedos
+    pub depth_sigma: Float,   // relative to the pixel's depth
+}
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            radius: 5,
+            spatial_sigma: 3.0,
+            color_sigma: 0.3,
+            normal_sigma: 0.2,
+            albedo_sigma: 0.1,
+            depth_sigma: 0.05,
+        }
+    }
+}
+impl Denoiser {
+    // aovs the filter is guided by, which are rendered along with the image when it's turned on
+    pub const GUIDES: [Aov; 3] = [Aov::WorldNormal, Aov::Albedo, Aov::Depth];
+
+    // filters the image using the normal, albedo, and depth aovs rendered along with it (all the same size)
+    pub fn denoise(&self, img: &HdrImage, normal: &HdrImage, albedo: &HdrImage, depth: &HdrImage) -> HdrImage {
+        let (width, height) = img.dimensions();
+        let pixel = |img: &HdrImage, x: u32, y: u32| pixel_color(img.get_pixel(x, y));
+        // lighting with the albedo divided out (where there's any to divide out)
+        let demodulate = |c: Color, a: Color| vec3(
+            if a.x > 0.01 { c.x / a.x } else { c.x },
+            if a.y > 0.01 { c.y / a.y } else { c.y },
+            if a.z > 0.01 { c.z / a.z } else { c.z },
+        );
+        let remodulate = |c: Color, a: Color| vec3(
+            if a.x > 0.01 { c.x * a.x } else { c.x },
+            if a.y > 0.01 { c.y * a.y } else { c.y },
+            if a.z > 0.01 { c.z * a.z } else { c.z },
+        );
+        // compresses highlights so fireflies don't stand out from everything around them as much in the color weight
+        let compress = |c: Color| c.map(|v| v / (1.0 + v));
+        let lighting: Vec<Color> = (0..height).flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| demodulate(pixel(img, x, y), pixel(albedo, x, y))).collect();
+        let r = self.radius as i32;
+        let rows: Vec<Vec<Rgb<f32>>> = (0..height).into_par_iter().map(|y| {
+            (0..width).map(|x| {
+                let center_light = compress(lighting[(y*width + x) as usize]);
+                let (center_normal, center_albedo, center_depth) = (pixel(normal, x, y), pixel(albedo, x, y), pixel(depth, x, y).x);
+                let mut sum = Color::zero();
+                let mut weight_sum = 0.0;
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                        if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 { continue }
+                        let (nx, ny) = (nx as u32, ny as u32);
+                        let light = lighting[(ny*width + nx) as usize];
+                        let depth_diff = (pixel(depth, nx, ny).x - center_depth) / center_depth.max(1e-4);
+                        let exponent = (dx*dx + dy*dy) as Float / (self.spatial_sigma*self.spatial_sigma)
+                            + (compress(light) - center_light).magnitude2() / (self.color_sigma*self.color_sigma)
+                            + (pixel(normal, nx, ny) - center_normal).magnitude2() / (self.normal_sigma*self.normal_sigma)
+                            + (pixel(albedo, nx, ny) - center_albedo).magnitude2() / (self.albedo_sigma*self.albedo_sigma)
+                            + depth_diff*depth_diff / (self.depth_sigma*self.depth_sigma);
+                        let weight = (-0.5*exponent).exp();
+                        sum += weight*light;
+                        weight_sum += weight;
+                    }
+                }
+                // the pixel itself always has weight 1, so there's never nothing to average
+                let color = remodulate(sum / weight_sum, center_albedo);
+                color_pixel(color)
+            }).collect()
+        }).collect();
+        HdrImage::from_fn(width, height, |x, y| rows[y as usize][x as usize])
+    }
+}
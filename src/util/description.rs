@@ -0,0 +1,196 @@
+// DESCRIPTION - Materials and objects written down as data, so scenes can be saved, loaded, and sent to other machines
+//
+// Cameras, render settings, and the materials that are only numbers (Lambertian, Metal, Dielectric, ...) serialize
+// with serde as they are. Everything else is shared as a trait object (Arc<dyn Material>, Arc<dyn Intersectable>),
+// which serde can't see into, so it's described by an enum tagged with its type instead, and built into the real thing
+// once it's been read, e.g. in json
+//     {"type": "sphere", "center": {"x": 0, "y": 1, "z": 0}, "radius": 0.5,
+//      "material": {"type": "dielectric", "idx_of_refraction": 1.5}}
+// Only what can be written down is described: textures and meshes by their file names, and nothing that has to be set
+// up in code (samplers, lights linked by their Arc, observers, density fields).
+//
+// Other crates can add their own kinds of objects: a type that reads its description with serde (and builds the object
+// from it) is registered under a name with register_object_type, after which scenes can describe it as a custom
+// object, e.g.
+//     {"type": "custom", "kind": "torus", "params": {"radii": [1, 0.25], "material": {"type": "lambertian"}}}
+// (materials in a custom object's description are best read as MaterialDesc, and built along with the object)
+
+#![allow(dead_code)]
+
+use cgmath::*;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use serde_value::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, OnceLock, RwLock};
+
+use super::tracing::*;
+use super::geometry::*;
+use super::materials::*;
+use super::material_library::*;
+
+// MATERIALS
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialDesc {
+    Lambertian(Lambertian),
+    OrenNayar(OrenNayar),
+    Metal(Metal),
+    Conductor(Conductor),
+    Dielectric(Dielectric),
+    CarPaint(CarPaint),
+    Velvet(Velvet),
+    Hair(Hair),
+    Subsurface(SubsurfaceMaterial),
+    Isotropic(Isotropic),
+    Layered { layers: Vec<LayerDesc> },     // top layer first
+    Mix { a: Box<MaterialDesc>, b: Box<MaterialDesc>, factor: Float },
+    Toon { material: Box<MaterialDesc>, style: ToonStyle },
+    ShadowCatcher { material: Box<MaterialDesc> },
+    Named { name: String, material: Box<MaterialDesc> },
+    Library { file: String, name: String }, // a material from a material library file (see MaterialLibrary)
+    // a material of any type a library file can use (including ones added with register_material_type), with the
+    // parameters it would have there, e.g. {"type": "params", "kind": "glitter", "params": {"albedo": [1, 0.8, 0.2]}}
+    Params { kind: String, params: BTreeMap<String, ParamValue> },
+}
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParamValue {
+    Number(Float),
+    Numbers(Vec<Float>),
+    Text(String),   // split into words, like a line of a library file
+}
+impl ParamValue {
+    fn tokens(&self) -> Vec<String> {
+        match self {
+            ParamValue::Number(n) => vec![n.to_string()],
+            ParamValue::Numbers(v) => v.iter().map(|n| n.to_string()).collect(),
+            ParamValue::Text(text) => text.split_whitespace().map(|t| t.to_string()).collect(),
+        }
+    }
+}
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LayerDesc {
+    pub material: MaterialDesc,
+    pub weight: LayerWeight,
+}
+impl MaterialDesc {
+    // the material it describes, or why it can't be made (e.g. a library file that can't be read)
+    pub fn build(&self) -> Result<Arc<dyn Material + Send + Sync>, String> {
+        Ok(match self {
+            MaterialDesc::Lambertian(m) => Arc::new(*m),
+            MaterialDesc::OrenNayar(m) => Arc::new(m.clone()),
+            MaterialDesc::Metal(m) => Arc::new(m.clone()),
+            MaterialDesc::Conductor(m) => Arc::new(m.clone()),
+            MaterialDesc::Dielectric(m) => Arc::new(m.clone()),
+            MaterialDesc::CarPaint(m) => Arc::new(m.clone()),
+            MaterialDesc::Velvet(m) => Arc::new(m.clone()),
+            MaterialDesc::Hair(m) => Arc::new(*m),
+            MaterialDesc::Subsurface(m) => Arc::new(m.clone()),
+            MaterialDesc::Isotropic(m) => Arc::new(m.clone()),
+            MaterialDesc::Layered { layers } => {
+                if layers.is_empty() { return Err("a layered material needs at least one layer".to_string()) }
+                Arc::new(LayeredMaterial {
+                    layers: layers.iter().map(|layer| Ok(Layer { material: layer.material.build()?, weight: layer.weight })).collect::<Result<_, String>>()?,
+                })
+            }
+            MaterialDesc::Mix { a, b, factor } => Arc::new(MixMaterial { a: a.build()?, b: b.build()?, factor: MixFactor::Constant(*factor) }),
+            MaterialDesc::Toon { material, style } => Arc::new(ToonMaterial { material: material.build()?, style: *style }),
+            MaterialDesc::ShadowCatcher { material } => Arc::new(ShadowCatcher { material: material.build()? }),
+            MaterialDesc::Named { name, material } => Arc::new(Named { material: material.build()?, name: name.clone() }),
+            MaterialDesc::Library { file, name } => {
+                let library = MaterialLibrary::load_from_file(file).ok_or_else(|| format!("couldn't load material library {}", file))?;
+                library.get(name).ok_or_else(|| format!("no material named {} in {}", name, file))?
+            }
+            MaterialDesc::Params { kind, params } => {
+                let mut p = MaterialParams::default();
+                for (key, value) in params {
+                    p.set(key, value.tokens());
+                }
+                build_material(kind, &p)?
+            }
+        })
+    }
+}
+
+// OBJECTS
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)]    // (descriptions are read once and built, so a mesh's transform needn't be boxed)
+pub enum ObjectDesc {
+    Sphere { center: Vec3, radius: Float, material: MaterialDesc },
+    Plane { point: Vec3, normal: Vec3, material: MaterialDesc },
+    Triangle { a: Vec3, b: Vec3, c: Vec3, material: MaterialDesc },
+    // an obj file, with one material or one built from texture files (see StaticMesh::load_from_file)
+    Mesh {
+        file: String,
+        material: Option<MaterialDesc>,
+        albedo_map: Option<String>,
+        emission_map: Option<String>,
+        metallic_map: Option<String>,
+        roughness_map: Option<String>,
+        normal_map: Option<String>,
+        #[serde(default = "identity")]
+        transform: Matrix4<Float>,
+        #[serde(default)]
+        accel: AccelType,   // structure built over its triangles (the bvh unless given)
+    },
+    Volume { boundary: Box<ObjectDesc>, phase_function: MaterialDesc, density: Float },
+    Moving { object: Box<ObjectDesc>, velocity: Vec3, max_time: Float },
+    Custom { kind: String, params: Value },   // an object of a type added with register_object_type
+}
+fn identity() -> Matrix4<Float> {
+    Matrix4::identity()
+}
+impl ObjectDesc {
+    // the object it describes (loading any files it names), or why it can't be made
+    pub fn build(&self) -> Result<Arc<dyn Intersectable + Send + Sync>, String> {
+        Ok(match self {
+            ObjectDesc::Sphere { center, radius, material } => Arc::new(Sphere { center: *center, radius: *radius, material: material.build()? }),
+            ObjectDesc::Plane { point, normal, material } => Arc::new(Plane { point: *point, normal: *normal, material: material.build()? }),
+            ObjectDesc::Triangle { a, b, c, material } => Arc::new(Triangle { a: *a, b: *b, c: *c, material: material.build()? }),
+            ObjectDesc::Mesh { file, material, albedo_map, emission_map, metallic_map, roughness_map, normal_map, transform, accel } => {
+                if !std::path::Path::new(file).is_file() { return Err(format!("mesh {} doesn't exist", file)) }
+                let material = material.as_ref().map(|m| m.build()).transpose()?;
+                Arc::new(StaticMesh::load_from_file(file, albedo_map.as_deref(), emission_map.as_deref(), metallic_map.as_deref(),
+                    roughness_map.as_deref(), normal_map.as_deref(), material, *transform).with_accel(*accel))
+            }
+            ObjectDesc::Volume { boundary, phase_function, density } => Arc::new(ConvexVolume {
+                boundary: boundary.build()?,
+                phase_function: phase_function.build()?,
+                density: *density,
+                density_field: None,
+            }),
+            ObjectDesc::Moving { object, velocity, max_time } => Arc::new(Moving { object: object.build()?, velocity: *velocity, max_time: *max_time }),
+            ObjectDesc::Custom { kind, params } => {
+                let constructor = REGISTERED_OBJECTS.get().and_then(|types| types.read().unwrap().get(kind).cloned());
+                match constructor {
+                    Some(constructor) => constructor(params.clone())?,
+                    None => return Err(format!("unknown object type '{}'", kind)),
+                }
+            }
+        })
+    }
+}
+
+// OBJECT TYPES FROM OTHER CRATES
+// the description of an object of another crate's type, which builds the object once it's been read
+pub trait CustomObject: DeserializeOwned {
+    fn build(self) -> Result<Arc<dyn Intersectable + Send + Sync>, String>;
+}
+type ObjectConstructor = fn(Value) -> Result<Arc<dyn Intersectable + Send + Sync>, String>;
+static REGISTERED_OBJECTS: OnceLock<RwLock<HashMap<String, ObjectConstructor>>> = OnceLock::new();
+
+// adds an object type for custom objects of this kind to be read as, e.g. register_object_type::<TorusDesc>("torus")
+// (registering a kind again replaces it)
+pub fn register_object_type<T: CustomObject>(kind: &str) {
+    REGISTERED_OBJECTS.get_or_init(Default::default).write().unwrap().insert(kind.to_string(), build_custom::<T>);
+}
+// kinds added with register_object_type
+pub fn registered_object_types() -> Vec<String> {
+    REGISTERED_OBJECTS.get().map_or(Vec::new(), |types| types.read().unwrap().keys().cloned().collect())
+}
+fn build_custom<T: CustomObject>(params: Value) -> Result<Arc<dyn Intersectable + Send + Sync>, String> {
+    let description: T = params.deserialize_into().map_err(|e| format!("bad description: {}", e))?;
+    description.build()
+}
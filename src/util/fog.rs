@@ -0,0 +1,87 @@
+// FOG - A participating medium filling the whole scene
+//
+// Unlike a ConvexVolume, fog has no boundary: every ray that isn't a shadow ray may scatter in it somewhere short of
+// what it hits (or short of the camera's max_trace_dist, if it hits nothing), and shadow rays are dimmed by it on the
+// way to the light. Far away things fade into the color of the light the fog scatters (aerial perspective), and light
+// slipping past the objects around it shows up as shafts. The fog can thin out with height, like a HeightDensity.
+// Its thickness changes smoothly along a ray (apart from where it crosses the base of the falloff), so where rays
+// scatter and how much light gets through are worked out exactly rather than stepped through like a volume.
+
+#![allow(dead_code)]
+
+use cgmath::*;
+use std::sync::Arc;
+
+use super::tracing::*;
+use super::materials::*;
+use super::geometry::*;
+use super::sampler::*;
+
+#[derive(Clone)]
+pub struct Fog {
+    pub density: Float,     // chance per unit length of a ray scattering or being absorbed (below the falloff's base)
+    pub phase_function: Arc<dyn Material + Send + Sync>,   // how light scatters, e.g. Isotropic, whose albedo is the fog's color
+    pub height_falloff: Option<HeightDensity>,   // how the fog thins out above some height (None = as thick everywhere)
+}
+impl Fog {
+    // where a ray in [t_min, t_max] scatters in the fog, if it does
+    pub fn scatter(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<Float> {
+        if self.density <= 0.0 { return None }
+        // the optical depth the ray gets through before it scatters is exponentially distributed
+        let mut depth = -Float::ln(1.0 - rand_f32());
+        for (t0, t1) in self.pieces(ray, t_min, t_max) {
+            if let Some(t) = self.piece_distance(ray, t0, t1, depth) {
+                return Some(t);
+            }
+            depth -= self.piece_depth(ray, t0, t1);
+        }
+        None
+    }
+    // share of light that gets through the fog along a ray in [t_min, t_max]
+    pub fn transmittance(&self, ray: &Ray, t_min: Float, t_max: Float) -> Float {
+        if self.density <= 0.0 || t_max <= t_min { return 1.0 }
+        let depth: Float = self.pieces(ray, t_min, t_max).into_iter().map(|(t0, t1)| self.piece_depth(ray, t0, t1)).sum();
+        Float::exp(-depth)
+    }
+
+    // the ray in [t_min, t_max], cut where it crosses the base of the falloff (the fog is as thick as it gets below it)
+    fn pieces(&self, ray: &Ray, t_min: Float, t_max: Float) -> Vec<(Float, Float)> {
+        if let Some(falloff) = &self.height_falloff {
+            let t = (falloff.base - ray.origin.y) / ray.direction.y;
+            if t > t_min && t < t_max { return vec![(t_min, t), (t, t_max)] }
+        }
+        vec![(t_min, t_max)]
+    }
+    // density per unit of t at the start of a piece, and the falloff's height scale along it (None where it's as thick
+    // all the way, either without a falloff or below its base)
+    fn piece_start(&self, ray: &Ray, t0: Float, t1: Float) -> (Float, Option<Float>) {
+        let length = ray.direction.magnitude();
+        match &self.height_falloff {
+            Some(falloff) if ray.origin.y + 0.5*(t0 + t1)*ray.direction.y > falloff.base => {
+                let y0 = ray.origin.y + t0*ray.direction.y;
+                (self.density*length*Float::min(1.0, Float::exp(-(y0 - falloff.base) / falloff.falloff)), Some(falloff.falloff))
+            }
+            _ => (self.density*length, None),
+        }
+    }
+    // optical depth along a piece of the ray
+    fn piece_depth(&self, ray: &Ray, t0: Float, t1: Float) -> Float {
+        let (start, scale) = self.piece_start(ray, t0, t1);
+        let k = scale.map_or(0.0, |scale| ray.direction.y / scale);
+        // the density falls off as e^(-k t) from the start of the piece
+        if (k*(t1 - t0)).abs() < 1e-4 { start*(t1 - t0) } else { start*(1.0 - Float::exp(-k*(t1 - t0))) / k }
+    }
+    // where along a piece of the ray the optical depth from its start reaches depth, if it does
+    fn piece_distance(&self, ray: &Ray, t0: Float, t1: Float, depth: Float) -> Option<Float> {
+        let (start, scale) = self.piece_start(ray, t0, t1);
+        if start <= 0.0 { return None }
+        let k = scale.map_or(0.0, |scale| ray.direction.y / scale);
+        let t = if (k*(t1 - t0)).abs() < 1e-4 { t0 + depth/start } else {
+            // (going up, the fog may thin out too fast to ever get that deep)
+            let remaining = 1.0 - depth*k/start;
+            if remaining <= 0.0 { return None }
+            t0 - Float::ln(remaining) / k
+        };
+        if t < t1 { Some(t) } else { None }
+    }
+}
@@ -5,12 +5,19 @@
 use std::{sync::Arc};
 use tobj::{self, Mesh};
 use cgmath::*;
-use std::mem;
-use rand::Rng;
+use serde::{Serialize, Deserialize};
+use super::tracing::consts::PI;
+use rayon::prelude::*;
+use ::tracing::{info, info_span};
 
 use super::tracing::*;
 use super::materials::*;
 use super::texture::*;
+use super::lights::LightLinks;
+use super::sampler::*;
+use super::sampling::*;
+use super::stats::*;
+use super::simd::*;
 
 
 ////////////////////////////////////////////////////////
@@ -19,27 +26,37 @@ use super::texture::*;
 
 // AXIS-ALIGNED BOUNDING BOX
 #[derive(Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]   // (named like the usual abbreviation, as are BVH and LBVH below)
 pub struct AABB {
     pub min: Vec3,
     pub max: Vec3,
 }
 impl AABB {
     // returns the bounding box surrounding two given bounding boxes
-    fn aabb_surrounding(a: &AABB, b: &AABB) -> AABB {
+    pub fn aabb_surrounding(a: &AABB, b: &AABB) -> AABB {
         AABB {
             min: vec3(
-                f32::min(a.min.x, b.min.x),
-                f32::min(a.min.y, b.min.y),
-                f32::min(a.min.z, b.min.z),
+                Float::min(a.min.x, b.min.x),
+                Float::min(a.min.y, b.min.y),
+                Float::min(a.min.z, b.min.z),
             ),
             max: vec3(
-                f32::max(a.max.x, b.max.x),
-                f32::max(a.max.y, b.max.y),
-                f32::max(a.max.z, b.max.z),
+                Float::max(a.max.x, b.max.x),
+                Float::max(a.max.y, b.max.y),
+                Float::max(a.max.z, b.max.z),
             ),
         }
     }
 }
+impl AABB {
+    pub fn center(&self) -> Vec3 {
+        0.5*(self.min + self.max)
+    }
+    // whether point is inside the box, or within epsilon of it
+    pub fn contains(&self, point: Vec3, epsilon: Float) -> bool {
+        (0..3).all(|axis| point[axis] >= self.min[axis] - epsilon && point[axis] <= self.max[axis] + epsilon)
+    }
+}
 impl Default for AABB {
     fn default() -> AABB {
         AABB {
@@ -47,26 +64,46 @@ impl Default for AABB {
         }
     }
 }
+impl AABB {
+    // returns the parametric interval where the ray is inside the box (clipped to [t_min, t_max]), if there is one
+    pub fn intersect_interval(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<(Float, Float)> {
+        // based on raytracing the next week, with the three slabs tested side by side (the fourth lane is unbounded)
+        count_ray_stats(|stats| stats.aabb_tests += 1);
+        let origin = Floatx4([ray.origin.x, ray.origin.y, ray.origin.z, 0.0]);
+        let inv_d = Floatx4([ray.direction.x, ray.direction.y, ray.direction.z, 1.0]).recip();
+        let t0 = (Floatx4([self.min.x, self.min.y, self.min.z, Float::NEG_INFINITY]) - origin) * inv_d;
+        let t1 = (Floatx4([self.max.x, self.max.y, self.max.z, Float::INFINITY]) - origin) * inv_d;
+        let flip = inv_d.lt(Floatx4::splat(0.0));
+        let tmin = Float::max(flip.select(t1, t0).max_of(3), t_min);
+        let tmax = Float::min(flip.select(t0, t1).min_of(3), t_max);
+        if tmax <= tmin {
+            return None;
+        }
+        Some((tmin, tmax))
+    }
+    // tests up to four rays against the box at once, each clipped to [t_min, its t_max]
+    // returns which of the active lanes enter the box
+    pub fn intersect_rays4(&self, rays: &Ray4, t_min: Float, t_max: Floatx4) -> Mask4 {
+        count_ray_stats(|stats| stats.aabb_tests += rays.active.count());
+        let slab = |min: Float, max: Float, origin: Floatx4, inv_d: Floatx4| {
+            let t0 = (Floatx4::splat(min) - origin) * inv_d;
+            let t1 = (Floatx4::splat(max) - origin) * inv_d;
+            let flip = inv_d.lt(Floatx4::splat(0.0));
+            (flip.select(t1, t0), flip.select(t0, t1))
+        };
+        let (near_x, far_x) = slab(self.min.x, self.max.x, rays.origin.x, rays.inv_direction.x);
+        let (near_y, far_y) = slab(self.min.y, self.max.y, rays.origin.y, rays.inv_direction.y);
+        let (near_z, far_z) = slab(self.min.z, self.max.z, rays.origin.z, rays.inv_direction.z);
+        let tmin = near_x.max(Floatx4::splat(t_min)).max(near_y).max(near_z);
+        let tmax = far_x.min(t_max).min(far_y).min(far_z);
+        rays.active & tmin.lt(tmax)
+    }
+}
 impl Intersectable for AABB {
     // this doesn't actually use the RayHit struct, so for now it just returns Some default or None
-    fn intersect_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
-        // based on raytracing the next week
-        let mut tmin = t_min.clone();
-        let mut tmax = t_max.clone();
-        for axis in 0..3 {
-            let inv_d = 1.0 / ray.direction[axis];
-            let mut t0 = (self.min[axis] - ray.origin[axis]) * inv_d;
-            let mut t1 = (self.max[axis] - ray.origin[axis]) * inv_d;
-            if inv_d < 0.0 {
-                mem::swap(&mut t0, &mut t1);
-            }
-            tmin = f32::max(t0, tmin);
-            tmax = f32::min(t1, tmax);
-            if tmax <= tmin {
-                return None;
-            }
-        }
-        return Some(RayHit {
+    fn intersect_ray(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
+        self.intersect_interval(ray, t_min, t_max)?;
+        Some(RayHit {
             frontface: true,
             distance: 0.0,
             hitpoint: Vec3::zero(),
@@ -75,50 +112,513 @@ impl Intersectable for AABB {
             tex_coords: None,
             tangent: None,
             bitangent: None,
+            uv_footprint: 0.0,
+            min_roughness: 0.0,
+            object_id: 0,
         })
     }
     fn bounding_box(&self) -> Option<AABB> {
-        Some(self.clone())
+        Some(*self)
+    }
+    fn intersect_any(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        self.intersect_interval(ray, t_min, t_max).is_some()
     }
 }
 
 // BOUNDING VOLUME HIERARCHY - tree of bounding boxes and primitives
-#[derive(Debug, Clone, Default)]
-pub struct BVHNode {
+// generic over the primitive type so it can hold mesh triangles, spheres, or any mix of Intersectables (via Arc<dyn Intersectable>)
+#[derive(Clone)]
+pub struct BVHNode<T: Intersectable = IndexedTriangle> {
     pub aabb: AABB,
-    pub left: Option<Box<BVHNode>>,
-    pub right: Option<Box<BVHNode>>,
-    pub primitive: Option<IndexedTriangle>,
+    pub left: Option<Box<BVHNode<T>>>,
+    pub right: Option<Box<BVHNode<T>>>,
+    pub primitive: Option<T>,
+}
+impl<T: Intersectable> Default for BVHNode<T> {
+    fn default() -> BVHNode<T> {
+        BVHNode {
+            aabb: AABB::default(),
+            left: None,
+            right: None,
+            primitive: None,
+        }
+    }
+}
+impl<T: Intersectable> BVHNode<T> {
+    // builds a bvh over a list of primitives (returns None if the list is empty)
+    // every primitive must have a bounding box, so unbounded primitives like planes should stay out of the bvh
+    pub fn build(prims: Vec<T>) -> Option<BVHNode<T>> {
+        if prims.is_empty() { return None }
+        let prims = prims.into_iter().map(|p| {
+            let aabb = p.bounding_box().expect("BVH primitives must have a bounding box");
+            (p, aabb)
+        }).collect();
+        Some(Self::build_helper(prims))
+    }
+    // helper for bvh construction recursion
+    fn build_helper(mut prims: Vec<(T, AABB)>) -> BVHNode<T> {
+        let mut node = BVHNode::default();
+        if prims.len() == 1 {
+            // make the node a leaf
+            let (prim, aabb) = prims.remove(0);
+            node.aabb = aabb;
+            node.primitive = Some(prim);
+        }
+        else {
+            // sort segment by random axis
+            let axis = (rand_f32()*3.0) as usize;
+            let comparator = |a: &(T, AABB), b: &(T, AABB)| {
+                a.1.min[axis].partial_cmp(&b.1.min[axis]).unwrap_or(std::cmp::Ordering::Equal)
+            };
+            prims.sort_by(comparator);
+            // recurse on each side
+            let right_prims = prims.split_off(prims.len()/2);
+            let left  = Self::build_helper(prims);
+            let right = Self::build_helper(right_prims);
+            node.aabb = AABB::aabb_surrounding(&left.aabb, &right.aabb);
+            node.left = Some(Box::new(left));
+            node.right = Some(Box::new(right));
+        }
+        node
+    }
 }
-impl Intersectable for BVHNode {
-    fn intersect_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
+impl<T: Intersectable> BVHNode<T> {
+    // recomputes every node's bounding box bottom-up from its primitives, keeping the tree's topology as is
+    // (much cheaper than a rebuild when primitives only move a little, e.g. per-frame animation)
+    pub fn refit_bounds(&mut self) -> AABB {
         if let Some(prim) = &self.primitive {
-            // node is a leaf
-            prim.intersect_ray(ray, t_min, t_max)
+            self.aabb = prim.bounding_box().unwrap_or_default();
         }
         else {
-            // node is interior - check if ray intersects aabb
-            let mut best_hit = None;
-            let mut best_t = t_max.clone();
-            if self.aabb.intersect_ray(ray, t_min, t_max).is_some() {
-                // recurse to children
-                if let Some(left_node) = &self.left {
-                    let hit_opt = left_node.intersect_ray(ray, t_min, t_max);
-                    if let Some(hit) = hit_opt { 
-                        best_hit = Some(hit.clone());
-                        best_t = hit.distance;
+            let left = self.left.as_mut().map(|node| node.refit_bounds());
+            let right = self.right.as_mut().map(|node| node.refit_bounds());
+            self.aabb = match (left, right) {
+                (Some(l), Some(r)) => AABB::aabb_surrounding(&l, &r),
+                (Some(b), None) | (None, Some(b)) => b,
+                (None, None) => AABB::default(),
+            };
+        }
+        self.aabb
+    }
+}
+impl BVHNode<IndexedTriangle> {
+    // points every leaf triangle at new mesh data and refits the bounding boxes
+    // the new mesh must have the same triangles (indices) as the one the bvh was built with - only vertex data may change
+    pub fn refit(&mut self, mesh: &Arc<Mesh>) {
+        self.retarget_leaves(mesh);
+        self.refit_bounds();
+    }
+    fn retarget_leaves(&mut self, mesh: &Arc<Mesh>) {
+        if let Some(tri) = &mut self.primitive {
+            tri.mesh = mesh.clone();
+        }
+        if let Some(node) = &mut self.left { node.retarget_leaves(mesh); }
+        if let Some(node) = &mut self.right { node.retarget_leaves(mesh); }
+    }
+}
+const BVH_STACK_CAPACITY: usize = 64;   // traversal stacks start this big (they grow if a tree is deeper)
+impl<T: Intersectable> Intersectable for BVHNode<T> {
+    fn intersect_ray(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
+        // iterative traversal with an explicit stack of (node, distance where the ray enters its box)
+        // children are pushed so the nearer one is visited first, letting closer hits cull the farther child
+        let mut best_hit = None;
+        let mut best_t = t_max;
+        let mut stack: Vec<(&BVHNode<T>, Float)> = Vec::with_capacity(BVH_STACK_CAPACITY);
+        if let Some((t_enter, _)) = self.aabb.intersect_interval(ray, t_min, t_max) {
+            stack.push((self, t_enter));
+        }
+        while let Some((node, t_enter)) = stack.pop() {
+            // skip nodes that are now behind the closest hit found since they were pushed
+            if t_enter > best_t { continue }
+            if let Some(prim) = &node.primitive {
+                // node is a leaf
+                if let Some(hit) = prim.intersect_ray(ray, t_min, best_t) {
+                    best_t = hit.distance;
+                    best_hit = Some(hit);
+                }
+                continue;
+            }
+            // node is interior - find which children the ray enters and push the nearer one last
+            let left = node.left.as_deref().and_then(|child| child.aabb.intersect_interval(ray, t_min, best_t).map(|(t, _)| (child, t)));
+            let right = node.right.as_deref().and_then(|child| child.aabb.intersect_interval(ray, t_min, best_t).map(|(t, _)| (child, t)));
+            match (left, right) {
+                (Some(l), Some(r)) => {
+                    if l.1 <= r.1 { stack.push(r); stack.push(l); }
+                    else { stack.push(l); stack.push(r); }
+                }
+                (Some(child), None) | (None, Some(child)) => stack.push(child),
+                (None, None) => {}
+            }
+        }
+        best_hit
+    }
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.aabb)
+    }
+    fn intersect_packet(&self, rays: &[Ray], t_min: Float, t_max: Float) -> Vec<Option<RayHit>> {
+        // traverses the tree once for the whole packet, only descending into nodes hit by at least one still-active ray
+        // active ray indices live in one shared pool; each stack entry refers to the range of the pool its parent filtered.
+        // since traversal is depth-first, everything past that range belongs to finished subtrees and can be dropped
+        let mut hits = vec![None; rays.len()];
+        let mut best_t = vec![t_max; rays.len()];
+        let mut pool: Vec<usize> = (0..rays.len()).collect();
+        let mut stack: Vec<(&BVHNode<T>, usize, usize)> = Vec::with_capacity(BVH_STACK_CAPACITY);
+        stack.push((self, 0, rays.len()));
+        while let Some((node, start, end)) = stack.pop() {
+            pool.truncate(end);
+            // keep only the rays that hit this node's aabb (closer than their current best hit), testing four at a time
+            for chunk_start in (start..end).step_by(LANES) {
+                let lanes = usize::min(LANES, end - chunk_start);
+                let mut indices = [0; LANES];
+                indices[..lanes].copy_from_slice(&pool[chunk_start..chunk_start + lanes]);
+                let group = Ray4::gather(rays, &indices[..lanes]);
+                let enters = node.aabb.intersect_rays4(&group, t_min, Floatx4::from_fn(|k| best_t[indices[k]]));
+                pool.extend((0..lanes).filter(|&k| enters.0[k]).map(|k| indices[k]));
+            }
+            if pool.len() == end { continue }
+            if let Some(prim) = &node.primitive {
+                // node is a leaf
+                for chunk in pool[end..].chunks(LANES) {
+                    let group = Ray4::gather(rays, chunk);
+                    let chunk_hits = prim.intersect_ray4(&group, t_min, Floatx4::from_fn(|k| best_t[chunk[k % chunk.len()]]));
+                    for (&i, hit) in chunk.iter().zip(chunk_hits) {
+                        if let Some(hit) = hit {
+                            best_t[i] = hit.distance;
+                            hits[i] = Some(hit);
+                        }
                     }
                 }
-                if let Some(right_node) = &self.right {
-                    let hit_opt = right_node.intersect_ray(ray, t_min, best_t);
-                    if hit_opt.is_some() { best_hit = hit_opt.clone(); }
+                continue;
+            }
+            let new_end = pool.len();
+            if let Some(right_node) = &node.right { stack.push((right_node, end, new_end)); }
+            if let Some(left_node) = &node.left { stack.push((left_node, end, new_end)); }
+        }
+        hits
+    }
+    fn intersect_any(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        // visit order doesn't matter for occlusion, so stop at the first hit
+        let mut stack: Vec<&BVHNode<T>> = Vec::with_capacity(BVH_STACK_CAPACITY);
+        stack.push(self);
+        while let Some(node) = stack.pop() {
+            if node.aabb.intersect_interval(ray, t_min, t_max).is_none() { continue }
+            if let Some(prim) = &node.primitive {
+                if prim.intersect_any(ray, t_min, t_max) { return true }
+                continue;
+            }
+            if let Some(right_node) = &node.right { stack.push(right_node); }
+            if let Some(left_node) = &node.left { stack.push(left_node); }
+        }
+        false
+    }
+}
+
+// LINEAR BVH - builds a BVHNode tree from primitives sorted along a morton (z-order) curve
+// based on Karras, "Maximizing Parallelism in the Construction of BVHs, Octrees, and k-d Trees"
+const LBVH_PARALLEL_THRESHOLD: usize = 4096;    // ranges smaller than this are built on a single thread
+impl<T: Intersectable + Send + Sync> BVHNode<T> {
+    // builds a bvh by sorting primitive centroids by morton code and splitting ranges where the codes' top bit differs
+    // much faster to build than BVHNode::build on huge meshes, at the cost of somewhat worse trees
+    pub fn build_lbvh(prims: Vec<T>) -> Option<BVHNode<T>> {
+        if prims.is_empty() { return None }
+        let boxes: Vec<AABB> = prims.par_iter().map(|p| p.bounding_box().expect("BVH primitives must have a bounding box")).collect();
+        let centroids: Vec<Vec3> = boxes.par_iter().map(|b| 0.5*(b.min + b.max)).collect();
+        let bounds = centroids.iter().fold(AABB { min: centroids[0], max: centroids[0] }, |acc, c| AABB::aabb_surrounding(&acc, &AABB { min: *c, max: *c }));
+
+        // compute morton codes and sort primitives along the curve
+        let extent = bounds.max - bounds.min;
+        let keys: Vec<(u32, u32)> = centroids.par_iter().enumerate().map(|(i, c)| {
+            let mut q = [0u32; 3];
+            for axis in 0..3 {
+                let k = if extent[axis] > 0.0 { (c[axis] - bounds.min[axis]) / extent[axis] } else { 0.5 };
+                q[axis] = (k*1023.0).clamp(0.0, 1023.0) as u32;
+            }
+            (morton_code(q[0], q[1], q[2]), i as u32)
+        }).collect();
+        let keys = radix_sort_parallel(keys);
+
+        // reorder primitives and build the tree over the sorted list
+        let mut slots: Vec<Option<T>> = prims.into_iter().map(Some).collect();
+        let mut codes = Vec::with_capacity(keys.len());
+        let mut sorted = Vec::with_capacity(keys.len());
+        for (code, idx) in keys {
+            codes.push(code);
+            sorted.push((slots[idx as usize].take().unwrap(), boxes[idx as usize]));
+        }
+        Some(Self::build_lbvh_helper(sorted, &codes))
+    }
+    // helper for lbvh construction recursion - prims and codes cover the same sorted range
+    fn build_lbvh_helper(mut prims: Vec<(T, AABB)>, codes: &[u32]) -> BVHNode<T> {
+        let mut node = BVHNode::default();
+        if prims.len() == 1 {
+            // make the node a leaf
+            let (prim, aabb) = prims.remove(0);
+            node.aabb = aabb;
+            node.primitive = Some(prim);
+            return node;
+        }
+        // split where the highest bit that differs across the range flips (or in the middle if all codes match)
+        let first = codes[0];
+        let last = codes[codes.len()-1];
+        let mid = if first == last {
+            codes.len()/2
+        }
+        else {
+            let common_prefix = (first ^ last).leading_zeros();
+            codes.partition_point(|&code| (first ^ code).leading_zeros() > common_prefix)
+        };
+        let right_prims = prims.split_off(mid);
+        let (left_codes, right_codes) = codes.split_at(mid);
+        let (left, right) = if codes.len() > LBVH_PARALLEL_THRESHOLD {
+            rayon::join(|| Self::build_lbvh_helper(prims, left_codes), || Self::build_lbvh_helper(right_prims, right_codes))
+        }
+        else {
+            (Self::build_lbvh_helper(prims, left_codes), Self::build_lbvh_helper(right_prims, right_codes))
+        };
+        node.aabb = AABB::aabb_surrounding(&left.aabb, &right.aabb);
+        node.left = Some(Box::new(left));
+        node.right = Some(Box::new(right));
+        node
+    }
+}
+// spreads the low 10 bits of v out so there are two zero bits between each one
+fn expand_bits(v: u32) -> u32 {
+    let mut v = v & 0x3ff;
+    v = (v | (v << 16)) & 0x030000ff;
+    v = (v | (v << 8)) & 0x0300f00f;
+    v = (v | (v << 4)) & 0x030c30c3;
+    v = (v | (v << 2)) & 0x09249249;
+    v
+}
+// 30-bit morton code interleaving three 10-bit coordinates
+fn morton_code(x: u32, y: u32, z: u32) -> u32 {
+    (expand_bits(x) << 2) | (expand_bits(y) << 1) | expand_bits(z)
+}
+// stable lsd radix sort of (key, value) pairs by key, 8 bits per pass
+// each pass buckets chunks of the input in parallel, then concatenates buckets in order
+fn radix_sort_parallel(mut items: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
+    let chunk_size = usize::max(items.len() / rayon::current_num_threads(), LBVH_PARALLEL_THRESHOLD);
+    for pass in 0..4 {
+        let shift = pass*8;
+        let chunk_buckets: Vec<Vec<Vec<(u32, u32)>>> = items.par_chunks(chunk_size).map(|chunk| {
+            let mut buckets = vec![Vec::new(); 256];
+            for &item in chunk {
+                buckets[((item.0 >> shift) & 0xff) as usize].push(item);
+            }
+            buckets
+        }).collect();
+        items.clear();
+        for bucket in 0..256 {
+            for buckets in &chunk_buckets {
+                items.extend_from_slice(&buckets[bucket]);
+            }
+        }
+    }
+    items
+}
+
+// ACCELERATION STRUCTURE TYPE - selects which structure is built over a list of primitives
+// (meshes in a scene description pick theirs with "accel", e.g. {"type": "mesh", "file": "scan.obj", "accel": "UniformGrid"})
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum AccelType {
+    #[default]
+    BVH,            // good general purpose choice
+    LBVH,           // bvh built from sorted morton codes; near-instant to build but slower to traverse (drafts, previews)
+    UniformGrid,    // cheaper to build and traverse for dense, evenly-distributed geometry (voxel scans, particles)
+}
+
+// ACCELERATOR - wraps whichever acceleration structure was selected so callers don't need to care
+#[derive(Clone)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Accelerator<T: Intersectable> {
+    BVH(BVHNode<T>),
+    UniformGrid(UniformGrid<T>),
+}
+impl<T: Intersectable + Send + Sync> Accelerator<T> {
+    // builds the selected acceleration structure over a list of primitives (returns None if the list is empty)
+    pub fn build(accel_type: AccelType, prims: Vec<T>) -> Option<Accelerator<T>> {
+        match accel_type {
+            AccelType::BVH => BVHNode::build(prims).map(Accelerator::BVH),
+            AccelType::LBVH => BVHNode::build_lbvh(prims).map(Accelerator::BVH),
+            AccelType::UniformGrid => UniformGrid::build(prims).map(Accelerator::UniformGrid),
+        }
+    }
+}
+impl<T: Intersectable> Intersectable for Accelerator<T> {
+    fn intersect_ray(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
+        match self {
+            Accelerator::BVH(bvh) => bvh.intersect_ray(ray, t_min, t_max),
+            Accelerator::UniformGrid(grid) => grid.intersect_ray(ray, t_min, t_max),
+        }
+    }
+    fn bounding_box(&self) -> Option<AABB> {
+        match self {
+            Accelerator::BVH(bvh) => bvh.bounding_box(),
+            Accelerator::UniformGrid(grid) => grid.bounding_box(),
+        }
+    }
+    fn intersect_packet(&self, rays: &[Ray], t_min: Float, t_max: Float) -> Vec<Option<RayHit>> {
+        match self {
+            Accelerator::BVH(bvh) => bvh.intersect_packet(rays, t_min, t_max),
+            Accelerator::UniformGrid(grid) => grid.intersect_packet(rays, t_min, t_max),
+        }
+    }
+    fn intersect_any(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        match self {
+            Accelerator::BVH(bvh) => bvh.intersect_any(ray, t_min, t_max),
+            Accelerator::UniformGrid(grid) => grid.intersect_any(ray, t_min, t_max),
+        }
+    }
+}
+
+// UNIFORM GRID - regular grid of cells that each list the primitives overlapping them, traversed with 3D-DDA
+// (based on Amanatides & Woo, "A Fast Voxel Traversal Algorithm for Ray Tracing")
+// a single level only: cells aren't subdivided further, so geometry clumped into a few cells of a large scene is better
+// served by the bvh (nested or hierarchical grids would handle it, but aren't implemented)
+const GRID_CELLS_PER_PRIMITIVE: Float = 4.0;  // target total cell count relative to primitive count
+const GRID_MAX_RESOLUTION: usize = 256;     // max cells along any axis
+#[derive(Clone)]
+pub struct UniformGrid<T: Intersectable> {
+    pub aabb: AABB,
+    pub resolution: [usize; 3],     // number of cells along each axis
+    cell_size: Vec3,
+    cells: Vec<Vec<usize>>,         // indices into prims for each cell (x-major)
+    prims: Vec<T>,
+}
+impl<T: Intersectable> UniformGrid<T> {
+    // builds a grid over a list of primitives (returns None if the list is empty)
+    // like the bvh, every primitive must have a bounding box
+    pub fn build(prims: Vec<T>) -> Option<UniformGrid<T>> {
+        if prims.is_empty() { return None }
+        let boxes: Vec<AABB> = prims.iter().map(|p| p.bounding_box().expect("grid primitives must have a bounding box")).collect();
+        let mut aabb = boxes.iter().fold(boxes[0], |acc, b| AABB::aabb_surrounding(&acc, b));
+        // pad the bounds slightly so flat geometry still has some volume
+        let pad = 0.0001*(aabb.max - aabb.min).magnitude() + 0.000001;
+        aabb.min -= vec3(pad, pad, pad);
+        aabb.max += vec3(pad, pad, pad);
+
+        // pick resolution so cells are roughly cubes and there are a few cells per primitive
+        let extent = aabb.max - aabb.min;
+        let volume = extent.x*extent.y*extent.z;
+        let cells_per_unit = (GRID_CELLS_PER_PRIMITIVE*prims.len() as Float / volume).cbrt();
+        let mut resolution = [1; 3];
+        for axis in 0..3 {
+            resolution[axis] = ((extent[axis]*cells_per_unit).ceil() as usize).clamp(1, GRID_MAX_RESOLUTION);
+        }
+        let cell_size = vec3(extent.x/resolution[0] as Float, extent.y/resolution[1] as Float, extent.z/resolution[2] as Float);
+
+        // insert each primitive into every cell its bounding box overlaps
+        let mut grid = UniformGrid {
+            aabb,
+            resolution,
+            cell_size,
+            cells: vec![Vec::new(); resolution[0]*resolution[1]*resolution[2]],
+            prims: Vec::new(),
+        };
+        for (i, b) in boxes.iter().enumerate() {
+            let lo = grid.cell_coords(b.min);
+            let hi = grid.cell_coords(b.max);
+            for x in lo[0]..=hi[0] {
+                for y in lo[1]..=hi[1] {
+                    for z in lo[2]..=hi[2] {
+                        let idx = grid.cell_index([x, y, z]);
+                        grid.cells[idx].push(i);
+                    }
                 }
             }
-            best_hit
         }
+        grid.prims = prims;
+        Some(grid)
+    }
+
+    // returns the (clamped) coordinates of the cell containing a point
+    fn cell_coords(&self, p: Vec3) -> [usize; 3] {
+        let mut coords = [0; 3];
+        for axis in 0..3 {
+            let c = ((p[axis] - self.aabb.min[axis]) / self.cell_size[axis]).floor() as isize;
+            coords[axis] = c.clamp(0, self.resolution[axis] as isize - 1) as usize;
+        }
+        coords
+    }
+    fn cell_index(&self, coords: [usize; 3]) -> usize {
+        (coords[0]*self.resolution[1] + coords[1])*self.resolution[2] + coords[2]
+    }
+}
+impl<T: Intersectable> UniformGrid<T> {
+    // walks the cells pierced by the ray front to back using 3D-DDA, calling visit with each cell's primitives
+    // and the t where the ray leaves that cell. the walk stops early when visit returns true
+    fn walk_cells<F: FnMut(&[usize], Float) -> bool>(&self, ray: &Ray, t_min: Float, t_max: Float, mut visit: F) {
+        // clip ray against the grid bounds
+        let (t_enter, t_exit) = match self.aabb.intersect_interval(ray, t_min, t_max) {
+            Some(interval) => interval,
+            None => return,
+        };
+        let entry = ray.origin + t_enter*ray.direction;
+        let coords = self.cell_coords(entry);
+
+        // set up 3D-DDA: for each axis, the t of the next cell boundary and the t spacing between boundaries
+        let mut cell = [coords[0] as isize, coords[1] as isize, coords[2] as isize];
+        let mut step = [0isize; 3];
+        let mut t_next = [Float::INFINITY; 3];
+        let mut t_delta = [Float::INFINITY; 3];
+        for axis in 0..3 {
+            let d = ray.direction[axis];
+            if d > 0.0 {
+                step[axis] = 1;
+                let boundary = self.aabb.min[axis] + (cell[axis] + 1) as Float * self.cell_size[axis];
+                t_next[axis] = t_enter + (boundary - entry[axis]) / d;
+                t_delta[axis] = self.cell_size[axis] / d;
+            }
+            else if d < 0.0 {
+                step[axis] = -1;
+                let boundary = self.aabb.min[axis] + cell[axis] as Float * self.cell_size[axis];
+                t_next[axis] = t_enter + (boundary - entry[axis]) / d;
+                t_delta[axis] = -self.cell_size[axis] / d;
+            }
+        }
+
+        loop {
+            let idx = self.cell_index([cell[0] as usize, cell[1] as usize, cell[2] as usize]);
+            let t_cell_exit = Float::min(Float::min(t_next[0], t_next[1]), Float::min(t_next[2], t_exit));
+            if visit(&self.cells[idx], t_cell_exit) { return }
+            // step to the neighboring cell along the axis with the closest boundary
+            let axis = if t_next[0] < t_next[1] {
+                if t_next[0] < t_next[2] { 0 } else { 2 }
+            } else if t_next[1] < t_next[2] { 1 } else { 2 };
+            if t_next[axis] > t_exit { return }
+            cell[axis] += step[axis];
+            if cell[axis] < 0 || cell[axis] >= self.resolution[axis] as isize { return }
+            t_next[axis] += t_delta[axis];
+        }
+    }
+}
+impl<T: Intersectable> Intersectable for UniformGrid<T> {
+    fn intersect_ray(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
+        let mut best_hit: Option<RayHit> = None;
+        let mut best_t = t_max;
+        self.walk_cells(ray, t_min, t_max, |cell_prims, t_cell_exit| {
+            for &prim_idx in cell_prims {
+                if let Some(hit) = self.prims[prim_idx].intersect_ray(ray, t_min, best_t) {
+                    best_t = hit.distance;
+                    best_hit = Some(hit);
+                }
+            }
+            // a hit inside the current cell can't be beaten by anything in later cells
+            best_hit.is_some() && best_t <= t_cell_exit
+        });
+        best_hit
     }
     fn bounding_box(&self) -> Option<AABB> {
-        Some(self.aabb.clone())
+        Some(self.aabb)
+    }
+    fn intersect_any(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        let mut found = false;
+        self.walk_cells(ray, t_min, t_max, |cell_prims, _| {
+            found = cell_prims.iter().any(|&prim_idx| self.prims[prim_idx].intersect_any(ray, t_min, t_max));
+            found
+        });
+        found
     }
 }
 
@@ -126,17 +626,20 @@ impl Intersectable for BVHNode {
 #[derive(Clone)]
 pub struct StaticMesh {
     mesh: Arc<Mesh>,    // contains geometry data
-    material: Option<Arc<dyn Material + Send + Sync>>, // used only if textures do not describe material
-    textures: [Option<Texture>; 5], // 0 - albedo, 1 - emission, 2 - metallic, 3 - roughness, 4 - normal
-    bvh_root: Option<Box<BVHNode>>, // root node of BVH
-    transform: Matrix4<f32>,        // describes position/orientation in scene
-    inv_transform: Matrix4<f32>,
+    material: Arc<dyn Material + Send + Sync>, // either given, or a ParameterizedMaterial built from the textures
+    textures: [Option<Arc<dyn Texture + Send + Sync>>; 6], // 0 - albedo, 1 - emission, 2 - metallic, 3 - roughness, 4 - normal, 5 - alpha
+    accel: Option<Box<Accelerator<IndexedTriangle>>>, // acceleration structure over the mesh's triangles (bvh by default)
+    accel_type: AccelType,
+    transform: Matrix4<Float>,        // describes position/orientation in scene
+    inv_transform: Matrix4<Float>,
 }
 impl StaticMesh {
     
     // load a mesh from file to create a new StaticMesh object
-    pub fn load_from_file(file_name: &str, albedo_path: Option<&str>, emission_path: Option<&str>, metallic_path: Option<&str>, roughness_path: Option<&str>, normal_path: Option<&str>, material: Option<Arc<dyn Material + Sync + Send>>, transform: Matrix4<f32>) -> StaticMesh {
+    #[allow(clippy::too_many_arguments)]    // (one per texture, any of which can be left out)
+    pub fn load_from_file(file_name: &str, albedo_path: Option<&str>, emission_path: Option<&str>, metallic_path: Option<&str>, roughness_path: Option<&str>, normal_path: Option<&str>, material: Option<Arc<dyn Material + Sync + Send>>, transform: Matrix4<Float>) -> StaticMesh {
         // load obj
+        let load = info_span!("load", file = %file_name).entered();
         let obj = tobj::load_obj(
             file_name,
             &tobj::LoadOptions {
@@ -148,72 +651,109 @@ impl StaticMesh {
         );
         assert!(obj.is_ok());
         let (mut models, materials) = obj.expect("Failed to load OBJ file");
-        let materials = materials.expect("Failed to load MTL file");
-        println!("Loaded {} successfully:", file_name);
-        println!("# of models: {}", models.len());
+        let _materials = materials.expect("Failed to load MTL file");
+        info!(models = models.len(), "loaded mesh");
         
+        let textures = [
+            albedo_path.and_then(|path| ImageTexture::load_shared(path, ColorSpace::Srgb)),
+            emission_path.and_then(|path| ImageTexture::load_shared(path, ColorSpace::Srgb)),
+            metallic_path.and_then(|path| ImageTexture::load_shared(path, ColorSpace::Linear)),
+            roughness_path.and_then(|path| ImageTexture::load_shared(path, ColorSpace::Linear)),
+            normal_path.and_then(|path| ImageTexture::load_shared(path, ColorSpace::Linear)),
+            None,
+        ];
+        // if object has a single specified material, then it describes the whole surface
+        let material = material.unwrap_or_else(|| Arc::new(ParameterizedMaterial {
+            albedo: textures[0].clone().unwrap_or_else(|| solid_value(0.0)),
+            emission: textures[1].clone().unwrap_or_else(|| solid_value(0.0)),
+            metallic: textures[2].clone().unwrap_or_else(|| solid_value(0.0)),
+            roughness: textures[3].clone().unwrap_or_else(|| solid_value(1.0)),
+            ..Default::default()
+        }));
+
         // assume there's only one mesh
         let mut sm = StaticMesh { 
             mesh: Arc::new(models.remove(0).mesh),
-            bvh_root: None,
-            material: material,
-            textures: [
-                if albedo_path.is_some() { Texture::load_from_file(albedo_path.unwrap()) } else { None },
-                if emission_path.is_some() { Texture::load_from_file(emission_path.unwrap()) } else { None },
-                if metallic_path.is_some() { Texture::load_from_file(metallic_path.unwrap()) } else { None },
-                if roughness_path.is_some() { Texture::load_from_file(roughness_path.unwrap()) } else { None },
-                if normal_path.is_some() { Texture::load_from_file(normal_path.unwrap()) } else { None },
-            ],
-            transform: transform,
+            accel: None,
+            accel_type: AccelType::BVH,
+            material,
+            textures,
+            transform,
             inv_transform: transform.inverse_transform().unwrap(),
         };
-        sm.build_bvh();
+        // (building its acceleration structure is timed on its own)
+        drop(load);
+        sm.build_accel();
         sm
     }
 
-    // build the StaticMesh's bvh using its mesh
-    pub fn build_bvh(&mut self) {
-        if self.bvh_root.is_some() { return }
-        print!("Building BVH...");
+    // build the StaticMesh's acceleration structure using its mesh
+    pub fn build_accel(&mut self) {
+        if self.accel.is_some() { return }
+        let _span = info_span!("build_accel", accel = ?self.accel_type, triangles = self.mesh.indices.len()/3).entered();
         // make temporary array of total triangles
         let mut tris = Vec::new();
         for i in 0..self.mesh.indices.len()/3 {
             tris.push(IndexedTriangle { idx: i, mesh: self.mesh.clone() })
         }
-        let start: usize = 0;
-        let end = tris.len();
-        let node = self.build_bvh_helper(&mut tris, start, end);        
-        self.bvh_root = Some(node);
-        println!("Done.");
+        self.accel = Accelerator::build(self.accel_type, tris).map(Box::new);
     }
-    // helper for bvh construction recursion
-    fn build_bvh_helper(&self, tris: &mut Vec<IndexedTriangle>, start: usize, end: usize) -> Box<BVHNode> { // start/end = triangle indices in range (0..indices.len()/3)
-        let mut node = BVHNode::default();
-        if end-start == 1 {
-            // make the node a leaf
-            let tri = IndexedTriangle { idx: start, mesh: self.mesh.clone()};
-            node.aabb = tri.bounding_box().unwrap_or_default();
-            node.primitive = Some(tri);
+    // rebuilds the mesh with a different acceleration structure, e.g.
+    // StaticMesh::load_from_file(...).with_accel(AccelType::UniformGrid)
+    pub fn with_accel(mut self, accel_type: AccelType) -> StaticMesh {
+        if accel_type != self.accel_type {
+            self.accel_type = accel_type;
+            self.accel = None;
+            self.build_accel();
         }
-        else {
-            // sort segment by random axis
-            let mut rng = rand::thread_rng();
-            let axis: usize = rng.gen_range(0..3);
-            let comparator = |a: &IndexedTriangle, b: &IndexedTriangle| {
-                let f = a.bounding_box().unwrap_or_default().min[axis];
-                let g = b.bounding_box().unwrap_or_default().min[axis];
-                f.partial_cmp(&g).unwrap_or(std::cmp::Ordering::Equal)
-            };
-            tris[start..end].sort_by(comparator);
-            // recurse on each side
-            let mid = start + (end-start)/2;
-            let left  = self.build_bvh_helper(tris, start, mid);
-            let right = self.build_bvh_helper(tris, mid, end);
-            node.aabb = AABB::aabb_surrounding(&left.aabb, &right.aabb);
-            node.left = Some(left);
-            node.right = Some(right);
+        self
+    }
+
+    // limits which of the scene's lights shine on the mesh
+    pub fn with_light_links(mut self, links: LightLinks) -> StaticMesh {
+        self.material = Arc::new(LightLinked { material: self.material, links });
+        self
+    }
+
+    // uses the alpha channel of an image to cut holes in the mesh (texels below ALPHA_CUTOFF are skipped)
+    pub fn with_alpha_map(mut self, alpha_path: &str) -> StaticMesh {
+        self.textures[5] = ImageTexture::load_shared(alpha_path, ColorSpace::Linear);
+        self
+    }
+
+    // swaps in new vertex data (e.g. the next frame of a skinned or simulated mesh)
+    // the bvh is refit rather than rebuilt, so the new mesh must keep the same triangles
+    pub fn update_mesh(&mut self, mesh: Mesh) {
+        assert_eq!(mesh.indices, self.mesh.indices, "updated mesh must have the same triangles");
+        self.mesh = Arc::new(mesh);
+        match self.accel.as_deref_mut() {
+            Some(Accelerator::BVH(bvh)) => bvh.refit(&self.mesh),
+            _ => {
+                // other structures don't support refitting
+                self.accel = None;
+                self.build_accel();
+            }
         }
-        Box::new(node)
+    }
+
+    // number of triangles in the mesh
+    pub fn triangle_count(&self) -> usize {
+        self.mesh.indices.len()/3
+    }
+    // whether the mesh has a uv layout (needed for textures and baking)
+    pub fn has_texcoords(&self) -> bool {
+        !self.mesh.texcoords.is_empty()
+    }
+    // corners of the idx'th triangle in world space, each with its normal and texture coordinates
+    pub fn world_triangle(&self, idx: usize) -> [(Vec3, Vec3, Vec2); 3] {
+        let (a,b,c) = Self::get_triangle_from_mesh(&self.mesh, idx);
+        let (na,nb,nc) = Self::get_normals_from_mesh(&self.mesh, idx);
+        let (ta,tb,tc) = Self::get_texcoords_from_mesh(&self.mesh, idx);
+        let normal_transform = self.inv_transform.transpose();
+        let corner = |p: Vec3, n: Vec3, uv: Vec2| {
+            (self.transform.transform_point(point3(p.x, p.y, p.z)).to_vec(), normal_transform.transform_vector(n).normalize(), uv)
+        };
+        [corner(a,na,ta), corner(b,nb,tb), corner(c,nc,tc)]
     }
 
     // retrieves the idx'th triangle from the mesh
@@ -222,52 +762,30 @@ impl StaticMesh {
     }
     pub fn get_triangle_from_mesh(mesh: &Mesh, idx: usize) -> (Vec3, Vec3, Vec3) {
         let (x,y,z) = (mesh.indices[idx*3] as usize, mesh.indices[idx*3+1] as usize, mesh.indices[idx*3+2] as usize);
-        let a = vec3(mesh.positions[x*3], mesh.positions[x*3+1], mesh.positions[x*3+2]);
-        let b = vec3(mesh.positions[y*3], mesh.positions[y*3+1], mesh.positions[y*3+2]);
-        let c = vec3(mesh.positions[z*3], mesh.positions[z*3+1], mesh.positions[z*3+2]);
+        let a = vec3(float(mesh.positions[x*3]), float(mesh.positions[x*3+1]), float(mesh.positions[x*3+2]));
+        let b = vec3(float(mesh.positions[y*3]), float(mesh.positions[y*3+1]), float(mesh.positions[y*3+2]));
+        let c = vec3(float(mesh.positions[z*3]), float(mesh.positions[z*3+1]), float(mesh.positions[z*3+2]));
         (a,b,c)
     }
     pub fn get_texcoords_from_mesh(mesh: &Mesh, idx: usize) -> (Vec2,Vec2,Vec2) {
         let (x,y,z) = (mesh.indices[idx*3] as usize, mesh.indices[idx*3+1] as usize, mesh.indices[idx*3+2] as usize);
-        let a = vec2(mesh.texcoords[x*2], mesh.texcoords[x*2+1]);
-        let b = vec2(mesh.texcoords[y*2], mesh.texcoords[y*2+1]);
-        let c = vec2(mesh.texcoords[z*2], mesh.texcoords[z*2+1]);
+        let a = vec2(float(mesh.texcoords[x*2]), float(mesh.texcoords[x*2+1]));
+        let b = vec2(float(mesh.texcoords[y*2]), float(mesh.texcoords[y*2+1]));
+        let c = vec2(float(mesh.texcoords[z*2]), float(mesh.texcoords[z*2+1]));
         (a,b,c)
     }
     pub fn get_normals_from_mesh(mesh: &Mesh, idx: usize) -> (Vec3, Vec3, Vec3) {
         let (x,y,z) = (mesh.indices[idx*3] as usize, mesh.indices[idx*3+1] as usize, mesh.indices[idx*3+2] as usize);
-        let a = vec3(mesh.normals[x*3], mesh.normals[x*3+1], mesh.normals[x*3+2]);
-        let b = vec3(mesh.normals[y*3], mesh.normals[y*3+1], mesh.normals[y*3+2]);
-        let c = vec3(mesh.normals[z*3], mesh.normals[z*3+1], mesh.normals[z*3+2]);
+        let a = vec3(float(mesh.normals[x*3]), float(mesh.normals[x*3+1]), float(mesh.normals[x*3+2]));
+        let b = vec3(float(mesh.normals[y*3]), float(mesh.normals[y*3+1]), float(mesh.normals[y*3+2]));
+        let c = vec3(float(mesh.normals[z*3]), float(mesh.normals[z*3+1]), float(mesh.normals[z*3+2]));
         (a,b,c)
     }
     // gets tangent vector for a triangle given texture coordinates and positions of each vertex
     pub fn get_tangent(uv1: Vec2, uv2: Vec2, uv3: Vec2, p1: Vec3, p2: Vec3, p3: Vec3) -> Vec3 {
         let (u1, u2, u3) = (uv1.x, uv2.x, uv3.x);
         let (v1, v2, v3) = (uv1.y, uv2.y, uv3.y);    
-        let t = ((v3-v1)*(p2-p1)-(v2-v1)*(p3-p1)) / ((u2-u1)*(v3-v1)-(v2-v1)*(u3-u1));
-        t
-    }
-
-    // sample different textures at a point and return an appropriate material
-    pub fn get_material_at_uv(&self, tex_coord: Option<Vec2>) -> Arc<dyn Material + Send + Sync> {
-        // if object has a single specified material, then it describes the whole surfaces
-        if self.material.is_some() || tex_coord.is_none() {
-            self.material.as_ref().unwrap().clone()
-        }
-        else {
-            let uv = tex_coord.unwrap();
-            let albedo = if let Some(tex) = self.textures[0].as_ref() {tex.sample(uv)} else {Vec3::zero()};
-            let emission = if let Some(tex) = self.textures[1].as_ref() {tex.sample(uv)} else {Vec3::zero()};
-            let metallic = if let Some(tex) = self.textures[2].as_ref() {tex.sample(uv).x} else {0.0};
-            let roughness = if let Some(tex) = self.textures[3].as_ref() {tex.sample(uv).x} else {1.0};
-            Arc::new(ParameterizedMaterial {
-                albedo: albedo,
-                emission: emission,
-                roughness: roughness,
-                metallic: metallic,
-            })
-        }
+        ((v3-v1)*(p2-p1)-(v2-v1)*(p3-p1)) / ((u2-u1)*(v3-v1)-(v2-v1)*(u3-u1))
     }
 
     // adjusts normal based on transform and normal map
@@ -278,8 +796,8 @@ impl StaticMesh {
                     if let Some(bitangent) = hit.bitangent {
                         // use normal map to adjust
                         let uv = hit.tex_coords.unwrap();
-                        let normalmap_sample = self.textures[4].as_ref().unwrap().sample(uv);
-                        let normalmap_vector = (2.0*normalmap_sample - vec3(1.0,1.0,1.0));
+                        let normalmap_sample = self.textures[4].as_ref().unwrap().sample(uv, hit.hitpoint);
+                        let normalmap_vector = 2.0*normalmap_sample - vec3(1.0,1.0,1.0);
                         Matrix3::from_cols(tangent, bitangent, hit.normal)*normalmap_vector
                     }
                     else {
@@ -297,26 +815,75 @@ impl StaticMesh {
         self.inv_transform.transpose().transform_vector(n).normalize()
     }
 }
+impl StaticMesh {
+    // moves a world space ray into the mesh's object space
+    fn to_object_space(&self, ray: &Ray) -> Ray {
+        Ray { origin: self.inv_transform.transform_point(point3(ray.origin.x, ray.origin.y, ray.origin.z)).to_vec(), direction: self.inv_transform.transform_vector(ray.direction), time: ray.time }
+    }
+    // whether an object space hit lands on a cut out part of the alpha map
+    fn is_cut_out(&self, hit: &RayHit) -> bool {
+        match (&self.textures[5], hit.tex_coords) {
+            (Some(tex), Some(uv)) => tex.sample_alpha(uv) < ALPHA_CUTOFF,
+            _ => false,
+        }
+    }
+    // adjust hitpoint, normal, and material based on transform and textures
+    fn finish_hit(&self, mut hit: RayHit) -> RayHit {
+        hit.hitpoint = self.transform.transform_point(point3(hit.hitpoint.x, hit.hitpoint.y, hit.hitpoint.z)).to_vec();
+        hit.normal = self.get_adjusted_normal(&hit);
+        hit.material = self.material.clone();
+        hit
+    }
+}
 impl Intersectable for StaticMesh {
-    fn intersect_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
+    fn intersect_ray(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
         // intersect bvh but replace material data
-        if let Some(root) = &self.bvh_root {
-            let transformed_ray = Ray { origin: self.inv_transform.transform_point(point3(ray.origin.x, ray.origin.y, ray.origin.z)).to_vec(), direction: self.inv_transform.transform_vector(ray.direction) };
-            if let Some(mut hit) = root.intersect_ray(&transformed_ray, t_min, t_max) {
-                // adjust hitpoint, normal, and material based on transform and textures
-                hit.hitpoint = self.transform.transform_point(point3(hit.hitpoint.x, hit.hitpoint.y, hit.hitpoint.z)).to_vec();
-                hit.normal = self.get_adjusted_normal(&hit);
-                hit.material = self.get_material_at_uv(hit.tex_coords);
-                return Some(hit);
+        // hits on cut out texels are skipped by searching again past them
+        if let Some(root) = &self.accel {
+            let object_ray = self.to_object_space(ray);
+            let mut t_min = t_min;
+            while let Some(hit) = root.intersect_ray(&object_ray, t_min, t_max) {
+                if !self.is_cut_out(&hit) {
+                    return Some(self.finish_hit(hit));
+                }
+                t_min = hit.distance + 0.0001;
             }
         }
-        return None;
+        None
     }
     fn bounding_box(&self) -> Option<AABB> {
-        match &self.bvh_root {
-            Some(node) => node.bounding_box(),
-            None => None
+        // transform the corners of the object space box into world space
+        let object_box = self.accel.as_ref()?.bounding_box()?;
+        let mut world_box = AABB { min: vec3(Float::MAX, Float::MAX, Float::MAX), max: vec3(Float::MIN, Float::MIN, Float::MIN) };
+        for i in 0..8 {
+            let corner = point3(
+                if i & 1 == 0 { object_box.min.x } else { object_box.max.x },
+                if i & 2 == 0 { object_box.min.y } else { object_box.max.y },
+                if i & 4 == 0 { object_box.min.z } else { object_box.max.z },
+            );
+            let p = self.transform.transform_point(corner).to_vec();
+            world_box = AABB::aabb_surrounding(&world_box, &AABB { min: p, max: p });
+        }
+        Some(world_box)
+    }
+    fn intersect_packet(&self, rays: &[Ray], t_min: Float, t_max: Float) -> Vec<Option<RayHit>> {
+        match &self.accel {
+            Some(root) => {
+                let transformed_rays: Vec<Ray> = rays.iter().map(|ray| self.to_object_space(ray)).collect();
+                root.intersect_packet(&transformed_rays, t_min, t_max).into_iter().zip(rays).map(|(hit, ray)| match hit {
+                    // rays that hit a cut out texel finish on their own
+                    Some(h) if self.is_cut_out(&h) => self.intersect_ray(ray, t_min, t_max),
+                    hit => hit.map(|h| self.finish_hit(h)),
+                }).collect()
+            }
+            None => rays.iter().map(|_| None).collect(),
+        }
+    }
+    fn intersect_any(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        if self.textures[5].is_some() {
+            return self.intersect_ray(ray, t_min, t_max).is_some()
         }
+        self.accel.as_ref().is_some_and(|root| root.intersect_any(&self.to_object_space(ray), t_min, t_max))
     }
 }
 
@@ -327,26 +894,50 @@ pub struct IndexedTriangle {
     pub idx: usize,
     pub mesh: Arc<Mesh>,
 }
-impl Intersectable for IndexedTriangle {
-    fn intersect_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
-        // lookup vertex data from mesh
-        let (a,b,c) = StaticMesh::get_triangle_from_mesh(&self.mesh, self.idx);
-        // efficient ray-triangle intersection algorithm based on 419 lectures
-        const EPSILON : f32 = 0.0001;
-        let e1 = b - a;
-        let e2 = c - a;
-        let q = ray.direction.cross(e2);
-        let g = e1.dot(q);
-        if g.abs() < EPSILON { return None; }
-        let f = 1.0/g;
-        let s = ray.origin - a;
-        let u = f*s.dot(q);
-        if u < 0.0 { return None; }
-        let r = s.cross(e1);
-        let v = f*ray.direction.dot(r);
-        if v < 0.0 || u+v > 1.0 { return None }
-        let t = f*e2.dot(r);
-        if t < t_min || t > t_max { return None }
+// efficient ray-triangle intersection algorithm based on 419 lectures
+// returns the distance along the ray and the barycentric coordinates (u,v) of b and c at the hit
+pub fn ray_triangle_intersection(ray: &Ray, a: Vec3, b: Vec3, c: Vec3, t_min: Float, t_max: Float) -> Option<(Float, Float, Float)> {
+    const EPSILON : Float = 0.0001;
+    count_ray_stats(|stats| stats.triangle_tests += 1);
+    let e1 = b - a;
+    let e2 = c - a;
+    let q = ray.direction.cross(e2);
+    let g = e1.dot(q);
+    if g.abs() < EPSILON { return None; }
+    let f = 1.0/g;
+    let s = ray.origin - a;
+    let u = f*s.dot(q);
+    if u < 0.0 { return None; }
+    let r = s.cross(e1);
+    let v = f*ray.direction.dot(r);
+    if v < 0.0 || u+v > 1.0 { return None }
+    let t = f*e2.dot(r);
+    if t < t_min || t > t_max { return None }
+    Some((t, u, v))
+}
+// the same test for up to four rays against one triangle, each clipped to [t_min, its t_max]
+// returns which of the active lanes hit, and their distances and barycentric coordinates
+pub fn ray_triangle_intersection4(rays: &Ray4, a: Vec3, b: Vec3, c: Vec3, t_min: Float, t_max: Floatx4) -> (Mask4, Floatx4, Floatx4, Floatx4) {
+    const EPSILON : Float = 0.0001;
+    count_ray_stats(|stats| stats.triangle_tests += rays.active.count());
+    let e1 = Vec3x4::splat(b - a);
+    let e2 = Vec3x4::splat(c - a);
+    let q = rays.direction.cross(e2);
+    let g = e1.dot(q);
+    let f = Floatx4::splat(1.0)/g;
+    let s = rays.origin - Vec3x4::splat(a);
+    let u = f*s.dot(q);
+    let r = s.cross(e1);
+    let v = f*rays.direction.dot(r);
+    let t = f*e2.dot(r);
+    let zero = Floatx4::splat(0.0);
+    let miss = g.abs().lt(Floatx4::splat(EPSILON)) | u.lt(zero) | v.lt(zero) | (u + v).gt(Floatx4::splat(1.0))
+        | t.lt(Floatx4::splat(t_min)) | t.gt(t_max);
+    (rays.active & !miss, t, u, v)
+}
+impl IndexedTriangle {
+    // fills in the hit for a ray that hit the triangle (a, b, c) at distance t and barycentric coordinates (u, v)
+    fn hit_at(&self, ray: &Ray, (a, b, c): (Vec3, Vec3, Vec3), t: Float, u: Float, v: Float) -> RayHit {
         let (na, nb, nc) = StaticMesh::get_normals_from_mesh(&self.mesh, self.idx);
         let mesh_normal = (u*nb+v*nc+(1.0-u-v)*na).normalize();
         let mut hit = RayHit::new(t, mesh_normal, Arc::new(Lambertian::default()), ray);
@@ -354,6 +945,12 @@ impl Intersectable for IndexedTriangle {
         // get texcoords an interpolate:
         let (tca, tcb, tcc) = StaticMesh::get_texcoords_from_mesh(&self.mesh, self.idx);
         hit.tex_coords = Some(u*tcb+v*tcc+(1.0-u-v)*tca);
+        // uv distance per unit of ray spread, from the ratio of the triangle's uv area to its area
+        let world_area = (b-a).cross(c-a).magnitude();
+        let uv_area = ((tcb.x-tca.x)*(tcc.y-tca.y) - (tcc.x-tca.x)*(tcb.y-tca.y)).abs();
+        if world_area > 0.0 {
+            hit.uv_footprint = t*ray.direction.magnitude()*Float::sqrt(uv_area/world_area);
+        }
 
         // compute tangent and bitangent vectors. current method uses approximate per-triangle tangent and per-vertex normal to get tnb frame
         let tan_approx = StaticMesh::get_tangent(tca, tcb, tcc, a, b, c);
@@ -362,23 +959,40 @@ impl Intersectable for IndexedTriangle {
         hit.tangent = Some(tangent);
         hit.bitangent = Some(bitangent);
 
-        Some(hit)
+        hit
+    }
+}
+impl Intersectable for IndexedTriangle {
+    fn intersect_ray(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
+        // lookup vertex data from mesh
+        let (a,b,c) = StaticMesh::get_triangle_from_mesh(&self.mesh, self.idx);
+        let (t, u, v) = ray_triangle_intersection(ray, a, b, c, t_min, t_max)?;
+        Some(self.hit_at(ray, (a, b, c), t, u, v))
+    }
+    fn intersect_ray4(&self, rays: &Ray4, t_min: Float, t_max: Floatx4) -> [Option<RayHit>; LANES] {
+        let (a,b,c) = StaticMesh::get_triangle_from_mesh(&self.mesh, self.idx);
+        let (hit, t, u, v) = ray_triangle_intersection4(rays, a, b, c, t_min, t_max);
+        std::array::from_fn(|i| if hit.0[i] { Some(self.hit_at(&rays.ray(i), (a, b, c), t.0[i], u.0[i], v.0[i])) } else { None })
     }
     fn bounding_box(&self) -> Option<AABB> {
         let (a,b,c) = StaticMesh::get_triangle_from_mesh(&self.mesh, self.idx);
         Some(AABB {
             min: vec3(
-                f32::min(a.x,f32::min(b.x, c.x)),
-                f32::min(a.y,f32::min(b.y, c.y)),
-                f32::min(a.z,f32::min(b.z, c.z))
+                Float::min(a.x,Float::min(b.x, c.x)),
+                Float::min(a.y,Float::min(b.y, c.y)),
+                Float::min(a.z,Float::min(b.z, c.z))
             ),
             max: vec3(
-                f32::max(a.x,f32::max(b.x, c.x)),
-                f32::max(a.y,f32::max(b.y, c.y)),
-                f32::max(a.z,f32::max(b.z, c.z))
+                Float::max(a.x,Float::max(b.x, c.x)),
+                Float::max(a.y,Float::max(b.y, c.y)),
+                Float::max(a.z,Float::max(b.z, c.z))
             ),
         })
     }
+    fn intersect_any(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        let (a,b,c) = StaticMesh::get_triangle_from_mesh(&self.mesh, self.idx);
+        ray_triangle_intersection(ray, a, b, c, t_min, t_max).is_some()
+    }
 }
 
 
@@ -388,28 +1002,34 @@ impl Intersectable for IndexedTriangle {
 // SPHERE
 pub struct Sphere {
     pub center: Vec3,
-    pub radius: f32,
+    pub radius: Float,
     pub material: Arc<dyn Material + Send + Sync>,
 }
-impl Intersectable for Sphere {
-    fn intersect_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
+impl Sphere {
+    // returns the distance to the closest intersection in [t_min, t_max]
+    fn hit_distance(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<Float> {
         // ray-sphere intersection algorithm from 419 lectures
         let f = ray.origin - self.center;
         let a = ray.direction.magnitude2();
         let b = 2.0*f.dot(ray.direction);
         let c = f.magnitude2() - self.radius*self.radius;
         let d = b*b - 4.0*a*c;
-        if d < 0.0 {
-            None
-        }
-        else {
-            let t1 = (-b - d.sqrt()) / (2.0*a);
-            let t2 = (-b + d.sqrt()) / (2.0*a);
-            let t = if t1 >= t_min { t1 } else { t2 };
-            let hitpoint = ray.origin + t*ray.direction;
-            if t < t_min || t > t_max { return None }
-            Some(RayHit::new(t, (hitpoint - self.center).normalize(), self.material.clone(), ray))
-        }
+        if d < 0.0 { return None }
+        let t1 = (-b - d.sqrt()) / (2.0*a);
+        let t2 = (-b + d.sqrt()) / (2.0*a);
+        let t = if t1 >= t_min { t1 } else { t2 };
+        if t < t_min || t > t_max { return None }
+        Some(t)
+    }
+}
+impl Intersectable for Sphere {
+    fn intersect_ray(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
+        let t = self.hit_distance(ray, t_min, t_max)?;
+        let hitpoint = ray.origin + t*ray.direction;
+        Some(RayHit::new(t, (hitpoint - self.center).normalize(), self.material.clone(), ray))
+    }
+    fn intersect_any(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        self.hit_distance(ray, t_min, t_max).is_some()
     }
     fn bounding_box(&self) -> Option<AABB> {
         Some(AABB {
@@ -417,6 +1037,13 @@ impl Intersectable for Sphere {
             max: self.center + vec3(self.radius,self.radius,self.radius),
         })
     }
+    fn area(&self) -> Float {
+        4.0*PI*self.radius*self.radius
+    }
+    fn sample_surface(&self) -> Option<(Vec3, Vec3)> {
+        let normal = sample_uniform_sphere(rand_vec2());
+        Some((self.center + self.radius*normal, normal))
+    }
 }
 
 // TRIANGLE
@@ -428,40 +1055,37 @@ pub struct Triangle {
     pub material: Arc<dyn Material + Send + Sync>,
 }
 impl Intersectable for Triangle {
-    fn intersect_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
-        // efficient ray-triangle intersection algorithm based on 419 lectures
-        const EPSILON : f32 = 0.0001;
-        let e1 = self.b - self.a;
-        let e2 = self.c - self.a;
-        let q = ray.direction.cross(e2);
-        let a = e1.dot(q);
-        if a.abs() < EPSILON { return None; }
-        let f = 1.0/a;
-        let s = ray.origin - self.a;
-        let u = f*s.dot(q);
-        if u < 0.0 { return None; }
-        let r = s.cross(e1);
-        let v = f*ray.direction.dot(r);
-        if v < 0.0 || u+v > 1.0 { return None }
-        let t = f*e2.dot(r);
-        if t < t_min || t > t_max { return None }
-
-        Some(RayHit::new(t, e1.cross(e2).normalize(), self.material.clone(), ray))
+    fn intersect_ray(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
+        let (t, _, _) = ray_triangle_intersection(ray, self.a, self.b, self.c, t_min, t_max)?;
+        Some(RayHit::new(t, (self.b - self.a).cross(self.c - self.a).normalize(), self.material.clone(), ray))
     }
     fn bounding_box(&self) -> Option<AABB> {
         Some(AABB {
             min: vec3(
-                f32::min(self.a.x,f32::min(self.b.x, self.c.x)),
-                f32::min(self.a.y,f32::min(self.b.y, self.c.y)),
-                f32::min(self.a.z,f32::min(self.b.z, self.c.z))
+                Float::min(self.a.x,Float::min(self.b.x, self.c.x)),
+                Float::min(self.a.y,Float::min(self.b.y, self.c.y)),
+                Float::min(self.a.z,Float::min(self.b.z, self.c.z))
             ),
             max: vec3(
-                f32::max(self.a.x,f32::max(self.b.x, self.c.x)),
-                f32::max(self.a.y,f32::max(self.b.y, self.c.y)),
-                f32::max(self.a.z,f32::max(self.b.z, self.c.z))
+                Float::max(self.a.x,Float::max(self.b.x, self.c.x)),
+                Float::max(self.a.y,Float::max(self.b.y, self.c.y)),
+                Float::max(self.a.z,Float::max(self.b.z, self.c.z))
             ),
         })
     }
+    fn intersect_any(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        ray_triangle_intersection(ray, self.a, self.b, self.c, t_min, t_max).is_some()
+    }
+    fn area(&self) -> Float {
+        0.5*(self.b - self.a).cross(self.c - self.a).magnitude()
+    }
+    fn sample_surface(&self) -> Option<(Vec3, Vec3)> {
+        // uniform barycentrics (the square root keeps points from bunching up at a)
+        let r1 = Float::sqrt(rand_f32());
+        let r2: Float = rand_f32();
+        let point = (1.0-r1)*self.a + r1*(1.0-r2)*self.b + r1*r2*self.c;
+        Some((point, (self.b - self.a).cross(self.c - self.a).normalize()))
+    }
 }
 
 // PLANE
@@ -471,7 +1095,7 @@ pub struct Plane {
     pub material: Arc<dyn Material + Send + Sync>,
 }
 impl Intersectable for Plane {
-    fn intersect_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
+    fn intersect_ray(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
         // ray-plane intersection
         let to_ray_origin = ray.origin - self.point;
         let origin_dist = dot(to_ray_origin, self.normal);
@@ -484,7 +1108,15 @@ impl Intersectable for Plane {
             let t = origin_dist.abs() / d.abs();
             if t < t_min || t > t_max { return None }
 
-            Some(RayHit::new(t, n, self.material.clone(), ray))
+            // texture coordinates are world space distances along the plane, so textures tile across it
+            let mut hit = RayHit::new(t, n, self.material.clone(), ray);
+            let frame = Frame::new(self.normal.normalize());
+            let offset = hit.hitpoint - self.point;
+            hit.tex_coords = Some(vec2(offset.dot(frame.tangent), offset.dot(frame.bitangent)));
+            hit.uv_footprint = t*ray.direction.magnitude();
+            hit.tangent = Some(frame.tangent);
+            hit.bitangent = Some(frame.bitangent);
+            Some(hit)
         }
     }
     fn bounding_box(&self) -> Option<AABB> {
@@ -492,39 +1124,203 @@ impl Intersectable for Plane {
     }
 }
 
+// CONVEX VOLUME - participating medium (smoke, fog, clouds) filling a convex boundary. rays are scattered (or
+// absorbed, by the phase function's albedo) at random distances inside it, found by delta tracking, so paths can
+// scatter through it any number of times. shadow rays see through it, dimmed by its transmittance
 pub struct ConvexVolume {
     pub boundary: Arc<dyn Intersectable + Send + Sync>,
     pub phase_function: Arc<dyn Material + Send + Sync>, 
-    pub density: f32,
+    pub density: Float,     // chance per unit length of a ray scattering or being absorbed (where density_field is 1)
+    pub density_field: Option<Arc<dyn DensityField + Send + Sync>>,    // how the density varies through the volume, scaling it at each point (None = the same everywhere)
     // phase function = probabiltiy distrubution function for scattering at each angle (https://www.pbr-book.org/3ed-2018/Volume_Scattering/Phase_Functions)
 }
-impl Intersectable for ConvexVolume {
-    fn intersect_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
+impl ConvexVolume {
+    // part of the ray in [t_min, t_max] that's inside the volume (if any)
+    fn span(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<(Float, Float)> {
         // find where ray enters and exits the volume (if at all)
         // intersection algorith based on ray tracing the next week
-        let hit_entr = self.boundary.intersect_ray(ray, f32::MIN, f32::MAX);
-        if hit_entr.is_none() { return None; }
-        let t_entr = hit_entr.unwrap().distance;
-        let hit_exit = self.boundary.intersect_ray(ray, t_entr+0.0001, f32::MAX);
-        if hit_exit.is_none() { return None; }
-        let t_exit = hit_exit.unwrap().distance;
+        let t_entr = self.boundary.intersect_ray(ray, Float::MIN, Float::MAX)?.distance;
+        let t_exit = self.boundary.intersect_ray(ray, t_entr+0.0001, Float::MAX)?.distance;
         // if ray exits before t_min or enters after t_max, return
         if t_exit < t_min || t_entr > t_max { return None }
-        let t_start = f32::max(t_entr, t_min);
-        let t_end = f32::min(t_exit, t_max);
-        let dist_in_volume = t_end-t_start;
-        // use density to get distribution of distances traveled before scattering. scatter at this distance if the photon is still in the volume
-        let dist_before_scatter = (-1.0/self.density) * f32::ln(rand::thread_rng().gen_range(0.0..1.0)); // not sure where this log comes from
-        if dist_before_scatter < dist_in_volume {
-            // ray scatters t_start + dist_before_scatter forward from its current location
-            Some(RayHit::new(t_start+dist_before_scatter, Vec3::zero(), self.phase_function.clone(), ray))
-            // (normal and front_face are arbitrary)
-        }
-        else {
-            None // ray passes through without scattering
+        Some((Float::max(t_entr, t_min), Float::min(t_exit, t_max)))
+    }
+    // highest density anywhere in the volume
+    fn max_density(&self) -> Float {
+        self.density*self.density_field.as_ref().map_or(1.0, |field| field.max_density())
+    }
+}
+impl Intersectable for ConvexVolume {
+    fn intersect_ray(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
+        let (t_start, t_end) = self.span(ray, t_min, t_max)?;
+        let sigma_max = self.max_density();
+        if sigma_max <= 0.0 { return None }
+        // delta tracking (Woodcock et al. 1965): step through the volume as if it were everywhere as dense as its
+        // densest point, with distances drawn from the exponential distribution, and stop at each step with the chance
+        // that the density there is real (the rest are null collisions that carry on). without a density field every
+        // step is real, so the first one is where the ray scatters
+        // (distances are along the ray's direction, which isn't always unit length)
+        let length = ray.direction.magnitude();
+        let mut t = t_start;
+        loop {
+            t += (-1.0/sigma_max) * Float::ln(rand_f32()) / length;
+            if t >= t_end { return None }   // ray passes through without scattering
+            let real = match &self.density_field {
+                None => true,
+                Some(field) => rand_f32()*field.max_density() < field.density(ray.origin + t*ray.direction),
+            };
+            if real {
+                // ray scatters t forward from its origin
+                return Some(RayHit::new(t, Vec3::zero(), self.phase_function.clone(), ray));
+                // (normal and front_face are arbitrary)
+            }
         }
     }
     fn bounding_box(&self) -> Option<AABB> {
         self.boundary.bounding_box()
     }
-}
\ No newline at end of file
+    fn transmittance(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<Float> {
+        let (t_start, t_end) = match self.span(ray, t_min, t_max) {
+            Some(span) => span,
+            None => return Some(1.0),
+        };
+        let length = ray.direction.magnitude();
+        let field = match &self.density_field {
+            // beer's law, exactly
+            None => return Some(Float::exp(-self.density*(t_end - t_start)*length)),
+            Some(field) if field.max_density() > 0.0 && self.density > 0.0 => field,
+            Some(_) => return Some(1.0),
+        };
+        // ratio tracking (Novak et al. 2014): the same steps as delta tracking, but rather than stopping at a real
+        // collision, every step keeps the share of the light that isn't collided with there
+        let sigma_max = self.max_density();
+        let mut t = t_start;
+        let mut transmittance = 1.0;
+        loop {
+            t -= Float::ln(1.0 - rand_f32()) / (sigma_max*length);
+            if t >= t_end { break }
+            transmittance *= 1.0 - field.density(ray.origin + t*ray.direction) / field.max_density();
+            if transmittance <= 0.0 { break }
+        }
+        Some(transmittance)
+    }
+}
+// DENSITY FIELDS - how the density of a volume varies through space, as a multiple of its density
+pub trait DensityField {
+    fn density(&self, point: Vec3) -> Float;    // at a point in world space, from 0 up to max_density
+    fn max_density(&self) -> Float;             // highest it gets anywhere (delta tracking steps by it, so keep it tight)
+}
+// wispy cloud of fractal value noise, cut off below a threshold so it breaks up into separate puffs
+pub struct NoiseDensity {
+    pub scale: Float,       // size of the largest puffs, in scene units
+    pub octaves: u32,       // layers of finer detail added on top, each half the size and strength of the last
+    pub threshold: Float,   // noise below this is empty, and the rest is stretched back to 0..1
+    pub seed: i32,
+}
+impl DensityField for NoiseDensity {
+    fn density(&self, point: Vec3) -> Float {
+        let mut p = point / self.scale;
+        let (mut sum, mut amplitude, mut total) = (0.0, 1.0, 0.0);
+        for octave in 0..u32::max(self.octaves, 1) {
+            sum += amplitude*value_noise(p, self.seed.wrapping_add(octave as i32));
+            total += amplitude;
+            amplitude *= 0.5;
+            p *= 2.0;
+        }
+        Float::max(0.0, (sum/total - self.threshold) / (1.0 - self.threshold))
+    }
+    fn max_density(&self) -> Float {
+        1.0
+    }
+}
+// ground fog, thinning out exponentially with height above a level
+#[derive(Debug, Clone, Copy)]
+pub struct HeightDensity {
+    pub base: Float,        // height the fog is fully dense up to
+    pub falloff: Float,     // height over which it thins to 1/e as thick, above the base
+}
+impl DensityField for HeightDensity {
+    fn density(&self, point: Vec3) -> Float {
+        Float::min(1.0, Float::exp(-(point.y - self.base) / self.falloff))
+    }
+    fn max_density(&self) -> Float {
+        1.0
+    }
+}
+// smoothly interpolated random values at integer points of space, in 0..1
+fn value_noise(p: Vec3, seed: i32) -> Float {
+    let cell = p.map(Float::floor);
+    let f = p - cell;
+    let f = f.map(|x| x*x*(3.0 - 2.0*x));
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let corner = |dx: i32, dy: i32, dz: i32| hash_to_float(hash3(x + dx, y + dy, z.wrapping_add(dz).wrapping_add(seed.wrapping_mul(131))));
+    let lerp = |a: Float, b: Float, t: Float| a + (b - a)*t;
+    lerp(
+        lerp(lerp(corner(0, 0, 0), corner(1, 0, 0), f.x), lerp(corner(0, 1, 0), corner(1, 1, 0), f.x), f.y),
+        lerp(lerp(corner(0, 0, 1), corner(1, 0, 1), f.x), lerp(corner(0, 1, 1), corner(1, 1, 1), f.x), f.y),
+        f.z,
+    )
+}
+// MOVING - carries another object along a straight line over time, so it's blurred while the camera's shutter is open
+pub struct Moving {
+    pub object: Arc<dyn Intersectable + Send + Sync>,
+    pub velocity: Vec3,     // distance moved per unit of time, starting from where the object is at time 0
+    pub max_time: Float,      // latest time it's seen at, to size its bounding box (usually the camera's shutter_close)
+}
+impl Moving {
+    // moves the ray back by how far the object has moved at its time, so it can be tested against the object at rest
+    fn to_rest(&self, ray: &Ray) -> Ray {
+        Ray { origin: ray.origin - self.velocity*ray.time, direction: ray.direction, time: ray.time }
+    }
+}
+impl Intersectable for Moving {
+    fn intersect_ray(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
+        let mut hit = self.object.intersect_ray(&self.to_rest(ray), t_min, t_max)?;
+        hit.hitpoint += self.velocity*ray.time;
+        Some(hit)
+    }
+    fn intersect_any(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        self.object.intersect_any(&self.to_rest(ray), t_min, t_max)
+    }
+    fn bounding_box(&self) -> Option<AABB> {
+        // everywhere the object's box passes through between time 0 and max_time
+        let start = self.object.bounding_box()?;
+        let offset = self.velocity*self.max_time;
+        Some(AABB::aabb_surrounding(&start, &AABB { min: start.min + offset, max: start.max + offset }))
+    }
+}
+// VISIBILITY - sets which kinds of rays see another object, e.g. a card that blocks light without the camera seeing it,
+// or an object that casts no shadows. only works on the scene's own objects (not ones inside a BVH or another object)
+pub struct Visibility {
+    pub object: Arc<dyn Intersectable + Send + Sync>,
+    pub rays: RayVisibility,
+}
+impl Intersectable for Visibility {
+    fn intersect_ray(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
+        self.object.intersect_ray(ray, t_min, t_max)
+    }
+    fn bounding_box(&self) -> Option<AABB> {
+        self.object.bounding_box()
+    }
+    fn intersect_packet(&self, rays: &[Ray], t_min: Float, t_max: Float) -> Vec<Option<RayHit>> {
+        self.object.intersect_packet(rays, t_min, t_max)
+    }
+    fn intersect_ray4(&self, rays: &Ray4, t_min: Float, t_max: Floatx4) -> [Option<RayHit>; LANES] {
+        self.object.intersect_ray4(rays, t_min, t_max)
+    }
+    fn intersect_any(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        self.object.intersect_any(ray, t_min, t_max)
+    }
+    fn transmittance(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<Float> {
+        self.object.transmittance(ray, t_min, t_max)
+    }
+    fn area(&self) -> Float {
+        self.object.area()
+    }
+    fn sample_surface(&self) -> Option<(Vec3, Vec3)> {
+        self.object.sample_surface()
+    }
+    fn visibility(&self) -> RayVisibility {
+        self.rays
+    }
+}
@@ -0,0 +1,702 @@
+// LIGHTS - Implements light sources that are sampled directly with shadow rays (next event estimation)
+// rather than only being found when a scattered ray happens to hit them
+
+#![allow(dead_code)]
+
+use cgmath::*;
+use super::tracing::consts::PI;
+use std::sync::Arc;
+
+use super::tracing::*;
+use super::geometry::{AABB, Sphere};
+use super::materials::luminance;
+use super::color::*;
+use super::background::equirectangular_uv;
+use super::texture::Texture;
+use super::sampler::*;
+use super::sampling::*;
+
+// a direction towards a light from a point being shaded, and the light arriving along it
+pub struct LightSample {
+    pub direction: Vec3,    // unit vector from the shaded point towards the light
+    pub distance: Float,      // distance to the light, for the shadow ray (infinite for distant lights)
+    pub radiance: Color,    // light arriving along direction (for point-like lights this includes the falloff with distance)
+    pub pdf: Float,           // solid angle density of having picked direction (for point-like lights, just the chance of
+                            // having picked the light - 1 unless it was one of several, see LightTree)
+    pub delta: bool,        // point-like lights can only be reached by sampling them, never by a scattered ray
+}
+
+// Trait for lights; anything that can pick a direction towards itself from a point
+pub trait Light {
+    // picks a direction towards the light from point (None if no light reaches it)
+    fn sample(&self, point: Vec3) -> Option<LightSample>;
+    // solid angle density sample would pick the ray's direction with, if hit is on this light (0 for point-like lights)
+    // used to weight light found by scattered rays against the same light found by sampling
+    fn pdf(&self, _ray: &Ray, _hit: &RayHit) -> Float {
+        0.0
+    }
+    // light arriving along a (unit) direction that escapes the scene without hitting anything
+    // (zero for lights that are somewhere in the scene)
+    fn escaped_radiance(&self, _direction: Vec3) -> Color {
+        Color::zero()
+    }
+    // solid angle density sample (from the ray's origin) would pick the ray's direction with, if it escapes
+    fn escaped_pdf(&self, _ray: &Ray) -> Float {
+        0.0
+    }
+    // box around everywhere the light comes from (None for lights infinitely far away)
+    fn bounds(&self) -> Option<AABB> {
+        None
+    }
+    // rough estimate of the total light given off (as luminance), used to pick between many lights
+    fn power(&self) -> Float {
+        0.0
+    }
+    // picks a ray of light leaving the light, and the light it carries over the pdf of having picked it, to trace
+    // photons out into the scene from (None for lights that don't give off photons, like ones infinitely far away)
+    fn sample_emission(&self) -> Option<(Ray, Color)> {
+        None
+    }
+}
+
+// LIGHT LINKING - limits which of the scene's lights shine on a surface (see LightLinked in materials)
+// lights are matched by their Arc, so a linked light has to be shared between the links and the scene's lights
+#[derive(Clone)]
+pub enum LightLinks {
+    Include(Vec<Arc<dyn Light + Send + Sync>>),     // only these lights
+    Exclude(Vec<Arc<dyn Light + Send + Sync>>),     // every light but these
+}
+impl LightLinks {
+    pub fn links(&self, light: &Arc<dyn Light + Send + Sync>) -> bool {
+        match self {
+            LightLinks::Include(lights) => lights.iter().any(|l| Arc::ptr_eq(l, light)),
+            LightLinks::Exclude(lights) => !lights.iter().any(|l| Arc::ptr_eq(l, light)),
+        }
+    }
+}
+
+// POINT - shines equally in all directions from a single point
+pub struct PointLight {
+    pub position: Vec3,
+    pub intensity: Color,   // light per unit solid angle
+}
+impl Light for PointLight {
+    fn sample(&self, point: Vec3) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance = to_light.magnitude();
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            radiance: self.intensity / (distance*distance),
+            pdf: 1.0,
+            delta: true,
+        })
+    }
+    fn bounds(&self) -> Option<AABB> {
+        Some(AABB { min: self.position, max: self.position })
+    }
+    fn power(&self) -> Float {
+        4.0*PI*luminance(self.intensity)
+    }
+    fn sample_emission(&self) -> Option<(Ray, Color)> {
+        let direction = sample_uniform_sphere(rand_vec2());
+        Some((Ray { origin: self.position, direction, time: 0.0 }, self.intensity / uniform_sphere_pdf()))
+    }
+}
+
+// DIRECTIONAL - parallel light from infinitely far away
+pub struct DirectionalLight {
+    pub direction: Vec3,    // direction the light travels in
+    pub irradiance: Color,  // light arriving on a surface facing it
+}
+impl Light for DirectionalLight {
+    fn sample(&self, _point: Vec3) -> Option<LightSample> {
+        Some(LightSample {
+            direction: -self.direction.normalize(),
+            distance: Float::INFINITY,
+            radiance: self.irradiance,
+            pdf: 1.0,
+            delta: true,
+        })
+    }
+}
+
+// SUN - a directional light that comes from a small disk in the sky instead of a single direction, so shadows get
+// a penumbra that widens with distance from the object casting them
+pub struct SunLight {
+    pub direction: Vec3,        // direction the light travels in
+    pub irradiance: Color,      // light arriving on a surface facing it
+    pub angular_radius: Float,    // apparent radius of the sun's disk, in degrees (the real sun is about 0.27, 0.53 across)
+}
+impl SunLight {
+    // solid angle covered by the sun's disk
+    fn solid_angle(&self) -> Float {
+        // 2pi(1 - cos), written to stay accurate for tiny angles
+        4.0*PI*(0.5*self.angular_radius.to_radians()).sin().powi(2)
+    }
+    // whether a unit direction points at the sun's disk
+    fn in_disk(&self, direction: Vec3) -> bool {
+        // compares sines rather than cosines, which are all too close to 1 for small disks
+        let to_sun = -self.direction.normalize();
+        direction.dot(to_sun) > 0.0 && direction.cross(to_sun).magnitude() <= self.angular_radius.to_radians().sin()
+    }
+}
+impl Light for SunLight {
+    fn sample(&self, point: Vec3) -> Option<LightSample> {
+        let to_sun = -self.direction.normalize();
+        let solid_angle = self.solid_angle();
+        if solid_angle <= 0.0 {
+            // no size at all, just a directional light
+            return DirectionalLight { direction: self.direction, irradiance: self.irradiance }.sample(point);
+        }
+        // uniform direction in the cone towards the disk
+        let direction = Frame::new(to_sun).to_world(sample_cone(rand_vec2(), solid_angle/(2.0*PI)));
+        Some(LightSample {
+            direction,
+            distance: Float::INFINITY,
+            radiance: self.irradiance / solid_angle,
+            pdf: 1.0 / solid_angle,
+            delta: false,
+        })
+    }
+    fn escaped_radiance(&self, direction: Vec3) -> Color {
+        let solid_angle = self.solid_angle();
+        if solid_angle > 0.0 && self.in_disk(direction) { self.irradiance / solid_angle } else { Color::zero() }
+    }
+    fn escaped_pdf(&self, ray: &Ray) -> Float {
+        let solid_angle = self.solid_angle();
+        if solid_angle > 0.0 && self.in_disk(ray.direction.normalize()) { 1.0 / solid_angle } else { 0.0 }
+    }
+}
+
+// SPOT - a point light limited to a cone, fading out between the inner and outer angles
+pub struct SpotLight {
+    pub position: Vec3,
+    pub direction: Vec3,    // axis of the cone
+    pub intensity: Color,   // light per unit solid angle inside the inner cone
+    pub inner_angle: Float,   // half angle of the fully lit cone, in degrees
+    pub outer_angle: Float,   // half angle where the light has faded out completely, in degrees
+    // image projected through the cone like a slide (a gobo or cookie), tinting the light (None = plain white);
+    // it spans the outer cone, with its top towards +y
+    pub gobo: Option<Arc<dyn Texture + Send + Sync>>,
+}
+impl SpotLight {
+    // color of the gobo in a direction leaving the spot
+    fn gobo_color(&self, from_light: Vec3) -> Color {
+        let gobo = match &self.gobo {
+            Some(gobo) => gobo,
+            None => return vec3(1.0, 1.0, 1.0),
+        };
+        // image axes across the cone, keeping its up side up unless the spot points straight up or down
+        let axis = self.direction.normalize();
+        let right = if axis.cross(Vec3::unit_y()).magnitude2() > 1e-6 { axis.cross(Vec3::unit_y()).normalize() } else { Vec3::unit_x() };
+        let up = right.cross(axis);
+        // project onto a plane in front of the spot, scaled so the outer cone just fits in the image
+        let forward = from_light.dot(axis);
+        if forward <= 0.0 { return Color::zero() }
+        let scale = 0.5 / self.outer_angle.to_radians().tan();
+        let uv = vec2(0.5 + scale*from_light.dot(right)/forward, 0.5 + scale*from_light.dot(up)/forward);
+        gobo.sample(uv, Vec3::zero())
+    }
+    // how much light leaves the spot in a direction, relative to its intensity
+    fn falloff(&self, from_light: Vec3) -> Float {
+        let cos = from_light.dot(self.direction.normalize());
+        let (cos_inner, cos_outer) = (self.inner_angle.to_radians().cos(), self.outer_angle.to_radians().cos());
+        if cos >= cos_inner { return 1.0 }
+        if cos <= cos_outer { return 0.0 }
+        // smoothstep across the edge of the cone
+        let t = (cos - cos_outer) / (cos_inner - cos_outer);
+        t*t*(3.0 - 2.0*t)
+    }
+}
+impl Light for SpotLight {
+    fn sample(&self, point: Vec3) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance = to_light.magnitude();
+        let direction = to_light / distance;
+        let falloff = self.falloff(-direction);
+        if falloff <= 0.0 { return None }
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: falloff*self.gobo_color(-direction).mul_element_wise(self.intensity) / (distance*distance),
+            pdf: 1.0,
+            delta: true,
+        })
+    }
+    fn bounds(&self) -> Option<AABB> {
+        Some(AABB { min: self.position, max: self.position })
+    }
+    fn power(&self) -> Float {
+        // solid angle of a cone halfway through the falloff
+        let half_angle = 0.5*(self.inner_angle + self.outer_angle).to_radians();
+        2.0*PI*(1.0 - half_angle.cos())*luminance(self.intensity)
+    }
+    fn sample_emission(&self) -> Option<(Ray, Color)> {
+        // uniform direction in the outer cone, where all of the light goes
+        let cone_size = 1.0 - self.outer_angle.to_radians().cos();
+        let direction = Frame::new(self.direction.normalize()).to_world(sample_cone(rand_vec2(), cone_size));
+        let light = self.falloff(direction)*self.gobo_color(direction).mul_element_wise(self.intensity);
+        Some((Ray { origin: self.position, direction, time: 0.0 }, light / cone_pdf(cone_size)))
+    }
+}
+
+// AREA - emissive geometry, sampled by picking a random point on its surface
+// the shape should also be one of the scene's objects (sharing the Arc), so scattered rays can hit it too;
+// its light comes from its material's emission
+pub struct AreaLight {
+    pub shape: Arc<dyn Intersectable + Send + Sync>,
+}
+impl Light for AreaLight {
+    fn sample(&self, point: Vec3) -> Option<LightSample> {
+        let (light_point, light_normal) = self.shape.sample_surface()?;
+        let to_light = light_point - point;
+        let distance = to_light.magnitude();
+        let direction = to_light / distance;
+        // find the emission at the sampled point; points hidden behind another part of the light are skipped
+        let ray = Ray { origin: point, direction, time: 0.0 };
+        let hit = self.shape.intersect_ray(&ray, 0.0001, distance*1.001)?;
+        if hit.distance < distance*0.999 { return None }
+        let cos_light = light_normal.dot(direction).abs();
+        if cos_light <= 0.0 { return None }
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: hit.material.emission(&hit),
+            pdf: distance*distance / (cos_light*self.shape.area()),
+            delta: false,
+        })
+    }
+    fn pdf(&self, ray: &Ray, hit: &RayHit) -> Float {
+        // check that the hit is on this light's shape, at the same spot
+        let own_hit = match self.shape.intersect_ray(ray, 0.0001, hit.distance*1.001 + 0.0001) {
+            Some(own_hit) => own_hit,
+            None => return 0.0,
+        };
+        if (own_hit.distance - hit.distance).abs() > 0.001*Float::max(hit.distance, 1.0) { return 0.0 }
+        let distance = own_hit.distance*ray.direction.magnitude();
+        let cos_light = own_hit.normal.dot(ray.direction.normalize()).abs();
+        if cos_light <= 0.0 { return 0.0 }
+        distance*distance / (cos_light*self.shape.area())
+    }
+    fn bounds(&self) -> Option<AABB> {
+        self.shape.bounding_box()
+    }
+    fn power(&self) -> Float {
+        // average the emission over a few points on the surface, found by casting a ray at each
+        const POWER_SAMPLES: u32 = 16;
+        let mut emission = 0.0;
+        for _ in 0..POWER_SAMPLES {
+            if let Some((point, normal)) = self.shape.sample_surface() {
+                let ray = Ray { origin: point + 0.001*normal, direction: -normal, time: 0.0 };
+                if let Some(hit) = self.shape.intersect_ray(&ray, 0.0, 0.002) {
+                    emission += luminance(hit.material.emission(&hit));
+                }
+            }
+        }
+        PI*self.shape.area()*emission / POWER_SAMPLES as Float
+    }
+    fn sample_emission(&self) -> Option<(Ray, Color)> {
+        sample_shape_emission(self.shape.as_ref(), true)
+    }
+}
+// ray of light leaving a random point on an emissive shape, in a cosine weighted direction, and the light it carries
+// over the pdf of having picked it. flat shapes give off light from both sides, closed ones only from the outside
+fn sample_shape_emission(shape: &(dyn Intersectable + Send + Sync), two_sided: bool) -> Option<(Ray, Color)> {
+    let (point, normal) = shape.sample_surface()?;
+    // find the emission at the point by casting a ray at it, like AreaLight's power
+    let probe = Ray { origin: point + 0.001*normal, direction: -normal, time: 0.0 };
+    let hit = shape.intersect_ray(&probe, 0.0, 0.002)?;
+    let (side, sides) = if two_sided && rand_f32() < 0.5 { (-normal, 2.0) } else if two_sided { (normal, 2.0) } else { (normal, 1.0) };
+    let direction = Frame::new(side).to_world(sample_cosine_hemisphere(rand_vec2()));
+    // emission*cos over the pdfs of the point (1/area), the direction (cos/pi), and the side
+    let light = sides*PI*shape.area()*hit.material.emission(&hit);
+    Some((Ray { origin: point + 0.0001*side, direction, time: 0.0 }, light))
+}
+
+// SPHERE - an emissive sphere, sampled by picking a direction inside the cone it covers as seen from the point
+// (so every sample lands on the side facing the point, unlike AreaLight's points spread over the whole surface)
+// like AreaLight, the sphere should also be one of the scene's objects, and its light comes from its material
+pub struct SphereLight {
+    pub sphere: Arc<Sphere>,
+}
+impl SphereLight {
+    // 1 - cosine of the half angle of the cone the sphere covers from point (None if point is inside it)
+    fn cone_size(&self, point: Vec3) -> Option<Float> {
+        let sin2 = self.sphere.radius*self.sphere.radius / (self.sphere.center - point).magnitude2();
+        if sin2 >= 1.0 { return None }
+        // (written to stay accurate for small, far away spheres)
+        Some(sin2 / (1.0 + (1.0 - sin2).sqrt()))
+    }
+    // points inside the sphere see all of it, so they fall back to sampling its surface
+    fn surface_light(&self) -> AreaLight {
+        AreaLight { shape: self.sphere.clone() }
+    }
+}
+impl Light for SphereLight {
+    fn sample(&self, point: Vec3) -> Option<LightSample> {
+        let cone_size = match self.cone_size(point) {
+            Some(cone_size) => cone_size,
+            None => return self.surface_light().sample(point),
+        };
+        // uniform direction in the cone
+        let frame = Frame::new((self.sphere.center - point).normalize());
+        let direction = frame.to_world(sample_cone(rand_vec2(), cone_size));
+        // directions right at the edge of the cone can just miss from rounding
+        let ray = Ray { origin: point, direction, time: 0.0 };
+        let hit = self.sphere.intersect_ray(&ray, 0.0001, Float::MAX)?;
+        Some(LightSample {
+            direction,
+            distance: hit.distance,
+            radiance: hit.material.emission(&hit),
+            pdf: cone_pdf(cone_size),
+            delta: false,
+        })
+    }
+    fn pdf(&self, ray: &Ray, hit: &RayHit) -> Float {
+        let cone_size = match self.cone_size(ray.origin) {
+            Some(cone_size) => cone_size,
+            None => return self.surface_light().pdf(ray, hit),
+        };
+        // check that the hit is on this light's sphere, at the same spot
+        match self.sphere.intersect_ray(ray, 0.0001, hit.distance*1.001 + 0.0001) {
+            Some(own_hit) if (own_hit.distance - hit.distance).abs() <= 0.001*Float::max(hit.distance, 1.0) => cone_pdf(cone_size),
+            _ => 0.0,
+        }
+    }
+    fn bounds(&self) -> Option<AABB> {
+        self.sphere.bounding_box()
+    }
+    fn power(&self) -> Float {
+        self.surface_light().power()
+    }
+    fn sample_emission(&self) -> Option<(Ray, Color)> {
+        sample_shape_emission(self.sphere.as_ref(), false)
+    }
+}
+
+// ENVIRONMENT - light arriving from infinitely far away in every direction, read from an equirectangular
+// (latitude-longitude) image. bright regions like the sun are sampled more often, using a 2D CDF over the pixels:
+// a row is picked by how much light the whole row gives, then a pixel within it
+// interiors only see the environment through their windows, so most of those directions would be blocked;
+// portals mark the openings, and then directions are picked through a random point on one of them instead
+pub struct EnvironmentLight {
+    pub intensity: Float,     // scales the image's values
+    pub rotation: Float,      // turns the image around the vertical axis, in degrees
+    pub portals: Vec<Portal>,
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,     // linear radiance, row by row from straight up (+y) to straight down
+    weights: Vec<Float>,      // how likely each pixel is to be picked: its brightness times the solid angle it covers
+    total_weight: Float,
+    row_cdf: Vec<Float>,      // cdf of picking each row (height+1 entries)
+    column_cdfs: Vec<Float>,  // cdf of picking each pixel within its row (width+1 entries per row)
+}
+impl EnvironmentLight {
+    pub fn new(pixels: Vec<Color>, width: usize, height: usize, intensity: Float) -> EnvironmentLight {
+        assert_eq!(pixels.len(), width*height);
+        let weights: Vec<Float> = pixels.iter().enumerate().map(|(i, &pixel)| {
+            // rows near the poles are squashed into less solid angle
+            let theta = PI*((i / width) as Float + 0.5) / height as Float;
+            Float::max(luminance(pixel), 0.0)*theta.sin()
+        }).collect();
+        let mut column_cdfs = Vec::with_capacity((width+1)*height);
+        let mut row_weights = Vec::with_capacity(height);
+        for row in weights.chunks(width) {
+            let (cdf, total) = build_cdf(row);
+            column_cdfs.extend(cdf);
+            row_weights.push(total);
+        }
+        let (row_cdf, total_weight) = build_cdf(&row_weights);
+        EnvironmentLight {
+            intensity,
+            rotation: 0.0,
+            portals: Vec::new(),
+            width,
+            height,
+            pixels,
+            weights,
+            total_weight,
+            row_cdf,
+            column_cdfs,
+        }
+    }
+    // loads a radiance .hdr image as is, or any other image format decoded from sRGB
+    pub fn load_from_file(file_name: &str, intensity: Float) -> Option<EnvironmentLight> {
+        if file_name.to_lowercase().ends_with(".hdr") {
+            let file = std::io::BufReader::new(std::fs::File::open(file_name).ok()?);
+            let decoder = image::codecs::hdr::HdrDecoder::new(file).ok()?;
+            let (width, height) = (decoder.metadata().width as usize, decoder.metadata().height as usize);
+            let pixels = decoder.read_image_hdr().ok()?.iter().map(pixel_color).collect();
+            Some(EnvironmentLight::new(pixels, width, height, intensity))
+        }
+        else {
+            let img = image::open(file_name).ok()?.to_rgb8();
+            let (width, height) = (img.width() as usize, img.height() as usize);
+            let pixels = img.pixels().map(|p| vec3(p[0], p[1], p[2]).map(|c| srgb_to_linear(c as Float / 255.0))).collect();
+            Some(EnvironmentLight::new(pixels, width, height, intensity))
+        }
+    }
+    pub fn with_rotation(mut self, degrees: Float) -> EnvironmentLight {
+        self.rotation = degrees;
+        self
+    }
+    pub fn with_portals(mut self, portals: Vec<Portal>) -> EnvironmentLight {
+        self.portals = portals;
+        self
+    }
+
+    // image coordinates (u around the horizon, v from top to bottom, both in [0,1)) of a unit world direction
+    fn direction_to_uv(&self, direction: Vec3) -> Vec2 {
+        equirectangular_uv(Matrix3::from_angle_y(Deg(-self.rotation)) * direction)
+    }
+    fn uv_to_direction(&self, uv: Vec2) -> Vec3 {
+        let (phi, theta) = (2.0*PI*uv.x, PI*uv.y);
+        let d = vec3(theta.sin()*phi.sin(), theta.cos(), -theta.sin()*phi.cos());
+        Matrix3::from_angle_y(Deg(self.rotation)) * d
+    }
+    fn pixel_index(&self, uv: Vec2) -> usize {
+        let x = usize::min((uv.x*self.width as Float) as usize, self.width-1);
+        let y = usize::min((uv.y*self.height as Float) as usize, self.height-1);
+        y*self.width + x
+    }
+    // converts a density over the image to a density over solid angle at the image's v coordinate
+    fn uv_pdf_to_solid_angle(pdf: Float, v: Float) -> Float {
+        let sin_theta = (PI*v).sin();
+        if sin_theta <= 0.0 { 0.0 } else { pdf / (2.0*PI*PI*sin_theta) }
+    }
+
+    // picks a direction by where it lands on the image
+    fn sample_image(&self) -> Option<LightSample> {
+        if self.total_weight <= 0.0 { return None }
+        let (row, v_offset) = sample_cdf(&self.row_cdf, rand_f32());
+        let row_start = row*(self.width+1);
+        let (column, u_offset) = sample_cdf(&self.column_cdfs[row_start..row_start+self.width+1], rand_f32());
+        let uv = vec2((column as Float + u_offset) / self.width as Float, (row as Float + v_offset) / self.height as Float);
+        let index = row*self.width + column;
+        let uv_pdf = self.weights[index]*(self.width*self.height) as Float / self.total_weight;
+        let pdf = EnvironmentLight::uv_pdf_to_solid_angle(uv_pdf, uv.y);
+        if pdf <= 0.0 { return None }
+        Some(LightSample {
+            direction: self.uv_to_direction(uv),
+            distance: Float::INFINITY,
+            radiance: self.intensity*self.pixels[index],
+            pdf,
+            delta: false,
+        })
+    }
+    fn image_pdf(&self, direction: Vec3) -> Float {
+        if self.total_weight <= 0.0 { return 0.0 }
+        let uv = self.direction_to_uv(direction);
+        let uv_pdf = self.weights[self.pixel_index(uv)]*(self.width*self.height) as Float / self.total_weight;
+        EnvironmentLight::uv_pdf_to_solid_angle(uv_pdf, uv.y)
+    }
+
+    // picks a direction from point through a uniformly random point on the portals (bigger portals more often)
+    fn sample_portals(&self, point: Vec3) -> Option<LightSample> {
+        let total_area: Float = self.portals.iter().map(|portal| portal.area()).sum();
+        let mut target = rand_f32()*total_area;
+        let portal = self.portals.iter().find(|portal| {
+            target -= portal.area();
+            target < 0.0
+        }).unwrap_or(&self.portals[self.portals.len()-1]);
+        let portal_point = portal.corner + rand_f32()*portal.edge_u + rand_f32()*portal.edge_v;
+        let direction = (portal_point - point).normalize();
+        let pdf = self.portal_pdf(point, direction);
+        if pdf <= 0.0 { return None }
+        Some(LightSample {
+            direction,
+            distance: Float::INFINITY,
+            radiance: self.escaped_radiance(direction),
+            pdf,
+            delta: false,
+        })
+    }
+    // density of sample_portals picking a (unit) direction from point, summed over every portal it passes through
+    fn portal_pdf(&self, point: Vec3, direction: Vec3) -> Float {
+        let total_area: Float = self.portals.iter().map(|portal| portal.area()).sum();
+        self.portals.iter().filter_map(|portal| {
+            let distance = portal.intersect(point, direction)?;
+            let cos = portal.normal().dot(direction).abs();
+            if cos <= 0.0 { return None }
+            Some(distance*distance / (cos*total_area))
+        }).sum()
+    }
+}
+impl Light for EnvironmentLight {
+    fn sample(&self, point: Vec3) -> Option<LightSample> {
+        if self.portals.is_empty() { self.sample_image() } else { self.sample_portals(point) }
+    }
+    fn escaped_radiance(&self, direction: Vec3) -> Color {
+        self.intensity*self.pixels[self.pixel_index(self.direction_to_uv(direction))]
+    }
+    fn escaped_pdf(&self, ray: &Ray) -> Float {
+        let direction = ray.direction.normalize();
+        if self.portals.is_empty() { self.image_pdf(direction) } else { self.portal_pdf(ray.origin, direction) }
+    }
+}
+
+// a parallelogram (usually a window or other opening) that an environment light shines into the scene through
+// it isn't an object in the scene; rays pass straight through it
+pub struct Portal {
+    pub corner: Vec3,
+    pub edge_u: Vec3,   // the two edges leaving the corner
+    pub edge_v: Vec3,
+}
+impl Portal {
+    fn area(&self) -> Float {
+        self.edge_u.cross(self.edge_v).magnitude()
+    }
+    fn normal(&self) -> Vec3 {
+        self.edge_u.cross(self.edge_v).normalize()
+    }
+    // distance along a (unit) direction from origin to the portal, if the ray passes through it
+    fn intersect(&self, origin: Vec3, direction: Vec3) -> Option<Float> {
+        let normal = self.edge_u.cross(self.edge_v);
+        let denom = normal.dot(direction);
+        if denom == 0.0 { return None }
+        let t = normal.dot(self.corner - origin) / denom;
+        if t <= 0.0 { return None }
+        // coordinates of the hit along each edge, using the edges' reciprocal vectors
+        let offset = origin + t*direction - self.corner;
+        let w = normal / normal.magnitude2();
+        let (a, b) = (offset.cross(self.edge_v).dot(w), self.edge_u.cross(offset).dot(w));
+        if (0.0..=1.0).contains(&a) && (0.0..=1.0).contains(&b) { Some(t) } else { None }
+    }
+}
+
+// LIGHT TREE - a bounding volume hierarchy over many lights, so a scene with hundreds of emitters (windows of a
+// city, instanced lamps) samples one of them in logarithmic time instead of casting a shadow ray to every one.
+// each step down the tree picks a child by a guess at how much light it gives the point being shaded: its power
+// over its squared distance. the tree is itself a light, so it goes in the scene's lights in place of the ones it holds
+// every light in it must have bounds, so lights infinitely far away should stay out of the tree
+pub struct LightTree {
+    root: LightNode,
+}
+struct LightNode {
+    aabb: AABB,
+    power: Float,
+    left: Option<Box<LightNode>>,
+    right: Option<Box<LightNode>>,
+    light: Option<Arc<dyn Light + Send + Sync>>,
+}
+impl LightTree {
+    // builds a tree over a list of lights (returns None if the list is empty)
+    pub fn build(lights: Vec<Arc<dyn Light + Send + Sync>>) -> Option<LightTree> {
+        if lights.is_empty() { return None }
+        let leaves = lights.into_iter().map(|light| LightNode {
+            aabb: light.bounds().expect("LightTree lights must have bounds"),
+            power: light.power(),
+            left: None,
+            right: None,
+            light: Some(light),
+        }).collect();
+        Some(LightTree { root: LightTree::build_helper(leaves) })
+    }
+    // helper for tree construction recursion
+    fn build_helper(mut leaves: Vec<LightNode>) -> LightNode {
+        if leaves.len() == 1 { return leaves.remove(0) }
+        // split at the median along the axis the lights are most spread out on
+        let centers = leaves.iter().fold(AABB { min: vec3(Float::MAX, Float::MAX, Float::MAX), max: vec3(Float::MIN, Float::MIN, Float::MIN) }, |b, leaf| {
+            let c = leaf.aabb.center();
+            AABB::aabb_surrounding(&b, &AABB { min: c, max: c })
+        });
+        let extent = centers.max - centers.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+        leaves.sort_by(|a, b| a.aabb.center()[axis].partial_cmp(&b.aabb.center()[axis]).unwrap_or(std::cmp::Ordering::Equal));
+        // recurse on each side
+        let right_leaves = leaves.split_off(leaves.len()/2);
+        let left = LightTree::build_helper(leaves);
+        let right = LightTree::build_helper(right_leaves);
+        LightNode {
+            aabb: AABB::aabb_surrounding(&left.aabb, &right.aabb),
+            power: left.power + right.power,
+            left: Some(Box::new(left)),
+            right: Some(Box::new(right)),
+            light: None,
+        }
+    }
+}
+impl LightNode {
+    // guess at how much of the node's light reaches point
+    fn importance(&self, point: Vec3) -> Float {
+        // points inside (or close to) the box are treated as being about its radius away
+        let radius2 = (self.aabb.max - self.aabb.min).magnitude2() / 4.0;
+        let distance2 = (self.aabb.center() - point).magnitude2();
+        self.power / Float::max(Float::max(distance2, radius2), 0.0001)
+    }
+    // chances of going left and right from this (interior) node
+    fn child_probabilities(&self, left: &LightNode, right: &LightNode, point: Vec3) -> Option<(Float, Float)> {
+        let (l, r) = (left.importance(point), right.importance(point));
+        if l + r <= 0.0 || !(l + r).is_finite() { return None }
+        Some((l/(l + r), r/(l + r)))
+    }
+    // chance of sample picking the light hit lies on, times that light's own pdf (summed, in case boxes overlap)
+    fn pdf(&self, ray: &Ray, hit: &RayHit, pmf: Float) -> Float {
+        if let Some(light) = &self.light {
+            return pmf*light.pdf(ray, hit);
+        }
+        let (left, right) = match (&self.left, &self.right) {
+            (Some(left), Some(right)) => (left, right),
+            _ => return 0.0,
+        };
+        let (p_left, p_right) = match self.child_probabilities(left, right, ray.origin) {
+            Some(p) => p,
+            None => return 0.0,
+        };
+        // only lights whose box holds the hit could have been hit
+        let mut pdf = 0.0;
+        if left.aabb.contains(hit.hitpoint, 0.001) { pdf += left.pdf(ray, hit, pmf*p_left); }
+        if right.aabb.contains(hit.hitpoint, 0.001) { pdf += right.pdf(ray, hit, pmf*p_right); }
+        pdf
+    }
+}
+impl Light for LightTree {
+    fn sample(&self, point: Vec3) -> Option<LightSample> {
+        let mut node = &self.root;
+        let mut pmf = 1.0;
+        while let (Some(left), Some(right)) = (&node.left, &node.right) {
+            let (p_left, p_right) = node.child_probabilities(left, right, point)?;
+            if rand_f32() < p_left {
+                node = left;
+                pmf *= p_left;
+            }
+            else {
+                node = right;
+                pmf *= p_right;
+            }
+        }
+        let mut sample = node.light.as_ref()?.sample(point)?;
+        sample.pdf *= pmf;
+        Some(sample)
+    }
+    fn pdf(&self, ray: &Ray, hit: &RayHit) -> Float {
+        if !self.root.aabb.contains(hit.hitpoint, 0.001) { return 0.0 }
+        self.root.pdf(ray, hit, 1.0)
+    }
+    fn bounds(&self) -> Option<AABB> {
+        Some(self.root.aabb)
+    }
+    fn power(&self) -> Float {
+        self.root.power
+    }
+    fn sample_emission(&self) -> Option<(Ray, Color)> {
+        // pick a light by its share of the power, since there's no point being lit to guess by
+        let mut node = &self.root;
+        let mut pmf = 1.0;
+        while let (Some(left), Some(right)) = (&node.left, &node.right) {
+            if node.power <= 0.0 { return None }
+            let p_left = left.power / node.power;
+            if rand_f32() < p_left {
+                node = left;
+                pmf *= p_left;
+            }
+            else {
+                node = right;
+                pmf *= 1.0 - p_left;
+            }
+        }
+        let (ray, light) = node.light.as_ref()?.sample_emission()?;
+        Some((ray, light / pmf))
+    }
+}
@@ -0,0 +1,325 @@
+// MATERIAL LIBRARY - Loads named materials from standalone library files so they can be shared between scenes
+//
+// Library files are plain text. Each material starts with a `material <name> <type>` line, followed by one
+// `<parameter> <values...>` line per parameter. Blank lines and lines starting with '#' are ignored. Parameters
+// that accept textures take either numbers or an image path, e.g.
+//
+//     material red_plastic parameterized
+//     albedo 0.8 0.05 0.05
+//     roughness 0.35
+//
+//     material brushed_gold conductor
+//     preset gold
+//     roughness 0.25
+//
+// Supported types and their parameters (anything left out keeps the material's default):
+//     lambertian      albedo, emission, temperature (kelvin intensity)
+//     oren_nayar      albedo, emission, roughness
+//     metal           albedo, emission, roughness
+//     conductor       preset (gold/copper/aluminum/silver), eta, k, roughness
+//     dielectric      ior, dispersion (preset bk7/flint/diamond, cauchy a b, or sellmeier b1 b2 b3 c1 c2 c3)
+//     parameterized   albedo*, emission*, roughness*, metallic*, clearcoat, clearcoat_roughness, sheen, sheen_tint
+//     principled      base_color*, emission*, metallic*, roughness*, specular, specular_tint, sheen, sheen_tint,
+//                     clearcoat, clearcoat_roughness, transmission, ior
+//     velvet          albedo, roughness
+//     subsurface      albedo, mean_free_path, ior
+//     hair            color or melanin (eumelanin pheomelanin), beta_m, beta_n, alpha, ior
+// (* = accepts a texture path; color images are decoded from sRGB, roughness/metallic images are read as linear data)
+//
+// Other crates can add their own material types with register_material_type, giving the type's name and a function
+// that builds the material from its parameters (read with MaterialParams' helpers, like the built in types), e.g.
+//
+//     register_material_type("glitter", Arc::new(|p: &MaterialParams| {
+//         p.check_known(&["albedo", "density"])?;
+//         Ok(Arc::new(Glitter { albedo: p.color("albedo", vec3(1.0,1.0,1.0))?, density: p.float("density", 0.2)? }) as _)
+//     }))
+//
+// after which library files (and material descriptions) can use `glitter` like any other type.
+
+#![allow(dead_code)]
+
+use cgmath::*;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+use ::tracing::{info, info_span, warn};
+
+use super::tracing::*;
+use super::materials::*;
+use super::texture::*;
+use super::color::*;
+
+pub struct MaterialLibrary {
+    materials: HashMap<String, Arc<dyn Material + Send + Sync>>,
+}
+impl MaterialLibrary {
+    // load a library file, logging any problems with it (returns None if it can't be read or has errors)
+    pub fn load_from_file(file_name: &str) -> Option<MaterialLibrary> {
+        let _span = info_span!("load", file = %file_name).entered();
+        let text = match std::fs::read_to_string(file_name) {
+            Ok(text) => text,
+            Err(e) => {
+                warn!(error = %e, "failed to read material library");
+                return None;
+            }
+        };
+        let library = MaterialLibrary::parse(&text, file_name);
+        if let Some(library) = &library {
+            info!(materials = library.materials.len(), "loaded material library");
+        }
+        library
+    }
+
+    // parse library text; file_name is only used for error messages
+    pub fn parse(text: &str, file_name: &str) -> Option<MaterialLibrary> {
+        let mut materials = HashMap::new();
+        let mut ok = true;
+        // (name, type, line number, parameters) of the material being read
+        let mut current: Option<(String, String, usize, MaterialParams)> = None;
+        let mut finish = |current: Option<(String, String, usize, MaterialParams)>, ok: &mut bool| {
+            if let Some((name, kind, line, params)) = current {
+                match build_material(&kind, &params) {
+                    // (named after their entry, for material cryptomattes)
+                    Ok(material) => { materials.insert(name.clone(), Arc::new(Named { material, name }) as Arc<dyn Material + Send + Sync>); }
+                    Err(e) => {
+                        warn!("{}:{}: material '{}': {}", file_name, line, name, e);
+                        *ok = false;
+                    }
+                }
+            }
+        };
+
+        for (i, line) in text.lines().enumerate() {
+            let line_number = i+1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue }
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens[0] == "material" {
+                if tokens.len() != 3 {
+                    warn!("{}:{}: expected 'material <name> <type>'", file_name, line_number);
+                    ok = false;
+                    continue;
+                }
+                finish(current.take(), &mut ok);
+                current = Some((tokens[1].to_string(), tokens[2].to_string(), line_number, MaterialParams::default()));
+            }
+            else if let Some((_, _, _, params)) = current.as_mut() {
+                params.values.insert(tokens[0].to_string(), tokens[1..].iter().map(|t| t.to_string()).collect());
+            }
+            else {
+                warn!("{}:{}: parameter outside of a material", file_name, line_number);
+                ok = false;
+            }
+        }
+        finish(current.take(), &mut ok);
+
+        if ok { Some(MaterialLibrary { materials }) } else { None }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Material + Send + Sync>> {
+        self.materials.get(name).cloned()
+    }
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.materials.keys()
+    }
+}
+
+// parameter lines of one material, by name
+#[derive(Default)]
+pub struct MaterialParams {
+    values: HashMap<String, Vec<String>>,
+}
+impl MaterialParams {
+    pub fn set(&mut self, key: &str, tokens: Vec<String>) {
+        self.values.insert(key.to_string(), tokens);
+    }
+    // the parameter's values as they were written, joined by spaces (e.g. a name or a file path)
+    pub fn text(&self, key: &str) -> Option<String> {
+        self.values.get(key).map(|tokens| tokens.join(" "))
+    }
+    pub fn numbers(&self, key: &str) -> Result<Option<Vec<Float>>, String> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(tokens) => tokens.iter()
+                .map(|t| t.parse::<Float>().map_err(|_| format!("'{}' expects numbers, got '{}'", key, t)))
+                .collect::<Result<Vec<Float>, String>>()
+                .map(Some),
+        }
+    }
+    pub fn float(&self, key: &str, default: Float) -> Result<Float, String> {
+        match self.numbers(key)? {
+            None => Ok(default),
+            Some(v) if v.len() == 1 => Ok(v[0]),
+            Some(_) => Err(format!("'{}' expects one number", key)),
+        }
+    }
+    // colors can be given as one gray value or three channels
+    pub fn color(&self, key: &str, default: Color) -> Result<Color, String> {
+        match self.numbers(key)? {
+            None => Ok(default),
+            Some(v) if v.len() == 1 => Ok(vec3(v[0], v[0], v[0])),
+            Some(v) if v.len() == 3 => Ok(vec3(v[0], v[1], v[2])),
+            Some(_) => Err(format!("'{}' expects one or three numbers", key)),
+        }
+    }
+    // numbers give a constant texture, anything else is loaded as an image in the given color space
+    pub fn texture(&self, key: &str, colorspace: ColorSpace, default: Arc<dyn Texture + Send + Sync>) -> Result<Arc<dyn Texture + Send + Sync>, String> {
+        match self.values.get(key) {
+            None => Ok(default),
+            Some(tokens) if tokens.len() == 1 && tokens[0].parse::<Float>().is_err() => {
+                ImageTexture::load_shared(&tokens[0], colorspace).ok_or(format!("couldn't load texture '{}'", tokens[0]))
+            }
+            Some(_) => Ok(solid_color(self.color(key, Color::zero())?)),
+        }
+    }
+    // a preset's name, or the kind of fit followed by its coefficients
+    pub fn dispersion(&self, key: &str) -> Result<Option<Dispersion>, String> {
+        let tokens = match self.values.get(key) {
+            None => return Ok(None),
+            Some(tokens) => tokens,
+        };
+        let kind = tokens.first().map_or("", |t| t.as_str());
+        let numbers = tokens.iter().skip(1)
+            .map(|t| t.parse::<Float>().map_err(|_| format!("'{}' expects numbers after '{}', got '{}'", key, kind, t)))
+            .collect::<Result<Vec<Float>, String>>()?;
+        match (kind, numbers.len()) {
+            ("bk7", 0) => Ok(Some(Dispersion::bk7())),
+            ("flint", 0) => Ok(Some(Dispersion::flint())),
+            ("diamond", 0) => Ok(Some(Dispersion::diamond())),
+            ("cauchy", 2) => Ok(Some(Dispersion::Cauchy { a: numbers[0], b: numbers[1] })),
+            ("sellmeier", 6) => Ok(Some(Dispersion::Sellmeier { b: [numbers[0], numbers[1], numbers[2]], c: [numbers[3], numbers[4], numbers[5]] })),
+            _ => Err(format!("'{}' expects bk7, flint, diamond, cauchy a b, or sellmeier b1 b2 b3 c1 c2 c3", key)),
+        }
+    }
+    pub fn check_known(&self, known: &[&str]) -> Result<(), String> {
+        match self.values.keys().find(|key| !known.contains(&key.as_str())) {
+            Some(key) => Err(format!("unknown parameter '{}'", key)),
+            None => Ok(()),
+        }
+    }
+}
+
+// builds a material from another crate's parameters
+pub type MaterialConstructor = Arc<dyn Fn(&MaterialParams) -> Result<Arc<dyn Material + Send + Sync>, String> + Send + Sync>;
+const BUILT_IN_TYPES: [&str; 10] = ["lambertian", "oren_nayar", "metal", "conductor", "dielectric", "parameterized", "principled", "velvet", "subsurface", "hair"];
+static REGISTERED_TYPES: OnceLock<RwLock<HashMap<String, MaterialConstructor>>> = OnceLock::new();
+
+// adds a material type by name (registering a name again replaces it, but the built in types can't be replaced)
+pub fn register_material_type(kind: &str, constructor: MaterialConstructor) -> Result<(), String> {
+    if BUILT_IN_TYPES.contains(&kind) {
+        return Err(format!("'{}' is a built in material type", kind));
+    }
+    REGISTERED_TYPES.get_or_init(Default::default).write().unwrap().insert(kind.to_string(), constructor);
+    Ok(())
+}
+// names of the types added with register_material_type
+pub fn registered_material_types() -> Vec<String> {
+    REGISTERED_TYPES.get().map_or(Vec::new(), |types| types.read().unwrap().keys().cloned().collect())
+}
+
+// builds a material of a built in or registered type from its parameters
+pub fn build_material(kind: &str, p: &MaterialParams) -> Result<Arc<dyn Material + Send + Sync>, String> {
+    Ok(match kind {
+        "lambertian" => {
+            p.check_known(&["albedo", "emission", "temperature"])?;
+            let emission = match p.numbers("temperature")? {
+                Some(v) if v.len() == 2 => blackbody_emission(v[0], v[1]),
+                Some(_) => return Err("'temperature' expects a temperature in kelvin and an intensity".to_string()),
+                None => p.color("emission", Color::zero())?,
+            };
+            Arc::new(Lambertian { albedo: p.color("albedo", vec3(1.0,1.0,1.0))?, emission })
+        }
+        "oren_nayar" => {
+            p.check_known(&["albedo", "emission", "roughness"])?;
+            Arc::new(OrenNayar { albedo: p.color("albedo", vec3(1.0,1.0,1.0))?, emission: p.color("emission", Color::zero())?, roughness: p.float("roughness", 0.5)? })
+        }
+        "metal" => {
+            p.check_known(&["albedo", "emission", "roughness"])?;
+            Arc::new(Metal { albedo: p.color("albedo", vec3(1.0,1.0,1.0))?, emission: p.color("emission", Color::zero())?, roughness: p.float("roughness", 0.0)? })
+        }
+        "conductor" => {
+            p.check_known(&["preset", "eta", "k", "roughness"])?;
+            let roughness = p.float("roughness", 0.0)?;
+            let mut conductor = match p.values.get("preset").map(|v| v.join(" ")) {
+                None => Conductor::silver(roughness),
+                Some(preset) => match preset.as_str() {
+                    "gold" => Conductor::gold(roughness),
+                    "copper" => Conductor::copper(roughness),
+                    "aluminum" => Conductor::aluminum(roughness),
+                    "silver" => Conductor::silver(roughness),
+                    _ => return Err(format!("unknown conductor preset '{}'", preset)),
+                },
+            };
+            conductor.eta = p.color("eta", conductor.eta)?;
+            conductor.k = p.color("k", conductor.k)?;
+            Arc::new(conductor)
+        }
+        "dielectric" => {
+            p.check_known(&["ior", "dispersion"])?;
+            Arc::new(Dielectric { idx_of_refraction: p.float("ior", 1.5)?, dispersion: p.dispersion("dispersion")? })
+        }
+        "parameterized" => {
+            p.check_known(&["albedo", "emission", "roughness", "metallic", "clearcoat", "clearcoat_roughness", "sheen", "sheen_tint"])?;
+            let d = ParameterizedMaterial::default();
+            Arc::new(ParameterizedMaterial {
+                albedo: p.texture("albedo", ColorSpace::Srgb, d.albedo)?,
+                emission: p.texture("emission", ColorSpace::Srgb, d.emission)?,
+                roughness: p.texture("roughness", ColorSpace::Linear, d.roughness)?,
+                metallic: p.texture("metallic", ColorSpace::Linear, d.metallic)?,
+                clearcoat: p.float("clearcoat", d.clearcoat)?,
+                clearcoat_roughness: p.float("clearcoat_roughness", d.clearcoat_roughness)?,
+                sheen: p.float("sheen", d.sheen)?,
+                sheen_tint: p.float("sheen_tint", d.sheen_tint)?,
+            })
+        }
+        "principled" => {
+            p.check_known(&["base_color", "emission", "metallic", "roughness", "specular", "specular_tint", "sheen", "sheen_tint", "clearcoat", "clearcoat_roughness", "transmission", "ior"])?;
+            let d = PrincipledMaterial::default();
+            Arc::new(PrincipledMaterial {
+                base_color: p.texture("base_color", ColorSpace::Srgb, d.base_color)?,
+                emission: p.texture("emission", ColorSpace::Srgb, d.emission)?,
+                metallic: p.texture("metallic", ColorSpace::Linear, d.metallic)?,
+                roughness: p.texture("roughness", ColorSpace::Linear, d.roughness)?,
+                specular: p.float("specular", d.specular)?,
+                specular_tint: p.float("specular_tint", d.specular_tint)?,
+                sheen: p.float("sheen", d.sheen)?,
+                sheen_tint: p.float("sheen_tint", d.sheen_tint)?,
+                clearcoat: p.float("clearcoat", d.clearcoat)?,
+                clearcoat_roughness: p.float("clearcoat_roughness", d.clearcoat_roughness)?,
+                transmission: p.float("transmission", d.transmission)?,
+                ior: p.float("ior", d.ior)?,
+            })
+        }
+        "velvet" => {
+            p.check_known(&["albedo", "roughness"])?;
+            Arc::new(Velvet { albedo: p.color("albedo", vec3(1.0,1.0,1.0))?, roughness: p.float("roughness", 0.5)? })
+        }
+        "subsurface" => {
+            p.check_known(&["albedo", "mean_free_path", "ior"])?;
+            Arc::new(SubsurfaceMaterial {
+                albedo: p.color("albedo", vec3(0.8,0.8,0.8))?,
+                mean_free_path: p.color("mean_free_path", vec3(0.1,0.1,0.1))?,
+                idx_of_refraction: p.float("ior", 1.3)?,
+            })
+        }
+        "hair" => {
+            p.check_known(&["color", "melanin", "beta_m", "beta_n", "alpha", "ior"])?;
+            let (beta_m, beta_n) = (p.float("beta_m", 0.3)?, p.float("beta_n", 0.3)?);
+            let mut hair = match p.numbers("melanin")? {
+                Some(v) if v.len() == 2 => Hair::from_melanin(v[0], v[1], beta_m, beta_n),
+                Some(_) => return Err("'melanin' expects eumelanin and pheomelanin concentrations".to_string()),
+                None => Hair::from_color(p.color("color", vec3(0.3,0.2,0.1))?, beta_m, beta_n),
+            };
+            hair.alpha = p.float("alpha", hair.alpha)?;
+            hair.eta = p.float("ior", hair.eta)?;
+            Arc::new(hair)
+        }
+        _ => {
+            // (the lock is released before the constructor runs, so it can build other materials itself)
+            let constructor = REGISTERED_TYPES.get().and_then(|types| types.read().unwrap().get(kind).cloned());
+            match constructor {
+                Some(constructor) => constructor(p)?,
+                None => return Err(format!("unknown material type '{}'", kind)),
+            }
+        }
+    })
+}
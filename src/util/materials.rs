@@ -3,20 +3,76 @@
 #![allow(dead_code)]
 
 use cgmath::*;
-use std::f32::consts::PI;
-use rand::Rng;
+use super::tracing::consts::PI;
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
 
 use super::tracing::*;
+use super::texture::*;
+use super::color::*;
+use super::lights::LightLinks;
+use super::sampler::*;
+use super::sampling::*;
 
 // Trait for material; materials scatter, attenuate, and emit light
 pub trait Material {
-    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, f32); // returns a new ray, its attenuation, and the probabiltiy it was chosen for a given material
-    fn emission(&self) -> Color;
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind); // returns a new ray, its attenuation, the probabiltiy it was chosen for a given material, and what kind of bounce it was
+    fn emission(&self, hit: &RayHit) -> Color;
+    // color of the surface without any lighting, for the albedo aov (and denoising guided by it). white for materials
+    // without a clear color of their own, like mirrors and glass
+    fn albedo(&self, _hit: &RayHit) -> Color {
+        vec3(1.0, 1.0, 1.0)
+    }
+    // value of the brdf for light arriving from wi and leaving towards wo (both world space, pointing away from the
+    // surface), and the pdf scatter would have picked wi with. used to sample lights directly, so materials that can
+    // only be sampled (perfect mirrors, glass, ...) return None and are only lit by scattered rays that find a light
+    fn eval(&self, _hit: &RayHit, _wo: Vec3, _wi: Vec3) -> Option<(Color, Float)> {
+        None
+    }
+    // opacity at the hit point - hits below ALPHA_CUTOFF are skipped as if the surface wasn't there
+    fn alpha(&self, _hit: &RayHit) -> Float {
+        1.0
+    }
+    // whether some of the light the surface reflects is spread over the whole hemisphere, rather than all of it going
+    // into a lobe around the mirror direction. photon mapping gathers photons on diffuse surfaces, but follows camera
+    // rays on past glossy ones, since photons would hardly ever land inside a narrow lobe
+    fn is_diffuse(&self, _hit: &RayHit) -> bool {
+        false
+    }
+    // materials with a toon style are drawn with flat toon shading even when the rest of the scene is path traced
+    fn toon_style(&self) -> Option<ToonStyle> {
+        None
+    }
+    // which of the scene's lights shine on the surface (None = all of them)
+    fn light_links(&self) -> Option<&LightLinks> {
+        None
+    }
+    // whether camera rays see through the surface to everything but the shadows and bounce light the scene casts on it
+    // (see ShadowCatcher)
+    fn is_shadow_catcher(&self) -> bool {
+        false
+    }
+    // what the material is called in material cryptomattes. materials without a name of their own (see Named) go by
+    // their type, so all of one kind are picked out together
+    fn name(&self) -> &str {
+        let type_name = std::any::type_name::<Self>();
+        type_name.rsplit("::").next().unwrap_or(type_name)
+    }
+}
+pub const ALPHA_CUTOFF: Float = 0.5;
+// kinds of bounces a scattered ray can make, each with its own limit on how many a path can take (see BounceLimits)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BounceKind {
+    Diffuse,        // spread over the whole hemisphere
+    Glossy,         // reflected in a (possibly sharp) lobe around the mirror direction
+    Transmission,   // passed through the surface, e.g. refracted by glass
+    Volume,         // scattered inside a medium
 }
 
 
 // LAMBERTIAN
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Lambertian {
     pub albedo: Vec3,   // base color
     pub emission: Vec3, // emitted light
@@ -30,170 +86,1691 @@ impl Default for Lambertian {
     }
 
 }
+impl Lambertian {
+    // emitter whose light color is set by a color temperature in Kelvin instead of an rgb triple
+    pub fn blackbody(kelvin: Float, intensity: Float) -> Lambertian {
+        Lambertian {
+            albedo: Vec3::zero(),
+            emission: blackbody_emission(kelvin, intensity),
+        }
+    }
+}
 impl Material for Lambertian {
-    fn scatter(&self, hit: &RayHit, _ray: &Ray) -> (Ray, Color, f32) {
-        let (dir, pdf) = sample_hemisphere(hit);    // light is diffused in all directions
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        let wi = sample_cosine_hemisphere(rand_vec2());    // light is diffused in all directions
         (
             Ray {
                 origin: hit.hitpoint,
-                direction: dir,
+                direction: Frame::new(hit.normal).to_world(wi),
+                time: ray.time,
             },
             self.albedo / PI,
-            pdf,
+            Float::max(cosine_hemisphere_pdf(wi.z), 0.0001),
+            BounceKind::Diffuse,
+        )
+    }
+    fn emission(&self, _hit: &RayHit) -> Color {
+        self.emission
+    }
+    fn albedo(&self, _hit: &RayHit) -> Color {
+        self.albedo
+    }
+    fn eval(&self, hit: &RayHit, _wo: Vec3, wi: Vec3) -> Option<(Color, Float)> {
+        if wi.dot(hit.normal) <= 0.0 { return Some((Color::zero(), 0.0)) }
+        Some((self.albedo / PI, cosine_hemisphere_pdf(wi.dot(hit.normal))))
+    }
+    fn is_diffuse(&self, _hit: &RayHit) -> bool {
+        true
+    }
+}
+
+// OREN-NAYAR
+// rough diffuse surface made of tiny lambertian v-grooves; stays brighter at grazing angles than a plain lambertian
+// (good for clay, plaster, the moon). uses the qualitative model from https://www.pbr-book.org/3ed-2018/Reflection_Models/Microfacet_Models
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OrenNayar {
+    pub albedo: Color,      // base color
+    pub emission: Color,    // emitted light
+    pub roughness: Float,     // standard deviation of the facet slope angle, in radians (0 = lambertian)
+}
+impl OrenNayar {
+    // value of the brdf for a pair of local space directions
+    fn eval_local(&self, wo: Vec3, wi: Vec3) -> Color {
+        if wo.z <= 0.0 || wi.z <= 0.0 { return Color::zero() }
+        let sigma2 = self.roughness*self.roughness;
+        let a = 1.0 - sigma2 / (2.0*(sigma2 + 0.33));
+        let b = 0.45*sigma2 / (sigma2 + 0.09);
+        // cosine of the azimuthal angle between the two directions
+        let sin_i = Float::sqrt(Float::max(0.0, 1.0 - wi.z*wi.z));
+        let sin_o = Float::sqrt(Float::max(0.0, 1.0 - wo.z*wo.z));
+        let cos_phi_diff = if sin_i > 0.0001 && sin_o > 0.0001 {
+            Float::max(0.0, (wi.x*wo.x + wi.y*wo.y) / (sin_i*sin_o))
+        } else { 0.0 };
+        // alpha = max(theta_i, theta_o), beta = min(theta_i, theta_o)
+        let (sin_alpha, tan_beta) = if wi.z > wo.z {
+            (sin_o, sin_i / wi.z)
+        } else {
+            (sin_i, sin_o / wo.z)
+        };
+        self.albedo / PI * (a + b*cos_phi_diff*sin_alpha*tan_beta)
+    }
+}
+impl Material for OrenNayar {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(-ray.direction.normalize());
+        let wi = sample_cosine_hemisphere(rand_vec2());
+        (
+            Ray {
+                origin: hit.hitpoint,
+                direction: frame.to_world(wi),
+                time: ray.time,
+            },
+            self.eval_local(wo, wi),
+            Float::max(cosine_hemisphere_pdf(wi.z), 0.0001),
+            BounceKind::Diffuse,
         )
     }
-    fn emission(&self) -> Color {
+    fn emission(&self, _hit: &RayHit) -> Color {
         self.emission
     }
+    fn albedo(&self, _hit: &RayHit) -> Color {
+        self.albedo
+    }
+    fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Option<(Color, Float)> {
+        let frame = Frame::new(hit.normal);
+        let wi = frame.to_local(wi);
+        Some((self.eval_local(frame.to_local(wo), wi), cosine_hemisphere_pdf(wi.z)))
+    }
+    fn is_diffuse(&self, _hit: &RayHit) -> bool {
+        true
+    }
 }
 
 // METAL
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Metal {
     pub albedo: Color,  // base color
     pub emission: Color,// emitted light
-    pub roughness: f32, // models microfacets that cause a glossy look
+    pub roughness: Float, // models microfacets that cause a glossy look
 }
 impl Material for Metal {
-    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, f32) {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
         (
             // metals reflect about normal
             Ray {
                 origin: hit.hitpoint,
-                direction: reflect(&ray.direction, &hit.normal) + self.roughness*rand_sphere_vec(),
+                direction: reflect(&ray.direction, &hit.normal) + Float::max(self.roughness, hit.min_roughness)*sample_uniform_ball(rand_vec2(), rand_f32()),
+                time: ray.time,
             },
             self.albedo,
-            1.0
+            1.0,
+            BounceKind::Glossy,
         )
     }
-    fn emission(&self) -> Color {
+    fn emission(&self, _hit: &RayHit) -> Color {
         self.emission
     }
+    fn albedo(&self, _hit: &RayHit) -> Color {
+        self.albedo
+    }
+}
+
+// CONDUCTOR - a metal described by its complex index of refraction (eta + i*k) per color channel,
+// so its colored reflectance comes from the exact fresnel equations rather than an albedo
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Conductor {
+    pub eta: Color,
+    pub k: Color,
+    pub roughness: Float,
+}
+impl Conductor {
+    // presets, using measured values sampled at roughly 650nm, 550nm and 450nm
+    pub fn gold(roughness: Float) -> Conductor {
+        Conductor { eta: vec3(0.143, 0.374, 1.442), k: vec3(3.983, 2.385, 1.603), roughness }
+    }
+    pub fn copper(roughness: Float) -> Conductor {
+        Conductor { eta: vec3(0.200, 0.924, 1.102), k: vec3(3.912, 2.452, 2.142), roughness }
+    }
+    pub fn aluminum(roughness: Float) -> Conductor {
+        Conductor { eta: vec3(1.657, 0.880, 0.521), k: vec3(9.224, 6.270, 4.837), roughness }
+    }
+    pub fn silver(roughness: Float) -> Conductor {
+        Conductor { eta: vec3(0.155, 0.117, 0.138), k: vec3(4.828, 3.122, 2.147), roughness }
+    }
+    // value of the brdf for a pair of local space directions
+    fn eval_local(&self, wo: Vec3, wi: Vec3, alpha: Float) -> Color {
+        if wo.z <= 0.0 || wi.z <= 0.0 { return Color::zero() }
+        let h = (wo + wi).normalize();
+        fresnel_conductor(wo.dot(h), self.eta, self.k) * ggx_d(h, alpha) * ggx_g2(wo, wi, alpha) / (4.0*wo.z*wi.z)
+    }
+}
+impl Material for Conductor {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        // reflect about a microfacet normal sampled from the visible GGX normals
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(-ray.direction.normalize());
+        let wo = vec3(wo.x, wo.y, Float::max(wo.z, 0.0001)).normalize();
+        let alpha = ggx_alpha(Float::max(self.roughness, hit.min_roughness));
+        let h = ggx_sample_vndf(wo, alpha, rand_vec2());
+        let wi = 2.0*wo.dot(h)*h - wo;
+        (
+            Ray {
+                origin: hit.hitpoint,
+                direction: frame.to_world(wi),
+                time: ray.time,
+            },
+            self.eval_local(wo, wi, alpha),
+            Float::max(ggx_reflection_pdf(wo, wi, alpha), 0.0001),
+            BounceKind::Glossy,
+        )
+    }
+    fn emission(&self, _hit: &RayHit) -> Color {
+        Vec3::zero()
+    }
+    fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Option<(Color, Float)> {
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(wo);
+        let wo = vec3(wo.x, wo.y, Float::max(wo.z, 0.0001)).normalize();
+        let wi = frame.to_local(wi);
+        let alpha = ggx_alpha(Float::max(self.roughness, hit.min_roughness));
+        Some((self.eval_local(wo, wi, alpha), ggx_reflection_pdf(wo, wi, alpha)))
+    }
 }
 
 // DIELECTRIC
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Dielectric {
-    pub idx_of_refraction: f32,
+    pub idx_of_refraction: Float,
+    pub dispersion: Option<Dispersion>, // index of refraction for each wavelength instead, to split light into rainbows
 }
 impl Material for Dielectric {
-    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, f32) {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        // with dispersion, each color channel bends by its own wavelength's index, so the path carries on with just one
+        // channel, picked at random (and weighted up by the odds of picking it) the first time it meets dispersion
+        let (idx_of_refraction, tint) = match &self.dispersion {
+            None => (self.idx_of_refraction, vec3(1.0,1.0,1.0)),
+            Some(dispersion) => {
+                let (channel, just_picked) = dispersion_channel();
+                let mut tint = Color::zero();
+                tint[channel] = if just_picked { 3.0 } else { 1.0 };
+                (dispersion.idx_of_refraction(RGB_WAVELENGTHS[channel]), tint)
+            }
+        };
         // index of refraction ratio depends on whether we're entering or leaving the object
-        let eta = if hit.frontface {1.0/self.idx_of_refraction} else {self.idx_of_refraction};
-        let critical_angle = eta*f32::sqrt(1.0-f32::min(-ray.direction.dot(hit.normal), 1.0).powi(2)) > 1.0;
-        let fresnel_factor = fresnel(&ray.direction, &hit.normal, self.idx_of_refraction);
+        let eta = if hit.frontface {1.0/idx_of_refraction} else {idx_of_refraction};
+        // when regularized, bend the ray about a microfacet normal instead, as if the glass were a little rough
+        let normal = if hit.min_roughness > 0.0 {
+            let frame = Frame::new(hit.normal);
+            let wo = frame.to_local(-ray.direction.normalize());
+            let wo = vec3(wo.x, wo.y, Float::max(wo.z, 0.0001)).normalize();
+            frame.to_world(ggx_sample_vndf(wo, ggx_alpha(hit.min_roughness), rand_vec2()))
+        } else { hit.normal };
+        let critical_angle = eta*Float::sqrt(1.0-Float::min(-ray.direction.dot(normal), 1.0).powi(2)) > 1.0;
+        let fresnel_factor = fresnel(&ray.direction, &normal, idx_of_refraction);
         // if angle is less than critical, then refract with probability according to fresnel coefficient (proportion of reflected/transmitted light)
-        let will_refract = !critical_angle && rand::thread_rng().gen_range(0.0..1.0) >= fresnel_factor;
+        let will_refract = !critical_angle && rand_f32() >= fresnel_factor;
         let new_dir = if will_refract {
-            refract(&ray.direction, &hit.normal, eta)
+            refract(&ray.direction, &normal, eta)
         }
         else {
-            reflect(&ray.direction, &hit.normal)
+            reflect(&ray.direction, &normal)
         };
         
         (
             Ray {
                 origin: hit.hitpoint,
-                direction: new_dir
+                direction: new_dir,
+                time: ray.time,
             },
-            vec3(1.0,1.0,1.0),
-            1.0
+            tint,
+            1.0,
+            if will_refract { BounceKind::Transmission } else { BounceKind::Glossy },
         )
     }
-    fn emission(&self) -> Color {
+    fn emission(&self, _hit: &RayHit) -> Color {
         Vec3::zero()    // dielectrics generally don't emit light
     }
 }
+// wavelengths (in micrometers) each color channel stands for when light is split up by dispersion
+pub const RGB_WAVELENGTHS: [Float; 3] = [0.61, 0.55, 0.465];
+// how a dielectric's index of refraction changes with wavelength (in micrometers), from fits to measurements of the
+// material. shorter wavelengths bend more, so white light fans out into a rainbow
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Dispersion {
+    Cauchy { a: Float, b: Float },              // n = a + b/wavelength^2 (b in um^2)
+    Sellmeier { b: [Float; 3], c: [Float; 3] }, // n^2 = 1 + sum of b*wavelength^2/(wavelength^2 - c) (c in um^2)
+}
+impl Dispersion {
+    // schott n-bk7, the usual crown glass for lenses and prisms (n = 1.517 for yellow light)
+    pub fn bk7() -> Dispersion {
+        Dispersion::Sellmeier { b: [1.039612, 0.2317923, 1.010469], c: [0.006000699, 0.02001791, 103.5607] }
+    }
+    // dense flint glass (schott sf11), which disperses light about three times as much as bk7
+    pub fn flint() -> Dispersion {
+        Dispersion::Sellmeier { b: [1.737596, 0.3136747, 1.898781], c: [0.01318871, 0.06230686, 155.2363] }
+    }
+    // diamond, which bends light a lot and spreads it out into its "fire"
+    pub fn diamond() -> Dispersion {
+        Dispersion::Sellmeier { b: [0.3306, 4.3356, 0.0], c: [0.030625, 0.011236, 0.0] }
+    }
+    pub fn idx_of_refraction(&self, wavelength: Float) -> Float {
+        let l2 = wavelength*wavelength;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b/l2,
+            Dispersion::Sellmeier { b, c } => Float::sqrt(1.0 + (0..3).map(|i| b[i]*l2 / (l2 - c[i])).sum::<Float>()),
+        }
+    }
+}
 
 // Represents a material that can be parameterized by standard textures
+// diffuse base plus a GGX specular lobe, blended by metallic (metals have no diffuse and tint their reflections),
+// optionally under a clear lacquer layer with its own specular lobe (car paint, varnished wood),
+// and with a sheen lobe for the soft grazing highlights of cloth
 pub struct ParameterizedMaterial {
-    pub albedo: Color,
-    pub emission: Color,
-    pub roughness: f32,
-    pub metallic: f32,
+    pub albedo: Arc<dyn Texture + Send + Sync>,
+    pub emission: Arc<dyn Texture + Send + Sync>,
+    pub roughness: Arc<dyn Texture + Send + Sync>,     // scalar textures use their red channel
+    pub metallic: Arc<dyn Texture + Send + Sync>,
+    pub clearcoat: Float,             // strength of the clear coat layer (0 = no coat)
+    pub clearcoat_roughness: Float,   // roughness of the clear coat's own reflection
+    pub sheen: Float,                 // strength of the sheen lobe (0 = no sheen)
+    pub sheen_tint: Float,            // tints the sheen from white towards the albedo
+}
+impl Default for ParameterizedMaterial {
+    fn default() -> ParameterizedMaterial {
+        ParameterizedMaterial {
+            albedo: solid_color(vec3(1.0,1.0,1.0)),
+            emission: solid_color(Vec3::zero()),
+            roughness: solid_value(1.0),
+            metallic: solid_value(0.0),
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.0,
+        }
+    }
+}
+const CLEARCOAT_F0: Float = 0.04; // clear coat is treated as a dielectric with an ior of 1.5
+// a ParameterizedMaterial's parameters evaluated at one point on a surface
+struct ParameterizedSurface {
+    albedo: Color,
+    roughness: Float,
+    metallic: Float,
+    clearcoat: Float,
+    clearcoat_roughness: Float,
+    sheen: Float,
+    sheen_tint: Float,
+}
+impl ParameterizedMaterial {
+    fn at(&self, hit: &RayHit) -> ParameterizedSurface {
+        let uv = hit.tex_coords.unwrap_or(Vec2::zero());
+        ParameterizedSurface {
+            albedo: self.albedo.sample_footprint(uv, hit.hitpoint, hit.uv_footprint),
+            roughness: Float::max(self.roughness.sample_footprint(uv, hit.hitpoint, hit.uv_footprint).x, hit.min_roughness),
+            metallic: self.metallic.sample_footprint(uv, hit.hitpoint, hit.uv_footprint).x,
+            clearcoat: self.clearcoat,
+            clearcoat_roughness: Float::max(self.clearcoat_roughness, hit.min_roughness),
+            sheen: self.sheen,
+            sheen_tint: self.sheen_tint,
+        }
+    }
+}
+impl ParameterizedSurface {
+    // reflectance at normal incidence - dielectrics reflect ~4% uncolored, metals reflect their albedo
+    fn f0(&self) -> Color {
+        lerpvec(vec3(0.04, 0.04, 0.04), self.albedo, self.metallic)
+    }
+    // fraction of light the clear coat reflects at a given angle
+    fn coat_fresnel(&self, cos_theta: Float) -> Float {
+        self.clearcoat*fresnel_schlick(vec3(CLEARCOAT_F0, CLEARCOAT_F0, CLEARCOAT_F0), cos_theta).x
+    }
+    fn sheen_color(&self) -> Color {
+        self.sheen*lerpvec(vec3(1.0,1.0,1.0), self.albedo, self.sheen_tint)
+    }
+    // probabilities of sampling the (clear coat, base specular, diffuse) lobes, based on how much each contributes
+    // (the sheen lobe is broad enough that it's sampled along with the diffuse lobe)
+    fn lobe_probabilities(&self, cos_o: Float) -> (Float, Float, Float) {
+        let coat_weight = self.coat_fresnel(cos_o);
+        let spec_weight = (1.0-coat_weight)*Float::max(luminance(fresnel_schlick(self.f0(), cos_o)), 0.05);
+        let diffuse_weight = (1.0-coat_weight)*((1.0-self.metallic)*luminance(self.albedo)*(1.0-spec_weight) + luminance(self.sheen_color()));
+        let total = coat_weight + spec_weight + diffuse_weight;
+        (coat_weight/total, spec_weight/total, diffuse_weight/total)
+    }
+    // value of the brdf for a pair of local space directions
+    fn eval_local(&self, wo: Vec3, wi: Vec3) -> Color {
+        if wo.z <= 0.0 || wi.z <= 0.0 { return Color::zero() }
+        let alpha = ggx_alpha(self.roughness);
+        let h = (wo + wi).normalize();
+        let fresnel = fresnel_schlick(self.f0(), wo.dot(h));
+        let specular = fresnel * ggx_d(h, alpha) * ggx_g2(wo, wi, alpha) / (4.0*wo.z*wi.z);
+        let mut diffuse = (1.0-self.metallic) * (1.0-luminance(fresnel_schlick(self.f0(), wo.z))) * self.albedo / PI;
+        if self.sheen > 0.0 {
+            diffuse += self.sheen_color() * charlie_brdf(wo, wi, self.roughness);
+        }
+        if self.clearcoat <= 0.0 { return specular + diffuse }
+        // the coat reflects some light itself and the rest reaches the base
+        let coat_alpha = ggx_alpha(self.clearcoat_roughness);
+        let coat = self.coat_fresnel(wo.dot(h)) * ggx_d(h, coat_alpha) * ggx_g2(wo, wi, coat_alpha) / (4.0*wo.z*wi.z);
+        let base_transmission = (1.0-self.coat_fresnel(wo.z)) * (1.0-self.coat_fresnel(wi.z));
+        vec3(coat, coat, coat) + base_transmission*(specular + diffuse)
+    }
+    // pdf of sampling wi given wo, accounting for every lobe that could have produced it
+    fn pdf_local(&self, wo: Vec3, wi: Vec3) -> Float {
+        let (p_coat, p_spec, p_diffuse) = self.lobe_probabilities(wo.z);
+        let mut pdf = p_spec*ggx_reflection_pdf(wo, wi, ggx_alpha(self.roughness));
+        pdf += p_diffuse*cosine_hemisphere_pdf(wi.z);
+        if p_coat > 0.0 { pdf += p_coat*ggx_reflection_pdf(wo, wi, ggx_alpha(self.clearcoat_roughness)); }
+        pdf
+    }
 }
 impl Material for ParameterizedMaterial {
-    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, f32) {
-        // based on https://typhomnt.github.io/teaching/ray_tracing/pbr_intro/
-        let fresnel = fresnel(&ray.direction, &hit.normal, 1.5);
-        let k_s = fresnel*(1.0-self.roughness);     // proportion of specular reflected light
-        let k_d = (1.0-k_s)*(1.0-self.metallic);    // proportion of diffusely reflected light
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        // pick one lobe to sample, then weight by the pdf of the whole mixture
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(-ray.direction.normalize());
+        let wo = vec3(wo.x, wo.y, Float::max(wo.z, 0.0001)).normalize();
+        let surface = self.at(hit);
+        let (p_coat, p_spec, _) = surface.lobe_probabilities(wo.z);
 
-        if rand::thread_rng().gen_range(0.0..1.0) < k_d {
+        let u = rand_f32();
+        let kind = if u < p_coat + p_spec { BounceKind::Glossy } else { BounceKind::Diffuse };
+        let wi = if u < p_coat + p_spec {
+            // specular (coat or base) - reflect about a microfacet normal sampled from the visible normal distribution
+            let alpha = if u < p_coat { ggx_alpha(surface.clearcoat_roughness) } else { ggx_alpha(surface.roughness) };
+            let h = ggx_sample_vndf(wo, alpha, rand_vec2());
+            2.0*wo.dot(h)*h - wo
+        }
+        else {
             // diffuse
-            let (dir, pdf) = sample_hemisphere(hit);
-            (
-                Ray {
-                    origin: hit.hitpoint,
-                    direction: dir,
-                },
-                self.albedo / PI,
-                pdf,
+            sample_cosine_hemisphere(rand_vec2())
+        };
+        (
+            Ray {
+                origin: hit.hitpoint,
+                direction: frame.to_world(wi),
+                time: ray.time,
+            },
+            surface.eval_local(wo, wi),
+            Float::max(surface.pdf_local(wo, wi), 0.0001),
+            kind,
+        )
+    }
+    fn emission(&self, hit: &RayHit) -> Color {
+        self.emission.sample_footprint(hit.tex_coords.unwrap_or(Vec2::zero()), hit.hitpoint, hit.uv_footprint)
+    }
+    fn albedo(&self, hit: &RayHit) -> Color {
+        self.at(hit).albedo
+    }
+    fn is_diffuse(&self, hit: &RayHit) -> bool {
+        self.at(hit).metallic < 1.0
+    }
+    fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Option<(Color, Float)> {
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(wo);
+        let wo = vec3(wo.x, wo.y, Float::max(wo.z, 0.0001)).normalize();
+        let wi = frame.to_local(wi);
+        let surface = self.at(hit);
+        Some((surface.eval_local(wo, wi), surface.pdf_local(wo, wi)))
+    }
+}
+
+// Car paint - a colored base with sparkling metallic flakes, under a glossy clear coat.
+// flakes are small cells in world space; each one has a random chance of holding a flake,
+// and each flake is a tiny tilted mirror with its own normal, so they glint individually
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CarPaint {
+    pub base_color: Color,
+    pub flake_color: Color,         // reflectance of the flakes at normal incidence
+    pub flake_density: Float,         // fraction of cells that hold a flake
+    pub flake_scale: Float,           // cells per unit of world space (larger = smaller flakes)
+    pub flake_tilt: Float,            // how far flake normals can tilt from the surface normal, in radians
+    pub flake_roughness: Float,
+    pub clearcoat: Float,
+    pub clearcoat_roughness: Float,
+}
+impl Default for CarPaint {
+    fn default() -> CarPaint {
+        CarPaint {
+            base_color: vec3(0.5,0.02,0.02),
+            flake_color: vec3(0.9,0.9,0.9),
+            flake_density: 0.3,
+            flake_scale: 200.0,
+            flake_tilt: 0.3,
+            flake_roughness: 0.15,
+            clearcoat: 1.0,
+            clearcoat_roughness: 0.03,
+        }
+    }
+}
+impl CarPaint {
+    // normal of the flake under the hit point, if there is one
+    fn flake_normal(&self, hit: &RayHit) -> Option<Vec3> {
+        let cell = (hit.hitpoint*self.flake_scale).map(|c| c.floor() as i32);
+        let h = hash3(cell.x, cell.y, cell.z);
+        if hash_to_float(h) >= self.flake_density { return None }
+        // tilt the normal by a random angle in a random direction
+        let tilt = self.flake_tilt*hash_to_float(hash3(h as i32, 1, 0)).sqrt();
+        let phi = 2.0*PI*hash_to_float(hash3(h as i32, 2, 0));
+        let frame = Frame::new(hit.normal);
+        Some(frame.to_world(vec3(tilt.sin()*phi.cos(), tilt.sin()*phi.sin(), tilt.cos())))
+    }
+}
+impl Material for CarPaint {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        // each layer is picked with exactly its share of the reflected light, so its own sample can be returned unchanged
+        let view = -ray.direction.normalize();
+        let coat_weight = self.clearcoat*fresnel_schlick(vec3(CLEARCOAT_F0, CLEARCOAT_F0, CLEARCOAT_F0), view.dot(hit.normal)).x;
+
+        // clear coat, or a flake
+        let specular = if rand_f32() < coat_weight {
+            // the coat's fresnel is already accounted for by how often it's picked
+            Some((hit.normal, None, self.clearcoat_roughness))
+        } else {
+            self.flake_normal(hit).filter(|n| n.dot(view) > 0.0).map(|n| (n, Some(self.flake_color), self.flake_roughness))
+        };
+        if let Some((normal, f0, roughness)) = specular {
+            let frame = Frame::new(normal);
+            let wo = frame.to_local(view);
+            let alpha = ggx_alpha(Float::max(roughness, hit.min_roughness));
+            let h = ggx_sample_vndf(wo, alpha, rand_vec2());
+            let wi = 2.0*wo.dot(h)*h - wo;
+            let direction = frame.to_world(wi);
+            let fresnel = f0.map_or(vec3(1.0,1.0,1.0), |f0| fresnel_schlick(f0, wo.dot(h)));
+            let brdf = if wi.z > 0.0 && direction.dot(hit.normal) > 0.0 {
+                fresnel * ggx_d(h, alpha) * ggx_g2(wo, wi, alpha) / (4.0*wo.z*wi.z)
+            } else { Color::zero() };
+            // the integrator uses the cosine with the surface normal, so swap it for the one with the facet's normal
+            let cos_ratio = wi.z / Float::max(direction.dot(hit.normal).abs(), 0.0001);
+            return (
+                Ray { origin: hit.hitpoint, direction, time: ray.time },
+                brdf*cos_ratio,
+                Float::max(ggx_reflection_pdf(wo, wi, alpha), 0.0001),
+                BounceKind::Glossy,
             )
         }
+
+        // paint base
+        let wi = sample_cosine_hemisphere(rand_vec2());
+        (Ray { origin: hit.hitpoint, direction: Frame::new(hit.normal).to_world(wi), time: ray.time }, self.base_color / PI, Float::max(cosine_hemisphere_pdf(wi.z), 0.0001), BounceKind::Diffuse)
+    }
+    fn emission(&self, _hit: &RayHit) -> Color {
+        Vec3::zero()
+    }
+    fn albedo(&self, _hit: &RayHit) -> Color {
+        self.base_color
+    }
+}
+
+// Velvet - only a sheen lobe, for cloth lit mostly by light grazing its fibers
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Velvet {
+    pub albedo: Color,
+    pub roughness: Float,
+}
+impl Material for Velvet {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        // the lobe is spread over the whole hemisphere, so uniform sampling works well
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(-ray.direction.normalize());
+        let wo = vec3(wo.x, wo.y, Float::max(wo.z, 0.0001)).normalize();
+        let wi = sample_uniform_hemisphere(rand_vec2());
+        (
+            Ray {
+                origin: hit.hitpoint,
+                direction: frame.to_world(wi),
+                time: ray.time,
+            },
+            self.albedo * charlie_brdf(wo, wi, self.roughness),
+            uniform_hemisphere_pdf(),
+            BounceKind::Diffuse,
+        )
+    }
+    fn emission(&self, _hit: &RayHit) -> Color {
+        Vec3::zero()
+    }
+    fn albedo(&self, _hit: &RayHit) -> Color {
+        self.albedo
+    }
+    // (the sheen lobe is spread wide enough to gather photons on)
+    fn is_diffuse(&self, _hit: &RayHit) -> bool {
+        true
+    }
+    fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Option<(Color, Float)> {
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(wo);
+        let wo = vec3(wo.x, wo.y, Float::max(wo.z, 0.0001)).normalize();
+        let wi = frame.to_local(wi);
+        if wi.z <= 0.0 { return Some((Color::zero(), 0.0)) }
+        Some((self.albedo * charlie_brdf(wo, wi, self.roughness), uniform_hemisphere_pdf()))
+    }
+}
+
+// Cloth - woven fabric as two sets of tiny cylindrical threads (warp along the surface tangent, weft across it)
+// each thread reflects off its surface in a cone around its axis, and scatters light that enters it with its color
+// from Sadeghi et al. 2013, "A Practical Microcylinder Appearance Model for Cloth Rendering"
+#[derive(Clone)]
+pub struct ThreadParams {
+    pub albedo: Color,              // color of light scattered inside the thread
+    pub ior: Float,
+    pub specular_width: Float,        // width of the surface reflection lobe, in degrees
+    pub volume_width: Float,          // width of the forward scattering lobe, in degrees
+    pub diffuse: Float,               // how much of the internally scattered light is fully diffuse
+    pub coverage: Float,              // fraction of the cloth's surface covered by this thread set
+    pub tangent_offsets: Vec<Float>,  // how far the thread tilts out of the surface along its length, in degrees (models the weave)
+}
+pub struct Cloth {
+    warp: ThreadParams,
+    weft: ThreadParams,
+    // the model isn't energy conserving, so reflection is scaled down towards view directions where white threads
+    // would reflect more light than they receive (indexed by view elevation and azimuth from the warp)
+    normalization: Vec<Float>,
+}
+impl Cloth {
+    const NORMALIZATION_COS_BINS: usize = 8;
+    const NORMALIZATION_PHI_BINS: usize = 32;
+    pub fn new(warp: ThreadParams, weft: ThreadParams) -> Cloth {
+        let mut cloth = Cloth { warp, weft, normalization: Vec::new() };
+        let white = Cloth {
+            warp: ThreadParams { albedo: vec3(1.0,1.0,1.0), ..cloth.warp.clone() },
+            weft: ThreadParams { albedo: vec3(1.0,1.0,1.0), ..cloth.weft.clone() },
+            normalization: Vec::new(),
+        };
+        // integrate the reflected light over a stratified grid of incoming directions, for each view direction in the table
+        const SAMPLES: usize = 32;
+        let spherical_dir = |cos: Float, phi: Float| {
+            let sin = Float::sqrt(1.0 - cos*cos);
+            vec3(sin*phi.cos(), sin*phi.sin(), cos)
+        };
+        for i in 0..Cloth::NORMALIZATION_COS_BINS {
+            for j in 0..Cloth::NORMALIZATION_PHI_BINS {
+                let cos_o = (i as Float + 0.5) / Cloth::NORMALIZATION_COS_BINS as Float;
+                let phi_o = 2.0*PI*j as Float / Cloth::NORMALIZATION_PHI_BINS as Float;
+                let wo = spherical_dir(cos_o, phi_o);
+                let mut albedo = 0.0;
+                for a in 0..SAMPLES {
+                    for b in 0..SAMPLES {
+                        let wi = spherical_dir((a as Float + 0.5) / SAMPLES as Float, 2.0*PI*(b as Float + 0.5) / SAMPLES as Float);
+                        // (eval includes the cosine, and directions are uniform over the hemisphere)
+                        let reflected = white.eval_weave(wo, wi, Vec3::unit_z(), Vec3::unit_x());
+                        albedo += 2.0*PI*reflected.x.max(reflected.y).max(reflected.z) / (SAMPLES*SAMPLES) as Float;
+                    }
+                }
+                cloth.normalization.push(1.0 / Float::max(albedo, 1.0));
+            }
+        }
+        cloth
+    }
+    // bilinear table lookup (azimuths wrap around)
+    fn normalization_at(&self, cos_o: Float, phi_o: Float) -> Float {
+        let (cos_bins, phi_bins) = (Cloth::NORMALIZATION_COS_BINS, Cloth::NORMALIZATION_PHI_BINS);
+        let x = (cos_o.clamp(0.0, 1.0)*cos_bins as Float - 0.5).clamp(0.0, (cos_bins-1) as Float);
+        let y = phi_o.rem_euclid(2.0*PI) / (2.0*PI) * phi_bins as Float;
+        let (i, tx) = (x.floor() as usize, x.fract());
+        let (j, ty) = (y.floor() as usize % phi_bins, y.fract());
+        let (i1, j1) = (usize::min(i+1, cos_bins-1), (j+1) % phi_bins);
+        let at = |i: usize, j: usize| self.normalization[i*phi_bins + j];
+        (at(i, j)*(1.0-ty) + at(i, j1)*ty)*(1.0-tx) + (at(i1, j)*(1.0-ty) + at(i1, j1)*ty)*tx
+    }
+    // presets roughly following the paper's fitted fabrics, with the warp thread color swapped in
+    pub fn denim(color: Color) -> Cloth {
+        // warp-faced twill - colored warp threads over undyed weft threads
+        Cloth::new(
+            ThreadParams { albedo: color, ior: 1.46, specular_width: 12.0, volume_width: 24.0, diffuse: 0.3, coverage: 0.67, tangent_offsets: vec![-30.0, -10.0, 10.0, 30.0] },
+            ThreadParams { albedo: vec3(0.8,0.8,0.75), ior: 1.46, specular_width: 18.0, volume_width: 36.0, diffuse: 0.3, coverage: 0.33, tangent_offsets: vec![-25.0, 25.0] },
+        )
+    }
+    pub fn silk(color: Color) -> Cloth {
+        // crepe de chine - very smooth filaments with tight highlights
+        Cloth::new(
+            ThreadParams { albedo: color, ior: 1.345, specular_width: 5.0, volume_width: 10.0, diffuse: 0.1, coverage: 0.75, tangent_offsets: vec![-25.0, 25.0] },
+            ThreadParams { albedo: color, ior: 1.345, specular_width: 18.0, volume_width: 32.0, diffuse: 0.1, coverage: 0.25, tangent_offsets: vec![0.0, 0.0] },
+        )
+    }
+    pub fn satin(color: Color) -> Cloth {
+        // charmeuse - long floating warp threads give the anisotropic sheen
+        Cloth::new(
+            ThreadParams { albedo: color, ior: 1.539, specular_width: 2.5, volume_width: 5.0, diffuse: 0.1, coverage: 0.9, tangent_offsets: vec![-32.0, -32.0, -18.0, 0.0, 0.0, 18.0, 32.0, 32.0] },
+            ThreadParams { albedo: color, ior: 1.539, specular_width: 30.0, volume_width: 60.0, diffuse: 0.1, coverage: 0.1, tangent_offsets: vec![0.0, 0.0] },
+        )
+    }
+    // brdf of the whole weave for world space directions. each thread's reflection is weighted by how much of it is
+    // both lit and visible, then normalized by how much thread area the viewer sees in total (paper eqs. 12-14)
+    fn eval_weave(&self, wo: Vec3, wi: Vec3, normal: Vec3, tangent: Vec3) -> Color {
+        let bitangent = normal.cross(tangent);
+        let mut reflected = Color::zero();
+        let mut visible_area = 0.0;
+        for (threads, direction) in [(&self.warp, tangent), (&self.weft, bitangent)] {
+            let weight = threads.coverage / Float::max(threads.tangent_offsets.len() as Float, 1.0);
+            for offset in threads.tangent_offsets.iter() {
+                let (sin_off, cos_off) = offset.to_radians().sin_cos();
+                let (thread_reflected, thread_area) = Cloth::eval_thread(threads, wo, wi, normal, cos_off*direction + sin_off*normal);
+                reflected += weight*thread_reflected;
+                visible_area += weight*thread_area;
+            }
+        }
+        if visible_area <= 0.0 { return Color::zero() }
+        reflected / visible_area
+    }
+    // reflected light from one thread direction t per unit of incoming light, and the thread area visible from wo
+    fn eval_thread(threads: &ThreadParams, wo: Vec3, wi: Vec3, normal: Vec3, t: Vec3) -> (Color, Float) {
+        // longitudinal angles (from the plane perpendicular to the thread) and the azimuth between the directions around it
+        let theta_i = wi.dot(t).clamp(-1.0, 1.0).asin();
+        let theta_o = wo.dot(t).clamp(-1.0, 1.0).asin();
+        // (the azimuth is undefined for directions along the thread, which only see its surface edge-on)
+        let (wi_perp, wo_perp, n_perp) = (wi - wi.dot(t)*t, wo - wo.dot(t)*t, normal - normal.dot(t)*t);
+        if wi_perp.magnitude2() < 1e-8 || wo_perp.magnitude2() < 1e-8 { return (Color::zero(), 0.0) }
+        let (wi_perp, wo_perp, n_perp) = (wi_perp.normalize(), wo_perp.normalize(), n_perp.normalize());
+        let phi_d = wi_perp.dot(wo_perp).clamp(-1.0, 1.0).acos();
+        let theta_h = 0.5*(theta_i + theta_o);
+        let cos_d = (0.5*(theta_o - theta_i)).cos();
+
+        let r0 = ((1.0-threads.ior)/(1.0+threads.ior)).powi(2);
+        let fresnel = |cos: Float| r0 + (1.0-r0)*schlick_weight(cos);
+        let gaussian = |width_deg: Float, x: Float| {
+            let width = width_deg.to_radians();
+            Float::exp(-0.5*(x/width).powi(2)) / (Float::sqrt(2.0*PI)*width)
+        };
+        // reflection off the thread's surface
+        let surface = fresnel(cos_d*(0.5*phi_d).cos()) * (0.5*phi_d).cos() * gaussian(threads.specular_width, theta_h);
+        // light refracted into the thread, scattered, and refracted back out
+        let transmission = (1.0-fresnel(theta_i.cos())) * (1.0-fresnel(theta_o.cos()));
+        let volume = transmission * ((1.0-threads.diffuse)*gaussian(threads.volume_width, theta_h) + threads.diffuse) / (theta_i.cos() + theta_o.cos());
+        let f = (vec3(surface, surface, surface) + volume*threads.albedo) / Float::max(cos_d*cos_d, 0.0001);
+
+        // shadowing and masking by the thread itself, blended towards the shared part when the directions line up
+        let (m_i, m_o) = (Float::max(wi_perp.dot(n_perp), 0.0), Float::max(wo_perp.dot(n_perp), 0.0));
+        let u = Float::exp(-0.5*(phi_d/Float::to_radians(20.0)).powi(2));
+        let masking = (1.0-u)*m_i*m_o + u*Float::min(m_i, m_o);
+        (f*masking*theta_i.cos(), masking*theta_o.cos())
+    }
+}
+impl Material for Cloth {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        let dir = Frame::new(hit.normal).to_world(sample_uniform_hemisphere(rand_vec2()));
+        let (brdf, _) = self.eval(hit, -ray.direction.normalize(), dir).unwrap();
+        (Ray { origin: hit.hitpoint, direction: dir, time: ray.time }, brdf, uniform_hemisphere_pdf(), BounceKind::Diffuse)
+    }
+    fn emission(&self, _hit: &RayHit) -> Color {
+        Vec3::zero()
+    }
+    fn albedo(&self, _hit: &RayHit) -> Color {
+        self.warp.coverage*self.warp.albedo + self.weft.coverage*self.weft.albedo
+    }
+    fn is_diffuse(&self, _hit: &RayHit) -> bool {
+        true
+    }
+    fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Option<(Color, Float)> {
+        if wo.dot(hit.normal) <= 0.0 || wi.dot(hit.normal) <= 0.0 { return Some((Color::zero(), 0.0)) }
+        // warp runs along the surface tangent and weft across it
+        let tangent = hit.tangent.unwrap_or(Frame::new(hit.normal).tangent);
+        let tangent = (tangent - tangent.dot(hit.normal)*hit.normal).normalize();
+        let bitangent = hit.normal.cross(tangent);
+        let normalization = self.normalization_at(wo.dot(hit.normal), Float::atan2(wo.dot(bitangent), wo.dot(tangent)));
+        // the thread model already includes the incoming cosine (on each thread), so undo the integrator's
+        let brdf = normalization * self.eval_weave(wo, wi, hit.normal, tangent) / Float::max(wi.dot(hit.normal), 0.0001);
+        Some((brdf, uniform_hemisphere_pdf()))
+    }
+}
+
+// Hair - fibers as rough dielectric cylinders with pigment inside, lit by light that reflects off the surface (R),
+// passes straight through (TT), or bounces once off the inside (TRT), with the higher orders lumped together
+// from Chiang et al. 2016, "A Practical and Controllable Hair and Fur Model for Production Path Tracing" (as in pbrt-v3)
+// the fiber runs along the hit's tangent, and where the normal points across it says which side of the fiber was hit
+// (so it works on any surface with tangents along the hair, like strands modeled as thin tubes or cards)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Hair {
+    pub sigma_a: Color,     // absorption inside the fiber, per fiber diameter
+    pub beta_m: Float,        // longitudinal roughness (0-1), how far highlights spread along the fiber
+    pub beta_n: Float,        // azimuthal roughness (0-1), how far they spread around it
+    pub alpha: Float,         // tilt of the cuticle scales in degrees, which shifts the R and TRT highlights apart
+    pub eta: Float,
+}
+impl Hair {
+    const P_MAX: usize = 3;
+    // hair colored by its concentration of brown-black eumelanin and red-yellow pheomelanin
+    // (roughly 0.3 eumelanin = blonde, 1.3 = brown, 8 = black, with a little pheomelanin for reddish tones)
+    pub fn from_melanin(eumelanin: Float, pheomelanin: Float, beta_m: Float, beta_n: Float) -> Hair {
+        Hair {
+            sigma_a: eumelanin*vec3(0.419, 0.697, 1.37) + pheomelanin*vec3(0.187, 0.4, 1.05),
+            ..Hair::from_color(vec3(1.0,1.0,1.0), beta_m, beta_n)
+        }
+    }
+    // absorption that gives hair (or fur) roughly the given color after multiple scattering
+    pub fn from_color(color: Color, beta_m: Float, beta_n: Float) -> Hair {
+        let b = beta_n;
+        let denom = 5.969 - 0.215*b + 2.532*b.powi(2) - 10.73*b.powi(3) + 5.574*b.powi(4) + 0.245*b.powi(5);
+        let channel = |c: Float| (c.clamp(0.0001, 1.0).ln() / denom).powi(2);
+        Hair {
+            sigma_a: vec3(channel(color.x), channel(color.y), channel(color.z)),
+            beta_m,
+            beta_n,
+            alpha: 2.0,
+            eta: 1.55,
+        }
+    }
+    // variance of each lobe's longitudinal scattering (wider for light that has been through the fiber)
+    fn longitudinal_variance(&self) -> [Float; 4] {
+        let v = (0.726*self.beta_m + 0.812*self.beta_m.powi(2) + 3.7*self.beta_m.powi(20)).powi(2);
+        [v, 0.25*v, 4.0*v, 4.0*v]
+    }
+    // logistic scale of the azimuthal scattering
+    fn azimuthal_scale(&self) -> Float {
+        Float::sqrt(PI/8.0)*(0.265*self.beta_n + 1.194*self.beta_n.powi(2) + 5.372*self.beta_n.powi(22))
+    }
+    // each lobe's direction is tilted by the scales (R by -2 alpha, TT by alpha, TRT by 4 alpha) as (sin, cos) pairs
+    fn tilted(&self, p: usize, sin_theta_o: Float, cos_theta_o: Float) -> (Float, Float) {
+        let (sin_a, cos_a) = self.alpha.to_radians().sin_cos();
+        let (sin_2a, cos_2a) = (2.0*cos_a*sin_a, cos_a*cos_a - sin_a*sin_a);
+        let (sin_4a, cos_4a) = (2.0*cos_2a*sin_2a, cos_2a*cos_2a - sin_2a*sin_2a);
+        let (sin_op, cos_op) = match p {
+            0 => (sin_theta_o*cos_2a - cos_theta_o*sin_2a, cos_theta_o*cos_2a + sin_theta_o*sin_2a),
+            1 => (sin_theta_o*cos_a + cos_theta_o*sin_a, cos_theta_o*cos_a - sin_theta_o*sin_a),
+            2 => (sin_theta_o*cos_4a + cos_theta_o*sin_4a, cos_theta_o*cos_4a - sin_theta_o*sin_4a),
+            _ => (sin_theta_o, cos_theta_o),
+        };
+        (sin_op, cos_op.abs())
+    }
+    // per-lobe attenuation, plus the angle of the refracted ray inside the fiber (gamma_t)
+    fn attenuation(&self, cos_theta_o: Float, sin_theta_o: Float, h: Float) -> ([Color; 4], Float) {
+        let sin_theta_t = sin_theta_o/self.eta;
+        let cos_theta_t = Float::sqrt(Float::max(1.0 - sin_theta_t*sin_theta_t, 0.0));
+        // the fiber's cross section as seen by the refracted ray behaves like a circle with a modified index
+        let eta_p = Float::sqrt(Float::max(self.eta*self.eta - sin_theta_o*sin_theta_o, 0.0)) / Float::max(cos_theta_o, 0.0001);
+        let sin_gamma_t = (h/eta_p).clamp(-1.0, 1.0);
+        let cos_gamma_t = Float::sqrt(1.0 - sin_gamma_t*sin_gamma_t);
+        // transmittance of one pass through the fiber
+        let path = 2.0*cos_gamma_t/Float::max(cos_theta_t, 0.0001);
+        let t = vec3(Float::exp(-self.sigma_a.x*path), Float::exp(-self.sigma_a.y*path), Float::exp(-self.sigma_a.z*path));
+
+        let cos_gamma_o = Float::sqrt(Float::max(1.0 - h*h, 0.0));
+        let f = fresnel_dielectric(cos_theta_o*cos_gamma_o, self.eta);
+        let r = vec3(f, f, f);
+        let tt = (1.0-f).powi(2)*t;
+        let trt = f*tt.mul_element_wise(t);
+        let rest = vec3(
+            trt.x*f*t.x/(1.0 - t.x*f),
+            trt.y*f*t.y/(1.0 - t.y*f),
+            trt.z*f*t.z/(1.0 - t.z*f),
+        );
+        ([r, tt, trt, rest], sin_gamma_t.asin())
+    }
+    // chance of sampling each lobe, by its share of the attenuated light
+    fn lobe_probabilities(attenuation: &[Color; 4]) -> [Float; 4] {
+        let weights = attenuation.map(luminance);
+        let total: Float = weights.iter().sum();
+        if total <= 0.0 { return [1.0, 0.0, 0.0, 0.0] }
+        weights.map(|w| w/total)
+    }
+    // brdf (times the cosine) and pdf in the fiber's frame: x along the fiber, z towards the surface normal
+    // h is where across the fiber the ray hit, from -1 to 1
+    fn eval_local(&self, wo: Vec3, wi: Vec3, h: Float) -> (Color, Float) {
+        let (sin_theta_o, sin_theta_i) = (wo.x.clamp(-1.0, 1.0), wi.x.clamp(-1.0, 1.0));
+        let cos_theta_o = Float::sqrt(1.0 - sin_theta_o*sin_theta_o);
+        let cos_theta_i = Float::sqrt(1.0 - sin_theta_i*sin_theta_i);
+        let phi = Float::atan2(wi.z, wi.y) - Float::atan2(wo.z, wo.y);
+        let gamma_o = h.clamp(-1.0, 1.0).asin();
+        let (attenuation, gamma_t) = self.attenuation(cos_theta_o, sin_theta_o, h);
+        let probabilities = Hair::lobe_probabilities(&attenuation);
+        let (v, s) = (self.longitudinal_variance(), self.azimuthal_scale());
+
+        let mut f = Color::zero();
+        let mut pdf = 0.0;
+        for p in 0..=Hair::P_MAX {
+            let (sin_op, cos_op) = self.tilted(p, sin_theta_o, cos_theta_o);
+            let m = hair_mp(cos_theta_i, cos_op, sin_theta_i, sin_op, v[p]);
+            let n = if p < Hair::P_MAX { hair_np(phi, p, s, gamma_o, gamma_t) } else { 1.0/(2.0*PI) };
+            f += m*n*attenuation[p];
+            pdf += m*n*probabilities[p];
+        }
+        (f, pdf)
+    }
+    // pick a lobe, then a direction from its longitudinal and azimuthal distributions
+    fn sample(&self, wo: Vec3, h: Float) -> Vec3 {
+        let sin_theta_o = wo.x.clamp(-1.0, 1.0);
+        let cos_theta_o = Float::sqrt(1.0 - sin_theta_o*sin_theta_o);
+        let phi_o = Float::atan2(wo.z, wo.y);
+        let gamma_o = h.clamp(-1.0, 1.0).asin();
+        let (attenuation, gamma_t) = self.attenuation(cos_theta_o, sin_theta_o, h);
+        let probabilities = Hair::lobe_probabilities(&attenuation);
+        let (v, s) = (self.longitudinal_variance(), self.azimuthal_scale());
+
+        let mut u: Float = rand_f32();
+        let mut p = Hair::P_MAX;
+        for (lobe, probability) in probabilities.iter().enumerate().take(Hair::P_MAX) {
+            if u < *probability { p = lobe; break }
+            u -= probability;
+        }
+        // longitudinal angle about the lobe's tilted direction
+        let (sin_op, cos_op) = self.tilted(p, sin_theta_o, cos_theta_o);
+        let u1 = Float::max(rand_f32(), 1e-5);
+        let cos_theta = 1.0 + v[p]*Float::ln(u1 + (1.0-u1)*Float::exp(-2.0/v[p]));
+        let sin_theta = Float::sqrt(Float::max(1.0 - cos_theta*cos_theta, 0.0));
+        let cos_phi = (2.0*PI*rand_f32()).cos();
+        let sin_theta_i = (-cos_theta*sin_op + sin_theta*cos_phi*cos_op).clamp(-1.0, 1.0);
+        let cos_theta_i = Float::sqrt(1.0 - sin_theta_i*sin_theta_i);
+        // azimuth around where the lobe leaves the fiber
+        let dphi = if p < Hair::P_MAX {
+            hair_phi(p, gamma_o, gamma_t) + sample_trimmed_logistic(rand_f32(), s, -PI, PI)
+        } else {
+            2.0*PI*rand_f32()
+        };
+        let phi_i = phi_o + dphi;
+        vec3(sin_theta_i, cos_theta_i*phi_i.cos(), cos_theta_i*phi_i.sin())
+    }
+}
+impl Hair {
+    // frame with x along the fiber and z towards the side that was hit (None if the normal runs along the fiber)
+    fn fiber_frame(hit: &RayHit) -> Option<(Vec3, Vec3, Vec3)> {
+        let tangent = hit.tangent.unwrap_or(Frame::new(hit.normal).tangent).normalize();
+        let normal = hit.normal - hit.normal.dot(tangent)*tangent;
+        if normal.magnitude2() < 1e-8 { return None }
+        let normal = normal.normalize();
+        Some((tangent, normal.cross(tangent), normal))
+    }
+    // offset across the fiber, from the angle between the view and the normal around the fiber
+    fn offset(wo: Vec3) -> Float {
+        -wo.y / Float::max(Float::sqrt(wo.y*wo.y + wo.z*wo.z), 0.0001)
+    }
+}
+impl Material for Hair {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        let (tangent, across, normal) = match Hair::fiber_frame(hit) {
+            Some(frame) => frame,
+            None => return (Ray { origin: hit.hitpoint, direction: ray.direction, time: ray.time }, Color::zero(), 1.0, BounceKind::Glossy),
+        };
+        let to_local = |v: Vec3| vec3(v.dot(tangent), v.dot(across), v.dot(normal));
+        let wo = to_local(-ray.direction.normalize());
+        let h = Hair::offset(wo);
+        let wi = self.sample(wo, h);
+        let (f, pdf) = self.eval_local(wo, wi, h);
+        let direction = wi.x*tangent + wi.y*across + wi.z*normal;
+        // the lobes already include the cosine, so undo the integrator's
+        let cos = Float::max(direction.dot(hit.normal).abs(), 0.0001);
+        // (light passing through the fiber counts as transmission, the rest reflects off its surface)
+        let kind = if direction.dot(hit.normal)*(-ray.direction).dot(hit.normal) < 0.0 { BounceKind::Transmission } else { BounceKind::Glossy };
+        (Ray { origin: hit.hitpoint, direction, time: ray.time }, f/cos, Float::max(pdf, 1e-6), kind)
+    }
+    fn emission(&self, _hit: &RayHit) -> Color {
+        Vec3::zero()
+    }
+    fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Option<(Color, Float)> {
+        let (tangent, across, normal) = Hair::fiber_frame(hit)?;
+        let to_local = |v: Vec3| vec3(v.dot(tangent), v.dot(across), v.dot(normal));
+        let wo = to_local(wo);
+        let (f, pdf) = self.eval_local(wo, to_local(wi), Hair::offset(wo));
+        Some((f / Float::max(wi.dot(hit.normal).abs(), 0.0001), pdf))
+    }
+}
+
+// Disney "principled" material, following Burley 2012/2015 so parameters from Blender's Principled BSDF carry over directly
+// (clearcoat uses a second GGX lobe with a roughness instead of Disney's gloss/GTR1 parameterization)
+pub struct PrincipledMaterial {
+    pub base_color: Arc<dyn Texture + Send + Sync>,
+    pub emission: Arc<dyn Texture + Send + Sync>,
+    pub metallic: Arc<dyn Texture + Send + Sync>,
+    pub roughness: Arc<dyn Texture + Send + Sync>,
+    pub specular: Float,              // dielectric reflectance, 0.5 = 4% at normal incidence
+    pub specular_tint: Float,         // tints dielectric reflections towards the base color
+    pub sheen: Float,                 // extra grazing reflection for cloth
+    pub sheen_tint: Float,
+    pub clearcoat: Float,
+    pub clearcoat_roughness: Float,
+    pub transmission: Float,          // fraction of the dielectric part that refracts through the surface (glass)
+    pub ior: Float,
+}
+impl Default for PrincipledMaterial {
+    fn default() -> PrincipledMaterial {
+        // same defaults as blender
+        PrincipledMaterial {
+            base_color: solid_color(vec3(0.8,0.8,0.8)),
+            emission: solid_color(Vec3::zero()),
+            metallic: solid_value(0.0),
+            roughness: solid_value(0.5),
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            transmission: 0.0,
+            ior: 1.45,
+        }
+    }
+}
+// a PrincipledMaterial's parameters evaluated at one point on a surface
+struct PrincipledSurface {
+    base_color: Color,
+    metallic: Float,
+    roughness: Float,
+    specular: Float,
+    specular_tint: Float,
+    sheen: Float,
+    sheen_tint: Float,
+    clearcoat: Float,
+    clearcoat_roughness: Float,
+    transmission: Float,
+    ior: Float,
+}
+impl PrincipledMaterial {
+    fn at(&self, hit: &RayHit) -> PrincipledSurface {
+        let uv = hit.tex_coords.unwrap_or(Vec2::zero());
+        PrincipledSurface {
+            base_color: self.base_color.sample_footprint(uv, hit.hitpoint, hit.uv_footprint),
+            metallic: self.metallic.sample_footprint(uv, hit.hitpoint, hit.uv_footprint).x,
+            roughness: Float::max(self.roughness.sample_footprint(uv, hit.hitpoint, hit.uv_footprint).x, hit.min_roughness),
+            specular: self.specular,
+            specular_tint: self.specular_tint,
+            sheen: self.sheen,
+            sheen_tint: self.sheen_tint,
+            clearcoat: self.clearcoat,
+            clearcoat_roughness: Float::max(self.clearcoat_roughness, hit.min_roughness),
+            transmission: self.transmission,
+            ior: self.ior,
+        }
+    }
+}
+impl PrincipledSurface {
+    // base color normalized to unit luminance, used for tinting
+    fn tint(&self) -> Color {
+        let lum = luminance(self.base_color);
+        if lum > 0.0 { self.base_color/lum } else { vec3(1.0,1.0,1.0) }
+    }
+    fn f0(&self) -> Color {
+        let dielectric = self.specular*0.08*lerpvec(vec3(1.0,1.0,1.0), self.tint(), self.specular_tint);
+        lerpvec(dielectric, self.base_color, self.metallic)
+    }
+    // how much of the material is diffuse and how much refracts
+    fn diffuse_weight(&self) -> Float {
+        (1.0-self.metallic)*(1.0-self.transmission)
+    }
+    fn transmission_weight(&self) -> Float {
+        (1.0-self.metallic)*self.transmission
+    }
+    // probabilities of sampling the (clear coat, specular, diffuse) reflection lobes
+    fn lobe_probabilities(&self, cos_o: Float) -> (Float, Float, Float) {
+        let coat_weight = 0.25*self.clearcoat*fresnel_schlick(vec3(0.04,0.04,0.04), cos_o).x;
+        let spec_weight = Float::max(luminance(fresnel_schlick(self.f0(), cos_o)), 0.05);
+        let diffuse_weight = self.diffuse_weight()*luminance(self.base_color) + self.diffuse_weight()*self.sheen;
+        let total = coat_weight + spec_weight + diffuse_weight;
+        (coat_weight/total, spec_weight/total, diffuse_weight/total)
+    }
+    // value of the reflection part of the brdf for a pair of local space directions
+    fn eval_local(&self, wo: Vec3, wi: Vec3) -> Color {
+        if wo.z <= 0.0 || wi.z <= 0.0 { return Color::zero() }
+        let h = (wo + wi).normalize();
+        let cos_d = wi.dot(h);
+
+        // diffuse with grazing retro-reflection, plus sheen
+        let fd90 = 0.5 + 2.0*self.roughness*cos_d*cos_d;
+        let fd = (1.0 + (fd90-1.0)*schlick_weight(wi.z)) * (1.0 + (fd90-1.0)*schlick_weight(wo.z));
+        let sheen = self.sheen*schlick_weight(cos_d)*lerpvec(vec3(1.0,1.0,1.0), self.tint(), self.sheen_tint);
+        let diffuse = self.diffuse_weight()*(fd*self.base_color/PI + sheen);
+
+        // specular (the refracting part reflects through the transmission lobe instead)
+        let alpha = ggx_alpha(self.roughness);
+        let specular = (1.0-self.transmission_weight()) * fresnel_schlick(self.f0(), cos_d) * ggx_d(h, alpha) * ggx_g2(wo, wi, alpha) / (4.0*wo.z*wi.z);
+
+        // clear coat
+        let coat_alpha = ggx_alpha(self.clearcoat_roughness);
+        let coat = 0.25*self.clearcoat*fresnel_schlick(vec3(0.04,0.04,0.04), cos_d).x
+            * ggx_d(h, coat_alpha) * ggx_g2(wo, wi, coat_alpha) / (4.0*wo.z*wi.z);
+
+        diffuse + specular + vec3(coat, coat, coat)
+    }
+    fn pdf_local(&self, wo: Vec3, wi: Vec3) -> Float {
+        let (p_coat, p_spec, p_diffuse) = self.lobe_probabilities(wo.z);
+        let mut pdf = p_spec*ggx_reflection_pdf(wo, wi, ggx_alpha(self.roughness));
+        pdf += p_diffuse*cosine_hemisphere_pdf(wi.z);
+        if p_coat > 0.0 { pdf += p_coat*ggx_reflection_pdf(wo, wi, ggx_alpha(self.clearcoat_roughness)); }
+        pdf
+    }
+    // samples the rough dielectric transmission lobe, returning the local direction and its weight (brdf*cos/pdf)
+    fn sample_transmission(&self, wo: Vec3, frontface: bool) -> (Vec3, Color) {
+        let alpha = ggx_alpha(self.roughness);
+        let h = ggx_sample_vndf(wo, alpha, rand_vec2());
+        let eta = if frontface {1.0/self.ior} else {self.ior};
+        let cos_i = wo.dot(h);
+        let sin2_t = eta*eta*(1.0-cos_i*cos_i);
+        // fresnel with schlick, using the angle on the denser side
+        let r0 = ((1.0-self.ior)/(1.0+self.ior)).powi(2);
+        let fresnel_factor = if sin2_t >= 1.0 { 1.0 }
+            else {
+                let cos = if eta > 1.0 { Float::sqrt(1.0-sin2_t) } else { cos_i };
+                r0 + (1.0-r0)*schlick_weight(cos)
+            };
+        if rand_f32() < fresnel_factor {
+            (2.0*cos_i*h - wo, vec3(1.0,1.0,1.0))
+        }
         else {
-            // specular
-            (
+            ((eta*cos_i - Float::sqrt(1.0-sin2_t))*h - eta*wo, self.base_color)
+        }
+    }
+}
+impl Material for PrincipledMaterial {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(-ray.direction.normalize());
+        let wo = vec3(wo.x, wo.y, Float::max(wo.z, 0.0001)).normalize();
+        let surface = self.at(hit);
+
+        // first choose between refraction and reflection, each branch is weighted separately
+        let p_transmission = surface.transmission_weight();
+        if p_transmission > 0.0 && rand_f32() < p_transmission {
+            let (wi, weight) = surface.sample_transmission(wo, hit.frontface);
+            // undo the cosine term the integrator applies, since the weight already includes it
+            return (
                 Ray {
                     origin: hit.hitpoint,
-                    direction: reflect(&ray.direction, &hit.normal) + self.roughness*rand_sphere_vec(),
+                    direction: frame.to_world(wi),
+                    time: ray.time,
                 },
-                lerpvec(vec3(1.0,1.0,1.0), self.albedo, self.metallic), // metals attenuate specular light more
-                1.0
+                weight / Float::max(wi.z.abs(), 0.0001),
+                1.0,
+                if wi.z < 0.0 { BounceKind::Transmission } else { BounceKind::Glossy },
             )
         }
 
+        // reflection - pick one lobe to sample, then weight by the pdf of the whole mixture
+        let (p_coat, p_spec, _) = surface.lobe_probabilities(wo.z);
+        let u = rand_f32();
+        let kind = if u < p_coat + p_spec { BounceKind::Glossy } else { BounceKind::Diffuse };
+        let wi = if u < p_coat + p_spec {
+            let alpha = if u < p_coat { ggx_alpha(surface.clearcoat_roughness) } else { ggx_alpha(surface.roughness) };
+            let h = ggx_sample_vndf(wo, alpha, rand_vec2());
+            2.0*wo.dot(h)*h - wo
+        }
+        else {
+            sample_cosine_hemisphere(rand_vec2())
+        };
+        (
+            Ray {
+                origin: hit.hitpoint,
+                direction: frame.to_world(wi),
+                time: ray.time,
+            },
+            surface.eval_local(wo, wi),
+            Float::max((1.0-p_transmission)*surface.pdf_local(wo, wi), 0.0001),
+            kind,
+        )
+    }
+    fn emission(&self, hit: &RayHit) -> Color {
+        self.emission.sample_footprint(hit.tex_coords.unwrap_or(Vec2::zero()), hit.hitpoint, hit.uv_footprint)
+    }
+    fn albedo(&self, hit: &RayHit) -> Color {
+        self.at(hit).base_color
+    }
+}
 
+// Subsurface scattering by a random walk through the inside of a closed object
+// light refracts in through a smooth boundary, scatters around the interior like a dense volume, and refracts back out.
+// each internal scattering event uses up one bounce of path depth, so dense media need a higher camera path_depth
+// based on Chiang et al. 2016, "Practical and Controllable Subsurface Scattering for Production Path Tracing"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SubsurfaceMaterial {
+    pub albedo: Color,          // overall color of the surface after multiple scattering
+    pub mean_free_path: Color,  // average distance light travels inside before scattering, per channel (in scene units)
+    pub idx_of_refraction: Float,
+}
+impl SubsurfaceMaterial {
+    // single-scattering albedo that produces the desired multiple-scattering albedo (Chiang et al. eq. 5)
+    fn single_scatter_albedo(&self) -> Color {
+        self.albedo.map(|a| {
+            let a = a.clamp(0.0, 0.999);
+            1.0 - (4.09712 + 4.20863*a - Float::sqrt(9.59217 + 41.6808*a + 17.7126*a*a)).powi(2)
+        })
     }
-    fn emission(&self) -> Color {
-        self.emission
+    fn extinction(&self) -> Color {
+        self.mean_free_path.map(|mfp| 1.0/Float::max(mfp, 0.00001))
+    }
+    // scattering at the boundary, same as a smooth dielectric
+    fn boundary_direction(&self, hit: &RayHit, dir: Vec3) -> Vec3 {
+        let eta = if hit.frontface {1.0/self.idx_of_refraction} else {self.idx_of_refraction};
+        let critical_angle = eta*Float::sqrt(1.0-Float::min(-dir.dot(hit.normal), 1.0).powi(2)) > 1.0;
+        let fresnel_factor = fresnel(&dir, &hit.normal, self.idx_of_refraction);
+        if !critical_angle && rand_f32() >= fresnel_factor {
+            refract(&dir, &hit.normal, eta)
+        }
+        else {
+            reflect(&dir, &hit.normal)
+        }
+    }
+}
+impl Material for SubsurfaceMaterial {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        let dir = ray.direction.normalize();
+        if hit.frontface {
+            // entering the object (or reflecting off of it)
+            let new_dir = self.boundary_direction(hit, dir);
+            let dot_term = Float::max(new_dir.dot(hit.normal).abs(), 0.0001);
+            return (Ray {origin: hit.hitpoint, direction: new_dir, time: ray.time}, vec3(1.0,1.0,1.0)/dot_term, 1.0, BounceKind::Transmission)
+        }
+
+        // inside the object - the hit is where the ray would leave, so check whether it scattered first.
+        // distances are sampled from a randomly chosen channel and weighted by the average pdf over all channels
+        let sigma_t = self.extinction();
+        let channel = (rand_f32()*3.0) as usize;
+        let t = -Float::ln(1.0 - rand_f32()) / sigma_t[channel];
+        let boundary_dist = hit.distance*ray.direction.magnitude();
+        if t < boundary_dist {
+            // scattered inside - continue the walk in a random direction from the scattering point
+            let transmittance = (-sigma_t*t).map(Float::exp);
+            let pdf = (sigma_t.mul_element_wise(transmittance)).sum()/3.0;
+            let weight = self.single_scatter_albedo().mul_element_wise(sigma_t).mul_element_wise(transmittance)/pdf;
+            // the integrator multiplies by the cosine with the boundary normal, which doesn't apply inside a medium
+            let new_dir = sample_uniform_sphere(rand_vec2());
+            let dot_term = Float::max(new_dir.dot(hit.normal).abs(), 0.0001);
+            (Ray {origin: ray.origin + dir*t, direction: new_dir, time: ray.time}, weight/dot_term, 1.0, BounceKind::Volume)
+        }
+        else {
+            // reached the boundary - refract out (or reflect back in)
+            let transmittance = (-sigma_t*boundary_dist).map(Float::exp);
+            let weight = transmittance/(transmittance.sum()/3.0);
+            let new_dir = self.boundary_direction(hit, dir);
+            let dot_term = Float::max(new_dir.dot(hit.normal).abs(), 0.0001);
+            (Ray {origin: hit.hitpoint, direction: new_dir, time: ray.time}, weight/dot_term, 1.0, BounceKind::Transmission)
+        }
+    }
+    fn emission(&self, _hit: &RayHit) -> Color {
+        Vec3::zero()
+    }
+    fn albedo(&self, _hit: &RayHit) -> Color {
+        self.albedo
+    }
+}
+
+// Stacks materials on top of each other. Each layer covers some fraction of what's under it,
+// and scatter picks which layer the ray interacts with by walking down from the top layer
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum LayerWeight {
+    Constant(Float),  // fraction of light this layer catches (e.g. dust coverage)
+    Fresnel(Float),   // angle-dependent, like a clear dielectric coat with the given ior
+}
+pub struct Layer {
+    pub material: Arc<dyn Material + Send + Sync>,
+    pub weight: LayerWeight,
+}
+pub struct LayeredMaterial {
+    pub layers: Vec<Layer>,     // top layer first, the last layer catches everything that gets through
+}
+impl LayeredMaterial {
+    fn coverage(layer: &Layer, hit: &RayHit, ray: &Ray) -> Float {
+        match layer.weight {
+            LayerWeight::Constant(w) => w.clamp(0.0, 1.0),
+            LayerWeight::Fresnel(ior) => fresnel(&ray.direction.normalize(), &hit.normal, ior),
+        }
+    }
+}
+impl Material for LayeredMaterial {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        // each layer is picked with exactly its share of the mix, so its own sample can be returned unchanged
+        let last = self.layers.len()-1;
+        for layer in self.layers[..last].iter() {
+            if rand_f32() < LayeredMaterial::coverage(layer, hit, ray) {
+                return layer.material.scatter(hit, ray)
+            }
+        }
+        self.layers[last].material.scatter(hit, ray)
+    }
+    fn emission(&self, hit: &RayHit) -> Color {
+        // emission doesn't know the view direction, so fresnel layers are treated as fully transparent here
+        let mut emission = Color::zero();
+        let mut remaining = 1.0;
+        for (i, layer) in self.layers.iter().enumerate() {
+            let coverage = match layer.weight {
+                _ if i == self.layers.len()-1 => 1.0,
+                LayerWeight::Constant(w) => w.clamp(0.0, 1.0),
+                LayerWeight::Fresnel(_) => 0.0,
+            };
+            emission += remaining*coverage*layer.material.emission(hit);
+            remaining *= 1.0-coverage;
+        }
+        emission
+    }
+    fn albedo(&self, hit: &RayHit) -> Color {
+        // (weighted the same way as emission)
+        let mut albedo = Color::zero();
+        let mut remaining = 1.0;
+        for (i, layer) in self.layers.iter().enumerate() {
+            let coverage = match layer.weight {
+                _ if i == self.layers.len()-1 => 1.0,
+                LayerWeight::Constant(w) => w.clamp(0.0, 1.0),
+                LayerWeight::Fresnel(_) => 0.0,
+            };
+            albedo += remaining*coverage*layer.material.albedo(hit);
+            remaining *= 1.0-coverage;
+        }
+        albedo
+    }
+}
+
+// Blends two materials by a factor (0 = all a, 1 = all b), which can come from a mask texture
+pub enum MixFactor {
+    Constant(Float),
+    Texture(Arc<dyn Texture + Send + Sync>),   // uses the red channel, sampled at the hit
+}
+pub struct MixMaterial {
+    pub a: Arc<dyn Material + Send + Sync>,
+    pub b: Arc<dyn Material + Send + Sync>,
+    pub factor: MixFactor,
+}
+impl MixMaterial {
+    fn factor_at(&self, hit: &RayHit) -> Float {
+        match &self.factor {
+            MixFactor::Constant(f) => *f,
+            MixFactor::Texture(tex) => tex.sample_footprint(hit.tex_coords.unwrap_or(Vec2::zero()), hit.hitpoint, hit.uv_footprint).x,
+        }
+    }
+}
+impl Material for MixMaterial {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        // pick one of the materials with probability equal to its share of the mix
+        if rand_f32() < self.factor_at(hit) {
+            self.b.scatter(hit, ray)
+        }
+        else {
+            self.a.scatter(hit, ray)
+        }
+    }
+    fn emission(&self, hit: &RayHit) -> Color {
+        lerpvec(self.a.emission(hit), self.b.emission(hit), self.factor_at(hit).clamp(0.0, 1.0))
+    }
+    fn albedo(&self, hit: &RayHit) -> Color {
+        lerpvec(self.a.albedo(hit), self.b.albedo(hit), self.factor_at(hit).clamp(0.0, 1.0))
+    }
+    fn is_diffuse(&self, hit: &RayHit) -> bool {
+        self.a.is_diffuse(hit) || self.b.is_diffuse(hit)
+    }
+    fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Option<(Color, Float)> {
+        // (only if both materials can be evaluated, since either one could have been sampled)
+        let ((brdf_a, pdf_a), (brdf_b, pdf_b)) = (self.a.eval(hit, wo, wi)?, self.b.eval(hit, wo, wi)?);
+        let factor = self.factor_at(hit).clamp(0.0, 1.0);
+        Some((lerpvec(brdf_a, brdf_b, factor), (1.0-factor)*pdf_a + factor*pdf_b))
+    }
+}
+
+// Cuts holes in another material using the alpha channel of a texture (leaves, fences, decals)
+pub struct AlphaCutout {
+    pub material: Arc<dyn Material + Send + Sync>,
+    pub alpha_map: Arc<dyn Texture + Send + Sync>,
+}
+impl Material for AlphaCutout {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        self.material.scatter(hit, ray)
+    }
+    fn emission(&self, hit: &RayHit) -> Color {
+        self.material.emission(hit)
+    }
+    fn albedo(&self, hit: &RayHit) -> Color {
+        self.material.albedo(hit)
+    }
+    fn is_diffuse(&self, hit: &RayHit) -> bool {
+        self.material.is_diffuse(hit)
+    }
+    fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Option<(Color, Float)> {
+        self.material.eval(hit, wo, wi)
+    }
+    fn alpha(&self, hit: &RayHit) -> Float {
+        hit.tex_coords.map_or(1.0, |uv| self.alpha_map.sample_alpha(uv))
+    }
+    fn toon_style(&self) -> Option<ToonStyle> {
+        self.material.toon_style()
+    }
+    fn light_links(&self) -> Option<&LightLinks> {
+        self.material.light_links()
+    }
+    fn is_shadow_catcher(&self) -> bool {
+        self.material.is_shadow_catcher()
+    }
+    fn name(&self) -> &str {
+        self.material.name()
+    }
+}
+
+// Draws another material with toon shading (its color comes from the wrapped material), for mixing illustrated
+// objects into path traced scenes or giving one object its own style under ShadingMode::Toon
+pub struct ToonMaterial {
+    pub material: Arc<dyn Material + Send + Sync>,
+    pub style: ToonStyle,
+}
+impl Material for ToonMaterial {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        self.material.scatter(hit, ray)
+    }
+    fn emission(&self, hit: &RayHit) -> Color {
+        self.material.emission(hit)
+    }
+    fn albedo(&self, hit: &RayHit) -> Color {
+        self.material.albedo(hit)
+    }
+    fn is_diffuse(&self, hit: &RayHit) -> bool {
+        self.material.is_diffuse(hit)
+    }
+    fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Option<(Color, Float)> {
+        self.material.eval(hit, wo, wi)
+    }
+    fn alpha(&self, hit: &RayHit) -> Float {
+        self.material.alpha(hit)
+    }
+    fn toon_style(&self) -> Option<ToonStyle> {
+        Some(self.style)
+    }
+    fn light_links(&self) -> Option<&LightLinks> {
+        self.material.light_links()
+    }
+    fn is_shadow_catcher(&self) -> bool {
+        self.material.is_shadow_catcher()
+    }
+    fn name(&self) -> &str {
+        self.material.name()
+    }
+}
+
+// Limits which of the scene's lights shine on another material (light linking), e.g. a rim light only for the hero
+// object, or keeping a bright fill light off the background
+pub struct LightLinked {
+    pub material: Arc<dyn Material + Send + Sync>,
+    pub links: LightLinks,
+}
+impl Material for LightLinked {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        self.material.scatter(hit, ray)
+    }
+    fn emission(&self, hit: &RayHit) -> Color {
+        self.material.emission(hit)
+    }
+    fn albedo(&self, hit: &RayHit) -> Color {
+        self.material.albedo(hit)
+    }
+    fn is_diffuse(&self, hit: &RayHit) -> bool {
+        self.material.is_diffuse(hit)
+    }
+    fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Option<(Color, Float)> {
+        self.material.eval(hit, wo, wi)
+    }
+    fn alpha(&self, hit: &RayHit) -> Float {
+        self.material.alpha(hit)
+    }
+    fn toon_style(&self) -> Option<ToonStyle> {
+        self.material.toon_style()
+    }
+    fn light_links(&self) -> Option<&LightLinks> {
+        Some(&self.links)
+    }
+    fn is_shadow_catcher(&self) -> bool {
+        self.material.is_shadow_catcher()
+    }
+    fn name(&self) -> &str {
+        self.material.name()
+    }
+}
+
+// Gives another material a name, for picking it out in material cryptomattes (materials loaded from a library are
+// named after their entry in it)
+pub struct Named {
+    pub material: Arc<dyn Material + Send + Sync>,
+    pub name: String,
+}
+impl Material for Named {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        self.material.scatter(hit, ray)
+    }
+    fn emission(&self, hit: &RayHit) -> Color {
+        self.material.emission(hit)
+    }
+    fn albedo(&self, hit: &RayHit) -> Color {
+        self.material.albedo(hit)
+    }
+    fn is_diffuse(&self, hit: &RayHit) -> bool {
+        self.material.is_diffuse(hit)
+    }
+    fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Option<(Color, Float)> {
+        self.material.eval(hit, wo, wi)
+    }
+    fn alpha(&self, hit: &RayHit) -> Float {
+        self.material.alpha(hit)
+    }
+    fn toon_style(&self) -> Option<ToonStyle> {
+        self.material.toon_style()
+    }
+    fn light_links(&self) -> Option<&LightLinks> {
+        self.material.light_links()
+    }
+    fn is_shadow_catcher(&self) -> bool {
+        self.material.is_shadow_catcher()
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// Makes another material a shadow catcher, for compositing rendered objects onto a photo (a backplate): a stand-in for
+// something in the photo, like the ground, that the camera doesn't see, except for the shadows and bounce light the
+// rest of the scene casts on it. camera rays that hit it find only the light bouncing onto it off other objects, and the
+// alpha aov there is how much of the lights' light they block, so the image (with a transparent background) can be laid
+// over the photo with premultiplied alpha. everything else sees it as the wrapped material, which should match the
+// photo's surface so reflections and bounce light off it look right
+pub struct ShadowCatcher {
+    pub material: Arc<dyn Material + Send + Sync>,
+}
+impl Material for ShadowCatcher {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
+        self.material.scatter(hit, ray)
+    }
+    fn emission(&self, hit: &RayHit) -> Color {
+        self.material.emission(hit)
+    }
+    fn albedo(&self, hit: &RayHit) -> Color {
+        self.material.albedo(hit)
+    }
+    fn is_diffuse(&self, hit: &RayHit) -> bool {
+        self.material.is_diffuse(hit)
+    }
+    fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Option<(Color, Float)> {
+        self.material.eval(hit, wo, wi)
+    }
+    fn alpha(&self, hit: &RayHit) -> Float {
+        self.material.alpha(hit)
+    }
+    fn toon_style(&self) -> Option<ToonStyle> {
+        self.material.toon_style()
+    }
+    fn light_links(&self) -> Option<&LightLinks> {
+        self.material.light_links()
+    }
+    fn is_shadow_catcher(&self) -> bool {
+        true
+    }
+    fn name(&self) -> &str {
+        self.material.name()
     }
 }
 
 // PHASE FUNCTIONS
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Isotropic {
     // An isotropic phase function is one where light scatters in all directions with equal probability
     // (there's only one such function, so this one is just parameterized by an albedo)
     pub albedo: Color,
-    pub emission: Color,
+    pub emission: Color,    // light given off inside a volume, where it absorbs light (so a volume that scatters all
+                            // the light reaching it, with an albedo of 1, doesn't glow)
 }
 impl Material for Isotropic {
-    fn scatter(&self, hit: &RayHit, _ray: &Ray) -> (Ray, Color, f32) {
+    fn scatter(&self, hit: &RayHit, ray: &Ray) -> (Ray, Color, Float, BounceKind) {
         // by definition, the isotropic phase function is where light scatters in all directions with equal distribution
-        (Ray {origin: hit.hitpoint, direction: rand_sphere_vec() }, self.albedo, 1.0)
+        (Ray {origin: hit.hitpoint, direction: sample_uniform_sphere(rand_vec2()), time: ray.time }, self.albedo, 1.0, BounceKind::Volume)
     }
-    fn emission(&self) -> Color {
-        self.emission
+    fn emission(&self, _hit: &RayHit) -> Color {
+        // every collision found in a volume either scatters (with the chance given by the albedo) or absorbs, so the
+        // emission found at one is the absorbing share of it
+        self.emission.mul_element_wise(vec3(1.0, 1.0, 1.0) - self.albedo)
+    }
+    fn albedo(&self, _hit: &RayHit) -> Color {
+        self.albedo
+    }
+    fn eval(&self, _hit: &RayHit, _wo: Vec3, _wi: Vec3) -> Option<(Color, Float)> {
+        Some((self.albedo / (4.0*PI), 1.0/(4.0*PI)))
     }
 }
 
 
-// SAMPLING FUNCTIONS
-// uniformly samples a hemisphere given by normal n
-pub fn sample_hemisphere(hit: &RayHit) -> (Vec3, f32) {
-    // get random vector in hemisphere
-    let mut dir = rand_sphere_vec();
-    dir.y = dir.y.abs();
-    // rotate relative to given normal
-    let rotation = cgmath::Basis3::between_vectors(Vec3::unit_y(), hit.normal);
-    (rotation.rotate_vector(dir), 1.0/(2.0*PI))
+// MICROFACET FUNCTIONS
+// GGX (Trowbridge-Reitz) distribution with Smith masking-shadowing, all in a local frame where the normal is +z
+// based on https://www.pbr-book.org/3ed-2018/Reflection_Models/Microfacet_Models (sampling it is in the sampling module)
+// maps perceptual roughness to the distribution's alpha (clamped so perfectly smooth surfaces stay finite)
+pub fn ggx_alpha(roughness: Float) -> Float {
+    Float::max(roughness*roughness, 0.001)
+}
+// normal distribution function
+pub fn ggx_d(h: Vec3, alpha: Float) -> Float {
+    if h.z <= 0.0 { return 0.0 }
+    let a2 = alpha*alpha;
+    let d = h.z*h.z*(a2-1.0) + 1.0;
+    a2 / (PI*d*d)
+}
+// smith lambda function, used for the masking-shadowing terms
+pub fn ggx_lambda(v: Vec3, alpha: Float) -> Float {
+    let cos2 = v.z*v.z;
+    if cos2 <= 0.0 { return Float::INFINITY }
+    let tan2 = (1.0-cos2) / cos2;
+    0.5*(-1.0 + Float::sqrt(1.0 + alpha*alpha*tan2))
+}
+// masking term for one direction
+pub fn ggx_g1(v: Vec3, alpha: Float) -> Float {
+    1.0 / (1.0 + ggx_lambda(v, alpha))
+}
+// height-correlated masking-shadowing term for a pair of directions
+pub fn ggx_g2(wo: Vec3, wi: Vec3, alpha: Float) -> Float {
+    1.0 / (1.0 + ggx_lambda(wo, alpha) + ggx_lambda(wi, alpha))
+}
+// "Charlie" sheen distribution with the Estevez-Kulla visibility fit, for cloth-like retroreflection at grazing angles
+// from Estevez and Kulla 2017, "Production Friendly Microfacet Sheen BRDF"
+pub fn charlie_d(h: Vec3, alpha: Float) -> Float {
+    let inv_alpha = 1.0/alpha;
+    let sin_theta = Float::sqrt(Float::max(1.0 - h.z*h.z, 0.0));
+    (2.0 + inv_alpha) * sin_theta.powf(inv_alpha) / (2.0*PI)
+}
+fn charlie_l(x: Float, alpha: Float) -> Float {
+    let t = (1.0-alpha)*(1.0-alpha);
+    let lerp = |a: Float, b: Float| a + (b-a)*t;
+    let (a, b, c, d, e) = (lerp(21.5473, 25.3245), lerp(3.82987, 3.32435), lerp(0.19823, 0.16801), lerp(-1.97760, -1.27393), lerp(-4.32054, -4.85967));
+    a/(1.0 + b*x.powf(c)) + d*x + e
+}
+pub fn charlie_lambda(cos_theta: Float, alpha: Float) -> Float {
+    if cos_theta < 0.5 { Float::exp(charlie_l(cos_theta, alpha)) }
+    else { Float::exp(2.0*charlie_l(0.5, alpha) - charlie_l(1.0-cos_theta, alpha)) }
+}
+// full sheen brdf (without color) for a pair of local space directions
+pub fn charlie_brdf(wo: Vec3, wi: Vec3, roughness: Float) -> Float {
+    if wo.z <= 0.0 || wi.z <= 0.0 { return 0.0 }
+    let alpha = Float::max(roughness*roughness, 0.001);
+    let h = (wo + wi).normalize();
+    let g = 1.0/(1.0 + charlie_lambda(wo.z, alpha) + charlie_lambda(wi.z, alpha));
+    charlie_d(h, alpha) * g / (4.0*wo.z*wi.z)
+}
+// schlick's approximation with a colored reflectance at normal incidence
+pub fn fresnel_schlick(f0: Color, cos_theta: Float) -> Color {
+    f0 + (vec3(1.0, 1.0, 1.0) - f0)*schlick_weight(cos_theta)
+}
+// exact fresnel reflectance of a conductor (from air) per color channel
+// from https://www.pbr-book.org/3ed-2018/Reflection_Models/Specular_Reflection_and_Transmission
+pub fn fresnel_conductor(cos_theta: Float, eta: Color, k: Color) -> Color {
+    let cos2 = cos_theta.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let channel = |eta: Float, k: Float| {
+        let t0 = eta*eta - k*k - sin2;
+        let a2_plus_b2 = Float::sqrt(t0*t0 + 4.0*eta*eta*k*k);
+        let t1 = a2_plus_b2 + cos2;
+        let a = Float::sqrt(Float::max(0.5*(a2_plus_b2 + t0), 0.0));
+        let t2 = 2.0*cos_theta*a;
+        let rs = (t1 - t2)/(t1 + t2);
+        let t3 = cos2*a2_plus_b2 + sin2*sin2;
+        let t4 = t2*sin2;
+        let rp = rs*(t3 - t4)/(t3 + t4);
+        0.5*(rp + rs)
+    };
+    vec3(channel(eta.x, k.x), channel(eta.y, k.y), channel(eta.z, k.z))
+}
+// exact fresnel reflectance of a dielectric, for light arriving from outside (eta = inside/outside)
+pub fn fresnel_dielectric(cos_theta_i: Float, eta: Float) -> Float {
+    let (cos_i, eta) = if cos_theta_i < 0.0 { (-cos_theta_i, 1.0/eta) } else { (cos_theta_i, eta) };
+    let sin_t = Float::sqrt(Float::max(1.0 - cos_i*cos_i, 0.0))/eta;
+    if sin_t >= 1.0 { return 1.0 }
+    let cos_t = Float::sqrt(Float::max(1.0 - sin_t*sin_t, 0.0));
+    let r_parallel = (eta*cos_i - cos_t)/(eta*cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta*cos_t)/(cos_i + eta*cos_t);
+    0.5*(r_parallel*r_parallel + r_perpendicular*r_perpendicular)
+}
+// (1-cos)^5 falloff shared by schlick fresnel and the disney lobes
+pub fn schlick_weight(cos_theta: Float) -> Float {
+    (1.0-cos_theta).clamp(0.0, 1.0).powi(5)
+}
+// perceived brightness of a linear color
+pub fn luminance(c: Color) -> Float {
+    0.2126*c.x + 0.7152*c.y + 0.0722*c.z
 }
 
-// based on http://three-eyed-games.com/2018/05/12/gpu-path-tracing-in-unity-part-2/
-pub fn alpha_sample(hit: &RayHit) -> (Vec3, f32) {
-    let alpha = 1.0;
-    let mut rng = rand::thread_rng();
-    // pick random point on sphere sitting on xz plane
-    let cos_theta = f32::powf(rng.gen_range(0.0..1.0), 1.0/(alpha+1.0));
-    let sin_theta = f32::sqrt(f32::max(0.0, 1.0 - cos_theta*cos_theta));
-    let phi = 2.0*PI*rng.gen_range(0.0..1.0);
-    let vec = vec3(f32::cos(phi)*sin_theta, f32::sin(phi)*sin_theta, cos_theta);
-    
-    // rotate relative to given normal
-    let rotation = cgmath::Basis3::between_vectors(Vec3::unit_z(), hit.normal);
-    (rotation.rotate_vector(vec), (alpha+1.0)*f32::powf(cos_theta, alpha) / (2.0*PI))
+// HAIR FUNCTIONS - longitudinal (M) and azimuthal (N) scattering of a rough fiber, from pbrt-v3
+// modified bessel function of the first kind (series, plenty for the arguments hair uses), and its log for large arguments
+fn bessel_i0(x: Float) -> Float {
+    let (mut value, mut x2i, mut factorial, mut four_i) = (0.0, 1.0, 1.0, 1.0);
+    for i in 0..10 {
+        if i > 1 { factorial *= i as Float; }
+        value += x2i / (four_i*factorial*factorial);
+        x2i *= x*x;
+        four_i *= 4.0;
+    }
+    value
+}
+fn log_bessel_i0(x: Float) -> Float {
+    if x > 12.0 { x + 0.5*(-Float::ln(2.0*PI) + Float::ln(1.0/x) + 1.0/(8.0*x)) } else { bessel_i0(x).ln() }
+}
+// longitudinal scattering with variance v
+fn hair_mp(cos_theta_i: Float, cos_theta_o: Float, sin_theta_i: Float, sin_theta_o: Float, v: Float) -> Float {
+    let a = cos_theta_i*cos_theta_o/v;
+    let b = sin_theta_i*sin_theta_o/v;
+    if v <= 0.1 {
+        Float::exp(log_bessel_i0(a) - b - 1.0/v + consts::LN_2 + Float::ln(1.0/(2.0*v)))
+    } else {
+        Float::exp(-b)*bessel_i0(a) / ((1.0/v).sinh()*2.0*v)
+    }
+}
+// azimuth at which lobe p leaves the fiber
+fn hair_phi(p: usize, gamma_o: Float, gamma_t: Float) -> Float {
+    2.0*p as Float*gamma_t - 2.0*gamma_o + p as Float*PI
+}
+fn logistic(x: Float, s: Float) -> Float {
+    let x = x.abs();
+    Float::exp(-x/s) / (s*(1.0 + Float::exp(-x/s)).powi(2))
+}
+fn logistic_cdf(x: Float, s: Float) -> Float {
+    1.0/(1.0 + Float::exp(-x/s))
+}
+fn trimmed_logistic(x: Float, s: Float, a: Float, b: Float) -> Float {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+fn sample_trimmed_logistic(u: Float, s: Float, a: Float, b: Float) -> Float {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s*Float::ln(1.0/(u*k + logistic_cdf(a, s)) - 1.0);
+    x.clamp(a, b)
+}
+// azimuthal scattering of lobe p, spread around where it leaves the fiber
+fn hair_np(phi: Float, p: usize, s: Float, gamma_o: Float, gamma_t: Float) -> Float {
+    let dphi = (phi - hair_phi(p, gamma_o, gamma_t) + PI).rem_euclid(2.0*PI) - PI;
+    trimmed_logistic(dphi, s, -PI, PI)
 }
 
-// based on raytracing in one weekend
-pub fn rtow_sample(hit: &RayHit) -> (Vec3, f32) {
-    let dir = rand_sphere_vec();
-    (hit.hitpoint + hit.normal + dir, 1.0/(2.0*PI))
+// HASHING - cheap deterministic randomness from integer coordinates, for procedural patterns
+pub fn hash3(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6b343) ^ (y as u32).wrapping_mul(0xd8163841) ^ (z as u32).wrapping_mul(0xcb1ab31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846ca68b);
+    h ^ (h >> 16)
+}
+// maps a hash to [0,1)
+pub fn hash_to_float(h: u32) -> Float {
+    (h >> 8) as Float / (1u32 << 24) as Float
 }
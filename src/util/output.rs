@@ -0,0 +1,46 @@
+// OUTPUT - Writes renders to image file formats that keep more than the 8-bit png does
+
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io::BufWriter;
+use image::{ImageResult, Rgb};
+use image::codecs::hdr::HdrEncoder;
+use exr::prelude::{AnyChannel, AnyChannels, AttributeValue, Encoding, FlatSamples, Image, ImageAttributes, IntegerBounds, Layer, LayerAttributes, Text, WritableImage};
+
+use super::tracing::HdrImage;
+use super::cryptomatte::CryptomatteImage;
+
+// OPENEXR
+// writes linear images to an exr file as 32-bit floats, with no gamma or clamping. each (name, image) is a layer with
+// R, G, and B channels, all the same size. a single layer is written unnamed, as the file's main image, and more
+// (like AOVs) are written as separate parts of the file, which some older software can't read. each cryptomatte gets a
+// part of its own too, holding all of its layers' channels along with the attributes describing it
+pub fn write_exr(file_name: &str, layers: &[(&str, &HdrImage)], mattes: &[CryptomatteImage]) -> exr::error::UnitResult {
+    let (width, height) = layers[0].1.dimensions();
+    let size = (width as usize, height as usize);
+    let mut exr_layers: Vec<_> = layers.iter().map(|&(name, img)| {
+        let channel = |c: usize| AnyChannel::new(["R", "G", "B"][c], FlatSamples::F32(img.pixels().map(|p| p[c]).collect()));
+        let attributes = if layers.len() == 1 && mattes.is_empty() { LayerAttributes::default() } else { LayerAttributes::named(name) };
+        Layer::new(size, attributes, Encoding::FAST_LOSSLESS, AnyChannels::sort(vec![channel(0), channel(1), channel(2)].into()))
+    }).collect();
+    exr_layers.extend(mattes.iter().map(|matte| {
+        let channels = matte.channels().into_iter().map(|(name, values)| AnyChannel::new(Text::new_or_panic(name), FlatSamples::F32(values)));
+        let mut attributes = LayerAttributes::named(matte.kind.name());
+        // (the manifest is json, which is utf-8, so names outside of latin-1 are kept as their utf-8 bytes)
+        for (name, value) in matte.metadata() {
+            attributes.other.insert(Text::new_or_panic(name), AttributeValue::Text(Text::from_slice_unchecked(value.as_bytes())));
+        }
+        Layer::new(size, attributes, Encoding::FAST_LOSSLESS, AnyChannels::sort(channels.collect()))
+    }));
+    Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions(size)), exr_layers).write().to_file(file_name)
+}
+
+// RADIANCE HDR
+// writes a linear image to a .hdr file, which stores each pixel as 8-bit red, green, and blue sharing an exponent (RGBE).
+// a lighter way to keep the range of light than exr, and what most tools (and EnvironmentLight) load environment maps from
+pub fn write_hdr(file_name: &str, img: &HdrImage) -> ImageResult<()> {
+    let file = BufWriter::new(File::create(file_name)?);
+    let pixels: Vec<Rgb<f32>> = img.pixels().cloned().collect();
+    HdrEncoder::new(file).encode(&pixels, img.width() as usize, img.height() as usize)
+}
@@ -0,0 +1,85 @@
+// PREVIEW - Prints a small copy of a render straight into the terminal, to check on it over ssh without copying files
+//
+// Half blocks work in any terminal with 24-bit color: each character is an upper half block (▀) colored with one
+// pixel and backed with the one below it, so a character cell (about twice as tall as it is wide) shows two square
+// pixels. Sixel graphics draw real pixels, in the terminals that support them (xterm -ti vt340, mlterm, WezTerm, foot,
+// ...), with the colors rounded to a 6x6x6 cube.
+
+#![allow(dead_code)]
+
+use image::imageops::{resize, FilterType};
+use image::RgbImage;
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TerminalPreview {
+    HalfBlocks { columns: u32 },    // this many characters wide
+    Sixel { width: u32 },           // this many pixels wide
+}
+impl TerminalPreview {
+    // escape sequences that draw the (displayable) image, scaled down to fit
+    pub fn draw(&self, img: &RgbImage) -> String {
+        match *self {
+            TerminalPreview::HalfBlocks { columns } => half_blocks(&shrink(img, columns)),
+            TerminalPreview::Sixel { width } => sixel(&shrink(img, width)),
+        }
+    }
+}
+
+// the image scaled to a width, keeping its aspect ratio
+fn shrink(img: &RgbImage, width: u32) -> RgbImage {
+    let width = u32::max(1, u32::min(width, img.width()));
+    let height = u32::max(1, (img.height() as u64 * width as u64 / u64::max(1, img.width() as u64)) as u32);
+    resize(img, width, height, FilterType::Triangle)
+}
+
+fn half_blocks(img: &RgbImage) -> String {
+    let mut out = String::new();
+    for y in (0..img.height()).step_by(2) {
+        for x in 0..img.width() {
+            let top = img.get_pixel(x, y);
+            out += &format!("\x1b[38;2;{};{};{}m", top[0], top[1], top[2]);
+            // (an odd row out at the bottom gets the terminal's own background)
+            if y + 1 < img.height() {
+                let bottom = img.get_pixel(x, y + 1);
+                out += &format!("\x1b[48;2;{};{};{}m", bottom[0], bottom[1], bottom[2]);
+            }
+            out += "▀";
+        }
+        out += "\x1b[0m\n";
+    }
+    out
+}
+
+fn sixel(img: &RgbImage) -> String {
+    let level = |c: u8| (c as u32 * 5 + 127) / 255;
+    let index = |p: &image::Rgb<u8>| (level(p[0])*36 + level(p[1])*6 + level(p[2])) as usize;
+    // start, with square pixels, then the palette (in percent)
+    let mut out = String::from("\x1bP0;1q\"1;1");
+    for i in 0..216 {
+        out += &format!("#{};2;{};{};{}", i, i/36*20, i/6%6*20, i%6*20);
+    }
+    // bands of six rows, each drawn one color at a time over the same stretch (with repeated characters run length coded)
+    for band in (0..img.height()).step_by(6) {
+        let rows = u32::min(6, img.height() - band);
+        let mut bits = vec![vec![0u8; img.width() as usize]; 216];
+        for x in 0..img.width() {
+            for row in 0..rows {
+                bits[index(img.get_pixel(x, band + row))][x as usize] |= 1 << row;
+            }
+        }
+        for (color, columns) in bits.iter().enumerate().filter(|(_, columns)| columns.iter().any(|&b| b != 0)) {
+            out += &format!("#{}", color);
+            let mut x = 0;
+            while x < columns.len() {
+                let run = columns[x..].iter().take_while(|&&b| b == columns[x]).count();
+                let c = (63 + columns[x]) as char;
+                if run > 3 { out += &format!("!{}{}", run, c) } else { out.extend(std::iter::repeat_n(c, run)) }
+                x += run;
+            }
+            out += "$";
+        }
+        out += "-";
+    }
+    out + "\x1b\\\n"
+}
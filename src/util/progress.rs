@@ -0,0 +1,114 @@
+// PROGRESS - Shows how far along a render is, as a progress bar for people or as a stream of json for programs
+//
+// With json on (the camera's progress_json, or --progress-json on the command line), the progress bar is replaced by
+// a line of json on stderr each time a tile or pass finishes, and once more at the end, e.g.
+//     {"event":"progress","unit":"pixels","done":2560,"total":10000,"percent":25.60,"elapsed":1.52,"eta":4.42,"rays_per_second":1510254,"tile":9}
+//     {"event":"done","unit":"pixels","done":10000,"total":10000,"percent":100.00,"elapsed":6.01,"eta":0.00,"rays_per_second":1502011}
+// so a gui or render farm manager can follow a render without scraping the terminal. times are in seconds, rays per
+// second only come with renders that count their rays, and tile (its index among the image's tiles) only with
+// renders that work tile by tile. eta is from the rate since the render started, not counting work picked up from a
+// checkpoint.
+//
+// A host application can also follow the render itself through a RenderObserver on the camera, which is handed each
+// tile's pixels or the image after each pass as they finish, to stream them into its own ui or over the network.
+
+#![allow(dead_code)]
+
+use indicatif::{ProgressBar, ProgressStyle};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::time::Instant;
+
+use super::tracing::*;
+
+pub struct RenderProgress {
+    bar: ProgressBar,   // hidden when reporting json
+    json: bool,
+    unit: &'static str,
+    total: u64,
+    done: AtomicU64,
+    resumed: AtomicU64, // done before the render started (from a checkpoint)
+    rays: AtomicU64,
+    start: Instant,
+}
+impl RenderProgress {
+    // counts up to total of unit (e.g. "pixels" or "passes")
+    pub fn new(total: u64, unit: &'static str, json: bool) -> RenderProgress {
+        let bar = if json { ProgressBar::hidden() } else { ProgressBar::new(total) };
+        let template = format!("[{{elapsed_precise}}, {{eta_precise}}] {{wide_bar:.green/blue}} {{pos:>7}}/{{len:7}} {}", unit);
+        bar.set_style(ProgressStyle::default_bar().template(&template).progress_chars("##-"));
+        RenderProgress { bar, json, unit, total, done: AtomicU64::new(0), resumed: AtomicU64::new(0), rays: AtomicU64::new(0), start: Instant::now() }
+    }
+    // work finished before the render started
+    pub fn resume(&self, n: u64) {
+        self.done.fetch_add(n, Ordering::Relaxed);
+        self.resumed.fetch_add(n, Ordering::Relaxed);
+        self.bar.inc(n);
+    }
+    // more work finished, that isn't worth an event by itself (e.g. a pixel of a tile)
+    pub fn tick(&self, n: u64) {
+        self.done.fetch_add(n, Ordering::Relaxed);
+        self.bar.inc(n);
+    }
+    // more work finished, e.g. a pass
+    pub fn inc(&self, n: u64) {
+        self.tick(n);
+        self.report("progress", None);
+    }
+    // a tile finished (after ticking off its pixels), having traced this many rays
+    pub fn finish_tile(&self, tile: usize, rays: u64) {
+        self.rays.fetch_add(rays, Ordering::Relaxed);
+        self.report("progress", Some(tile));
+    }
+    pub fn finish(&self) {
+        self.bar.finish();
+        self.report("done", None);
+    }
+    // prints a message above the progress bar (or straight out when there isn't one, e.g. with the output going to a log)
+    pub fn println(&self, message: &str) {
+        if self.bar.is_hidden() { print!("{}", message) } else { self.bar.println(message) }
+    }
+
+    fn report(&self, event: &str, tile: Option<usize>) {
+        if !self.json { return }
+        let done = self.done.load(Ordering::Relaxed);
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let rate = (done - self.resumed.load(Ordering::Relaxed)) as f64 / elapsed.max(1e-6);
+        let eta = if rate > 0.0 { self.total.saturating_sub(done) as f64 / rate } else { 0.0 };
+        let mut line = format!("{{\"event\":\"{}\",\"unit\":\"{}\",\"done\":{},\"total\":{},\"percent\":{:.2},\"elapsed\":{:.2},\"eta\":{:.2}",
+            event, self.unit, done, self.total, 100.0*done as f64 / self.total.max(1) as f64, elapsed, eta);
+        let rays = self.rays.load(Ordering::Relaxed);
+        if rays > 0 {
+            line += &format!(",\"rays_per_second\":{:.0}", rays as f64 / elapsed.max(1e-6));
+        }
+        if let Some(tile) = tile {
+            line += &format!(",\"tile\":{}", tile);
+        }
+        eprintln!("{}}}", line);
+    }
+}
+
+// RENDER OBSERVER - told about the render's work as it finishes, on the rendering threads, in whatever order it finishes
+// (so keep it quick, or send the work on to another thread, like a channel does)
+pub trait RenderObserver: std::fmt::Debug {
+    // a tile finished (in renders that work tile by tile, like render_to_image), with its pixels row by row, as far as
+    // its own samples go (pixels on its edges also get samples from the tiles next to it, with wider pixel filters)
+    fn on_tile_complete(&self, _tile: &Tile, _pixels: &[Color]) {}
+    // a pass finished (in render_progressive), with the image so far
+    fn on_pass_complete(&self, _samples_per_pixel: u32, _image: &HdrImage) {}
+}
+// what a channel observer is sent
+#[derive(Debug, Clone)]
+pub enum RenderEvent {
+    TileComplete(Tile, Vec<Color>),
+    PassComplete(u32, HdrImage),
+}
+// sends events to whoever holds the receiver (and stops quietly if they hang up)
+impl RenderObserver for Sender<RenderEvent> {
+    fn on_tile_complete(&self, tile: &Tile, pixels: &[Color]) {
+        self.send(RenderEvent::TileComplete(*tile, pixels.to_vec())).ok();
+    }
+    fn on_pass_complete(&self, samples_per_pixel: u32, image: &HdrImage) {
+        self.send(RenderEvent::PassComplete(samples_per_pixel, image.clone())).ok();
+    }
+}
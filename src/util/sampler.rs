@@ -0,0 +1,583 @@
+// SAMPLER - Supplies the random numbers used while rendering
+//
+// Every random decision made for a pixel sample (where in the pixel the camera ray goes, where on the lens it starts,
+// which way each bounce scatters, how far into a volume it goes, which light gets sampled, ...) takes its numbers
+// from the scene's sampler, one dimension at a time. Swapping the sampler changes how those numbers are spread out
+// (independent random, stratified, quasi-random sequences) without touching any of the code that uses them.
+
+#![allow(dead_code)]
+
+use cgmath::*;
+use rand::Rng;
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+use super::tracing::*;
+
+// number of dimensions used by the camera for each sample (pixel offset x/y, lens position u/v, time);
+// shading picks up from the dimension after these
+pub const CAMERA_DIMENSIONS: u32 = 5;
+
+// Trait for samplers; anything that gives a number in [0, 1) for one dimension of one sample of a pixel
+pub trait Sampler: std::fmt::Debug {
+    fn get(&self, pixel: (u32, u32), index: u32, dimension: u32) -> Float;
+}
+
+// RANDOM SAMPLER - independent uniform random numbers
+// each number is a hash of the seed and which sample and dimension it's for, so the same seed always renders the
+// same image, however the pixels end up split between threads
+#[derive(Debug)]
+pub struct RandomSampler {
+    pub seed: u64,
+}
+impl Sampler for RandomSampler {
+    fn get(&self, pixel: (u32, u32), index: u32, dimension: u32) -> Float {
+        hash_to_unit(&[self.seed as u32, (self.seed >> 32) as u32, pixel.0, pixel.1, index, dimension]) as Float
+    }
+}
+
+// HALTON SAMPLER - quasi-random Halton sequence, using the d'th prime as the base of dimension d
+// any prefix of the sequence is well spread out, so it stays low noise however many samples a pixel ends up with.
+// higher bases line up badly on their own (their first points fall along diagonals), so each base's digits are
+// shuffled by a permutation. each pixel's points are also shifted by a random amount, so neighboring pixels don't
+// repeat the same pattern, and dimensions past the last prime get plain random numbers
+#[derive(Debug, Clone, Copy)]
+pub enum HaltonScrambling {
+    Faure,          // deterministic permutations that keep the digits evenly mixed
+    Random(u64),    // a random permutation per base, from a seed
+}
+#[derive(Debug)]
+pub struct HaltonSampler {
+    permutations: Vec<Vec<u16>>,    // digit permutation for each dimension's base
+}
+impl HaltonSampler {
+    const DIMENSIONS: usize = 128;
+
+    pub fn new(scrambling: HaltonScrambling) -> HaltonSampler {
+        let bases = primes(HaltonSampler::DIMENSIONS);
+        let permutations = match scrambling {
+            HaltonScrambling::Faure => {
+                let faure = faure_permutations(*bases.last().unwrap());
+                bases.iter().map(|&b| faure[b].clone()).collect()
+            }
+            HaltonScrambling::Random(seed) => {
+                let mut rng = StdRng::seed_from_u64(seed);
+                bases.iter().map(|&b| {
+                    let mut permutation: Vec<u16> = (0..b as u16).collect();
+                    permutation.shuffle(&mut rng);
+                    permutation
+                }).collect()
+            }
+        };
+        HaltonSampler { permutations }
+    }
+}
+impl Sampler for HaltonSampler {
+    fn get(&self, pixel: (u32, u32), index: u32, dimension: u32) -> Float {
+        let permutation = match self.permutations.get(dimension as usize) {
+            Some(permutation) => permutation,
+            None => return hash_to_unit(&[pixel.0, pixel.1, index, dimension]) as Float,
+        };
+        let rotation = hash_to_unit(&[pixel.0, pixel.1, dimension]);
+        (scrambled_radical_inverse(index, permutation) + rotation).fract() as Float
+    }
+}
+
+// SOBOL SAMPLER - quasi-random Sobol sequence, built from the bits of the sample index
+// every power of two prefix of the sequence is perfectly stratified in each dimension and in many of the pairs, and
+// it's much faster to generate than Halton. its points lie on a rigid binary grid though, which shows up as structured
+// patterns in gradients (defocus, soft shadows) unless the sequence is scrambled. Owen scrambling randomly flips each
+// bit of a point depending on all the bits above it, which breaks up the grid but keeps every stratification property.
+// dimensions past the end of the direction number table get plain random numbers
+#[derive(Debug, Clone, Copy)]
+pub enum SobolScrambling {
+    None,           // the plain sequence, shifted by a random amount per pixel
+    Owen(u64),      // nested uniform scrambling from a seed, different for each pixel and dimension
+}
+#[derive(Debug)]
+pub struct SobolSampler {
+    pub scrambling: SobolScrambling,
+    directions: Vec<[u32; 32]>,     // direction numbers of each dimension, one per bit of the index
+}
+impl SobolSampler {
+    pub fn new(scrambling: SobolScrambling) -> SobolSampler {
+        SobolSampler { scrambling, directions: sobol_directions() }
+    }
+}
+impl Sampler for SobolSampler {
+    fn get(&self, pixel: (u32, u32), index: u32, dimension: u32) -> Float {
+        let directions = match self.directions.get(dimension as usize) {
+            Some(directions) => directions,
+            None => return hash_to_unit(&[pixel.0, pixel.1, index, dimension]) as Float,
+        };
+        let to_unit = |bits: u32| bits as f64 / (1u64 << 32) as f64;
+        match self.scrambling {
+            SobolScrambling::None => {
+                let rotation = hash_to_unit(&[pixel.0, pixel.1, dimension]);
+                (to_unit(sobol_bits(index, directions)) + rotation).fract() as Float
+            }
+            SobolScrambling::Owen(seed) => {
+                let hash = |values: &[u32]| (hash_to_unit(values) * (1u64 << 32) as f64) as u32;
+                let (seed_lo, seed_hi) = (seed as u32, (seed >> 32) as u32);
+                // the pixel's samples are also taken in a shuffled order (the same for all dimensions), so pixels
+                // don't share the same sequence. shuffling keeps power of two prefixes whole, so they stay stratified
+                let index = owen_scramble(index, hash(&[seed_lo, seed_hi, pixel.0, pixel.1]));
+                let bits = owen_scramble(sobol_bits(index, directions), hash(&[seed_lo, seed_hi, pixel.0, pixel.1, dimension]));
+                to_unit(bits) as Float
+            }
+        }
+    }
+}
+
+// BLUE NOISE SAMPLER - gives every pixel the same sequence from another sampler, shifted by a blue noise mask
+// the shift varies smoothly in rank but never repeats nearby, so neighboring pixels' errors are spread evenly
+// rather than clumping into blotches: what noise is left at low sample counts is fine grained, looks much less
+// noisy, and is easier to blur away. meant for quasi-random samplers (a random sequence stays white noise).
+// the mask is tiled across the image and offset differently for each dimension so they don't line up
+#[derive(Debug)]
+pub struct BlueNoiseSampler {
+    pub sampler: Arc<dyn Sampler + Send + Sync>,
+    mask: Vec<Float>,     // BLUE_NOISE_SIZE x BLUE_NOISE_SIZE tile of values in [0, 1)
+}
+const BLUE_NOISE_SIZE: usize = 64;
+impl BlueNoiseSampler {
+    pub fn new(sampler: Arc<dyn Sampler + Send + Sync>) -> BlueNoiseSampler {
+        BlueNoiseSampler { sampler, mask: blue_noise_mask(BLUE_NOISE_SIZE) }
+    }
+}
+impl Sampler for BlueNoiseSampler {
+    fn get(&self, pixel: (u32, u32), index: u32, dimension: u32) -> Float {
+        let size = BLUE_NOISE_SIZE as u32;
+        let offset_x = (hash_to_unit(&[dimension, 0]) * size as f64) as u32;
+        let offset_y = (hash_to_unit(&[dimension, 1]) * size as f64) as u32;
+        let (x, y) = ((pixel.0 + offset_x) % size, (pixel.1 + offset_y) % size);
+        (self.sampler.get((0, 0), index, dimension) + self.mask[(y*size + x) as usize]).fract()
+    }
+}
+// METROPOLIS SAMPLER - a point in primary sample space (one number per dimension) that Metropolis light transport
+// wanders around (Kelemen et al. 2002, "A Simple and Robust Mutation Strategy for the Metropolis Light Transport
+// Algorithm"). each iteration either perturbs every number a little (a small step, exploring near a bright path) or
+// replaces them all with new random ones (a large step, so no part of the image gets missed), and the proposal is then
+// accepted or rejected. numbers are only mutated when they're asked for, so a path that doesn't use a dimension
+// doesn't pay for it. follows pbrt-v3's MLTSampler. the first three dimensions pick where on the image the sample
+// goes (see image_sample), so get() starts after them and ignores the pixel and sample it's asked for
+#[derive(Debug)]
+pub struct MltSampler {
+    state: Mutex<MltState>,
+    sigma: Float,                 // standard deviation of small steps
+    large_step_probability: Float,
+}
+#[derive(Debug)]
+struct MltState {
+    rng: StdRng,
+    values: Vec<PrimarySample>,
+    iteration: u64,             // (starts at 1, so new numbers are always behind it)
+    large_step: bool,
+    last_large_step: u64,       // iteration of the last accepted large step
+}
+#[derive(Debug, Clone, Copy, Default)]
+struct PrimarySample {
+    value: Float,
+    last_modified: u64,         // iteration the value was last brought up to date in
+    backup: Float,                // value and last_modified from before the current iteration, to go back to on a reject
+    backup_modified: u64,
+}
+const MLT_IMAGE_DIMENSIONS: u32 = 3;
+impl MltSampler {
+    // a new point with every number random (as if it just took a large step), from its own random sequence
+    pub fn new(seed: u64, sigma: Float, large_step_probability: Float) -> MltSampler {
+        MltSampler {
+            state: Mutex::new(MltState { rng: StdRng::seed_from_u64(seed), values: Vec::new(), iteration: 1, large_step: true, last_large_step: 0 }),
+            sigma,
+            large_step_probability,
+        }
+    }
+    // proposes the next point, to be followed by accept or reject once the path it gives has been traced
+    pub fn start_iteration(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.iteration += 1;
+        state.large_step = state.rng.gen::<Float>() < self.large_step_probability;
+    }
+    pub fn accept(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.large_step {
+            state.last_large_step = state.iteration;
+        }
+    }
+    pub fn reject(&self) {
+        let mut state = self.state.lock().unwrap();
+        let iteration = state.iteration;
+        for x in state.values.iter_mut().filter(|x| x.last_modified == iteration) {
+            x.value = x.backup;
+            x.last_modified = x.backup_modified;
+        }
+        state.iteration -= 1;
+    }
+    // where on the image the current point's sample goes, and which of the pixel's samples it is (all in [0, 1))
+    pub fn image_sample(&self) -> (Float, Float, Float) {
+        (self.value(0), self.value(1), self.value(2))
+    }
+    // a number of the current point, brought up to date with the mutations it missed
+    fn value(&self, dimension: u32) -> Float {
+        let mut state = self.state.lock().unwrap();
+        let i = dimension as usize;
+        if i >= state.values.len() {
+            state.values.resize(i+1, PrimarySample::default());
+        }
+        let (iteration, large_step, last_large_step) = (state.iteration, state.large_step, state.last_large_step);
+        // numbers untouched since the last large step are new random ones
+        if state.values[i].last_modified < last_large_step {
+            let u = state.rng.gen::<Float>();
+            state.values[i].value = u;
+            state.values[i].last_modified = last_large_step;
+        }
+        // (numbers asked for more than once in an iteration stay the same)
+        if state.values[i].last_modified < iteration {
+            let mut x = state.values[i];
+            x.backup = x.value;
+            x.backup_modified = x.last_modified;
+            if large_step {
+                x.value = state.rng.gen::<Float>();
+            }
+            else {
+                // the small steps it missed add up to one with a wider spread, wrapped around to stay in [0, 1)
+                let small_steps = (iteration - x.last_modified) as Float;
+                let (u1, u2): (Float, Float) = (state.rng.gen(), state.rng.gen());
+                let normal = Float::sqrt(-2.0*Float::max(1.0 - u1, Float::MIN_POSITIVE).ln()) * (2.0*consts::PI*u2).cos();
+                x.value += normal*self.sigma*small_steps.sqrt();
+                x.value -= x.value.floor();
+            }
+            x.last_modified = iteration;
+            state.values[i] = x;
+        }
+        state.values[i].value
+    }
+}
+impl Sampler for MltSampler {
+    fn get(&self, _pixel: (u32, u32), _index: u32, dimension: u32) -> Float {
+        self.value(dimension + MLT_IMAGE_DIMENSIONS)
+    }
+}
+
+// SHIFTED SAMPLER - another sampler's numbers, moved over by some pixels, so each pixel replays the numbers of the one
+// offset pixels before it. gradient-domain path tracing traces a neighbor's paths with these, so they take the same
+// random decisions as the pixel's own paths and only differ by where they start
+#[derive(Debug)]
+pub struct ShiftedSampler {
+    pub sampler: Arc<dyn Sampler + Send + Sync>,
+    pub offset: (i32, i32),
+}
+impl Sampler for ShiftedSampler {
+    fn get(&self, pixel: (u32, u32), index: u32, dimension: u32) -> Float {
+        let pixel = (pixel.0.wrapping_add_signed(-self.offset.0), pixel.1.wrapping_add_signed(-self.offset.1));
+        self.sampler.get(pixel, index, dimension)
+    }
+}
+
+// SKIPPED SAMPLER - another sampler's numbers, starting some samples into each pixel's sequence. temporal accumulation
+// renders each frame with these, so a pixel's frames carry on its sequence instead of repeating the same noise
+#[derive(Debug)]
+pub struct SkippedSampler {
+    pub sampler: Arc<dyn Sampler + Send + Sync>,
+    pub skip: u32,
+}
+impl Sampler for SkippedSampler {
+    fn get(&self, pixel: (u32, u32), index: u32, dimension: u32) -> Float {
+        self.sampler.get(pixel, index.wrapping_add(self.skip), dimension)
+    }
+}
+
+// builds a tileable blue noise mask with the void and cluster method (Ulichney 1993):
+// pixels are ranked by repeatedly taking the one in the tightest cluster of the pattern (or the emptiest void of its
+// inverse), where closeness is measured by summing a gaussian of each pixel's distance to every set pixel
+fn blue_noise_mask(size: usize) -> Vec<Float> {
+    let n = size*size;
+    // gaussian falloff for each (wrapped) offset between two pixels
+    let sigma = 1.5;
+    let kernel: Vec<Float> = (0..n).map(|i| {
+        let wrap = |d: usize| usize::min(d, size - d) as Float;
+        let (dx, dy) = (wrap(i % size), wrap(i / size));
+        Float::exp(-(dx*dx + dy*dy)/(2.0*sigma*sigma))
+    }).collect();
+    let toggle = |energy: &mut Vec<Float>, p: usize, sign: Float| {
+        let (px, py) = (p % size, p / size);
+        for (i, e) in energy.iter_mut().enumerate() {
+            let (dx, dy) = ((i % size + size - px) % size, (i / size + size - py) % size);
+            *e += sign*kernel[dy*size + dx];
+        }
+    };
+    // tightest cluster = set pixel with the highest energy, largest void = unset pixel with the lowest
+    let extreme = |pattern: &Vec<bool>, energy: &Vec<Float>, set: bool| -> usize {
+        let candidates = (0..n).filter(|&i| pattern[i] == set);
+        if set { candidates.max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap() }
+        else { candidates.min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap() }
+    };
+
+    // start from a sparse random pattern and move points from clusters into voids until it's evenly spread
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let initial_count = n/10;
+    while pattern.iter().filter(|&&set| set).count() < initial_count {
+        let p = rng.gen_range(0..n);
+        if !pattern[p] {
+            pattern[p] = true;
+            toggle(&mut energy, p, 1.0);
+        }
+    }
+    loop {
+        let cluster = extreme(&pattern, &energy, true);
+        pattern[cluster] = false;
+        toggle(&mut energy, cluster, -1.0);
+        let void = extreme(&pattern, &energy, false);
+        pattern[void] = true;
+        toggle(&mut energy, void, 1.0);
+        if void == cluster { break }
+    }
+
+    let mut ranks = vec![0; n];
+    // the initial points are ranked from the last removed, then every other pixel from the first added
+    let (initial_pattern, initial_energy) = (pattern.clone(), energy.clone());
+    for rank in (0..initial_count).rev() {
+        let cluster = extreme(&pattern, &energy, true);
+        pattern[cluster] = false;
+        toggle(&mut energy, cluster, -1.0);
+        ranks[cluster] = rank;
+    }
+    let (mut pattern, mut energy) = (initial_pattern, initial_energy);
+    for rank in initial_count..n {
+        let void = extreme(&pattern, &energy, false);
+        pattern[void] = true;
+        toggle(&mut energy, void, 1.0);
+        ranks[void] = rank;
+    }
+    ranks.iter().map(|&rank| (rank as Float + 0.5) / n as Float).collect()
+}
+// digits of the index in base b (b = permutation length), permuted and mirrored around the decimal point
+fn scrambled_radical_inverse(index: u32, permutation: &[u16]) -> f64 {
+    let base = permutation.len() as u64;
+    let inv_base = 1.0 / base as f64;
+    let mut index = index as u64;
+    let mut reversed_digits = 0;
+    let mut inv_base_n = 1.0;
+    while index > 0 {
+        reversed_digits = reversed_digits*base + permutation[(index % base) as usize] as u64;
+        inv_base_n *= inv_base;
+        index /= base;
+    }
+    // the infinitely many leading zeros of the index are permuted too, which adds a geometric series
+    inv_base_n*(reversed_digits as f64 + inv_base*permutation[0] as f64/(1.0 - inv_base))
+}
+// primitive polynomials and initial direction numbers for Sobol dimensions 1 and up, from Joe and Kuo 2008,
+// "Constructing Sobol sequences with better two-dimensional projections" (new-joe-kuo-6.21201).
+// each entry is the polynomial's degree s, its inner coefficients a as bits, and the first s direction numbers m
+const SOBOL_POLYNOMIALS: &[(u32, u32, &[u32])] = &[
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+    (5, 11, &[1, 1, 5, 1, 1]),
+    (5, 13, &[1, 1, 1, 3, 11]),
+    (5, 14, &[1, 3, 5, 5, 31]),
+    (6, 1, &[1, 3, 3, 9, 7, 49]),
+    (6, 13, &[1, 1, 1, 15, 21, 21]),
+    (6, 16, &[1, 3, 1, 13, 27, 49]),
+    (6, 19, &[1, 1, 1, 15, 7, 5]),
+    (6, 22, &[1, 3, 1, 15, 13, 25]),
+    (6, 25, &[1, 1, 5, 5, 19, 61]),
+    (7, 1, &[1, 3, 7, 11, 23, 15, 103]),
+    (7, 4, &[1, 3, 7, 13, 13, 15, 69]),
+    (7, 7, &[1, 1, 3, 13, 7, 35, 63]),
+    (7, 8, &[1, 3, 5, 9, 1, 25, 53]),
+    (7, 14, &[1, 3, 1, 13, 9, 35, 107]),
+    (7, 19, &[1, 3, 1, 5, 27, 61, 31]),
+    (7, 21, &[1, 1, 5, 11, 19, 41, 61]),
+    (7, 28, &[1, 3, 5, 3, 3, 13, 69]),
+    (7, 31, &[1, 1, 7, 13, 1, 19, 1]),
+    (7, 32, &[1, 3, 7, 5, 13, 19, 59]),
+    (7, 37, &[1, 1, 3, 9, 25, 29, 41]),
+    (7, 41, &[1, 3, 5, 13, 23, 1, 55]),
+    (7, 42, &[1, 3, 7, 3, 13, 59, 17]),
+    (7, 50, &[1, 3, 1, 3, 5, 53, 69]),
+    (7, 55, &[1, 1, 5, 5, 23, 33, 13]),
+    (7, 56, &[1, 1, 7, 7, 1, 61, 123]),
+    (7, 59, &[1, 1, 7, 9, 13, 61, 49]),
+    (7, 62, &[1, 3, 3, 5, 3, 55, 33]),
+    (8, 14, &[1, 3, 1, 15, 31, 13, 49, 245]),
+    (8, 21, &[1, 3, 5, 15, 31, 59, 63, 97]),
+    (8, 22, &[1, 3, 1, 11, 11, 11, 77, 249]),
+];
+// direction numbers (as 32 bit binary fractions) for each Sobol dimension. dimension 0 is the van der Corput
+// sequence, and the rest follow the recurrence from their polynomial (Bratley and Fox 1988)
+fn sobol_directions() -> Vec<[u32; 32]> {
+    let mut directions = vec![std::array::from_fn(|k| 1u32 << (31 - k))];
+    for &(s, a, m) in SOBOL_POLYNOMIALS {
+        let s = s as usize;
+        let mut v = [0u32; 32];
+        for k in 0..32 {
+            v[k] = if k < s { m[k] << (31 - k) }
+                else {
+                    let mut x = v[k-s] ^ (v[k-s] >> s);
+                    for j in 1..s {
+                        if (a >> (s - 1 - j)) & 1 == 1 { x ^= v[k-j] }
+                    }
+                    x
+                };
+        }
+        directions.push(v);
+    }
+    directions
+}
+// point of the Sobol sequence with the given direction numbers, as a 32 bit binary fraction
+fn sobol_bits(index: u32, directions: &[u32; 32]) -> u32 {
+    let mut bits = 0;
+    let mut index = index;
+    let mut k = 0;
+    while index > 0 {
+        if index & 1 == 1 { bits ^= directions[k] }
+        index >>= 1;
+        k += 1;
+    }
+    bits
+}
+// nested uniform (Owen) scramble of a 32 bit binary fraction, where each bit is flipped by a hash of the seed and
+// the bits above it. done with a hash that only carries from low bits to high, on the reversed bits.
+// from Burley 2020, "Practical Hash-based Owen Scrambling", with Vegdahl's improved hash
+fn owen_scramble(bits: u32, seed: u32) -> u32 {
+    let mut x = bits.reverse_bits();
+    x ^= x.wrapping_mul(0x3d20adea);
+    x = x.wrapping_add(seed);
+    x = x.wrapping_mul((seed >> 16) | 1);
+    x ^= x.wrapping_mul(0x05526c56);
+    x ^= x.wrapping_mul(0x53a22864);
+    x.reverse_bits()
+}
+// first n prime numbers
+fn primes(n: usize) -> Vec<usize> {
+    let mut primes: Vec<usize> = Vec::new();
+    let mut candidate = 2;
+    while primes.len() < n {
+        if primes.iter().all(|p| candidate % p != 0) {
+            primes.push(candidate);
+        }
+        candidate += 1;
+    }
+    primes
+}
+// Faure's permutations of 0..b for every b up to max_base, built up from smaller bases
+// (even bases interleave two copies of half the base, odd bases insert their middle digit into the base below)
+fn faure_permutations(max_base: usize) -> Vec<Vec<u16>> {
+    let mut permutations: Vec<Vec<u16>> = vec![vec![], vec![0], vec![0, 1]];
+    for b in 3..=max_base {
+        let permutation = if b % 2 == 0 {
+            let half = &permutations[b/2];
+            half.iter().map(|&d| 2*d).chain(half.iter().map(|&d| 2*d + 1)).collect()
+        }
+        else {
+            let middle = ((b-1)/2) as u16;
+            let mut permutation: Vec<u16> = permutations[b-1].iter().map(|&d| if d >= middle { d + 1 } else { d }).collect();
+            permutation.insert(b/2, middle);
+            permutation
+        };
+        permutations.push(permutation);
+    }
+    permutations
+}
+// hashes a few integers together into a number in [0, 1)
+fn hash_to_unit(values: &[u32]) -> f64 {
+    let mut h: u64 = 0x9e3779b97f4a7c15;
+    for &v in values {
+        // splitmix64 finalizer
+        h = (h ^ v as u64).wrapping_add(0x9e3779b97f4a7c15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+        h ^= h >> 31;
+    }
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+////////////////////////////////////////////////////////
+/////   CURRENT SAMPLE
+////////////////////////////////////////////////////////
+// the sample being rendered on each thread, and the next dimension it will hand out
+struct SampleState {
+    sampler: Arc<dyn Sampler + Send + Sync>,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32,
+    dispersion_channel: Option<usize>,  // color channel the sample's path was narrowed to by a dispersive surface, if it met one
+}
+// (random numbers asked for outside of a render, e.g. while building a scene, come from one fixed random sequence,
+// so the scene is set up the same way every run)
+thread_local! {
+    static CURRENT_SAMPLE: RefCell<SampleState> = RefCell::new(SampleState {
+        sampler: Arc::new(RandomSampler { seed: 0 }),
+        pixel: (0, 0),
+        index: 0,
+        dimension: 0,
+        dispersion_channel: None,
+    });
+}
+// makes this thread's random numbers come from one sample of a pixel, starting at the given dimension
+pub fn start_sample(sampler: &Arc<dyn Sampler + Send + Sync>, pixel: (u32, u32), index: u32, dimension: u32) {
+    CURRENT_SAMPLE.with(|s| {
+        let mut s = s.borrow_mut();
+        s.sampler = sampler.clone();
+        s.pixel = pixel;
+        s.index = index;
+        s.dimension = dimension;
+        s.dispersion_channel = None;
+    });
+}
+// where a sample's random numbers are up to, so a path can be put aside and carried on with later (see shade_wavefront)
+#[derive(Debug, Clone, Copy)]
+pub struct SampleCursor {
+    pub pixel: (u32, u32),
+    pub index: u32,
+    pub dimension: u32,
+    dispersion_channel: Option<usize>,
+}
+// where this thread's current sample is up to
+pub fn save_sample() -> SampleCursor {
+    CURRENT_SAMPLE.with(|s| {
+        let s = s.borrow();
+        SampleCursor { pixel: s.pixel, index: s.index, dimension: s.dimension, dispersion_channel: s.dispersion_channel }
+    })
+}
+// makes this thread's random numbers carry on from a saved point of a sample
+pub fn resume_sample(sampler: &Arc<dyn Sampler + Send + Sync>, cursor: SampleCursor) {
+    start_sample(sampler, cursor.pixel, cursor.index, cursor.dimension);
+    CURRENT_SAMPLE.with(|s| s.borrow_mut().dispersion_channel = cursor.dispersion_channel);
+}
+// next number in [0, 1) of this thread's current sample
+pub fn rand_f32() -> Float {
+    CURRENT_SAMPLE.with(|s| {
+        let mut s = s.borrow_mut();
+        let u = s.sampler.get(s.pixel, s.index, s.dimension);
+        s.dimension += 1;
+        // samplers working in f32 can round up to exactly 1
+        Float::min(u, 1.0 - Float::EPSILON)
+    })
+}
+// color channel (0 red, 1 green, 2 blue) the current sample's path follows once a dispersive surface splits its light
+// up by wavelength, picked at random the first time it's asked for. also returns whether it was only just picked, since
+// that's when the path's other two channels are dropped and the one it follows has to carry their share of the light
+pub fn dispersion_channel() -> (usize, bool) {
+    if let Some(channel) = CURRENT_SAMPLE.with(|s| s.borrow().dispersion_channel) {
+        return (channel, false);
+    }
+    let channel = usize::min((rand_f32()*3.0) as usize, 2);
+    CURRENT_SAMPLE.with(|s| s.borrow_mut().dispersion_channel = Some(channel));
+    (channel, true)
+}
+// next two numbers of this thread's current sample
+pub fn rand_vec2() -> Vec2 {
+    let u = rand_f32();
+    vec2(u, rand_f32())
+}
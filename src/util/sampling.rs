@@ -0,0 +1,180 @@
+// SAMPLING - Turns uniform random numbers (from the sampler) into points and directions, along with their pdfs
+//
+// Directions are sampled in a local frame around +z (the surface normal, or the axis of a cone) and moved into
+// world space with a Frame. Every mapping is direct rather than rejection based, so each sample takes a fixed
+// number of the sampler's dimensions.
+
+#![allow(dead_code)]
+
+use cgmath::*;
+use super::tracing::consts::PI;
+
+use super::tracing::*;
+use super::materials::{ggx_d, ggx_g1};
+
+// ORTHONORMAL FRAME - moves directions into and out of a local space where the given normal is +z
+pub struct Frame {
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    pub normal: Vec3,
+}
+impl Frame {
+    pub fn new(n: Vec3) -> Frame {
+        // branchless basis from Duff et al. 2017, "Building an Orthonormal Basis, Revisited"
+        let sign = Float::copysign(1.0, n.z);
+        let a = -1.0 / (sign + n.z);
+        let b = n.x*n.y*a;
+        Frame {
+            tangent: vec3(1.0 + sign*n.x*n.x*a, sign*b, -sign*n.x),
+            bitangent: vec3(b, sign + n.y*n.y*a, -n.y),
+            normal: n,
+        }
+    }
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        vec3(v.dot(self.tangent), v.dot(self.bitangent), v.dot(self.normal))
+    }
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        v.x*self.tangent + v.y*self.bitangent + v.z*self.normal
+    }
+}
+
+// DISK
+// uniform point in the unit disk, using the concentric mapping (Shirley and Chiu 1997), which keeps
+// neighboring samples neighbors on the disk so stratified numbers stay stratified
+pub fn sample_uniform_disk(u: Vec2) -> Vec2 {
+    let offset = 2.0*u - vec2(1.0, 1.0);
+    if offset.x == 0.0 && offset.y == 0.0 { return Vec2::zero() }
+    let (r, theta) = if offset.x.abs() > offset.y.abs() {
+        (offset.x, 0.25*PI*(offset.y/offset.x))
+    } else {
+        (offset.y, 0.5*PI - 0.25*PI*(offset.x/offset.y))
+    };
+    r*vec2(theta.cos(), theta.sin())
+}
+
+// REGULAR POLYGON
+// uniform point in the regular polygon with the given number of sides inscribed in the unit circle, with its first
+// corner turned counterclockwise from +x by rotation (radians). picks one of the equal triangles between the center
+// and two neighboring corners with the first number, then a point in it
+pub fn sample_regular_polygon(u: Vec2, sides: u32, rotation: Float) -> Vec2 {
+    let scaled = u.x*sides as Float;
+    let side = Float::min(scaled.floor(), sides as Float - 1.0);
+    let corner = |k: Float| {
+        let angle = rotation + 2.0*PI*k/sides as Float;
+        vec2(angle.cos(), angle.sin())
+    };
+    // uniform point in the triangle (center, corner k, corner k+1), reusing what's left of the first number
+    let (a, b) = (corner(side), corner(side + 1.0));
+    let s = (scaled - side).sqrt();
+    s*((1.0 - u.y)*a + u.y*b)
+}
+
+// SPHERE
+// uniform direction over the whole sphere
+pub fn sample_uniform_sphere(u: Vec2) -> Vec3 {
+    let z = 1.0 - 2.0*u.x;
+    let r = Float::sqrt(Float::max(0.0, 1.0 - z*z));
+    let phi = 2.0*PI*u.y;
+    vec3(r*phi.cos(), r*phi.sin(), z)
+}
+pub fn uniform_sphere_pdf() -> Float {
+    1.0/(4.0*PI)
+}
+// uniform point inside the unit ball (a direction, pushed in by the cube root of the third number)
+pub fn sample_uniform_ball(u: Vec2, u_radius: Float) -> Vec3 {
+    u_radius.cbrt()*sample_uniform_sphere(u)
+}
+
+// HEMISPHERE
+// uniform direction over the hemisphere around +z
+pub fn sample_uniform_hemisphere(u: Vec2) -> Vec3 {
+    let z = u.x;
+    let r = Float::sqrt(Float::max(0.0, 1.0 - z*z));
+    let phi = 2.0*PI*u.y;
+    vec3(r*phi.cos(), r*phi.sin(), z)
+}
+pub fn uniform_hemisphere_pdf() -> Float {
+    1.0/(2.0*PI)
+}
+// direction over the hemisphere around +z with density proportional to its cosine, which cancels the cosine in
+// the rendering equation (so diffuse bounces waste fewer samples on grazing directions that carry little light)
+// points on the disk are projected up onto the hemisphere (Malley's method)
+pub fn sample_cosine_hemisphere(u: Vec2) -> Vec3 {
+    let d = sample_uniform_disk(u);
+    vec3(d.x, d.y, Float::sqrt(Float::max(0.0, 1.0 - d.x*d.x - d.y*d.y)))
+}
+pub fn cosine_hemisphere_pdf(cos_theta: Float) -> Float {
+    Float::max(cos_theta, 0.0)/PI
+}
+
+// CONE
+// uniform direction in the cone around +z whose half angle has cosine 1 - cone_size
+// (the cone is given by 1 - cos rather than cos so narrow cones, like the sun's, stay accurate)
+pub fn sample_cone(u: Vec2, cone_size: Float) -> Vec3 {
+    let cos_theta = 1.0 - u.x*cone_size;
+    let sin_theta = Float::sqrt(Float::max(0.0, 1.0 - cos_theta*cos_theta));
+    let phi = 2.0*PI*u.y;
+    vec3(sin_theta*phi.cos(), sin_theta*phi.sin(), cos_theta)
+}
+pub fn cone_pdf(cone_size: Float) -> Float {
+    1.0/(2.0*PI*cone_size)
+}
+
+// GGX
+// samples a microfacet normal visible from wo (wo must be in the upper hemisphere)
+// from Heitz 2018, "Sampling the GGX Distribution of Visible Normals"
+pub fn ggx_sample_vndf(wo: Vec3, alpha: Float, u: Vec2) -> Vec3 {
+    // stretch view direction so the distribution becomes a hemisphere
+    let vh = vec3(alpha*wo.x, alpha*wo.y, wo.z).normalize();
+    // orthonormal basis around the stretched view direction
+    let len2 = vh.x*vh.x + vh.y*vh.y;
+    let t1 = if len2 > 0.0 { vec3(-vh.y, vh.x, 0.0) / len2.sqrt() } else { vec3(1.0, 0.0, 0.0) };
+    let t2 = vh.cross(t1);
+    // sample a point on the projected hemisphere
+    let r = u.x.sqrt();
+    let phi = 2.0*PI*u.y;
+    let p1 = r*phi.cos();
+    let s = 0.5*(1.0 + vh.z);
+    let p2 = (1.0-s)*Float::sqrt(1.0 - p1*p1) + s*r*phi.sin();
+    let nh = p1*t1 + p2*t2 + Float::sqrt(Float::max(0.0, 1.0 - p1*p1 - p2*p2))*vh;
+    // unstretch back to the ellipsoid
+    vec3(alpha*nh.x, alpha*nh.y, Float::max(nh.z, 0.000001)).normalize()
+}
+// pdf of sampling the microfacet normal h with ggx_sample_vndf
+pub fn ggx_vndf_pdf(wo: Vec3, h: Vec3, alpha: Float) -> Float {
+    if wo.z <= 0.0 { return 0.0 }
+    ggx_g1(wo, alpha) * Float::max(0.0, wo.dot(h)) * ggx_d(h, alpha) / wo.z
+}
+// pdf of the reflected direction wi when reflecting wo about a vndf-sampled microfacet normal
+pub fn ggx_reflection_pdf(wo: Vec3, wi: Vec3, alpha: Float) -> Float {
+    if wi.z <= 0.0 { return 0.0 }
+    let h = (wo + wi).normalize();
+    let o_dot_h = wo.dot(h);
+    if o_dot_h <= 0.0 { return 0.0 }
+    ggx_vndf_pdf(wo, h, alpha) / (4.0*o_dot_h)
+}
+
+// TABULATED
+// running sum of weights normalized to end at 1 (uniform if they're all zero), and their total
+pub fn build_cdf(weights: &[Float]) -> (Vec<Float>, Float) {
+    let n = weights.len();
+    let mut cdf = Vec::with_capacity(n+1);
+    cdf.push(0.0);
+    let mut total = 0.0;
+    for &w in weights {
+        total += w;
+        cdf.push(total);
+    }
+    for (i, c) in cdf.iter_mut().enumerate() {
+        *c = if total > 0.0 { *c / total } else { i as Float / n as Float };
+    }
+    cdf[n] = 1.0;
+    (cdf, total)
+}
+// finds the bin of a cdf that u (in [0,1)) falls in, and how far into the bin it is
+pub fn sample_cdf(cdf: &[Float], u: Float) -> (usize, Float) {
+    let i = usize::min(cdf.partition_point(|&c| c <= u).saturating_sub(1), cdf.len()-2);
+    let width = cdf[i+1] - cdf[i];
+    let t = if width > 0.0 { (u - cdf[i]) / width } else { 0.5 };
+    (i, t.clamp(0.0, 0.9999))
+}
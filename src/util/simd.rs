@@ -0,0 +1,190 @@
+// SIMD - Four wide float vectors for testing several rays against the same box or triangle at once
+//
+// Plain arrays with every operation written lane by lane, which the compiler turns into sse/neon instructions
+// (std::simd isn't available on stable rust). Lanes line up with each other, so lane i of a Vec3x4 is lane i's
+// vector, and lane i of a Ray4 is the i-th ray of the group.
+
+#![allow(dead_code)]
+
+use std::ops::{Add, Sub, Mul, Div, Neg, BitAnd, BitOr, Not};
+use cgmath::*;
+
+use super::tracing::*;
+
+pub const LANES: usize = 4;
+
+// four Floats operated on together
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C, align(16))]
+pub struct Floatx4(pub [Float; LANES]);
+impl Floatx4 {
+    pub fn splat(v: Float) -> Floatx4 {
+        Floatx4([v; LANES])
+    }
+    pub fn from_fn(f: impl FnMut(usize) -> Float) -> Floatx4 {
+        Floatx4(std::array::from_fn(f))
+    }
+    fn map2(self, other: Floatx4, f: impl Fn(Float, Float) -> Float) -> Floatx4 {
+        Floatx4::from_fn(|i| f(self.0[i], other.0[i]))
+    }
+    fn compare(self, other: Floatx4, f: impl Fn(Float, Float) -> bool) -> Mask4 {
+        Mask4(std::array::from_fn(|i| f(self.0[i], other.0[i])))
+    }
+    // lane-wise Float::min/max (so a NaN lane takes the other value)
+    pub fn min(self, other: Floatx4) -> Floatx4 {
+        self.map2(other, Float::min)
+    }
+    pub fn max(self, other: Floatx4) -> Floatx4 {
+        self.map2(other, Float::max)
+    }
+    pub fn lt(self, other: Floatx4) -> Mask4 {
+        self.compare(other, |a, b| a < b)
+    }
+    pub fn le(self, other: Floatx4) -> Mask4 {
+        self.compare(other, |a, b| a <= b)
+    }
+    pub fn gt(self, other: Floatx4) -> Mask4 {
+        self.compare(other, |a, b| a > b)
+    }
+    pub fn ge(self, other: Floatx4) -> Mask4 {
+        self.compare(other, |a, b| a >= b)
+    }
+    pub fn abs(self) -> Floatx4 {
+        Floatx4::from_fn(|i| self.0[i].abs())
+    }
+    pub fn recip(self) -> Floatx4 {
+        Floatx4::from_fn(|i| 1.0 / self.0[i])
+    }
+    // largest and smallest of the first n lanes
+    pub fn max_of(self, n: usize) -> Float {
+        self.0[..n].iter().fold(Float::NEG_INFINITY, |a, &b| Float::max(a, b))
+    }
+    pub fn min_of(self, n: usize) -> Float {
+        self.0[..n].iter().fold(Float::INFINITY, |a, &b| Float::min(a, b))
+    }
+}
+impl Add for Floatx4 {
+    type Output = Floatx4;
+    fn add(self, other: Floatx4) -> Floatx4 { self.map2(other, |a, b| a + b) }
+}
+impl Sub for Floatx4 {
+    type Output = Floatx4;
+    fn sub(self, other: Floatx4) -> Floatx4 { self.map2(other, |a, b| a - b) }
+}
+impl Mul for Floatx4 {
+    type Output = Floatx4;
+    fn mul(self, other: Floatx4) -> Floatx4 { self.map2(other, |a, b| a * b) }
+}
+impl Div for Floatx4 {
+    type Output = Floatx4;
+    fn div(self, other: Floatx4) -> Floatx4 { self.map2(other, |a, b| a / b) }
+}
+impl Neg for Floatx4 {
+    type Output = Floatx4;
+    fn neg(self) -> Floatx4 { Floatx4::from_fn(|i| -self.0[i]) }
+}
+
+// per-lane true/false, from comparing Floatx4s
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mask4(pub [bool; LANES]);
+impl Mask4 {
+    pub fn splat(v: bool) -> Mask4 {
+        Mask4([v; LANES])
+    }
+    // the first n lanes set
+    pub fn first(n: usize) -> Mask4 {
+        Mask4(std::array::from_fn(|i| i < n))
+    }
+    pub fn any(self) -> bool {
+        self.0.iter().any(|&b| b)
+    }
+    pub fn count(self) -> u64 {
+        self.0.iter().filter(|&&b| b).count() as u64
+    }
+    // picks a's lane where the mask is set and b's where it isn't
+    pub fn select(self, a: Floatx4, b: Floatx4) -> Floatx4 {
+        Floatx4::from_fn(|i| if self.0[i] { a.0[i] } else { b.0[i] })
+    }
+}
+impl BitAnd for Mask4 {
+    type Output = Mask4;
+    fn bitand(self, other: Mask4) -> Mask4 { Mask4(std::array::from_fn(|i| self.0[i] & other.0[i])) }
+}
+impl BitOr for Mask4 {
+    type Output = Mask4;
+    fn bitor(self, other: Mask4) -> Mask4 { Mask4(std::array::from_fn(|i| self.0[i] | other.0[i])) }
+}
+impl Not for Mask4 {
+    type Output = Mask4;
+    fn not(self) -> Mask4 { Mask4(self.0.map(|b| !b)) }
+}
+
+// four Vec3s stored component by component
+#[derive(Debug, Clone, Copy)]
+pub struct Vec3x4 {
+    pub x: Floatx4,
+    pub y: Floatx4,
+    pub z: Floatx4,
+}
+impl Vec3x4 {
+    pub fn splat(v: Vec3) -> Vec3x4 {
+        Vec3x4 { x: Floatx4::splat(v.x), y: Floatx4::splat(v.y), z: Floatx4::splat(v.z) }
+    }
+    pub fn from_fn(mut f: impl FnMut(usize) -> Vec3) -> Vec3x4 {
+        let v: [Vec3; LANES] = std::array::from_fn(&mut f);
+        Vec3x4 {
+            x: Floatx4::from_fn(|i| v[i].x),
+            y: Floatx4::from_fn(|i| v[i].y),
+            z: Floatx4::from_fn(|i| v[i].z),
+        }
+    }
+    pub fn lane(&self, i: usize) -> Vec3 {
+        vec3(self.x.0[i], self.y.0[i], self.z.0[i])
+    }
+    // same operation order as cgmath's, so lanes match the scalar results exactly
+    pub fn dot(self, other: Vec3x4) -> Floatx4 {
+        self.x*other.x + self.y*other.y + self.z*other.z
+    }
+    pub fn cross(self, other: Vec3x4) -> Vec3x4 {
+        Vec3x4 {
+            x: self.y*other.z - self.z*other.y,
+            y: self.z*other.x - self.x*other.z,
+            z: self.x*other.y - self.y*other.x,
+        }
+    }
+}
+impl Sub for Vec3x4 {
+    type Output = Vec3x4;
+    fn sub(self, other: Vec3x4) -> Vec3x4 {
+        Vec3x4 { x: self.x - other.x, y: self.y - other.y, z: self.z - other.z }
+    }
+}
+
+// up to four rays traced together, with their inverse directions for box tests
+// lanes past the number of rays are copies of the first ray and are left out by the active mask
+#[derive(Debug, Clone, Copy)]
+pub struct Ray4 {
+    pub origin: Vec3x4,
+    pub direction: Vec3x4,
+    pub inv_direction: Vec3x4,
+    pub time: Floatx4,
+    pub active: Mask4,
+}
+impl Ray4 {
+    // packs rays[indices[i]] into lane i (indices must have 1 to 4 entries)
+    pub fn gather(rays: &[Ray], indices: &[usize]) -> Ray4 {
+        let ray = |i: usize| &rays[indices[if i < indices.len() { i } else { 0 }]];
+        let direction = Vec3x4::from_fn(|i| ray(i).direction);
+        Ray4 {
+            origin: Vec3x4::from_fn(|i| ray(i).origin),
+            direction,
+            inv_direction: Vec3x4 { x: direction.x.recip(), y: direction.y.recip(), z: direction.z.recip() },
+            time: Floatx4::from_fn(|i| ray(i).time),
+            active: Mask4::first(indices.len()),
+        }
+    }
+    // lane i as a ray of its own
+    pub fn ray(&self, i: usize) -> Ray {
+        Ray { origin: self.origin.lane(i), direction: self.direction.lane(i), time: self.time.0[i] }
+    }
+}
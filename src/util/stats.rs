@@ -0,0 +1,90 @@
+// STATS - Counters for the work a render does (rays traced, acceleration structure tests, texture lookups)
+//
+// Counts are kept per thread, so counting stays cheap while rendering in parallel. Whoever did the work collects them
+// with take_ray_stats (for renders, once per pixel, which also gives the per-region breakdowns).
+
+#![allow(dead_code)]
+
+use std::cell::Cell;
+use std::ops::{Add, AddAssign};
+use std::iter::Sum;
+use ::tracing::info;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RayStats {
+    pub primary_rays: u64,      // rays generated by the camera
+    pub closest_hit_rays: u64,  // rays traced through the scene for their closest hit, camera rays included
+    pub shadow_rays: u64,       // rays traced towards lights to see if anything is in the way
+    pub aabb_tests: u64,        // ray-bounding box tests, in bvhs and grids
+    pub triangle_tests: u64,    // ray-triangle tests
+    pub texture_samples: u64,   // lookups into image textures
+}
+impl RayStats {
+    // closest hit rays that didn't come from the camera (bounces, photons, and rays traced again past cutouts)
+    pub fn secondary_rays(&self) -> u64 {
+        self.closest_hit_rays.saturating_sub(self.primary_rays)
+    }
+    pub fn total_rays(&self) -> u64 {
+        self.closest_hit_rays + self.shadow_rays
+    }
+    // logs the counts as one event, with rays per second over the given time, e.g. log(seconds, "ray statistics")
+    pub fn log(&self, seconds: f32, what: &str) {
+        let rays = self.total_rays();
+        let per_ray = |count: u64| count as f32 / u64::max(rays, 1) as f32;
+        info!(
+            seconds,
+            primary_rays = self.primary_rays,
+            secondary_rays = self.secondary_rays(),
+            shadow_rays = self.shadow_rays,
+            total_rays = rays,
+            million_rays_per_second = rays as f32 / seconds.max(1e-6) / 1e6,
+            aabb_tests = self.aabb_tests,
+            aabb_tests_per_ray = per_ray(self.aabb_tests),
+            triangle_tests = self.triangle_tests,
+            triangle_tests_per_ray = per_ray(self.triangle_tests),
+            texture_samples = self.texture_samples,
+            "{}", what
+        );
+    }
+}
+impl Add for RayStats {
+    type Output = RayStats;
+    fn add(self, other: RayStats) -> RayStats {
+        RayStats {
+            primary_rays: self.primary_rays + other.primary_rays,
+            closest_hit_rays: self.closest_hit_rays + other.closest_hit_rays,
+            shadow_rays: self.shadow_rays + other.shadow_rays,
+            aabb_tests: self.aabb_tests + other.aabb_tests,
+            triangle_tests: self.triangle_tests + other.triangle_tests,
+            texture_samples: self.texture_samples + other.texture_samples,
+        }
+    }
+}
+impl AddAssign for RayStats {
+    fn add_assign(&mut self, other: RayStats) {
+        *self = *self + other;
+    }
+}
+impl Sum for RayStats {
+    fn sum<I: Iterator<Item = RayStats>>(iter: I) -> RayStats {
+        iter.fold(RayStats::default(), |a, b| a + b)
+    }
+}
+
+thread_local! {
+    static RAY_STATS: Cell<RayStats> = const { Cell::new(RayStats {
+        primary_rays: 0, closest_hit_rays: 0, shadow_rays: 0, aabb_tests: 0, triangle_tests: 0, texture_samples: 0,
+    }) };
+}
+// adds to this thread's counts, e.g. count_ray_stats(|stats| stats.shadow_rays += 1)
+pub fn count_ray_stats(f: impl FnOnce(&mut RayStats)) {
+    RAY_STATS.with(|cell| {
+        let mut stats = cell.get();
+        f(&mut stats);
+        cell.set(stats);
+    });
+}
+// returns the counts for work done on this thread since the last take, and zeroes them
+pub fn take_ray_stats() -> RayStats {
+    RAY_STATS.with(|cell| cell.replace(RayStats::default()))
+}
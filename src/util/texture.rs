@@ -4,30 +4,256 @@
 
 use image::*;
 use cgmath::*;
+use std::sync::{Arc, OnceLock};
 
 use super::tracing::*;
+use super::color::*;
+use super::stats::*;
 
 
+// Trait for textures; anything that gives a color over a surface
+pub trait Texture {
+    // sample at texture coordinates uv / world space point hitpoint (procedural textures may use either)
+    fn sample(&self, uv: Vec2, hitpoint: Vec3) -> Color;
+    // sample averaged over a footprint around uv (its width in uv space), for filtering distant surfaces
+    fn sample_footprint(&self, uv: Vec2, hitpoint: Vec3, _footprint: Float) -> Color {
+        self.sample(uv, hitpoint)
+    }
+    // alpha channel at uv
+    fn sample_alpha(&self, _uv: Vec2) -> Float {
+        1.0
+    }
+}
+
+// shorthands for textures that are the same everywhere
+pub fn solid_color(color: Color) -> Arc<dyn Texture + Send + Sync> {
+    Arc::new(ConstantTexture { color })
+}
+pub fn solid_value(value: Float) -> Arc<dyn Texture + Send + Sync> {
+    Arc::new(ConstantTexture { color: vec3(value, value, value) })
+}
+
+// CONSTANT
+#[derive(Debug, Clone, Copy)]
+pub struct ConstantTexture {
+    pub color: Color,
+}
+impl Texture for ConstantTexture {
+    fn sample(&self, _uv: Vec2, _hitpoint: Vec3) -> Color {
+        self.color
+    }
+}
+
+// IMAGE
+// what happens to uvs outside of [0,1]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WrapMode {
+    #[default]
+    Clamp,  // stretch the edge texels
+    Repeat, // tile the image
+}
+// how the image's color channels are encoded (alpha is always linear)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,   // colors meant to be looked at: albedo, emission
+    Linear, // data: roughness, metallic, normals, masks
+}
+// lookup table decoding 8-bit srgb values to linear
+fn srgb_table() -> &'static [Float; 256] {
+    static TABLE: OnceLock<[Float; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| std::array::from_fn(|i| srgb_to_linear(i as Float/255.0)))
+}
+// images are stored as a mip pyramid (each level half the size of the last) so distant surfaces can be
+// sampled from a pre-filtered level instead of aliasing
 #[derive(Debug, Clone)]
-pub struct Texture {
-    img: DynamicImage
+pub struct ImageTexture {
+    levels: Vec<RgbaImage>,
+    colorspace: ColorSpace,
+    pub wrap: WrapMode,
 }
-impl Texture {
-    pub fn load_from_file(file_name: &str) -> Option<Texture> {
+impl ImageTexture {
+    // srgb images are decoded to linear when sampled, so shading (and filtering) always happens on linear values
+    pub fn load_from_file(file_name: &str, colorspace: ColorSpace) -> Option<ImageTexture> {
         if let Ok(img) = image::open(file_name) {
-            Some(Texture {
-                img: img,
+            Some(ImageTexture {
+                levels: ImageTexture::build_mips(img.to_rgba8(), colorspace),
+                colorspace,
+                wrap: WrapMode::Clamp,
             })
         }
         else {
             None
         }
     }
-    pub fn sample(&self, uv: Vec2) -> Color {
-        // simple clamped sampling for now...
-        let x = u32::min((uv.x.clamp(0.0, 0.999)*self.img.width() as f32) as u32, self.img.width()-1);
-        let y = u32::min(((1.0-uv.y.clamp(0.0, 0.999))*self.img.height() as f32) as u32, self.img.height()-1);
-        let pxl = self.img.get_pixel(x,y).to_rgb();
-        vec3(pxl[0] as f32/255.0, pxl[1] as f32/255.0, pxl[2] as f32/255.0)
+    pub fn with_wrap(mut self, wrap: WrapMode) -> ImageTexture {
+        self.wrap = wrap;
+        self
+    }
+    // loads an image straight into the form materials take
+    pub fn load_shared(file_name: &str, colorspace: ColorSpace) -> Option<Arc<dyn Texture + Send + Sync>> {
+        ImageTexture::load_from_file(file_name, colorspace).map(|tex| Arc::new(tex) as Arc<dyn Texture + Send + Sync>)
+    }
+    // halves the image with a box filter until it's a single texel
+    // (srgb colors are averaged as linear light, otherwise distant textures come out too dark)
+    fn build_mips(base: RgbaImage, colorspace: ColorSpace) -> Vec<RgbaImage> {
+        let decode = |c: usize, v: u8| if c < 3 && colorspace == ColorSpace::Srgb { srgb_table()[v as usize] } else { v as Float/255.0 };
+        let encode = |c: usize, v: Float| {
+            let v = if c < 3 && colorspace == ColorSpace::Srgb { linear_to_srgb(v) } else { v };
+            (v*255.0).round().clamp(0.0, 255.0) as u8
+        };
+        let mut levels = vec![base];
+        loop {
+            let prev = levels.last().unwrap();
+            let (w, h) = prev.dimensions();
+            if w == 1 && h == 1 { break }
+            let next = RgbaImage::from_fn(u32::max(w/2, 1), u32::max(h/2, 1), |x, y| {
+                let mut sum = [0.0; 4];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let p = prev.get_pixel(u32::min(2*x+dx, w-1), u32::min(2*y+dy, h-1));
+                    for (c, s) in sum.iter_mut().enumerate() { *s += decode(c, p[c]); }
+                }
+                Rgba(std::array::from_fn(|c| encode(c, sum[c]/4.0)))
+            });
+            levels.push(next);
+        }
+        levels
+    }
+    // texel at integer coordinates, wrapped according to the wrap mode
+    fn fetch(&self, level: usize, x: i64, y: i64) -> Vector4<Float> {
+        let img = &self.levels[level];
+        let (w, h) = (img.width() as i64, img.height() as i64);
+        let (x, y) = match self.wrap {
+            WrapMode::Clamp => (x.clamp(0, w-1), y.clamp(0, h-1)),
+            WrapMode::Repeat => (x.rem_euclid(w), y.rem_euclid(h)),
+        };
+        let p = img.get_pixel(x as u32, y as u32);
+        match self.colorspace {
+            ColorSpace::Srgb => {
+                let table = srgb_table();
+                vec4(table[p[0] as usize], table[p[1] as usize], table[p[2] as usize], p[3] as Float/255.0)
+            }
+            ColorSpace::Linear => vec4(p[0] as Float, p[1] as Float, p[2] as Float, p[3] as Float)/255.0,
+        }
+    }
+    // blends the four texels around uv on one mip level
+    fn bilinear(&self, level: usize, uv: Vec2) -> Vector4<Float> {
+        let img = &self.levels[level];
+        let x = uv.x*img.width() as Float - 0.5;
+        let y = (1.0-uv.y)*img.height() as Float - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = self.fetch(level, x0, y0)*(1.0-fx) + self.fetch(level, x0+1, y0)*fx;
+        let bottom = self.fetch(level, x0, y0+1)*(1.0-fx) + self.fetch(level, x0+1, y0+1)*fx;
+        top*(1.0-fy) + bottom*fy
+    }
+    // blends between the two mip levels closest to the footprint's size
+    fn trilinear(&self, uv: Vec2, footprint: Float) -> Vector4<Float> {
+        let base = &self.levels[0];
+        let lod = Float::log2(footprint*u32::max(base.width(), base.height()) as Float);
+        if lod.is_nan() || lod <= 0.0 { return self.bilinear(0, uv) }
+        let max_level = (self.levels.len()-1) as Float;
+        let lod = Float::min(lod, max_level);
+        let level = lod.floor() as usize;
+        if level as Float >= max_level { return self.bilinear(level, uv) }
+        let t = lod - level as Float;
+        self.bilinear(level, uv)*(1.0-t) + self.bilinear(level+1, uv)*t
+    }
+}
+impl Texture for ImageTexture {
+    fn sample(&self, uv: Vec2, _hitpoint: Vec3) -> Color {
+        count_ray_stats(|stats| stats.texture_samples += 1);
+        self.bilinear(0, uv).truncate()
+    }
+    fn sample_footprint(&self, uv: Vec2, _hitpoint: Vec3, footprint: Float) -> Color {
+        count_ray_stats(|stats| stats.texture_samples += 1);
+        self.trilinear(uv, footprint).truncate()
+    }
+    fn sample_alpha(&self, uv: Vec2) -> Float {
+        count_ray_stats(|stats| stats.texture_samples += 1);
+        self.bilinear(0, uv).w
+    }
+}
+
+// CHECKER - alternates between two textures in a checkerboard, either over the surface's uvs or through 3d space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckerSpace {
+    UV,     // squares in texture space
+    World,  // cubes in world space, so it works on surfaces without uvs
+}
+#[derive(Clone)]
+pub struct CheckerTexture {
+    pub even: Arc<dyn Texture + Send + Sync>,
+    pub odd: Arc<dyn Texture + Send + Sync>,
+    pub scale: Float,     // checks per unit of uv / world space
+    pub space: CheckerSpace,
+}
+impl CheckerTexture {
+    // checkerboard of two flat colors
+    pub fn from_colors(even: Color, odd: Color, scale: Float, space: CheckerSpace) -> CheckerTexture {
+        CheckerTexture {
+            even: solid_color(even),
+            odd: solid_color(odd),
+            scale,
+            space,
+        }
+    }
+    fn is_even(&self, uv: Vec2, hitpoint: Vec3) -> bool {
+        let cell = match self.space {
+            CheckerSpace::UV => (uv.x*self.scale).floor() as i64 + (uv.y*self.scale).floor() as i64,
+            CheckerSpace::World => (hitpoint.x*self.scale).floor() as i64 + (hitpoint.y*self.scale).floor() as i64 + (hitpoint.z*self.scale).floor() as i64,
+        };
+        cell.rem_euclid(2) == 0
+    }
+}
+impl Texture for CheckerTexture {
+    fn sample(&self, uv: Vec2, hitpoint: Vec3) -> Color {
+        if self.is_even(uv, hitpoint) { self.even.sample(uv, hitpoint) } else { self.odd.sample(uv, hitpoint) }
+    }
+    fn sample_footprint(&self, uv: Vec2, hitpoint: Vec3, footprint: Float) -> Color {
+        if self.is_even(uv, hitpoint) { self.even.sample_footprint(uv, hitpoint, footprint) } else { self.odd.sample_footprint(uv, hitpoint, footprint) }
+    }
+}
+
+// UV TRANSFORM - tiles, offsets, and rotates the uvs used to sample another texture
+// (image textures should use WrapMode::Repeat to tile)
+#[derive(Debug, Clone, Copy)]
+pub struct UvTransform {
+    pub scale: Vec2,        // number of times the texture repeats across the uv square
+    pub offset: Vec2,
+    pub rotation: Deg<Float>, // counter-clockwise, about the uv origin
+}
+impl Default for UvTransform {
+    fn default() -> UvTransform {
+        UvTransform {
+            scale: vec2(1.0, 1.0),
+            offset: Vec2::zero(),
+            rotation: Deg(0.0),
+        }
+    }
+}
+impl UvTransform {
+    pub fn apply(&self, uv: Vec2) -> Vec2 {
+        let (sin, cos) = Rad::from(self.rotation).0.sin_cos();
+        let rotated = vec2(cos*uv.x - sin*uv.y, sin*uv.x + cos*uv.y);
+        rotated.mul_element_wise(self.scale) + self.offset
+    }
+}
+#[derive(Clone)]
+pub struct TransformedTexture {
+    pub texture: Arc<dyn Texture + Send + Sync>,
+    pub transform: UvTransform,
+}
+impl Texture for TransformedTexture {
+    fn sample(&self, uv: Vec2, hitpoint: Vec3) -> Color {
+        self.texture.sample(self.transform.apply(uv), hitpoint)
+    }
+    fn sample_footprint(&self, uv: Vec2, hitpoint: Vec3, footprint: Float) -> Color {
+        // tiling shrinks the texture, so the same footprint covers more of it
+        let stretch = Float::max(self.transform.scale.x.abs(), self.transform.scale.y.abs());
+        self.texture.sample_footprint(self.transform.apply(uv), hitpoint, footprint*stretch)
+    }
+    fn sample_alpha(&self, uv: Vec2) -> Float {
+        self.texture.sample_alpha(self.transform.apply(uv))
     }
 }
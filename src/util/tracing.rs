@@ -7,31 +7,544 @@
 ////////////////////////////////////////////////////////
 use image::*;
 use cgmath::*;
-use rand::Rng;
-use indicatif::{ProgressBar, ProgressStyle};
 use std::sync::Arc;
 use rayon::prelude::*;
 use std::ops::Neg;
+use std::time::Instant;
+use std::path::Path;
+use std::io::IsTerminal;
+use std::collections::HashMap;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use ::tracing::{info, info_span, warn};
+use tracing_subscriber::{EnvFilter, fmt::format::FmtSpan};
+use serde::{Serialize, Deserialize};
 
 use super::geometry::*;
 use super::materials::*;
+use super::texture::*;
+use super::lights::*;
+use super::background::*;
+use super::sampler::*;
+use super::sampling::*;
+use super::color::ToneMapping;
+use super::output::*;
+use super::aov::*;
+use super::denoise::*;
+use super::stats::*;
+use super::cancel::*;
+use super::simd::*;
+use super::cryptomatte::*;
+use super::fog::*;
+use super::batch::*;
+use super::preview::*;
+use super::progress::*;
 
 ////////////////////////////////////////////////////////
 /////   CONSTANTS, TYPEDEFS, ENUMS
 ////////////////////////////////////////////////////////
-pub type Vec3 = Vector3<f32>;
-pub type Vec2 = Vector2<f32>;
+// scalar type all the math is done in: f32, or f64 with the f64 feature, for scenes so big (or so far from the origin)
+// that f32 positions aren't precise enough and surfaces start shadowing themselves
+#[cfg(not(feature = "f64"))]
+pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
+#[cfg(not(feature = "f64"))]
+pub use std::f32::consts;
+#[cfg(feature = "f64")]
+pub use std::f64::consts;
+// f32s from outside the renderer (mesh files, image pixels) into Float, and back
+pub fn float(v: f32) -> Float {
+    v as Float
+}
+#[allow(clippy::unnecessary_cast)]    // (it isn't, with the f64 feature)
+pub fn to_f32(v: Float) -> f32 {
+    v as f32
+}
+// Floats into f64, for math that needs the precision even when the renderer doesn't
+#[allow(clippy::unnecessary_cast)]    // (it isn't, without the f64 feature)
+pub fn to_f64(v: Float) -> f64 {
+    v as f64
+}
+pub type Vec3 = Vector3<Float>;
+pub type Vec2 = Vector2<Float>;
 pub type Color = Vec3;
+pub type HdrImage = ImageBuffer<Rgb<f32>, Vec<f32>>;   // image of linear radiance, before it's made displayable
+// colors out of and into HdrImage pixels
+pub fn pixel_color(p: &Rgb<f32>) -> Color {
+    vec3(float(p[0]), float(p[1]), float(p[2]))
+}
+pub fn color_pixel(c: Color) -> Rgb<f32> {
+    Rgb([to_f32(c.x), to_f32(c.y), to_f32(c.z)])
+}
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum CameraProjectionMode {
     Orthographic,
     Perspective,
 }
-#[derive(Debug, Clone, Copy)]
+// which side of the image the focal length (and field of view) is measured against
+// the image plane is one unit along that side, and as long as the aspect ratio needs along the other
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum SensorFit {
+    Vertical,   // the height, so wider images see more to the sides and taller ones see less
+    Horizontal, // the width, so taller images see more above and below and wider ones see less
+    Auto,       // whichever side is longer, so turning the image between landscape and portrait keeps the same view
+}
+// renders a view for each eye into one image, for viewing in a VR headset
+// the eyes look parallel but their image planes are shifted so they agree at the focus distance, which is where
+// things appear at the depth of the screen (anything closer pops out of it)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Stereo {
+    pub ipd: Float,   // interpupillary distance, the distance between the two eyes in scene units (about 0.064 for meters)
+    pub layout: StereoLayout,
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum StereoLayout {
+    SideBySide, // left eye on the left, right eye on the right, so the image is twice as wide as screen_width
+    OverUnder,  // left eye on top, right eye below, so the image is twice as tall as screen_height
+}
+// camera settings that set how bright the image comes out, like a real camera's, for scenes lit in physical units
+// (radiance in W/(sr m^2), or anything proportional to cd/m^2). assumes scene units are meters and a 35mm full frame
+// sensor (24mm tall), so the f-number gives a physically sized lens opening. opening up one stop (doubling the ISO
+// or the shutter speed, or dividing the f-number by sqrt 2) doubles the brightness, which makes bracketing easy
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PhysicalExposure {
+    pub iso: Float,           // sensor sensitivity
+    pub shutter_speed: Float, // how long the shutter is open, in seconds (exposure only, see shutter_open/close for blur)
+    pub f_number: Float,      // focal length over the lens opening's diameter
+}
+impl PhysicalExposure {
+    // scale from radiance to image values (1 = white), from the saturation based sensitivity of a sensor:
+    // a luminance of 1.2 * 2^EV100 saturates it, where EV100 = log2(f_number^2 / shutter_speed * 100 / iso)
+    // (Lagarde and de Rousiers 2014, "Moving Frostbite to Physically Based Rendering")
+    pub fn scale(&self) -> Float {
+        self.shutter_speed*self.iso / (1.2*100.0*self.f_number*self.f_number)
+    }
+}
+// how a lens darkens the edges of the image
+// natural vignetting is the cos^4 falloff of light reaching the sensor at an angle. mechanical vignetting is the far end
+// of the lens barrel blocking part of the opening for light coming in off axis, which also squashes out of focus
+// highlights towards the edges into cat's eyes
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Vignetting {
+    pub natural: Float,       // how much of the cos^4 falloff to apply (0 = none, 1 = physical)
+    pub barrel_length: Float, // length of the lens barrel in front of the opening, in lens radii (0 = no mechanical vignetting)
+}
+// shape of the lens opening, which is the shape out of focus highlights (bokeh) take
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Aperture {
+    Disk,
+    Blades { count: u32, rotation: Float },   // polygon left open by count straight blades, turned by rotation degrees
+    #[serde(skip)]  // (the image isn't kept with the camera's settings, so it has to be loaded again)
+    Image(Arc<ApertureImage>),              // how much light each part of the lens lets through, from an image
+}
+impl Aperture {
+    // point on the aperture, within the unit circle (or the square around it, for images)
+    pub fn sample(&self, u: Vec2) -> Vec2 {
+        match self {
+            Aperture::Disk => sample_uniform_disk(u),
+            Aperture::Blades { count, rotation } => sample_regular_polygon(u, u32::max(*count, 3), rotation.to_radians()),
+            Aperture::Image(image) => image.sample(u),
+        }
+    }
+}
+// how much a sample counts towards the pixel it was taken for, by how far from the pixel's center it landed (in pixels)
+// each pixel's samples are spread over the filter's whole width and averaged by their weights, so wider filters blend in
+// a little of the neighbouring pixels to smooth edges, and ones with negative lobes (mitchell) sharpen them as well
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum PixelFilter {
+    Box { radius: Float },                        // every sample counts the same (radius 0.5 = plain average over the pixel)
+    Tent { radius: Float },                       // falls off in a straight line to nothing at the radius
+    Gaussian { radius: Float, alpha: Float },       // bell curve, falling off faster with higher alpha (cut off at the radius)
+    Mitchell { radius: Float, b: Float, c: Float },   // cubic that dips below zero, b blurs and c rings
+                                                // (Mitchell and Netravali 1988, "Reconstruction Filters in Computer Graphics")
+}
+impl Default for PixelFilter {
+    fn default() -> PixelFilter {
+        PixelFilter::Box { radius: 0.5 }
+    }
+}
+impl PixelFilter {
+    // the usual settings for each filter
+    pub fn tent() -> PixelFilter {
+        PixelFilter::Tent { radius: 1.0 }
+    }
+    pub fn gaussian() -> PixelFilter {
+        PixelFilter::Gaussian { radius: 1.5, alpha: 2.0 }
+    }
+    pub fn mitchell() -> PixelFilter {
+        PixelFilter::Mitchell { radius: 2.0, b: 1.0/3.0, c: 1.0/3.0 }
+    }
+    // how far from the pixel's center samples are spread (in pixels, in x and y)
+    pub fn radius(&self) -> Float {
+        match *self {
+            PixelFilter::Box { radius } | PixelFilter::Tent { radius } | PixelFilter::Gaussian { radius, .. } | PixelFilter::Mitchell { radius, .. } => radius,
+        }
+    }
+    // weight of a sample offset this far from the pixel's center (filters are separable, so the weights in x and y multiply)
+    pub fn weight(&self, offset: Vec2) -> Float {
+        self.weight_1d(offset.x) * self.weight_1d(offset.y)
+    }
+    fn weight_1d(&self, x: Float) -> Float {
+        let x = x.abs();
+        match *self {
+            PixelFilter::Box { radius } => if x < radius { 1.0 } else { 0.0 },
+            PixelFilter::Tent { radius } => Float::max(0.0, radius - x),
+            PixelFilter::Gaussian { radius, alpha } => Float::max(0.0, (-alpha*x*x).exp() - (-alpha*radius*radius).exp()),
+            PixelFilter::Mitchell { radius, b, c } => {
+                // the cubic spans -2 to 2, stretched over the radius
+                let x = 2.0*x / radius;
+                if x >= 2.0 { 0.0 }
+                else if x > 1.0 {
+                    ((-b - 6.0*c)*x*x*x + (6.0*b + 30.0*c)*x*x + (-12.0*b - 48.0*c)*x + (8.0*b + 24.0*c)) / 6.0
+                }
+                else {
+                    ((12.0 - 9.0*b - 6.0*c)*x*x*x + (-18.0 + 12.0*b + 6.0*c)*x*x + (6.0 - 2.0*b)) / 6.0
+                }
+            }
+        }
+    }
+}
+// average of samples by their filter weights (falling back to black if the weights cancel out, which negative lobes can
+// do with very few samples), clamped at black since negative lobes can also ring below it next to bright edges
+fn filtered_average(weighted_sum: Color, weight_sum: Float) -> Color {
+    if weight_sum > 0.0 { (weighted_sum / weight_sum).map(|c| c.max(0.0)) } else { Color::zero() }
+}
+// grayscale image covering the square around the lens, where brighter pixels let more light through
+// points are picked with a 2D CDF over the pixels (a row by how bright it is overall, then a pixel within it)
+#[derive(Debug)]
+pub struct ApertureImage {
+    width: usize,
+    height: usize,
+    row_cdf: Vec<Float>,      // cdf of picking each row (height+1 entries)
+    column_cdfs: Vec<Float>,  // cdf of picking each pixel within its row (width+1 entries per row)
+}
+impl ApertureImage {
+    // weights are row by row from the top of the image
+    pub fn new(weights: &[Float], width: usize, height: usize) -> ApertureImage {
+        assert_eq!(weights.len(), width*height);
+        let mut column_cdfs = Vec::with_capacity((width+1)*height);
+        let mut row_weights = Vec::with_capacity(height);
+        for row in weights.chunks(width) {
+            let (cdf, total) = build_cdf(row);
+            column_cdfs.extend(cdf);
+            row_weights.push(total);
+        }
+        ApertureImage { width, height, row_cdf: build_cdf(&row_weights).0, column_cdfs }
+    }
+    pub fn load_from_file(file_name: &str) -> Option<ApertureImage> {
+        let img = image::open(file_name).ok()?.to_luma8();
+        let weights: Vec<Float> = img.pixels().map(|p| p[0] as Float / 255.0).collect();
+        Some(ApertureImage::new(&weights, img.width() as usize, img.height() as usize))
+    }
+    fn sample(&self, u: Vec2) -> Vec2 {
+        let (row, v_offset) = sample_cdf(&self.row_cdf, u.y);
+        let row_start = row*(self.width+1);
+        let (column, u_offset) = sample_cdf(&self.column_cdfs[row_start..row_start+self.width+1], u.x);
+        vec2(
+            2.0*(column as Float + u_offset) / self.width as Float - 1.0,
+            1.0 - 2.0*(row as Float + v_offset) / self.height as Float,
+        )
+    }
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum ShadingMode {
     Phong,
     PathTrace,
+    Toon(ToonStyle),    // flat illustrated look, lit by the scene's point light
+    Sppm(SppmSettings), // stochastic progressive photon mapping, for caustics (see render_sppm)
+    Mlt(MltSettings),   // metropolis light transport, for light that only reaches the camera along a few paths (see render_mlt)
+    Wavefront,          // path tracing that moves all of a pixel's paths along together, one stage at a time (see shade_wavefront)
+    LightTrace(LightTraceSettings), // paths traced out from the lights and splatted onto the camera (see render_light_trace)
+    Gradient(GradientSettings),     // path tracing of the differences between neighboring pixels, solved back into an image (see render_gradient)
+}
+// settings for stochastic progressive photon mapping
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SppmSettings {
+    pub iterations: u32,            // passes of one camera ray per pixel, each followed by a pass of photons
+    pub photons_per_iteration: u32,
+    pub initial_radius: Float,        // how far from each camera ray's hit photons are gathered at first, in scene units
+                                    // (shrinks as photons come in, so the image gets sharper the longer it renders)
+}
+impl Default for SppmSettings {
+    fn default() -> Self {
+        Self { iterations: 64, photons_per_iteration: 200_000, initial_radius: 0.1 }
+    }
+}
+// settings for metropolis light transport
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MltSettings {
+    pub mutations_per_pixel: u32,   // paths proposed in all, per pixel of the image
+    pub chains: u32,                // independent chains the proposals are shared out between
+    pub bootstrap_samples: u32,     // random paths traced first, to find how bright the image is and where chains start
+    pub sigma: Float,                 // spread of the small steps in primary sample space
+    pub large_step_probability: Float,    // chance of proposing an entirely new path instead of a small step
+}
+impl Default for MltSettings {
+    fn default() -> Self {
+        Self { mutations_per_pixel: 64, chains: 1000, bootstrap_samples: 100_000, sigma: 0.01, large_step_probability: 0.3 }
+    }
+}
+// settings for light tracing
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LightTraceSettings {
+    pub paths_per_pixel: u32,       // light paths traced in all, per pixel of the image (one pass of them per camera ray)
+}
+impl Default for LightTraceSettings {
+    fn default() -> Self {
+        Self { paths_per_pixel: 64 }
+    }
+}
+// settings for gradient-domain path tracing
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct GradientSettings {
+    pub alpha: Float,               // how closely the image keeps to the path traced pixels, against their gradients
+                                    // (lower trusts the gradients more: less noise, but errors spread further)
+    pub iterations: u32,            // conjugate gradient steps solving for the image
+    pub reweighting_passes: u32,    // times it's solved for again, to keep bad gradients from spreading (0 = least squares, see screened_poisson)
+}
+impl Default for GradientSettings {
+    fn default() -> Self {
+        Self { alpha: 0.2, iterations: 100, reweighting_passes: 1 }
+    }
+}
+// look of toon shading: banded diffuse lighting, a hard rim light, and ink outlines
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ToonStyle {
+    pub bands: u32,             // number of flat lighting levels, from shadow to fully lit
+    pub rim_width: Float,         // how far the rim light reaches in from silhouettes (0 = no rim, 1 = everywhere)
+    pub rim_color: Color,
+    pub outlines: bool,         // ink outlines are drawn over the whole image, so they only come from ShadingMode::Toon
+    pub ink_color: Color,
+    pub depth_threshold: Float,   // relative jump in depth between neighboring pixels that counts as an edge
+    pub crease_angle: Float,      // angle between neighboring pixels' normals that counts as an edge, in degrees
+}
+impl Default for ToonStyle {
+    fn default() -> ToonStyle {
+        ToonStyle {
+            bands: 3,
+            rim_width: 0.15,
+            rim_color: vec3(0.3, 0.3, 0.3),
+            outlines: true,
+            ink_color: Vec3::zero(),
+            depth_threshold: 0.1,
+            crease_angle: 50.0,
+        }
+    }
+}
+// most bounces of each kind a path can take (on top of the camera's overall path_depth), so each can be given as
+// many as it needs: glass needs lots of transmission bounces to not go black, but diffuse light barely changes after a few
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BounceLimits {
+    pub diffuse: u32,
+    pub glossy: u32,
+    pub transmission: u32,
+    pub volume: u32,
+}
+impl BounceLimits {
+    // only limited by path_depth
+    pub fn unlimited() -> BounceLimits {
+        BounceLimits { diffuse: u32::MAX, glossy: u32::MAX, transmission: u32::MAX, volume: u32::MAX }
+    }
+}
+// bounces a path has taken so far, of each kind
+#[derive(Debug, Clone, Copy, Default)]
+struct Bounces {
+    diffuse: u32,
+    glossy: u32,
+    transmission: u32,
+    volume: u32,
+}
+impl Bounces {
+    fn total(&self) -> u32 {
+        self.diffuse + self.glossy + self.transmission + self.volume
+    }
+    fn after(mut self, kind: BounceKind) -> Bounces {
+        match kind {
+            BounceKind::Diffuse => self.diffuse += 1,
+            BounceKind::Glossy => self.glossy += 1,
+            BounceKind::Transmission => self.transmission += 1,
+            BounceKind::Volume => self.volume += 1,
+        }
+        self
+    }
+    // roughness surfaces are raised to after these bounces, growing by strength with every bounce that wasn't
+    // straight through a surface (so glass still looks sharp through glass)
+    fn min_roughness(&self, strength: Float) -> Float {
+        Float::min(1.0, strength*(self.diffuse + self.glossy + self.volume) as Float)
+    }
+    fn within(&self, limits: &BounceLimits) -> bool {
+        self.diffuse <= limits.diffuse && self.glossy <= limits.glossy && self.transmission <= limits.transmission && self.volume <= limits.volume
+    }
+}
+// clamp and scale for one part of the light a path tracer finds (see Camera's direct_light and indirect_light)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LightAdjustment {
+    pub clamp: Option<Float>, // highest radiance a single sample of it can carry, to remove fireflies (adds a little bias)
+    pub scale: Float,         // multiplier for it, to brighten or darken it on its own
+}
+impl LightAdjustment {
+    // leaves the light as it is
+    pub fn none() -> LightAdjustment {
+        LightAdjustment { clamp: None, scale: 1.0 }
+    }
+    pub fn clamp(max: Float) -> LightAdjustment {
+        LightAdjustment { clamp: Some(max), scale: 1.0 }
+    }
+    fn apply(&self, radiance: Color) -> Color {
+        self.scale*clamp_radiance(radiance, self.clamp)
+    }
+}
+#[derive(Debug, Clone, Copy)]
+pub enum HeatmapMetric {
+    AABBTests,      // bounding box tests per camera ray
+    TriangleTests,  // triangle tests per camera ray
+}
+
+// when set, run() also writes traversal cost heatmaps next to the render (useful for spotting degenerate bvhs)
+const RENDER_TRAVERSAL_HEATMAPS: bool = false;
+// when set, run() also writes images of each pixel's noise and how many samples it took next to the render
+const RENDER_SAMPLE_STATS: bool = false;
+// file run() writes the render to (e.g. "render.exr" or "render.hdr" to keep the full range of light)
+const OUTPUT_FILE: &str = "render.png";
+// when set, run() renders in passes of one sample per pixel instead, rewriting the render every this many seconds
+// (to see how the lighting looks without waiting for every sample)
+const PROGRESSIVE_SAVE_INTERVAL: Option<Float> = None;
+// how far apart in a sample's random number sequence the paths split off from one hit start (see shade_wavefront)
+const WAVEFRONT_BRANCH_DIMENSIONS: u32 = 1 << 16;
+
+// noise statistics of one pixel's samples, from their luminance, and the work it took to render them
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PixelStats {
+    pub sample_count: u32,  // samples taken for the pixel
+    pub variance: Float,      // variance of the pixel's value (the mean of its samples), so it shrinks as samples are added
+    pub rays: RayStats,     // rays traced, and tests and lookups done along them, for the pixel
+}
+impl PixelStats {
+    fn from_sums(sample_count: u32, sum: Float, sum_squares: Float, rays: RayStats) -> PixelStats {
+        let n = sample_count as Float;
+        let sample_variance = if sample_count > 1 { Float::max(0.0, (sum_squares - sum*sum/n) / (n - 1.0)) } else { 0.0 };
+        PixelStats { sample_count, variance: sample_variance / n, rays }
+    }
+}
+// what the finished work in a render's checkpoint is counted in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CheckpointKind {
+    Tiles,  // the indices of the tiles that were finished (for render_to_image and the like)
+    Passes, // how many passes were finished (for render_progressive)
+}
+// rectangle of the image that's rendered as one piece of work
+#[derive(Debug, Clone, Copy)]
+pub struct Tile {
+    pub x: u32,         // top left pixel
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+impl Tile {
+    // the tile's pixels, row by row
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let tile = *self;
+        (tile.y..tile.y+tile.height).flat_map(move |y| (tile.x..tile.x+tile.width).map(move |x| (x, y)))
+    }
+}
+// samples splatted onto the pixels around them by their pixel filter weights, over a rectangle of the image
+// each sample counts towards every pixel whose filter reaches it, so wide filters share samples between pixels. a tile's
+// buffer is grown by the filter's radius so samples near its edges reach the neighbouring tiles' pixels too
+struct SplatBuffer {
+    x: i32,                 // top left pixel (tiles' buffers can reach off the image)
+    y: i32,
+    width: u32,
+    height: u32,
+    signed: Vec<bool>,      // whether each channel takes the filter's negative lobes (light does, but aovs of what
+                            // surfaces were hit skip them so they never overshoot)
+    sums: Vec<Color>,       // filter weighted sums of each pixel's channels, one after the other
+    weights: Vec<Float>,      // filter weights of each pixel's samples summed
+    positive_weights: Vec<Float>, // same, without the negative lobes
+}
+impl SplatBuffer {
+    fn new(x: i32, y: i32, width: u32, height: u32, signed: Vec<bool>) -> SplatBuffer {
+        let pixels = (width*height) as usize;
+        SplatBuffer { x, y, width, height, sums: vec![Color::zero(); pixels*signed.len()], weights: vec![0.0; pixels], positive_weights: vec![0.0; pixels], signed }
+    }
+    // buffer for samples taken in a tile
+    fn for_tile(tile: &Tile, filter: &PixelFilter, signed: Vec<bool>) -> SplatBuffer {
+        let margin = filter.radius().ceil() as u32;
+        SplatBuffer::new(tile.x as i32 - margin as i32, tile.y as i32 - margin as i32, tile.width + 2*margin, tile.height + 2*margin, signed)
+    }
+    // index of a pixel in the buffer, if it's in it
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        let (x, y) = (x - self.x, y - self.y);
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 { return None }
+        Some((y as u32*self.width + x as u32) as usize)
+    }
+    // adds a sample's channels, taken at position (in pixels from the image's top left corner), to the pixels in area
+    // whose filters reach it
+    fn add(&mut self, filter: &PixelFilter, position: Vec2, area: &Tile, values: &[Color]) {
+        let radius = filter.radius();
+        let (x0, x1) = (Float::max((position.x - 0.5 - radius).ceil(), area.x as Float), Float::min((position.x - 0.5 + radius).floor(), (area.x + area.width) as Float - 1.0));
+        let (y0, y1) = (Float::max((position.y - 0.5 - radius).ceil(), area.y as Float), Float::min((position.y - 0.5 + radius).floor(), (area.y + area.height) as Float - 1.0));
+        for y in y0 as i32..=y1 as i32 {
+            for x in x0 as i32..=x1 as i32 {
+                let i = match self.index(x, y) { Some(i) => i, None => continue };
+                let weight = filter.weight(vec2(x as Float + 0.5, y as Float + 0.5) - position);
+                if weight == 0.0 { continue }
+                let positive_weight = Float::max(weight, 0.0);
+                self.weights[i] += weight;
+                self.positive_weights[i] += positive_weight;
+                for (c, (value, signed)) in values.iter().zip(self.signed.iter()).enumerate() {
+                    self.sums[i*self.signed.len() + c] += if *signed { weight } else { positive_weight } * value;
+                }
+            }
+        }
+    }
+    // adds in the splats from another buffer (where it overlaps this one)
+    fn merge(&mut self, other: &SplatBuffer) {
+        let channels = self.signed.len();
+        for y in 0..other.height as i32 {
+            for x in 0..other.width as i32 {
+                let j = (y as u32*other.width + x as u32) as usize;
+                let i = match self.index(other.x + x, other.y + y) { Some(i) => i, None => continue };
+                self.weights[i] += other.weights[j];
+                self.positive_weights[i] += other.positive_weights[j];
+                for c in 0..channels {
+                    self.sums[i*channels + c] += other.sums[j*channels + c];
+                }
+            }
+        }
+    }
+    // the buffer as little endian words: its size, the channels and whether each is signed, then the sums and weights
+    // (always starting at the image's top left corner, which is the only place buffers that get saved start)
+    fn to_words(&self) -> Vec<u32> {
+        let mut words = vec![self.width, self.height, self.signed.len() as u32];
+        words.extend(self.signed.iter().map(|&signed| signed as u32));
+        words.extend(self.sums.iter().flat_map(|sum| [to_f32(sum.x).to_bits(), to_f32(sum.y).to_bits(), to_f32(sum.z).to_bits()]));
+        words.extend(self.weights.iter().chain(self.positive_weights.iter()).map(|&weight| to_f32(weight).to_bits()));
+        words
+    }
+    // reads a buffer back from to_words, if it's the size and has the channels expected
+    fn from_words(words: &mut impl Iterator<Item = u32>, width: u32, height: u32, signed: &[bool]) -> Option<SplatBuffer> {
+        if words.next()? != width || words.next()? != height || words.next()? != signed.len() as u32 { return None }
+        for &expected in signed {
+            if words.next()? != expected as u32 { return None }
+        }
+        let mut buffer = SplatBuffer::new(0, 0, width, height, signed.to_vec());
+        for sum in buffer.sums.iter_mut() {
+            *sum = vec3(float(f32::from_bits(words.next()?)), float(f32::from_bits(words.next()?)), float(f32::from_bits(words.next()?)));
+        }
+        for weight in buffer.weights.iter_mut().chain(buffer.positive_weights.iter_mut()) {
+            *weight = float(f32::from_bits(words.next()?));
+        }
+        Some(buffer)
+    }
+    // a channel's weighted average at a pixel
+    fn average(&self, x: u32, y: u32, channel: usize) -> Color {
+        let i = self.index(x as i32, y as i32).unwrap();
+        let sum = self.sums[i*self.signed.len() + channel];
+        if self.signed[channel] { filtered_average(sum, self.weights[i]) }
+        else if self.positive_weights[i] > 0.0 { sum / self.positive_weights[i] }
+        else { Color::zero() }
+    }
 }
 
 ////////////////////////////////////////////////////////
@@ -41,9 +554,99 @@ pub enum ShadingMode {
 // Define trait for anything that can intersect a ray
 pub trait Intersectable {
     // tests for intersection with a given ray and returns hit info
-    fn intersect_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayHit>;
+    fn intersect_ray(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit>;
     // returns the axis-aligned bounding box of the intersectable, if there is one
     fn bounding_box(&self) -> Option<AABB>; // Option because not all primitives have bounding boxes (e.g. plane)
+    // tests a packet of rays at once and returns the closest hit (if any) for each ray
+    // coherent packets (e.g. all aa samples of a pixel) let acceleration structures share node tests between rays
+    fn intersect_packet(&self, rays: &[Ray], t_min: Float, t_max: Float) -> Vec<Option<RayHit>> {
+        rays.iter().map(|ray| self.intersect_ray(ray, t_min, t_max)).collect()
+    }
+    // tests the active lanes of up to four rays at once, each against its own t_max (used by packet traversal)
+    // primitives with a wide test (e.g. triangles) override this, the rest test the lanes one at a time
+    fn intersect_ray4(&self, rays: &Ray4, t_min: Float, t_max: Floatx4) -> [Option<RayHit>; LANES] {
+        std::array::from_fn(|i| if rays.active.0[i] { self.intersect_ray(&rays.ray(i), t_min, t_max.0[i]) } else { None })
+    }
+    // tests whether the ray hits anything at all in [t_min, t_max] (e.g. for shadow rays)
+    // implementations can exit on the first hit they find and skip building a RayHit
+    fn intersect_any(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        self.intersect_ray(ray, t_min, t_max).is_some()
+    }
+    // share of light a participating medium lets through along the ray in [t_min, t_max], for shadow rays to see
+    // through it (None for everything else, which blocks shadow rays wherever intersect_any finds it)
+    fn transmittance(&self, _ray: &Ray, _t_min: Float, _t_max: Float) -> Option<Float> {
+        None
+    }
+    // surface area, for shapes that can be sampled as lights (0 for the rest)
+    fn area(&self) -> Float {
+        0.0
+    }
+    // uniformly random point on the surface and the normal there
+    fn sample_surface(&self) -> Option<(Vec3, Vec3)> {
+        None
+    }
+    // which kinds of rays see the object, when it's one of the scene's objects (see Visibility)
+    fn visibility(&self) -> RayVisibility {
+        RayVisibility::default()
+    }
+}
+// lets shared (Arc'd) intersectables be used anywhere an intersectable is expected, e.g. as BVH primitives
+impl<T: Intersectable + ?Sized> Intersectable for Arc<T> {
+    fn intersect_ray(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
+        (**self).intersect_ray(ray, t_min, t_max)
+    }
+    fn bounding_box(&self) -> Option<AABB> {
+        (**self).bounding_box()
+    }
+    fn intersect_packet(&self, rays: &[Ray], t_min: Float, t_max: Float) -> Vec<Option<RayHit>> {
+        (**self).intersect_packet(rays, t_min, t_max)
+    }
+    fn intersect_ray4(&self, rays: &Ray4, t_min: Float, t_max: Floatx4) -> [Option<RayHit>; LANES] {
+        (**self).intersect_ray4(rays, t_min, t_max)
+    }
+    fn intersect_any(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        (**self).intersect_any(ray, t_min, t_max)
+    }
+    fn transmittance(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<Float> {
+        (**self).transmittance(ray, t_min, t_max)
+    }
+    fn area(&self) -> Float {
+        (**self).area()
+    }
+    fn sample_surface(&self) -> Option<(Vec3, Vec3)> {
+        (**self).sample_surface()
+    }
+    fn visibility(&self) -> RayVisibility {
+        (**self).visibility()
+    }
+}
+// what a ray traced through the scene is for, which decides which objects it can hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RayKind {
+    Camera,     // from the camera (and on through mirrors and glass, for renderers that follow camera paths that way)
+    Shadow,     // towards a light, to see whether it's blocked
+    Indirect,   // scattered off a surface (or left a light), looking for light
+}
+// which kinds of rays see an object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RayVisibility {
+    pub camera: bool,
+    pub shadow: bool,   // whether it casts shadows
+    pub indirect: bool, // whether it shows up in reflections, refractions, and bounce light
+}
+impl Default for RayVisibility {
+    fn default() -> RayVisibility {
+        RayVisibility { camera: true, shadow: true, indirect: true }
+    }
+}
+impl RayVisibility {
+    pub fn sees(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Shadow => self.shadow,
+            RayKind::Indirect => self.indirect,
+        }
+    }
 }
 
 
@@ -55,45 +658,162 @@ pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
     v - 2.0*v.dot(*n)*n
 }
 // Approximates the fresnel reflection-transmission coefficient using Schlick's approximation (https://en.wikipedia.org/wiki/Schlick%27s_approximation)
-pub fn fresnel(v: &Vec3, n: &Vec3, ir: f32) -> f32 {
+pub fn fresnel(v: &Vec3, n: &Vec3, ir: Float) -> Float {
     // (first index of refraction is assumed to be air (1.0). the equation is symmetric so it doesn't matter which medium is first)
     let r0 = ((ir-1.0)/(ir+1.0)).powi(2);
     r0 + (1.0-r0)*(1.0-v.dot(*n).abs()).powi(5)
 }
 // Refract function from raytracing in one weekend:
-pub fn refract(v: &Vec3, n: &Vec3, eta: f32) -> Vec3 {
-    let cos_theta = f32::min((v.neg()).dot(*n), 1.0);
+pub fn refract(v: &Vec3, n: &Vec3, eta: Float) -> Vec3 {
+    let cos_theta = Float::min((v.neg()).dot(*n), 1.0);
     let r_out_perp =  eta * (v + cos_theta*n);
-    let r_out_parallel = -f32::sqrt((1.0 - r_out_perp.magnitude2()).abs()) * n;
-    return r_out_perp + r_out_parallel;
+    let r_out_parallel = -Float::sqrt((1.0 - r_out_perp.magnitude2()).abs()) * n;
+    r_out_perp + r_out_parallel
 }
-// random vector in a unit sphere (rejection method)
-pub fn rand_sphere_vec() -> Vec3 {
-    let mut rng = rand::thread_rng();
-    loop {
-        let dir = Vec3 { x: rng.gen_range(-1.0..1.0), y: rng.gen_range(-1.0..1.0), z: rng.gen_range(-1.0..1.0) };
-        if dir.magnitude2() <= 1.0 {
-            return dir;
-        }
+// clamps a vector
+pub fn clampvec(v: Vec3, min: Float, max: Float) -> Vec3 {
+    vec3(v.x.clamp(min, max), v.y.clamp(min, max), v.z.clamp(min, max))
+}
+// scales radiance down (keeping its hue) so no channel is above max
+pub fn clamp_radiance(radiance: Color, max: Option<Float>) -> Color {
+    let peak = radiance.x.max(radiance.y).max(radiance.z);
+    match max {
+        Some(max) if peak > max => radiance * (max / peak),
+        _ => radiance,
     }
 }
-// random vector in a unit disk in xy plane (rejection method)
-pub fn rand_disk_vec() -> Vec3 {
-    let mut rng = rand::thread_rng();
-    loop {
-        let dir = Vec3 { x: rng.gen_range(-1.0..1.0), y: rng.gen_range(-1.0..1.0), z: 0.0 };
-        if dir.magnitude2() <= 1.0 {
-            return dir;
+// linear interpolation for vectors
+pub fn lerpvec(a: Vec3, b: Vec3, k: Float) -> Vec3 {
+    (1.0-k)*a+k*b
+}
+// splits an image into tiles of size by size pixels (smaller along the right and bottom edges if it doesn't divide evenly)
+pub fn image_tiles(width: u32, height: u32, size: u32) -> Vec<Tile> {
+    let size = u32::max(size, 1);
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(size as usize) {
+        for x in (0..width).step_by(size as usize) {
+            tiles.push(Tile { x, y, width: u32::min(size, width - x), height: u32::min(size, height - y) });
         }
     }
+    tiles
 }
-// clamps a vector
-pub fn clampvec(v: Vec3, min: f32, max: f32) -> Vec3 {
-    vec3(v.x.clamp(min, max), v.y.clamp(min, max), v.z.clamp(min, max))
+// turns a pixel's color into what's shown on screen, from 0 to 1: tone mapped, then gamma corrected
+pub fn display_color(color: Color, tone_mapping: ToneMapping, gamma: Float) -> Color {
+    tone_mapping.apply(color).map(|c| Float::powf(c, 1.0/gamma))
 }
-// linear interpolation for vectors
-pub fn lerpvec(a: Vec3, b: Vec3, k: f32) -> Vec3 {
-    (1.0-k)*a+k*b
+// maps per-pixel values to a color ramp, from blue (0) through green and yellow to red (max and above)
+pub fn heatmap(width: u32, height: u32, values: &[Float], max: Float) -> RgbImage {
+    let ramp = [vec3(0.0, 0.0, 1.0), vec3(0.0, 1.0, 0.0), vec3(1.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0)];
+    RgbImage::from_fn(width, height, |x, y| {
+        let k = if max > 0.0 { Float::min(values[(y*width + x) as usize] / max, 1.0) } else { 0.0 };
+        let segment = Float::min(k*(ramp.len()-1) as Float, (ramp.len()-1) as Float - 0.0001);
+        let color = lerpvec(ramp[segment as usize], ramp[segment as usize + 1], segment.fract());
+        Rgb([(color.x*255.0) as u8, (color.y*255.0) as u8, (color.z*255.0) as u8])
+    })
+}
+// moves red outwards and blue inwards from the middle of each view (tiles of view_width by view_height pixels), by amount
+// of their distance from it, like the lateral chromatic aberration of a lens that makes each wavelength's image a slightly
+// different size. green stays put
+pub fn lateral_chromatic_aberration(img: &HdrImage, amount: Float, view_width: u32, view_height: u32) -> HdrImage {
+    HdrImage::from_fn(img.width(), img.height(), |x, y| {
+        let (x0, y0) = ((x / view_width)*view_width, (y / view_height)*view_height);
+        let center = vec2(x0 as Float + 0.5*view_width as Float, y0 as Float + 0.5*view_height as Float);
+        let offset = vec2(x as Float + 0.5, y as Float + 0.5) - center;
+        // each channel is read (bilinearly, within the view) from where its scaled image puts this pixel's light
+        let channel = |c: usize, scale: Float| {
+            let p = center + offset/scale - vec2(0.5, 0.5);
+            let px = p.x.clamp(x0 as Float, (x0 + view_width - 1) as Float);
+            let py = p.y.clamp(y0 as Float, (y0 + view_height - 1) as Float);
+            let (ix, iy) = (px as u32, py as u32);
+            let (ix1, iy1) = (u32::min(ix+1, x0 + view_width - 1), u32::min(iy+1, y0 + view_height - 1));
+            let (tx, ty) = (px.fract(), py.fract());
+            let top = (1.0-tx)*float(img.get_pixel(ix, iy)[c]) + tx*float(img.get_pixel(ix1, iy)[c]);
+            let bottom = (1.0-tx)*float(img.get_pixel(ix, iy1)[c]) + tx*float(img.get_pixel(ix1, iy1)[c]);
+            (1.0-ty)*top + ty*bottom
+        };
+        Rgb([to_f32(channel(0, 1.0 + amount)), img.get_pixel(x, y)[1], to_f32(channel(2, 1.0 - amount))])
+    })
+}
+// finds the image (width by height, row by row) whose pixels are closest to primal, weighted by alpha squared, and whose
+// differences to the right and down are closest to gradient_x and gradient_y (zero where there's no neighbor). with no
+// reweighting passes that's in the least squares sense, a screened poisson equation. each reweighting pass solves it
+// again with every pixel and difference weighted by one over how far off it was the last time, which moves it towards
+// the least absolute differences (iteratively reweighted least squares): a few very wrong gradients, like those across
+// an edge the shifted paths couldn't follow, then only move the image locally instead of bleeding into everything
+// around them. that isn't unbiased though, and each pass takes a little more light off the brightest spots
+pub fn screened_poisson(primal: &[Color], gradient_x: &[Color], gradient_y: &[Color], width: usize, height: usize, settings: &GradientSettings) -> Vec<Color> {
+    let ones = vec![vec3(1.0, 1.0, 1.0); primal.len()];
+    let mut img = solve_weighted_poisson(primal, gradient_x, gradient_y, width, height, settings, [&ones, &ones, &ones]);
+    // (errors are only cut down to size so far, relative to how bright the image is, so nothing gets an infinite weight)
+    let mean = primal.iter().sum::<Color>() / primal.len() as Float;
+    let floor = Float::max(0.01*luminance(mean), 1e-6);
+    let weight = |error: Color| error.map(|e| 1.0 / Float::max(e.abs(), floor));
+    for _ in 0..settings.reweighting_passes {
+        let weights_primal: Vec<Color> = (0..img.len()).map(|p| weight(settings.alpha*(img[p] - primal[p]))).collect();
+        let weights_x: Vec<Color> = (0..img.len()).map(|p| {
+            if p % width + 1 < width { weight(img[p+1] - img[p] - gradient_x[p]) } else { Color::zero() }
+        }).collect();
+        let weights_y: Vec<Color> = (0..img.len()).map(|p| {
+            if p + width < img.len() { weight(img[p+width] - img[p] - gradient_y[p]) } else { Color::zero() }
+        }).collect();
+        img = solve_weighted_poisson(primal, gradient_x, gradient_y, width, height, settings, [&weights_primal, &weights_x, &weights_y]);
+    }
+    img
+}
+// solves screened_poisson's least squares problem with each pixel's match to primal, and each of its differences to the
+// right and down, weighted by the given weights, using conjugate gradients run on each channel at once (starting from
+// the primal image)
+fn solve_weighted_poisson(primal: &[Color], gradient_x: &[Color], gradient_y: &[Color], width: usize, height: usize, settings: &GradientSettings, weights: [&[Color]; 3]) -> Vec<Color> {
+    let a2 = settings.alpha*settings.alpha;
+    let [weights_primal, weights_x, weights_y] = weights;
+    // each pixel's neighbors, along with the weight of the difference between them
+    let neighbors = |p: usize| {
+        let (x, y) = (p % width, p / width);
+        [
+            (x > 0, p.wrapping_sub(1), p.wrapping_sub(1), weights_x),
+            (x+1 < width, p+1, p, weights_x),
+            (y > 0, p.wrapping_sub(width), p.wrapping_sub(width), weights_y),
+            (y+1 < height, p+width, p, weights_y),
+        ].into_iter().filter(|&(inside, ..)| inside).map(|(_, q, edge, weights)| (q, weights[edge]))
+    };
+    // the weighted match of the image to primal, plus the weighted sum of its differences from each pixel's neighbors
+    // (the left hand side)
+    let apply = |img: &[Color]| -> Vec<Color> {
+        (0..img.len()).into_par_iter().map(|p| {
+            neighbors(p).fold(a2*weights_primal[p].mul_element_wise(img[p]), |sum, (q, w)| sum + w.mul_element_wise(img[p] - img[q]))
+        }).collect()
+    };
+    let dot = |a: &[Color], b: &[Color]| -> Color {
+        a.iter().zip(b).fold(Color::zero(), |sum, (a, b)| sum + a.mul_element_wise(*b))
+    };
+    let ratio = |a: Color, b: Color| a.zip(b, |a, b| if b > 0.0 { a / b } else { 0.0 });
+    // the weighted primal image, plus how much more each pixel's weighted gradients say it is than its neighbors
+    let b: Vec<Color> = (0..primal.len()).map(|p| {
+        let (x, y) = (p % width, p / width);
+        let mut divergence = -weights_x[p].mul_element_wise(gradient_x[p]) - weights_y[p].mul_element_wise(gradient_y[p]);
+        if x > 0 { divergence += weights_x[p-1].mul_element_wise(gradient_x[p-1]) }
+        if y > 0 { divergence += weights_y[p-width].mul_element_wise(gradient_y[p-width]) }
+        a2*weights_primal[p].mul_element_wise(primal[p]) + divergence
+    }).collect();
+    let mut img = primal.to_vec();
+    let mut residual: Vec<Color> = b.iter().zip(apply(&img)).map(|(b, a)| b - a).collect();
+    let mut direction = residual.clone();
+    let mut rr = dot(&residual, &residual);
+    for _ in 0..settings.iterations {
+        let ad = apply(&direction);
+        let step = ratio(rr, dot(&direction, &ad));
+        for p in 0..img.len() {
+            img[p] += step.mul_element_wise(direction[p]);
+            residual[p] -= step.mul_element_wise(ad[p]);
+        }
+        let rr_next = dot(&residual, &residual);
+        let beta = ratio(rr_next, rr);
+        for p in 0..img.len() {
+            direction[p] = residual[p] + beta.mul_element_wise(direction[p]);
+        }
+        rr = rr_next;
+    }
+    img
 }
 
 ////////////////////////////////////////////////////////
@@ -104,10 +824,11 @@ pub fn lerpvec(a: Vec3, b: Vec3, k: f32) -> Vec3 {
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    pub time: Float,      // when the ray was cast, within the camera's shutter interval (for moving objects)
 }
 #[derive(Clone)]
 pub struct RayHit {
-    pub distance: f32,  // from origin to hit point
+    pub distance: Float,  // from origin to hit point
     pub hitpoint: Vec3, // location of intersection
     pub normal: Vec3,   // normal at hit point
     pub material: Arc<dyn Material + Send + Sync>, // material properties at hit point
@@ -115,169 +836,1767 @@ pub struct RayHit {
     pub tex_coords: Option<Vec2>,   // tex coords at hit point
     pub tangent: Option<Vec3>,      // tangent vector at hit point
     pub bitangent: Option<Vec3>,    // bitangent vector at hit point
+    pub uv_footprint: Float,          // width of the pixel's footprint in uv space, for texture filtering (0 = sharpest)
+    pub min_roughness: Float,         // glossy and refractive surfaces are at least this rough here (see roughness_regularization)
+    pub object_id: u32,             // which of the scene's objects was hit, numbered from 1 (set by the scene, 0 until then)
 }
 impl RayHit {
     // ray hit constructor
-    pub fn new(distance: f32, normal: Vec3, material: Arc<dyn Material + Send + Sync>, ray: &Ray) -> RayHit {
+    pub fn new(distance: Float, normal: Vec3, material: Arc<dyn Material + Send + Sync>, ray: &Ray) -> RayHit {
         let frontface = normal.dot(ray.direction) < 0.0;
         RayHit { 
-            distance: distance,
+            distance,
             hitpoint: ray.origin+ray.direction*distance,
             normal: if frontface {normal} else {-normal},
-            material: material,
-            frontface: frontface,
+            material,
+            frontface,
             tex_coords: None,
             tangent: None,
             bitangent: None,
+            uv_footprint: 0.0,
+            min_roughness: 0.0,
+            object_id: 0,
         }
     }
 }
 
 // CAMERA
 #[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize)]
+#[serde(default)]   // (settings left out of a description keep their defaults)
 pub struct Camera {
     // camera model based on 419 lectures
     pub eyepoint: Vec3, // 3d location of camera
     pub view_dir: Vec3, // direction from eyepoint through center of image plane
     pub up: Vec3,       // camera up vector
     pub projection_mode: CameraProjectionMode,
+    pub ortho_height: Float,      // height of the view in scene units, for orthographic cameras (whatever the sensor fit)
     pub shading_mode: ShadingMode,
     pub path_depth: u32,        // recursion depth for rendering equation
+    pub bounce_limits: BounceLimits,    // separate limits for each kind of bounce, within path_depth
     pub path_samples: u32,      // number of sample rays to generate per recurive step (anything above 1 is unnecessary)
-    pub screen_width: u32,      // in pixels
+    pub screen_width: u32,      // in pixels (of each eye, when rendering in stereo)
     pub screen_height: u32,     // ""
-    pub focal_length: f32,      // distance from eyepoint to image plane
-    pub focus_dist: f32,        // distance from eyepoint to plane where everything is in focus
-    pub lens_radius: f32,       // radius of approximated thin lens
+    pub sensor_fit: SensorFit,  // side of the image the field of view spans, for images that aren't square
+    pub stereo: Option<Stereo>, // render for both eyes (only perspective cameras have any depth between them)
+    pub focal_length: Float,      // distance from eyepoint to image plane
+    pub focus_dist: Float,        // distance from eyepoint to plane where everything is in focus
+    pub lens_radius: Float,       // radius of approximated thin lens (unless the exposure sets it from its f-number)
+    pub exposure: Option<PhysicalExposure>, // scales the image's brightness like a real camera (None = radiance is shown as is)
+    pub exposure_compensation: Float,         // brightens (or darkens, if negative) the image by this many stops
+    pub aperture: Aperture,     // shape of the lens opening, within lens_radius
+    pub pixel_filter: PixelFilter,  // how samples are weighted into their pixel by where in (or around) it they land
+    pub vignetting: Option<Vignetting>, // darkens the edges of the image like a real lens (None = evenly lit)
+    pub chromatic_aberration: Float,      // how much bigger the red image is than the green (and the blue smaller), as a fraction of its size
+    pub lens_shift: Vec2,       // slides the image sideways (x) and up (y), in image heights, e.g. to keep verticals straight
+    pub lens_tilt: Vec2,        // tilts the plane in focus forward (x) and turns it sideways (y), in degrees, e.g. for a miniature look
+    pub autofocus: Option<Vec2>,    // point of the image to focus on before rendering, from its top left (0, 0) to bottom right (1, 1)
+    pub shutter_open: Float,      // time the shutter opens, rays are cast at times from here...
+    pub shutter_close: Float,     // ...to here (the same time means no motion blur)
     pub aa_sample_count: u32,   // number of samples per pixel (should be perfect square)
-    pub max_trace_dist: f32,    // maximum distance from ray origin to consider intersections
-    pub gamma: f32,             // color gamma correction
+    pub tile_size: u32,         // width and height of the tiles the image is split into to render in parallel, in pixels
+    pub max_trace_dist: Float,    // maximum distance from ray origin to consider intersections
+    pub tone_mapping: ToneMapping,  // how colors too bright for the screen are brought into range
+    pub gamma: Float,             // color gamma correction
+    pub bit_depth: u32,         // bits per channel of the saved image (8, or 16 for png and tiff)
+    pub firefly_clamp: Option<Float>, // highest radiance a path trace sample can carry, to remove fireflies (adds a little bias)
+    pub direct_light: LightAdjustment,      // light reaching the camera after one bounce (lights seen directly are left alone)
+    pub indirect_light: LightAdjustment,    // light reaching the camera after more than one bounce
+    pub aovs: Vec<Aov>,         // extra images of what the camera rays first hit to render along with the image
+    pub cryptomattes: Vec<Cryptomatte>, // id mattes of what the camera rays first hit, saved along with the image (to exr files only)
+    pub transparent_background: bool,   // camera rays that miss everything see black instead of the background (which still
+                                        // lights the scene), for compositing with the alpha aov
+    #[serde(skip)]
+    pub observer: Option<Arc<dyn RenderObserver + Send + Sync>>,  // told about each tile or pass of the render as it finishes (None = nobody)
+    pub progress_json: bool,    // reports progress as lines of json on stderr instead of showing a progress bar (see RenderProgress)
+    pub terminal_preview: Option<TerminalPreview>,  // prints the image into the terminal whenever a progressive render saves it
+    pub ray_stats_regions: Option<u32>, // also breaks the ray statistics printed after a render down over a grid of this many
+                                        // regions across and down, to find the expensive parts of the image (None = whole image only)
+    pub denoiser: Option<Denoiser>, // filters noise out of the finished image, guided by aovs (None = left noisy)
+    pub clay: Option<Color>,    // renders every surface that doesn't give off light as matte this color, to judge the lighting
+                                // apart from the materials (None = surfaces keep their own materials)
+    pub roughness_regularization: Option<Float>,  // roughness added to glossy and refractive surfaces per bounce along a path, to blur away
+                                                // fireflies from caustics that are hard to sample (adds some bias, mostly to caustics)
+    #[serde(skip)]
+    pub sampler: Arc<dyn Sampler + Send + Sync>,    // source of every random number used to render a pixel sample
+    #[serde(skip)]
+    pub cancel: CancelToken,    // stops the render early, keeping what's finished, when cancelled (e.g. from another thread)
+    pub checkpoint_file: Option<String>,    // where a cancelled render saves its progress, and where a render picks up from
+                                            // if it finds one that fits (None = always start over)
+}
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera {
+            eyepoint: Vec3::zero(),
+            view_dir: -Vec3::unit_z(),
+            up: Vec3::unit_y(),
+            projection_mode: CameraProjectionMode::Perspective,
+            ortho_height: 1.0,
+            shading_mode: ShadingMode::PathTrace,
+            path_depth: 10,
+            bounce_limits: BounceLimits::unlimited(),
+            path_samples: 1,
+            screen_width: 100,
+            screen_height: 100,
+            sensor_fit: SensorFit::Vertical,
+            stereo: None,
+            focal_length: 1.0,
+            focus_dist: 1.0,
+            lens_radius: 0.0,
+            exposure: None,
+            exposure_compensation: 0.0,
+            aperture: Aperture::Disk,
+            pixel_filter: PixelFilter::default(),
+            vignetting: None,
+            chromatic_aberration: 0.0,
+            lens_shift: Vec2::zero(),
+            lens_tilt: Vec2::zero(),
+            autofocus: None,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            aa_sample_count: 16,
+            tile_size: 16,
+            max_trace_dist: 100.0,
+            tone_mapping: ToneMapping::Clamp,
+            gamma: 2.0,
+            bit_depth: 8,
+            firefly_clamp: None,
+            direct_light: LightAdjustment::none(),
+            indirect_light: LightAdjustment::none(),
+            roughness_regularization: None,
+            aovs: Vec::new(),
+            cryptomattes: Vec::new(),
+            transparent_background: false,
+            observer: None,
+            progress_json: false,
+            terminal_preview: None,
+            ray_stats_regions: None,
+            denoiser: None,
+            clay: None,
+            sampler: Arc::new(RandomSampler { seed: 0 }),
+            cancel: CancelToken::new(),
+            checkpoint_file: None,
+        }
+    }
+}
+// CAMERA BUILDER - starts from the default camera, changes settings one at a time, and checks they make sense together
+// before handing the camera over, e.g.
+// Camera::builder().eyepoint(vec3(0.0, 2.0, 5.5)).look_at(vec3(0.0, 1.0, 0.0)).vfov(50.0).resolution(800, 600).aa_sample_count(64).build()
+// (settings without a method of their own can be changed with configure)
+pub struct CameraBuilder {
+    camera: Camera,
+    target: Option<Vec3>,       // (aimed at and focused on when built, wherever the eyepoint ends up)
+    vfov: Option<Float>,          // (turned into a focal length when built, once the resolution is known)
+    focus_dist: Option<Float>,
+}
+impl CameraBuilder {
+    pub fn eyepoint(mut self, eyepoint: Vec3) -> CameraBuilder {
+        self.camera.eyepoint = eyepoint;
+        self
+    }
+    pub fn view_dir(mut self, view_dir: Vec3) -> CameraBuilder {
+        self.camera.view_dir = view_dir;
+        self.target = None;
+        self
+    }
+    // points the camera at a target, and focuses on it unless given a focus distance
+    pub fn look_at(mut self, target: Vec3) -> CameraBuilder {
+        self.target = Some(target);
+        self
+    }
+    pub fn up(mut self, up: Vec3) -> CameraBuilder {
+        self.camera.up = up;
+        self
+    }
+    pub fn focal_length(mut self, focal_length: Float) -> CameraBuilder {
+        self.camera.focal_length = focal_length;
+        self.vfov = None;
+        self
+    }
+    // field of view in degrees, along the side the sensor fit measures against (sets the focal length)
+    pub fn vfov(mut self, vfov_degrees: Float) -> CameraBuilder {
+        self.vfov = Some(vfov_degrees);
+        self
+    }
+    pub fn focus_dist(mut self, focus_dist: Float) -> CameraBuilder {
+        self.focus_dist = Some(focus_dist);
+        self
+    }
+    pub fn lens_radius(mut self, lens_radius: Float) -> CameraBuilder {
+        self.camera.lens_radius = lens_radius;
+        self
+    }
+    pub fn resolution(mut self, width: u32, height: u32) -> CameraBuilder {
+        self.camera.screen_width = width;
+        self.camera.screen_height = height;
+        self
+    }
+    pub fn aa_sample_count(mut self, aa_sample_count: u32) -> CameraBuilder {
+        self.camera.aa_sample_count = aa_sample_count;
+        self
+    }
+    pub fn path_depth(mut self, path_depth: u32) -> CameraBuilder {
+        self.camera.path_depth = path_depth;
+        self
+    }
+    pub fn max_trace_dist(mut self, max_trace_dist: Float) -> CameraBuilder {
+        self.camera.max_trace_dist = max_trace_dist;
+        self
+    }
+    pub fn shading_mode(mut self, shading_mode: ShadingMode) -> CameraBuilder {
+        self.camera.shading_mode = shading_mode;
+        self
+    }
+    pub fn tone_mapping(mut self, tone_mapping: ToneMapping) -> CameraBuilder {
+        self.camera.tone_mapping = tone_mapping;
+        self
+    }
+    pub fn gamma(mut self, gamma: Float) -> CameraBuilder {
+        self.camera.gamma = gamma;
+        self
+    }
+    pub fn sampler(mut self, sampler: Arc<dyn Sampler + Send + Sync>) -> CameraBuilder {
+        self.camera.sampler = sampler;
+        self
+    }
+    // changes any other settings, e.g. .configure(|camera| camera.denoiser = Some(Denoiser::default()))
+    pub fn configure(mut self, f: impl FnOnce(&mut Camera)) -> CameraBuilder {
+        f(&mut self.camera);
+        self
+    }
+
+    // the camera, or everything wrong with its settings
+    pub fn build(self) -> Result<Camera, String> {
+        let mut camera = self.camera;
+        if let Some(target) = self.target {
+            camera.view_dir = target - camera.eyepoint;
+            camera.focus_dist = (target - camera.eyepoint).magnitude();
+        }
+        if let Some(focus_dist) = self.focus_dist {
+            camera.focus_dist = focus_dist;
+        }
+        let mut problems = Vec::new();
+        if camera.screen_width == 0 || camera.screen_height == 0 {
+            problems.push(format!("resolution {}x{} has no pixels", camera.screen_width, camera.screen_height));
+        }
+        else if let Some(vfov) = self.vfov {
+            if vfov <= 0.0 || vfov >= 180.0 {
+                problems.push(format!("field of view {} isn't between 0 and 180 degrees", vfov));
+            }
+            camera.focal_length = 0.5*camera.image_plane_size().y / (0.5*vfov.to_radians()).tan();
+        }
+        let root = (camera.aa_sample_count as f64).sqrt().round() as u32;
+        if camera.aa_sample_count == 0 || root*root != camera.aa_sample_count {
+            problems.push(format!("aa_sample_count {} isn't a perfect square (each pixel is split into a square grid of strata)", camera.aa_sample_count));
+        }
+        if camera.view_dir.magnitude2() == 0.0 {
+            problems.push("view_dir is zero (or the camera is looking at its own eyepoint)".to_string());
+        }
+        else if camera.up.cross(camera.view_dir).magnitude2() <= 1e-12*camera.up.magnitude2()*camera.view_dir.magnitude2() {
+            problems.push("up is zero or parallel to view_dir, so which way is up in the image is undefined".to_string());
+        }
+        if camera.focal_length <= 0.0 {
+            problems.push(format!("focal_length {} isn't positive", camera.focal_length));
+        }
+        if camera.focus_dist <= 0.0 {
+            problems.push(format!("focus_dist {} isn't positive", camera.focus_dist));
+        }
+        if camera.lens_radius < 0.0 {
+            problems.push(format!("lens_radius {} is negative", camera.lens_radius));
+        }
+        if camera.path_depth == 0 {
+            problems.push("path_depth is 0, so no light is ever found".to_string());
+        }
+        if camera.max_trace_dist <= 0.0 {
+            problems.push(format!("max_trace_dist {} isn't positive", camera.max_trace_dist));
+        }
+        if camera.gamma <= 0.0 {
+            problems.push(format!("gamma {} isn't positive", camera.gamma));
+        }
+        if camera.tile_size == 0 {
+            problems.push("tile_size is 0".to_string());
+        }
+        if camera.bit_depth != 8 && camera.bit_depth != 16 {
+            problems.push(format!("bit_depth {} isn't 8 or 16", camera.bit_depth));
+        }
+        if camera.shutter_close < camera.shutter_open {
+            problems.push(format!("shutter closes at {} before it opens at {}", camera.shutter_close, camera.shutter_open));
+        }
+        if problems.is_empty() { Ok(camera) } else { Err(problems.join("; ")) }
+    }
 }
 impl Camera {
-    // generate camera rays given pixel coordinates and sample count
-    // currently uses multi-jittered sampling
-    pub fn generate_rays(&self, screen_x: u32, screen_y: u32) -> Vec<Ray> {
-        let pixel_size = 1.0 / self.screen_height as f32;
-        let mut rays = Vec::new();
-        let n = self.aa_sample_count as f32;
-        let rootn = n.sqrt();
-        let mut rng = rand::thread_rng();
-        for i in 0..self.aa_sample_count {
-            // compute multi-jittered pixel offset
-            let rand_x = rng.gen_range(0..self.aa_sample_count) as f32;
-            let rand_y = rng.gen_range(0..self.aa_sample_count) as f32;
-            let subpixel_x = (i / rootn as u32) as f32;
-            let subpixel_y = (i % rootn as u32) as f32;
-            let subpixel_offset = vec2(
-                (subpixel_x - 0.5*rootn)*pixel_size/rootn + (rand_x - 0.5*n)*pixel_size/n,
-                (subpixel_y - 0.5*rootn)*pixel_size/rootn + (rand_y - 0.5*n)*pixel_size/n,
-             );
-            
-            // compute pixel center and offset by jitter
-            let cam_space_pixel_center = vec3(
-                pixel_size*(screen_x as f32 - 0.5*(self.screen_width as f32) + 0.5) + subpixel_offset.x,
-                pixel_size*(0.5 + 0.5*(self.screen_height as f32) - screen_y as f32) + subpixel_offset.y,
-                -self.focal_length
-            );
-            // cast ray from random location in disk to point on focus plane
-            let focus_plane_pixel_center = cam_space_pixel_center.normalize()*self.focus_dist;
-            let lens_origin = self.lens_radius*rand_disk_vec();
-
-            // find rotation from camera to world space:
-            let rotation = Matrix3::from_cols(
-                self.view_dir.cross(self.up).normalize(),
-                self.up,
-                -self.view_dir
-            );
-           
-            // create ray with direction still in camera space
-            let mut ray = Ray {
-                origin: match self.projection_mode {
-                    CameraProjectionMode::Orthographic => vec3(cam_space_pixel_center.x, cam_space_pixel_center.y, 0.0 ),
-                    CameraProjectionMode::Perspective => self.eyepoint + rotation*lens_origin,
-                },
-                direction: match self.projection_mode {
-                    CameraProjectionMode::Orthographic => self.view_dir,
-                    CameraProjectionMode::Perspective => (focus_plane_pixel_center - lens_origin).normalize()
-                },
+    // a builder starting from the default camera (see CameraBuilder)
+    pub fn builder() -> CameraBuilder {
+        CameraBuilder { camera: Camera::default(), target: None, vfov: None, focus_dist: None }
+    }
+    // camera at eye looking at target, with a vertical field of view in degrees and an aspect ratio (width / height)
+    // that sets the image width from the default height. in focus at the target, everything else is the default
+    // (change the rest with struct update syntax, e.g. Camera { aa_sample_count: 100, ..Camera::look_at(...) })
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3, vfov_degrees: Float, aspect: Float) -> Camera {
+        let view_dir = (target - eye).normalize();
+        let right = view_dir.cross(up).normalize();
+        let default = Camera::default();
+        Camera {
+            eyepoint: eye,
+            view_dir,
+            up: right.cross(view_dir),
+            // the image plane is one unit tall with the default sensor fit, so it's this far away to fill the field of view
+            focal_length: 0.5 / (0.5*vfov_degrees.to_radians()).tan(),
+            focus_dist: (target - eye).magnitude(),
+            screen_width: (default.screen_height as Float * aspect).round() as u32,
+            ..default
+        }
+    }
+    // vertical field of view in degrees
+    pub fn vfov(&self) -> Float {
+        2.0*(0.5*self.image_plane_size().y / self.focal_length).atan().to_degrees()
+    }
+    // width over height of each eye's view
+    pub fn aspect(&self) -> Float {
+        self.screen_width as Float / self.screen_height as Float
+    }
+    // size of the image plane, one unit along the side the sensor fit measures against
+    pub fn image_plane_size(&self) -> Vec2 {
+        let aspect = self.aspect();
+        let fit_height = match self.sensor_fit {
+            SensorFit::Vertical => true,
+            SensorFit::Horizontal => false,
+            SensorFit::Auto => aspect <= 1.0,
+        };
+        if fit_height { vec2(aspect, 1.0) } else { vec2(1.0, 1.0/aspect) }
+    }
+    // size of the rendered image, in pixels (which holds both eyes' views in stereo)
+    pub fn image_size(&self) -> (u32, u32) {
+        match self.stereo {
+            None => (self.screen_width, self.screen_height),
+            Some(Stereo { layout: StereoLayout::SideBySide, .. }) => (2*self.screen_width, self.screen_height),
+            Some(Stereo { layout: StereoLayout::OverUnder, .. }) => (self.screen_width, 2*self.screen_height),
+        }
+    }
+    // which eye's view a pixel of the image is in (-1 left, 0 no stereo, 1 right), and its pixel in that view
+    fn eye_pixel(&self, image_x: u32, image_y: u32) -> (Float, u32, u32) {
+        match self.stereo {
+            None => (0.0, image_x, image_y),
+            Some(Stereo { layout: StereoLayout::SideBySide, .. }) if image_x < self.screen_width => (-1.0, image_x, image_y),
+            Some(Stereo { layout: StereoLayout::SideBySide, .. }) => (1.0, image_x - self.screen_width, image_y),
+            Some(Stereo { layout: StereoLayout::OverUnder, .. }) if image_y < self.screen_height => (-1.0, image_x, image_y),
+            Some(Stereo { layout: StereoLayout::OverUnder, .. }) => (1.0, image_x, image_y - self.screen_height),
+        }
+    }
+    // the part of the image the pixel's eye takes up (all of it, unless rendering in stereo)
+    fn eye_area(&self, image_x: u32, image_y: u32) -> Tile {
+        let (width, height) = (self.screen_width, self.screen_height);
+        match self.stereo {
+            None => Tile { x: 0, y: 0, width, height },
+            Some(Stereo { layout: StereoLayout::SideBySide, .. }) => Tile { x: if image_x < width { 0 } else { width }, y: 0, width, height },
+            Some(Stereo { layout: StereoLayout::OverUnder, .. }) => Tile { x: 0, y: if image_y < height { 0 } else { height }, width, height },
+        }
+    }
+    // angle between neighboring pixels' rays, used to estimate how much of a texture a pixel covers
+    // (orthographic rays don't spread, so they always get the sharpest texture level)
+    pub fn pixel_spread(&self) -> Float {
+        match self.projection_mode {
+            CameraProjectionMode::Orthographic => 0.0,
+            CameraProjectionMode::Perspective => self.image_plane_size().y / (self.screen_height as Float * self.focal_length),
+        }
+    }
+    // normal of the plane in focus, in camera space: facing the camera, unless the lens is tilted
+    fn focus_plane_normal(&self) -> Vec3 {
+        Matrix3::from_angle_y(Deg(self.lens_tilt.y)) * Matrix3::from_angle_x(Deg(self.lens_tilt.x)) * Vec3::unit_z()
+    }
+    // where the ray from the center of the lens through a point on the image plane meets the plane in focus
+    // (the plane goes through the point focus_dist in front of the camera. a tilted lens tilts it about that point by
+    // the tilt angles, so with the Scheimpflug principle things at different distances across the image can be sharp)
+    fn focus_point(&self, image_point: Vec3) -> Vec3 {
+        let direction = image_point.normalize();
+        let normal = self.focus_plane_normal();
+        let t = -self.focus_dist*normal.z / direction.dot(normal);
+        // rays parallel to (or facing away from) a steeply tilted plane never reach it, so they focus at the usual distance
+        if t > 0.0 && t.is_finite() { t*direction } else { self.focus_dist*direction }
+    }
+    // radius of the lens opening, in scene units
+    pub fn lens_radius(&self) -> Float {
+        match self.exposure {
+            // the image plane is one unit along its fitted side, so the focal length is in sensor heights (24mm)
+            Some(exposure) => 0.5*self.focal_length*0.024 / exposure.f_number,
+            None => self.lens_radius,
+        }
+    }
+    // scale from radiance to image values
+    pub fn exposure_scale(&self) -> Float {
+        self.exposure.map_or(1.0, |exposure| exposure.scale()) * Float::powf(2.0, self.exposure_compensation)
+    }
+    // how much light the lens lets through to a pixel (of the whole image), relative to the middle of the image
+    pub fn vignetting(&self, image_x: u32, image_y: u32) -> Float {
+        let vignetting = match (self.vignetting, self.projection_mode) {
+            (Some(vignetting), CameraProjectionMode::Perspective) => vignetting,
+            _ => return 1.0,    // orthographic rays all come in straight
+        };
+        let (_, screen_x, screen_y) = self.eye_pixel(image_x, image_y);
+        let plane = self.image_plane_size();
+        let center = vec2(
+            plane.x*((screen_x as Float + 0.5) / self.screen_width as Float - 0.5) + self.lens_shift.x,
+            plane.y*(0.5 - (screen_y as Float + 0.5) / self.screen_height as Float) + self.lens_shift.y,
+        );
+        // natural: cos^4 of the angle the pixel is seen at
+        let cos_theta = self.focal_length / center.extend(self.focal_length).magnitude();
+        let natural = 1.0 + vignetting.natural*(cos_theta.powi(4) - 1.0);
+        // mechanical: how much of the opening overlaps the far end of the barrel, two circles one lens radius across
+        // (exact for a round aperture, and close enough for bladed ones)
+        let d = self.barrel_offset(center.extend(-self.focal_length)).magnitude();
+        let overlap = if d >= 2.0 { 0.0 } else { (2.0*(0.5*d).acos() - 0.5*d*Float::sqrt(4.0 - d*d)) / consts::PI };
+        natural*overlap
+    }
+    // how far the far end of the lens barrel looks shifted across the opening, in lens radii, from a point on the image
+    // plane (in camera space). points of the opening further than one radius from it are blocked
+    fn barrel_offset(&self, image_point: Vec3) -> Vec2 {
+        let barrel_length = self.vignetting.map_or(0.0, |vignetting| vignetting.barrel_length);
+        barrel_length*image_point.truncate() / -image_point.z
+    }
+    // moves a world space direction into camera space (x right, y up, z towards the camera)
+    pub fn to_camera_space(&self, v: Vec3) -> Vec3 {
+        self.rotation().transpose() * v
+    }
+    // rotation from camera to world space
+    // (up is only a hint, so it's made perpendicular to the view direction to keep the frame from skewing)
+    fn rotation(&self) -> Matrix3<Float> {
+        let view_dir = self.view_dir.normalize();
+        let right = view_dir.cross(self.up).normalize();
+        Matrix3::from_cols(
+            right,
+            right.cross(view_dir),
+            -view_dir
+        )
+    }
+    // ray from the center of the lens through a point of the image, given from its top left corner (0, 0) to its bottom
+    // right (1, 1), ignoring stereo, the aperture, and antialiasing
+    pub fn center_ray(&self, point: Vec2) -> Ray {
+        let plane = self.image_plane_size();
+        let cam_space_point = vec3(
+            (point.x - 0.5)*plane.x + self.lens_shift.x,
+            (0.5 - point.y)*plane.y + self.lens_shift.y,
+            -self.focal_length
+        );
+        Ray { origin: self.eyepoint, direction: self.rotation() * cam_space_point.normalize(), time: self.shutter_open }
+    }
+    // the point of the image whose center ray goes in a (world space) direction, if it's in front of the camera
+    // (the inverse of center_ray, so the point may be outside the image)
+    pub fn center_ray_point(&self, direction: Vec3) -> Option<Vec2> {
+        let cam_space_dir = self.to_camera_space(direction);
+        if cam_space_dir.z >= 0.0 { return None }
+        let image_point = cam_space_dir * (-self.focal_length / cam_space_dir.z);
+        let plane = self.image_plane_size();
+        Some(vec2(
+            (image_point.x - self.lens_shift.x) / plane.x + 0.5,
+            0.5 - (image_point.y - self.lens_shift.y) / plane.y,
+        ))
+    }
+    // generate camera rays given pixel coordinates (in the whole image) and sample count
+    pub fn generate_rays(&self, image_x: u32, image_y: u32) -> Vec<Ray> {
+        (0..self.aa_sample_count).map(|i| self.generate_ray(image_x, image_y, i)).collect()
+    }
+    // generate the camera ray for one of a pixel's samples (0 to aa_sample_count-1)
+    pub fn generate_ray(&self, image_x: u32, image_y: u32, i: u32) -> Ray {
+        self.generate_sample(image_x, image_y, i).0
+    }
+    // same, along with where in the image the sample was taken (in pixels from its top left corner), to splat it onto
+    // the pixels around it by the pixel filter
+    // currently uses jittered sampling, with the jitter and lens position taken from the sampler
+    pub fn generate_sample(&self, image_x: u32, image_y: u32, i: u32) -> (Ray, Vec2) {
+        count_ray_stats(|stats| stats.primary_rays += 1);
+        let (eye, screen_x, screen_y) = self.eye_pixel(image_x, image_y);
+        // each eye sits half the ipd to the side, with its image plane shifted back towards the middle
+        let eye_offset = eye*0.5*self.stereo.map_or(0.0, |stereo| stereo.ipd);
+        // pixels are square, so both sides of the image plane give the same size
+        let pixel_size = self.image_plane_size().y / self.screen_height as Float;
+        let rootn = u32::max(1, (self.aa_sample_count as Float).sqrt() as u32);
+        // (sample counts that aren't perfect squares get extra columns of strata)
+        let columns = self.aa_sample_count.div_ceil(rootn);
+        start_sample(&self.sampler, (image_x, image_y), i, 0);
+        // compute jittered offset from the pixel's center (up is positive on the image plane, but down in the image)
+        let jitter = vec2(
+            ((i / rootn) as Float + rand_f32()) / columns as Float - 0.5,
+            ((i % rootn) as Float + rand_f32()) / rootn as Float - 0.5,
+        );
+        let image_position = vec2(image_x as Float + 0.5 + jitter.x, image_y as Float + 0.5 - jitter.y);
+        let subpixel_offset = jitter*pixel_size;
+
+        // compute pixel center and offset by jitter
+        // (and by the lens shift, which slides the image plane sideways instead of turning the camera)
+        let cam_space_pixel_center = vec3(
+            pixel_size*(screen_x as Float - 0.5*(self.screen_width as Float) + 0.5) + subpixel_offset.x - eye_offset*self.focal_length/self.focus_dist + self.lens_shift.x,
+            pixel_size*(0.5*(self.screen_height as Float) - screen_y as Float - 0.5) + subpixel_offset.y + self.lens_shift.y,
+            -self.focal_length
+        );
+        // cast ray from random location in disk to point on focus plane
+        let focus_plane_pixel_center = self.focus_point(cam_space_pixel_center);
+        let mut lens_point = self.aperture.sample(rand_vec2());
+        // only sample the part of the opening the lens barrel leaves open (vignetting() accounts for the light lost),
+        // retrying with more random numbers. a pixel that's nearly cut off gives up and uses what it has
+        let barrel_offset = self.barrel_offset(cam_space_pixel_center);
+        for _ in 0..16 {
+            if (lens_point + barrel_offset).magnitude() <= 1.0 { break }
+            lens_point = self.aperture.sample(rand_vec2());
+        }
+        let lens_origin = self.lens_radius()*lens_point.extend(0.0);
+        // random time while the shutter is open, so anything moving is blurred along its path
+        let time = self.shutter_open + rand_f32()*(self.shutter_close - self.shutter_open);
+
+        let rotation = self.rotation();
+       
+        // create ray with direction still in camera space
+        // (orthographic rays start on the image plane scaled to ortho_height tall, around the eyepoint)
+        let mut ray = Ray {
+            origin: match self.projection_mode {
+                CameraProjectionMode::Orthographic => self.eyepoint + rotation*(self.ortho_height/self.image_plane_size().y*vec3(cam_space_pixel_center.x, cam_space_pixel_center.y, 0.0)),
+                CameraProjectionMode::Perspective => self.eyepoint + rotation*(lens_origin + vec3(eye_offset, 0.0, 0.0)),
+            },
+            direction: match self.projection_mode {
+                CameraProjectionMode::Orthographic => -Vec3::unit_z(),
+                CameraProjectionMode::Perspective => (focus_plane_pixel_center - lens_origin).normalize()
+            },
+            time,
+        };
+        ray.direction = rotation * ray.direction;
+        (ray, image_position)
+    }
+}
+
+// SCENE
+// a pixel's running photon mapping estimate
+struct SppmPixel {
+    radius: Float,        // photons within this distance of the visible point are gathered
+    direct: Color,      // light found by the camera rays themselves, summed over the iterations so far
+    photon_count: Float,  // (reduced) number of photons gathered so far
+    flux: Color,        // light the photons gathered so far bring to the camera, scaled down along with the radius
+    visible: Option<VisiblePoint>,  // where this iteration's camera ray gathers photons (None if nowhere)
+}
+// surface a camera ray reached after any mirrors and glass, and how much of its light reaches the camera
+struct VisiblePoint {
+    hit: RayHit,
+    wo: Vec3,
+    beta: Color,
+}
+// where a light path landed, the direction it came from, and how much light it brought there
+struct LightVertex {
+    hit: RayHit,
+    wi: Vec3,
+    time: Float,
+    beta: Color,
+}
+// grid over the visible points, so photons only look at the ones near them. each visible point is listed in every
+// cell its radius overlaps, and cells are as big across as the largest radius, so a photon's cell lists all it needs
+struct SppmGrid {
+    cell_size: Float,
+    cells: HashMap<(i32, i32, i32), Vec<usize>>,
+}
+impl SppmGrid {
+    fn build(pixels: &[SppmPixel]) -> SppmGrid {
+        let max_radius = pixels.iter().filter(|p| p.visible.is_some()).map(|p| p.radius).fold(0.0, Float::max);
+        let mut grid = SppmGrid { cell_size: Float::max(2.0*max_radius, 1e-6), cells: HashMap::new() };
+        for (i, pixel) in pixels.iter().enumerate() {
+            if let Some(visible) = &pixel.visible {
+                let r = vec3(pixel.radius, pixel.radius, pixel.radius);
+                let (lo, hi) = (grid.cell(visible.hit.hitpoint - r), grid.cell(visible.hit.hitpoint + r));
+                for x in lo.0..=hi.0 {
+                    for y in lo.1..=hi.1 {
+                        for z in lo.2..=hi.2 {
+                            grid.cells.entry((x, y, z)).or_default().push(i);
+                        }
+                    }
+                }
+            }
+        }
+        grid
+    }
+    fn cell(&self, p: Vec3) -> (i32, i32, i32) {
+        ((p.x / self.cell_size).floor() as i32, (p.y / self.cell_size).floor() as i32, (p.z / self.cell_size).floor() as i32)
+    }
+    // visible points that might be within their radius of p
+    fn near(&self, p: Vec3) -> &[usize] {
+        self.cells.get(&self.cell(p)).map_or(&[], |cell| cell.as_slice())
+    }
+}
+
+// a path waiting in a wavefront queue: the ray it's following, and where the light it finds goes
+struct WavefrontPath {
+    ray: Ray,
+    hit: Option<RayHit>,    // found by the intersect stage (camera rays come with theirs)
+    sample: usize,          // which of the pixel's samples the path belongs to
+    branch: Option<usize>,  // which of the sample's first-hit bounces it follows (None until it leaves the first hit)
+    throughput: Color,      // how much of the light it finds reaches the camera
+    bounces: Bounces,
+    bsdf_pdf: Option<Float>,  // pdf it was scattered with, if lights were also sampled from where it left (see shade_ray)
+    from: Option<Arc<dyn Material + Send + Sync>>,  // surface it left from, for its light links
+    cursor: SampleCursor,   // where its random numbers are up to
+}
+impl WavefrontPath {
+    fn links(&self) -> Option<&LightLinks> {
+        self.from.as_ref().and_then(|material| material.light_links())
+    }
+}
+// light found so far for one of a pixel's samples. the first hit's bounces keep their light apart until the end,
+// since direct_light and indirect_light are applied to each of them as a whole (see shade_hit_parts)
+#[derive(Clone)]
+struct WavefrontSample {
+    emitted: Color,         // emitted at the first hit (or the background, for camera rays that miss)
+    direct: Color,          // sampled from lights at the first hit
+    branches: Vec<(Color, Color)>,  // light each of the first hit's bounces finds on its next hit, and after that
+    next_dimension: u32,    // where the next branching path's random numbers start (see shade_wavefront)
+}
+
+pub struct Scene {
+    pub camera: Camera,
+    pub cameras: Vec<(String, Camera)>, // more views of the scene, each rendered to a file named after it (see render_cameras)
+    pub objects: Arc<Vec<Arc<dyn Intersectable + Send + Sync>>>,
+    pub lights: Vec<Arc<dyn Light + Send + Sync>>,  // lights sampled directly at every bounce when path tracing
+    pub background: Arc<dyn Background + Send + Sync>, // seen by rays that don't hit anything
+    pub point_light_pos: Vec3,  // point light only used for phong shading (which was just for debuging) and toon shading
+    pub ambient: Vec3,          // ambient light used for phong shading (and possibly when pathtracing stops recursing)
+    pub object_names: Vec<String>,  // names of the objects, in the same order, for object cryptomattes (see object_name)
+    pub fog: Option<Fog>,       // participating medium filling the whole scene (None = clear air)
+}
+impl Scene {
+    // closest hit on an object, skipping hits on parts its material marks transparent
+    fn intersect_opaque(object: &(dyn Intersectable + Send + Sync), ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
+        let mut t_min = t_min;
+        while let Some(hit) = object.intersect_ray(ray, t_min, t_max) {
+            if hit.material.alpha(&hit) >= ALPHA_CUTOFF {
+                return Some(hit);
+            }
+            t_min = hit.distance + 0.0001;
+        }
+        None
+    }
+    // closest hit in the scene that a kind of ray can see (intersect_ray finds the closest one for an indirect ray)
+    pub fn intersect_ray_kind(&self, ray: &Ray, t_min: Float, t_max: Float, kind: RayKind) -> Option<RayHit> {
+        count_ray_stats(|stats| stats.closest_hit_rays += 1);
+        self.closest_hit(ray, t_min, t_max, kind)
+    }
+    // same, without counting a ray in the ray statistics
+    fn closest_hit(&self, ray: &Ray, t_min: Float, t_max: Float, kind: RayKind) -> Option<RayHit> {
+        // iterate over all objects in the list and return the closest intersection
+        // each object only needs to look closer than the best hit so far, so t_max shrinks as hits are found
+        let mut best_hit = None;
+        let mut best_t = t_max;
+        for (i, object) in self.objects.iter().enumerate().filter(|(_, object)| object.visibility().sees(kind)) {
+            if let Some(mut hit) = Scene::intersect_opaque(object.as_ref(), ray, t_min, best_t) {
+                best_t = hit.distance;
+                hit.object_id = i as u32 + 1;
+                best_hit = Some(hit);
+            }
+        }
+        if let Some(fog_hit) = self.fog_hit(ray, t_min, best_t) {
+            best_hit = Some(fog_hit);
+        }
+        // objects report their uv footprint per unit of spread, so scale it by how fast the camera's rays spread out
+        // (later bounces reuse the camera's spread, which is a rough but cheap approximation)
+        if let Some(hit) = best_hit.as_mut() {
+            hit.uv_footprint *= self.camera.pixel_spread();
+            self.apply_clay(hit);
+        }
+        best_hit
+    }
+    // share of light that gets through along a shadow ray in [t_min, t_max]: participating media let some of it through,
+    // and anything else blocks it all
+    fn transmittance(&self, ray: &Ray, t_min: Float, t_max: Float) -> Float {
+        count_ray_stats(|stats| stats.shadow_rays += 1);
+        let mut transmittance = self.fog.as_ref().map_or(1.0, |fog| fog.transmittance(ray, t_min, t_max));
+        for object in self.objects.iter().filter(|object| object.visibility().shadow) {
+            match object.transmittance(ray, t_min, t_max) {
+                Some(t) => transmittance *= t,
+                None => if Scene::intersect_opaque(object.as_ref(), ray, t_min, t_max).is_some() { return 0.0 },
+            }
+            if transmittance <= 0.0 { return 0.0 }
+        }
+        transmittance
+    }
+    // closest surface along a shadow ray, seeing through participating media, and the share of light the media let
+    // through up to t_max
+    fn shadow_hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> (Option<RayHit>, Float) {
+        count_ray_stats(|stats| stats.shadow_rays += 1);
+        let mut best_hit = None;
+        let mut best_t = t_max;
+        let mut transmittance = 1.0;
+        for (i, object) in self.objects.iter().enumerate().filter(|(_, object)| object.visibility().shadow) {
+            if let Some(t) = object.transmittance(ray, t_min, t_max) {
+                transmittance *= t;
+            }
+            else if let Some(mut hit) = Scene::intersect_opaque(object.as_ref(), ray, t_min, best_t) {
+                best_t = hit.distance;
+                hit.object_id = i as u32 + 1;
+                best_hit = Some(hit);
+            }
+        }
+        if let Some(fog) = &self.fog {
+            transmittance *= fog.transmittance(ray, t_min, best_t);
+        }
+        (best_hit, transmittance)
+    }
+    // where a ray in [t_min, t_max] scatters in the scene's fog, if it does
+    fn fog_hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
+        let fog = self.fog.as_ref()?;
+        let t = fog.scatter(ray, t_min, t_max)?;
+        // (like scattering in a volume, the normal is left empty)
+        Some(RayHit::new(t, Vec3::zero(), fog.phase_function.clone(), ray))
+    }
+    // swaps the hit's material for matte clay when the camera asks for it, unless the surface gives off light
+    // (done after transparent parts are skipped, so cutouts keep their shape)
+    fn apply_clay(&self, hit: &mut RayHit) {
+        if let Some(albedo) = self.camera.clay {
+            if hit.material.emission(hit) == Vec3::zero() {
+                let clay: Arc<dyn Material + Send + Sync> = Arc::new(Lambertian { albedo, ..Default::default() });
+                // (shadow catchers stay shadow catchers)
+                hit.material = if hit.material.is_shadow_catcher() { Arc::new(ShadowCatcher { material: clay }) } else { clay };
+            }
+        }
+    }
+    // sets the camera's focus distance so whatever is under its autofocus point is sharp (when it has one)
+    // surfaces are focused on at their depth in front of the camera, since that's what the plane in focus is measured by
+    pub fn autofocus(&mut self) {
+        let point = match self.camera.autofocus {
+            Some(point) => point,
+            None => return,
+        };
+        let ray = self.camera.center_ray(point);
+        match self.intersect_ray_kind(&ray, 0.001, self.camera.max_trace_dist, RayKind::Camera) {
+            Some(hit) => {
+                let depth = hit.distance*ray.direction.dot(self.camera.view_dir.normalize());
+                self.camera.focus_dist = depth;
+                info!(focus_dist = depth, "autofocus");
+            }
+            None => warn!(focus_dist = self.camera.focus_dist, "autofocus found nothing to focus on, keeping the focus distance"),
+        }
+    }
+    // render scene to image
+    pub fn render_to_image(&self) -> RgbImage {
+        self.display_image(&self.render_with_stats().0)
+    }
+    // render scene to an image of linear radiance (exposed, but not clamped or gamma corrected)
+    pub fn render_to_hdr(&self) -> HdrImage {
+        self.render_with_stats().0
+    }
+    // same, along with an image for each of the camera's aovs
+    pub fn render_with_aovs(&self) -> (HdrImage, Vec<(Aov, HdrImage)>) {
+        let (img, _, aovs) = self.render_with_stats();
+        (img, aovs)
+    }
+    // render scene to image, along with images of each pixel's variance and sample count
+    // (as heatmaps, variance scaled so only the noisiest 1% of pixels saturate to red)
+    pub fn render_with_sample_stats(&self) -> (RgbImage, RgbImage, RgbImage) {
+        let (hdr, stats, _) = self.render_with_stats();
+        let img = self.display_image(&hdr);
+        let (width, height) = self.camera.image_size();
+        let variances: Vec<Float> = stats.iter().map(|s| s.variance).collect();
+        let sample_counts: Vec<Float> = stats.iter().map(|s| s.sample_count as Float).collect();
+        let mut sorted = variances.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let variance_scale = sorted[(0.99*(sorted.len()-1) as Float) as usize];
+        let max_samples = sample_counts.iter().cloned().fold(0.0, Float::max);
+        info!(max_samples, variance_scale, "sample statistics (99th percentile variance)");
+        (
+            img,
+            heatmap(width, height, &variances, variance_scale),
+            heatmap(width, height, &sample_counts, max_samples),
+        )
+    }
+    fn render_with_stats(&self) -> (HdrImage, Vec<PixelStats>, Vec<(Aov, HdrImage)>) {
+        let (width, height) = self.camera.image_size();
+        // (no noise statistics, ray statistics, or aovs from these)
+        if let ShadingMode::Sppm(settings) = self.camera.shading_mode {
+            return (self.render_sppm(&settings), vec![PixelStats::default(); (width*height) as usize], Vec::new());
+        }
+        if let ShadingMode::Mlt(settings) = self.camera.shading_mode {
+            return (self.render_mlt(&settings), vec![PixelStats::default(); (width*height) as usize], Vec::new());
+        }
+        if let ShadingMode::Gradient(settings) = self.camera.shading_mode {
+            return (self.render_gradient(&settings), vec![PixelStats::default(); (width*height) as usize], Vec::new());
+        }
+        if let ShadingMode::LightTrace(settings) = self.camera.shading_mode {
+            if self.camera.stereo.is_none() && matches!(self.camera.projection_mode, CameraProjectionMode::Perspective) {
+                return (self.render_light_trace(&settings), vec![PixelStats::default(); (width*height) as usize], Vec::new());
+            }
+            warn!("light tracing needs a perspective camera without stereo, path tracing instead");
+        }
+        let _span = info_span!("render", mode = "path trace", width, height, samples = self.camera.aa_sample_count).entered();
+        let progress = RenderProgress::new((width*height) as u64, "pixels", self.camera.progress_json);
+        let mut stats = vec![PixelStats::default(); (width*height) as usize];
+        let aov_list = self.rendered_aovs();
+        // splats of the image, followed by each aov
+        let signed: Vec<bool> = std::iter::once(true).chain(aov_list.iter().map(|aov| aov.is_light())).collect();
+        let all_tiles = image_tiles(width, height, self.camera.tile_size);
+        // pick up where a cancelled render left off, if there's a checkpoint from one
+        // (the tiles finished before then don't have noise or ray statistics)
+        let (mut splats, done) = self.load_checkpoint(CheckpointKind::Tiles, &signed)
+            .unwrap_or_else(|| (SplatBuffer::new(0, 0, width, height, signed.clone()), Vec::new()));
+        let mut finished = vec![false; all_tiles.len()];
+        for &i in &done {
+            finished[i as usize] = true;
+            progress.resume((all_tiles[i as usize].width*all_tiles[i as usize].height) as u64);
+        }
+        let start = Instant::now();
+        // render tiles in parallel. idle threads steal tiles from busy ones, so the expensive parts of the image (the mesh,
+        // the glass) get shared out instead of holding up the render while threads with cheap background sit idle
+        // once the render is cancelled, tiles that haven't started yet are skipped (and left for the checkpoint)
+        let tiles: Vec<(u32, Tile, Vec<PixelStats>, SplatBuffer)> = all_tiles.par_iter().enumerate().filter(|(i, _)| !finished[*i]).filter_map(|(i, tile)| {
+            if self.camera.cancel.is_cancelled() { return None }
+            let mut tile_splats = SplatBuffer::for_tile(tile, &self.camera.pixel_filter, signed.clone());
+            let pixels: Vec<PixelStats> = tile.pixels().map(|(x, y)| {
+                let pixel_stats = self.render_pixel(x, y, &aov_list, &mut tile_splats);
+                progress.tick(1);
+                pixel_stats
+            }).collect();
+            progress.finish_tile(i, pixels.iter().map(|pixel| pixel.rays.total_rays()).sum());
+            if let Some(observer) = &self.camera.observer {
+                let colors: Vec<Color> = tile.pixels().map(|(x, y)| tile_splats.average(x, y, 0) * self.camera.exposure_scale() * self.camera.vignetting(x, y)).collect();
+                observer.on_tile_complete(tile, &colors);
+            }
+            Some((i as u32, *tile, pixels, tile_splats))
+        }).collect();
+        // gather tiles' splats (which spill over onto their neighbours) and write the weighted averages to the image
+        for (i, tile, pixels, tile_splats) in tiles {
+            splats.merge(&tile_splats);
+            finished[i as usize] = true;
+            for ((x, y), pixel_stats) in tile.pixels().zip(pixels) {
+                stats[(y*width + x) as usize] = pixel_stats;
+            }
+        }
+        if self.camera.cancel.is_cancelled() {
+            let done: Vec<u32> = (0..all_tiles.len() as u32).filter(|&i| finished[i as usize]).collect();
+            warn!(finished = done.len(), tiles = all_tiles.len(), "render cancelled");
+            self.save_checkpoint(CheckpointKind::Tiles, &splats, &done);
+        } else {
+            self.remove_checkpoint();
+        }
+        let light_scale = |x: u32, y: u32| self.camera.exposure_scale() * self.camera.vignetting(x, y);
+        let mut img = HdrImage::from_fn(width, height, |x, y| color_pixel(splats.average(x, y, 0) * light_scale(x, y)));
+        let mut aovs: Vec<(Aov, HdrImage)> = aov_list.iter().enumerate().map(|(i, aov)| {
+            (*aov, HdrImage::from_fn(width, height, |x, y| color_pixel(splats.average(x, y, i+1) * if aov.is_light() { light_scale(x, y) } else { 1.0 })))
+        }).collect();
+        progress.finish();
+        self.log_ray_stats(&stats, start.elapsed().as_secs_f32());
+        if let Some(denoiser) = &self.camera.denoiser {
+            let _span = info_span!("denoise").entered();
+            let guide = |wanted: Aov| &aovs.iter().find(|(aov, _)| *aov == wanted).unwrap().1;
+            img = denoiser.denoise(&img, guide(Aov::WorldNormal), guide(Aov::Albedo), guide(Aov::Depth));
+            // guides the camera didn't ask for were only rendered for the denoiser
+            aovs.truncate(self.camera.aovs.len());
+        }
+        self.apply_image_effects(&mut img);
+        (img, stats, aovs)
+    }
+
+    // CHECKPOINTS - what a cancelled render had finished, so it can carry on where it left off
+    // saved in the camera's checkpoint file, as little endian words: what kind of render it was, its tile size and sample
+    // count, its splats (see SplatBuffer::to_words), then how many pieces of work it finished and which ones (tile indices, or the
+    // number of passes)
+    fn save_checkpoint(&self, kind: CheckpointKind, splats: &SplatBuffer, done: &[u32]) {
+        let file_name = match &self.camera.checkpoint_file {
+            Some(file_name) => file_name,
+            None => return,
+        };
+        let mut words = vec![kind as u32, self.camera.tile_size, self.camera.aa_sample_count];
+        words.extend(splats.to_words());
+        words.push(done.len() as u32);
+        words.extend(done);
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        match std::fs::write(file_name, bytes) {
+            Ok(()) => info!(file = %file_name, "saved checkpoint (render again to carry on)"),
+            Err(error) => warn!(file = %file_name, %error, "couldn't save checkpoint"),
+        }
+    }
+    // the splats and finished work from the camera's checkpoint file, if there is one and it's from the same kind of
+    // render at the same size and sample count (it can't tell if the scene changed since, so delete the file to start over)
+    fn load_checkpoint(&self, kind: CheckpointKind, signed: &[bool]) -> Option<(SplatBuffer, Vec<u32>)> {
+        let file_name = self.camera.checkpoint_file.as_ref()?;
+        let bytes = std::fs::read(file_name).ok()?;
+        let mut words = bytes.chunks_exact(4).map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+        let (width, height) = self.camera.image_size();
+        let checkpoint = (|| {
+            if words.next()? != kind as u32 || words.next()? != self.camera.tile_size || words.next()? != self.camera.aa_sample_count { return None }
+            let splats = SplatBuffer::from_words(&mut words, width, height, signed)?;
+            let count = words.next()?;
+            let done: Vec<u32> = words.by_ref().take(count as usize).collect();
+            let pieces = match kind {
+                CheckpointKind::Tiles => image_tiles(width, height, self.camera.tile_size).len() as u32,
+                CheckpointKind::Passes => u32::MAX,
+            };
+            if done.len() != count as usize || done.iter().any(|&i| i >= pieces) { return None }
+            Some((splats, done))
+        })();
+        match &checkpoint {
+            Some(_) => info!(file = %file_name, "carrying on from checkpoint (delete it to start over)"),
+            None => warn!(file = %file_name, "ignoring checkpoint from a different render"),
+        }
+        checkpoint
+    }
+    // deletes the checkpoint once the render it was for is finished
+    fn remove_checkpoint(&self) {
+        if let Some(file_name) = &self.camera.checkpoint_file {
+            if Path::new(file_name).exists() && std::fs::remove_file(file_name).is_err() {
+                warn!(file = %file_name, "couldn't delete finished checkpoint");
+            }
+        }
+    }
+
+    // logs the work the render did, from each pixel's ray statistics, and where in the image it was done if the camera
+    // asks for regions
+    fn log_ray_stats(&self, stats: &[PixelStats], seconds: f32) {
+        let (width, height) = self.camera.image_size();
+        stats.iter().map(|s| s.rays).sum::<RayStats>().log(seconds, "ray statistics");
+        let regions = match self.camera.ray_stats_regions {
+            Some(regions) => regions.max(1),
+            None => return,
+        };
+        // (regions get a share of the render time by how many rays they traced, since pixels aren't timed)
+        let total_rays = stats.iter().map(|s| s.rays.total_rays()).sum::<u64>().max(1);
+        for region_y in 0..regions {
+            for region_x in 0..regions {
+                let (x0, x1) = (region_x*width / regions, (region_x+1)*width / regions);
+                let (y0, y1) = (region_y*height / regions, (region_y+1)*height / regions);
+                let rays: RayStats = (y0..y1).flat_map(|y| (x0..x1).map(move |x| (x, y)))
+                    .map(|(x, y)| stats[(y*width + x) as usize].rays).sum();
+                rays.log(seconds * rays.total_rays() as f32 / total_rays as f32,
+                    &format!("ray statistics for region ({}, {}), pixels ({}, {}) to ({}, {})", region_x, region_y, x0, y0, x1, y1));
+            }
+        }
+    }
+
+    // the camera's aovs, followed by any more the denoiser needs
+    fn rendered_aovs(&self) -> Vec<Aov> {
+        let mut aovs = self.camera.aovs.clone();
+        if self.camera.denoiser.is_some() {
+            for guide in Denoiser::GUIDES {
+                if !aovs.contains(&guide) { aovs.push(guide) }
+            }
+        }
+        aovs
+    }
+
+    // name of one of the scene's objects (numbered from 1, like hits' object ids) in object cryptomattes: its name in
+    // object_names, or else its number
+    pub fn object_name(&self, object_id: u32) -> String {
+        match self.object_names.get(object_id as usize - 1) {
+            Some(name) => name.clone(),
+            None => format!("object{}", object_id),
+        }
+    }
+    // the camera's cryptomattes, from the first hits of each pixel's camera rays (the same rays its samples start from,
+    // each counting the same, whatever the pixel filter). rays that hit nothing leave their share of the pixel uncovered
+    pub fn render_cryptomattes(&self) -> Vec<CryptomatteImage> {
+        if self.camera.cryptomattes.is_empty() { return Vec::new() }
+        let (width, height) = self.camera.image_size();
+        let hits: Vec<Vec<Option<RayHit>>> = (0..width*height).into_par_iter().map(|i| {
+            let cam_rays = self.camera.generate_rays(i % width, i / width);
+            self.intersect_packet(&cam_rays, self.camera_t_min(), self.camera.max_trace_dist)
+        }).collect();
+        self.camera.cryptomattes.iter().map(|&kind| {
+            let names = hits.iter().map(|hits| {
+                let names = hits.iter().flatten().map(|hit| match kind {
+                    Cryptomatte::Object => (self.object_name(hit.object_id), 1.0),
+                    Cryptomatte::Material => (hit.material.name().to_string(), 1.0),
+                }).collect();
+                (names, hits.len() as Float)
+            }).collect();
+            CryptomatteImage::from_names(kind, width, height, names)
+        }).collect()
+    }
+
+    // traces a pixel's samples and splats them onto the pixels around it (before they're saturated and gamma corrected)
+    // along with their values in the given aovs, returning the pixel's noise statistics
+    fn render_pixel(&self, x: u32, y: u32, aov_list: &[Aov], splats: &mut SplatBuffer) -> PixelStats {
+        // get rays and trace them together as a packet
+        // (whatever was counted on this thread before the pixel started isn't the pixel's)
+        take_ray_stats();
+        let (cam_rays, positions): (Vec<Ray>, Vec<Vec2>) = (0..self.camera.aa_sample_count).map(|i| self.camera.generate_sample(x, y, i)).unzip();
+        let cam_hits = self.intersect_packet(&cam_rays, self.camera_t_min(), self.camera.max_trace_dist);
+        let shaded: Vec<(Color, Color, Color)> = match self.camera.shading_mode {
+            ShadingMode::Wavefront => self.shade_wavefront(x, y, &cam_rays, &cam_hits),
+            _ => cam_rays.iter().zip(&cam_hits).enumerate().map(|(i, (ray, hit))| self.shade_sample(x, y, i as u32, ray, hit.clone())).collect(),
+        };
+        let area = self.camera.eye_area(x, y);
+        let (mut sum, mut sum_squares) = (0.0, 0.0);
+        let mut values = Vec::with_capacity(aov_list.len() + 1);
+        for ((ray, hit), (position, (sample, direct, indirect))) in cam_rays.iter().zip(cam_hits).zip(positions.into_iter().zip(shaded)) {
+            sum += luminance(sample);
+            sum_squares += luminance(sample)*luminance(sample);
+            values.clear();
+            values.push(sample);
+            let alpha = if aov_list.contains(&Aov::Alpha) { self.sample_alpha(ray, &hit) } else { 1.0 };
+            values.extend(aov_list.iter().map(|aov| aov.value(&self.camera, ray, &hit, direct, indirect, alpha)));
+            splats.add(&self.camera.pixel_filter, position, &area, &values);
+        }
+        PixelStats::from_sums(cam_rays.len() as u32, sum, sum_squares, take_ray_stats())
+    }
+    // closest a camera ray's hit can be (phong shading is just for debugging, so it doesn't worry about acne)
+    fn camera_t_min(&self) -> Float {
+        if matches!(self.camera.shading_mode, ShadingMode::Phong) { 0.0 } else { 0.001 }
+    }
+    // shades one of a pixel's samples from its camera ray's hit, along with the direct and indirect light in it
+    // (path tracing only, and without the firefly clamp)
+    fn shade_sample(&self, x: u32, y: u32, i: u32, ray: &Ray, hit: Option<RayHit>) -> (Color, Color, Color) {
+        // shading continues the sample its camera ray was generated from
+        start_sample(&self.camera.sampler, (x, y), i, CAMERA_DIMENSIONS);
+        match self.camera.shading_mode {
+            ShadingMode::Phong => (self.phong_shade_hit(ray, hit), Color::zero(), Color::zero()),
+            // (sppm, mlt, light tracing and gradient-domain path tracing render whole images at a time, so renders that
+            // shade pixels one at a time path trace them instead, which is also how mlt and gradient-domain rendering
+            // trace their paths)
+            // (wavefront path tracing shades a pixel's samples all together, see render_pixel)
+            ShadingMode::PathTrace | ShadingMode::Sppm(_) | ShadingMode::Mlt(_) | ShadingMode::Wavefront | ShadingMode::LightTrace(_) | ShadingMode::Gradient(_) => {
+                let (emitted, direct, indirect) = self.shade_hit_parts(ray, hit, Bounces::default(), None, None);
+                (clamp_radiance(emitted + direct + indirect, self.camera.firefly_clamp), direct, indirect)
+            }
+            ShadingMode::Toon(style) => (self.toon_shade_hit(ray, hit, style), Color::zero(), Color::zero()),
+        }
+    }
+
+    // renders in passes of one sample for every pixel, adding them up in an accumulation buffer, and writes the image so
+    // far to file_name (in any format save_image can write) every save_interval seconds, and when done. takes the same samples as render_to_image (other than
+    // the random numbers volumes draw), but shows how the lighting looks after the first few passes instead of only at the end
+    // (without aovs or denoising, which need the finished image). cancelling it stops after the pass it's on
+    pub fn render_progressive(&self, file_name: &str, save_interval: Float) -> HdrImage {
+        let (width, height) = self.camera.image_size();
+        let _span = info_span!("render", mode = "progressive", width, height, samples = self.camera.aa_sample_count).entered();
+        let passes = self.camera.aa_sample_count;
+        let progress = RenderProgress::new(passes as u64, "passes", self.camera.progress_json);
+        let tiles = image_tiles(width, height, self.camera.tile_size);
+        // pick up where a cancelled render left off, if there's a checkpoint from one
+        let (mut accumulated, done) = self.load_checkpoint(CheckpointKind::Passes, &[true])
+            .unwrap_or_else(|| (SplatBuffer::new(0, 0, width, height, vec![true]), vec![0]));
+        let first_pass = u32::min(done.first().copied().unwrap_or(0), passes);
+        progress.resume(first_pass as u64);
+        let mut img = HdrImage::new(width, height);
+        let mut last_save = Instant::now();
+        for pass in first_pass..passes {
+            if self.camera.cancel.is_cancelled() {
+                warn!(finished = pass, passes, "render cancelled");
+                self.save_checkpoint(CheckpointKind::Passes, &accumulated, &[pass]);
+                break;
+            }
+            // trace this pass's sample of every pixel (tiles are shared out between threads just like render_to_image)
+            let samples: Vec<SplatBuffer> = tiles.par_iter().map(|tile| {
+                let mut splats = SplatBuffer::for_tile(tile, &self.camera.pixel_filter, vec![true]);
+                for (x, y) in tile.pixels() {
+                    let (ray, position) = self.camera.generate_sample(x, y, pass);
+                    let hit = self.intersect_ray_kind(&ray, self.camera_t_min(), self.camera.max_trace_dist, RayKind::Camera);
+                    let color = self.shade_sample(x, y, pass, &ray, hit).0;
+                    splats.add(&self.camera.pixel_filter, position, &self.camera.eye_area(x, y), &[color]);
+                }
+                splats
+            }).collect();
+            for splats in samples {
+                accumulated.merge(&splats);
+            }
+            progress.inc(1);
+            // write the average of the passes so far (and what there is when cancelled)
+            let save = pass == passes-1 || last_save.elapsed().as_secs_f32() as Float >= save_interval || self.camera.cancel.is_cancelled();
+            if save || self.camera.observer.is_some() {
+                let scale = self.camera.exposure_scale();
+                img = HdrImage::from_fn(width, height, |x, y| {
+                    let color = accumulated.average(x, y, 0) * scale * self.camera.vignetting(x, y);
+                    color_pixel(color)
+                });
+                self.apply_image_effects(&mut img);
+                if let Some(observer) = &self.camera.observer {
+                    observer.on_pass_complete(pass + 1, &img);
+                }
+            }
+            if save {
+                self.save_image(&img, file_name);
+                if let Some(preview) = self.camera.terminal_preview {
+                    progress.println(&preview.draw(&self.display_image(&img)));
+                }
+                last_save = Instant::now();
+            }
+        }
+        if !self.camera.cancel.is_cancelled() {
+            self.remove_checkpoint();
+        }
+        progress.finish();
+        img
+    }
+
+    // SPPM - stochastic progressive photon mapping (Hachisuka and Jensen 2009), as in pbrt-v3
+    // each iteration traces a camera ray through every pixel, following mirrors, glass, and glossy surfaces until it lands
+    // on a diffuse surface that light can be gathered at (a visible point), and lights that with light sampling like the path tracer does.
+    // then photons are traced out from the lights, and every photon landing within a visible point's radius after its
+    // first bounce adds to its indirect light. the radius shrinks as photons come in, so the estimate gets sharper as
+    // well as less noisy. caustics (light focused onto a surface through glass) can only be found by paths from the
+    // lights, which is why the path tracer renders them as noise. only lights with sample_emission give off photons,
+    // and volumes are path traced as usual
+    pub fn render_sppm(&self, settings: &SppmSettings) -> HdrImage {
+        const RADIUS_REDUCTION: Float = 2.0/3.0;    // share of new photons kept in each pixel's count (alpha in the paper)
+        let (width, height) = self.camera.image_size();
+        let _span = info_span!("render", mode = "photon mapping", width, height, iterations = settings.iterations).entered();
+        let progress = RenderProgress::new(settings.iterations as u64, "iterations", self.camera.progress_json);
+        let mut pixels: Vec<SppmPixel> = (0..width*height).map(|_| SppmPixel {
+            radius: settings.initial_radius,
+            direct: Color::zero(),
+            photon_count: 0.0,
+            flux: Color::zero(),
+            visible: None,
+        }).collect();
+        // lights are picked by how much light they give off
+        let (light_cdf, total_power) = build_cdf(&self.lights.iter().map(|light| light.power()).collect::<Vec<Float>>());
+        // (cancelling stops after the iteration it's on, keeping the ones before it)
+        let mut iterations = 0;
+        for iteration in 0..settings.iterations {
+            if self.camera.cancel.is_cancelled() {
+                warn!(finished = iteration, iterations = settings.iterations, "render cancelled");
+                break;
+            }
+            iterations += 1;
+            // camera pass
+            pixels.par_iter_mut().enumerate().for_each(|(i, pixel)| {
+                let (x, y) = (i as u32 % width, i as u32 / width);
+                let (direct, visible) = self.sppm_camera_path(x, y, iteration);
+                pixel.direct += direct;
+                pixel.visible = visible;
+            });
+            // photon pass, with each thread adding up the photons it finds at every pixel on its own
+            if total_power > 0.0 {
+                let grid = SppmGrid::build(&pixels);
+                let threads = rayon::current_num_threads() as u32;
+                let found: Vec<Vec<(Color, u32)>> = (0..threads).into_par_iter().map(|thread| {
+                    let mut found = vec![(Color::zero(), 0); pixels.len()];
+                    for photon in (thread..settings.photons_per_iteration).step_by(threads as usize) {
+                        // photons take their numbers from a sample of their own, past the edge of the image
+                        start_sample(&self.camera.sampler, (u32::MAX, iteration), photon, 0);
+                        self.sppm_trace_photon(&light_cdf, &grid, &pixels, &mut found);
+                    }
+                    found
+                }).collect();
+                // shrink the radius of every pixel that found photons, keeping its flux to what's within the new radius
+                for (i, pixel) in pixels.iter_mut().enumerate() {
+                    let (phi, m) = found.iter().fold((Color::zero(), 0), |(phi, m), thread| (phi + thread[i].0, m + thread[i].1));
+                    let beta = match &pixel.visible {
+                        Some(visible) if m > 0 => visible.beta,
+                        _ => continue,
+                    };
+                    let count = pixel.photon_count + RADIUS_REDUCTION*m as Float;
+                    let radius = pixel.radius*Float::sqrt(count / (pixel.photon_count + m as Float));
+                    pixel.flux = (pixel.flux + beta.mul_element_wise(phi)) * (radius*radius) / (pixel.radius*pixel.radius);
+                    pixel.photon_count = count;
+                    pixel.radius = radius;
+                }
+            }
+            progress.inc(1);
+        }
+        progress.finish();
+        // light found by the camera rays, plus the density of the photons found around them
+        let photons = u32::max(iterations, 1) as Float * settings.photons_per_iteration as Float;
+        let mut img = HdrImage::from_fn(width, height, |x, y| {
+            let pixel = &pixels[(y*width + x) as usize];
+            let color = pixel.direct / u32::max(iterations, 1) as Float + pixel.flux / (photons*consts::PI*pixel.radius*pixel.radius);
+            let color = color * self.camera.exposure_scale() * self.camera.vignetting(x, y);
+            color_pixel(color)
+        });
+        self.apply_image_effects(&mut img);
+        img
+    }
+    // traces one iteration's camera ray through a pixel until it finds a visible point (if it finds any), returning the
+    // light it found on the way (from emission, the background, and light sampling at the visible point)
+    fn sppm_camera_path(&self, x: u32, y: u32, iteration: u32) -> (Color, Option<VisiblePoint>) {
+        let mut ray = self.camera.generate_ray(x, y, iteration);
+        start_sample(&self.camera.sampler, (x, y), iteration, CAMERA_DIMENSIONS);
+        let mut beta = vec3(1.0, 1.0, 1.0);
+        let mut light = Color::zero();
+        let (mut t_min, mut kind) = (self.camera_t_min(), RayKind::Camera);
+        for _ in 0..self.camera.path_depth {
+            let hit = match self.intersect_ray_kind(&ray, t_min, self.camera.max_trace_dist, kind) {
+                Some(hit) => hit,
+                None => return (light + beta.mul_element_wise(self.escaped_light(&ray, None, None)), None),
+            };
+            (t_min, kind) = (0.001, RayKind::Indirect);
+            // volumes have nowhere to gather photons on, so they're path traced
+            if hit.normal.magnitude2() == 0.0 {
+                return (light + beta.mul_element_wise(self.shade_hit(&ray, Some(hit), Bounces::default(), None, None)), None);
+            }
+            light += beta.mul_element_wise(hit.material.emission(&hit));
+            // diffuse surfaces gather photons, and camera rays carry on past anything else (mirrors, glass, glossy surfaces)
+            let wo = -ray.direction.normalize();
+            if hit.material.is_diffuse(&hit) && hit.material.eval(&hit, wo, wo).is_some() {
+                // light straight from the lights, found by sampling them and by a scattered ray (weighted against each
+                // other like the path tracer does). photons only bring the light that's bounced at least once more
+                light += beta.mul_element_wise(self.sample_lights(&hit, &ray));
+                let (new_ray, brdf, pdf, _) = hit.material.scatter(&hit, &ray);
+                let bsdf_pdf = hit.material.eval(&hit, wo, new_ray.direction.normalize()).map(|(_, pdf)| pdf).filter(|&pdf| pdf > 0.0 && !self.lights.is_empty());
+                let links = hit.material.light_links();
+                let found = match self.intersect_ray(&new_ray, 0.001, self.camera.max_trace_dist) {
+                    Some(next_hit) => self.found_emission(&new_ray, &next_hit, bsdf_pdf, links),
+                    None => self.escaped_light(&new_ray, bsdf_pdf, links),
+                };
+                let weight = brdf * new_ray.direction.normalize().dot(hit.normal).abs() / pdf;
+                light += beta.mul_element_wise(weight.mul_element_wise(found));
+                return (light, Some(VisiblePoint { hit, wo, beta }));
+            }
+            let (new_ray, brdf, pdf, _) = hit.material.scatter(&hit, &ray);
+            beta = beta.mul_element_wise(brdf) * new_ray.direction.normalize().dot(hit.normal).abs() / pdf;
+            if beta == Color::zero() || !beta.x.is_finite() { break }
+            ray = new_ray;
+        }
+        (light, None)
+    }
+    // traces a photon out from a light, adding it to every visible point it lands near after its first bounce
+    // (light straight from the lights is already sampled at the visible points)
+    fn sppm_trace_photon(&self, light_cdf: &[Float], grid: &SppmGrid, pixels: &[SppmPixel], found: &mut [(Color, u32)]) {
+        let (i, _) = sample_cdf(light_cdf, rand_f32());
+        let (mut ray, light) = match self.lights[i].sample_emission() {
+            Some(emission) => emission,
+            None => return,
+        };
+        let mut beta = light / (light_cdf[i+1] - light_cdf[i]);
+        for depth in 0..self.camera.path_depth {
+            let hit = match self.intersect_ray(&ray, 0.001, self.camera.max_trace_dist) {
+                Some(hit) => hit,
+                None => return,
+            };
+            let wi = -ray.direction.normalize();
+            if depth > 0 && hit.normal.magnitude2() > 0.0 {
+                for &j in grid.near(hit.hitpoint) {
+                    let visible = match &pixels[j].visible {
+                        Some(visible) => visible,
+                        None => continue,
+                    };
+                    if (visible.hit.hitpoint - hit.hitpoint).magnitude2() > pixels[j].radius*pixels[j].radius { continue }
+                    if let Some((brdf, _)) = visible.hit.material.eval(&visible.hit, visible.wo, wi) {
+                        found[j].0 += beta.mul_element_wise(brdf);
+                        found[j].1 += 1;
+                    }
+                }
+            }
+            let (new_ray, brdf, pdf, _) = hit.material.scatter(&hit, &ray);
+            let dot_term = if hit.normal.magnitude2() > 0.0 { new_ray.direction.normalize().dot(hit.normal).abs() } else { 1.0 };
+            let new_beta = beta.mul_element_wise(brdf) * dot_term / pdf;
+            // russian roulette, keeping the photon with the share of its power that survived the bounce
+            let survival = Float::min(luminance(new_beta) / luminance(beta), 1.0);
+            if survival.is_nan() || survival <= 0.0 || rand_f32() >= survival { return }
+            beta = new_beta / survival;
+            ray = new_ray;
+        }
+    }
+
+    // MLT - primary sample space metropolis light transport (Kelemen et al. 2002), as in pbrt-v3 but with paths traced
+    // by the path tracer instead of bidirectionally. every random number a sample uses is one coordinate of a point in
+    // primary sample space (see MltSampler), and chains of points wander through it, each step proposing a point nearby
+    // (or an entirely new one) and moving there with a chance given by how much brighter its path is. chains spend their
+    // time on paths in proportion to how bright they are, so once one finds light that only gets through along a few
+    // paths (through a gap, or a caustic seen in a mirror) it keeps exploring the paths around it instead of losing it
+    // again. the image is a histogram of where the chains went, scaled by the brightness of random bootstrap paths
+    pub fn render_mlt(&self, settings: &MltSettings) -> HdrImage {
+        let (width, height) = self.camera.image_size();
+        let _span = info_span!("render", mode = "metropolis", width, height, mutations_per_pixel = settings.mutations_per_pixel).entered();
+        // the render seed picks every chain's random numbers, so the same seed still renders the same image
+        let seed = (to_f64(self.camera.sampler.get((u32::MAX, u32::MAX), 0, 0)) * u32::MAX as f64) as u64;
+        let sampler_seed = |i: u64| seed ^ i.wrapping_mul(0x9e3779b97f4a7c15);
+        let new_sampler = |i: u64| Arc::new(MltSampler::new(sampler_seed(i), settings.sigma, settings.large_step_probability));
+        // bootstrap, tracing one path from each of many random points
+        let weights: Vec<Float> = (0..settings.bootstrap_samples).into_par_iter().map(|i| {
+            let sampler = new_sampler(i as u64);
+            luminance(self.with_sampler(sampler.clone()).mlt_sample(&sampler).2)
+        }).collect();
+        let (bootstrap_cdf, total) = build_cdf(&weights);
+        let brightness = total / settings.bootstrap_samples as Float;
+        let mut img = HdrImage::new(width, height);
+        if brightness <= 0.0 || !brightness.is_finite() {
+            warn!("no light found");
+            return img;
+        }
+        // run the chains, with each thread adding up its chains' samples on its own
+        let progress = RenderProgress::new(settings.chains as u64, "chains", self.camera.progress_json);
+        let mutations = settings.mutations_per_pixel as u64 * (width*height) as u64;
+        let threads = rayon::current_num_threads() as u32;
+        // (cancelling stops every chain where it is, and the image is scaled by the mutations made before then)
+        let histograms: Vec<(Vec<Color>, u64)> = (0..threads).into_par_iter().map(|thread| {
+            let mut histogram = vec![Color::zero(); (width*height) as usize];
+            let mut mutations_made = 0;
+            for chain in (thread..settings.chains).step_by(threads as usize) {
+                // start from a bootstrap path, picked by its brightness
+                let mut rng = StdRng::seed_from_u64(sampler_seed(settings.bootstrap_samples as u64 + chain as u64));
+                let (start, _) = sample_cdf(&bootstrap_cdf, rng.gen());
+                let sampler = new_sampler(start as u64);
+                let scene = self.with_sampler(sampler.clone());
+                let (mut x, mut y, mut current) = scene.mlt_sample(&sampler);
+                let chain_mutations = mutations / settings.chains as u64 + if (chain as u64) < mutations % settings.chains as u64 { 1 } else { 0 };
+                for _ in 0..chain_mutations {
+                    if self.camera.cancel.is_cancelled() { break }
+                    mutations_made += 1;
+                    sampler.start_iteration();
+                    let (new_x, new_y, proposed) = scene.mlt_sample(&sampler);
+                    let proposed = if luminance(proposed).is_finite() { proposed } else { Color::zero() };
+                    let (l_current, l_proposed) = (luminance(current), luminance(proposed));
+                    let accept = if l_current > 0.0 { Float::min(1.0, l_proposed / l_current) } else { 1.0 };
+                    // both paths count towards the image, by how likely the chain is to be at each after this step
+                    // (their colors are divided by their brightness, which the chain already visits them in proportion to)
+                    if accept > 0.0 && l_proposed > 0.0 {
+                        histogram[(new_y*width + new_x) as usize] += proposed * accept / l_proposed;
+                    }
+                    if l_current > 0.0 {
+                        histogram[(y*width + x) as usize] += current * (1.0 - accept) / l_current;
+                    }
+                    if rng.gen::<Float>() < accept {
+                        (x, y, current) = (new_x, new_y, proposed);
+                        sampler.accept();
+                    }
+                    else {
+                        sampler.reject();
+                    }
+                }
+                progress.inc(1);
+            }
+            (histogram, mutations_made)
+        }).collect();
+        progress.finish();
+        let mutations_made: u64 = histograms.iter().map(|(_, count)| count).sum();
+        if self.camera.cancel.is_cancelled() {
+            warn!(finished = mutations_made, mutations, "render cancelled");
+        }
+        let scale = brightness * (width*height) as Float / u64::max(mutations_made, 1) as Float;
+        for (i, pixel) in img.pixels_mut().enumerate() {
+            let color: Color = histograms.iter().map(|(histogram, _)| histogram[i]).sum::<Color>() * scale;
+            *pixel = color_pixel(color);
+        }
+        self.apply_image_effects(&mut img);
+        img
+    }
+    // shallow copy of the scene, with a camera that takes its random numbers from another sampler (a metropolis
+    // sampler, or a shifted one)
+    fn with_sampler(&self, sampler: Arc<dyn Sampler + Send + Sync>) -> Scene {
+        self.with_camera(Camera { sampler, ..self.camera.clone() })
+    }
+    // shallow copy of the scene seen through another camera (sharing its objects, so their bvhs and textures too)
+    pub fn with_camera(&self, camera: Camera) -> Scene {
+        Scene {
+            camera,
+            cameras: self.cameras.clone(),
+            objects: self.objects.clone(),
+            lights: self.lights.clone(),
+            background: self.background.clone(),
+            point_light_pos: self.point_light_pos,
+            ambient: self.ambient,
+            object_names: self.object_names.clone(),
+            fog: self.fog.clone(),
+        }
+    }
+    // traces the path given by the sampler's current point (for a scene from with_sampler), returning the pixel it
+    // lands in and the light it brings back there
+    fn mlt_sample(&self, sampler: &MltSampler) -> (u32, u32, Color) {
+        let (width, height) = self.camera.image_size();
+        let (u, v, w) = sampler.image_sample();
+        let x = u32::min((u*width as Float) as u32, width-1);
+        let y = u32::min((v*height as Float) as u32, height-1);
+        let i = u32::min((w*self.camera.aa_sample_count as Float) as u32, self.camera.aa_sample_count-1);
+        let ray = self.camera.generate_ray(x, y, i);
+        let hit = self.intersect_ray_kind(&ray, self.camera_t_min(), self.camera.max_trace_dist, RayKind::Camera);
+        let color = self.shade_sample(x, y, i, &ray, hit).0 * self.camera.exposure_scale() * self.camera.vignetting(x, y);
+        (x, y, color)
+    }
+
+    // LIGHT TRACING - the path tracer turned around: paths are traced out from the lights (like sppm's photons), and
+    // every surface they land on is connected straight to the camera through a random point of the lens, adding the
+    // light it sends that way to the pixel it's seen in. light focused through glass onto a small spot (a caustic) is
+    // easy to find from the lights, while camera rays only find it by chance. only light that's bounced at least once
+    // is light traced: lights seen directly, and emission and the background seen through mirrors and glass, come
+    // from one camera ray per pixel for every pass of light paths. only lights with sample_emission give off light
+    // paths, so emissive surfaces that aren't among the scene's lights (and the background) light nothing, and
+    // surfaces that are only seen in mirrors or through glass stay dark, since there's nothing to connect them to the
+    // camera through. light links are ignored, and the pixel filter and chromatic aberration aren't applied
+    pub fn render_light_trace(&self, settings: &LightTraceSettings) -> HdrImage {
+        let (width, height) = self.camera.image_size();
+        let _span = info_span!("render", mode = "light trace", width, height, paths_per_pixel = settings.paths_per_pixel).entered();
+        let pixel_count = (width*height) as usize;
+        let progress = RenderProgress::new(settings.paths_per_pixel as u64, "passes", self.camera.progress_json);
+        // lights are picked by how much light they give off
+        let (light_cdf, total_power) = build_cdf(&self.lights.iter().map(|light| light.power()).collect::<Vec<Float>>());
+        let mut seen = vec![Color::zero(); pixel_count];
+        let mut splatted = vec![Color::zero(); pixel_count];
+        // (cancelling stops after the pass it's on, keeping the ones before it)
+        let mut passes = 0;
+        for pass in 0..settings.paths_per_pixel {
+            if self.camera.cancel.is_cancelled() {
+                warn!(finished = pass, passes = settings.paths_per_pixel, "render cancelled");
+                break;
+            }
+            passes += 1;
+            // camera pass
+            seen.par_iter_mut().enumerate().for_each(|(i, light)| {
+                *light += self.light_trace_camera_path(i as u32 % width, i as u32 / width, pass);
+            });
+            // light pass of one path per pixel, with each thread splatting its paths onto an image of its own
+            if total_power > 0.0 {
+                let threads = rayon::current_num_threads() as u32;
+                let found: Vec<Vec<Color>> = (0..threads).into_par_iter().map(|thread| {
+                    let mut found = vec![Color::zero(); pixel_count];
+                    for path in (thread..pixel_count as u32).step_by(threads as usize) {
+                        // light paths take their numbers from a sample of their own, past the edge of the image
+                        // (and apart from sppm's photons)
+                        start_sample(&self.camera.sampler, (u32::MAX - 1, pass), path, 0);
+                        for vertex in self.light_subpath(&light_cdf) {
+                            if let Some((x, y, light)) = self.connect_to_camera(&vertex) {
+                                found[(y*width + x) as usize] += light;
+                            }
+                        }
+                    }
+                    found
+                }).collect();
+                for thread in found {
+                    for (pixel, light) in splatted.iter_mut().zip(thread) {
+                        *pixel += light;
+                    }
+                }
+            }
+            progress.inc(1);
+        }
+        progress.finish();
+        // each pass traces as many light paths as there are pixels, so a path's splat is already the pixel's share of it
+        let passes = u32::max(passes, 1) as Float;
+        let mut img = HdrImage::from_fn(width, height, |x, y| {
+            let i = (y*width + x) as usize;
+            let color = (seen[i] + splatted[i]) / passes * self.camera.exposure_scale() * self.camera.vignetting(x, y);
+            color_pixel(color)
+        });
+        self.apply_image_effects(&mut img);
+        img
+    }
+    // light one pass's camera ray through a pixel finds on its own: emission and the background, seen directly or
+    // through mirrors and glass, up to the first surface light paths can be connected to
+    fn light_trace_camera_path(&self, x: u32, y: u32, pass: u32) -> Color {
+        let mut ray = self.camera.generate_ray(x, y, pass);
+        start_sample(&self.camera.sampler, (x, y), pass, CAMERA_DIMENSIONS);
+        let mut beta = vec3(1.0, 1.0, 1.0);
+        let mut light = Color::zero();
+        let (mut t_min, mut kind) = (self.camera_t_min(), RayKind::Camera);
+        for _ in 0..self.camera.path_depth {
+            let hit = match self.intersect_ray_kind(&ray, t_min, self.camera.max_trace_dist, kind) {
+                Some(hit) => hit,
+                None => return light + beta.mul_element_wise(self.escaped_light(&ray, None, None)),
             };
-            ray.direction = rotation * ray.direction;
-
-            rays.push(ray);
+            (t_min, kind) = (0.001, RayKind::Indirect);
+            light += beta.mul_element_wise(hit.material.emission(&hit));
+            let wo = -ray.direction.normalize();
+            if hit.material.eval(&hit, wo, wo).is_some() { break }
+            let (new_ray, brdf, pdf, _) = hit.material.scatter(&hit, &ray);
+            let dot_term = if hit.normal.magnitude2() > 0.0 { new_ray.direction.normalize().dot(hit.normal).abs() } else { 1.0 };
+            beta = beta.mul_element_wise(brdf) * dot_term / pdf;
+            if beta == Color::zero() || !beta.x.is_finite() { break }
+            ray = new_ray;
         }
-        return rays;
+        light
+    }
+    // traces a path out from one of the lights (picked by how much light they give off), with a vertex everywhere it
+    // lands. these are the light paths of light tracing, and the half of a path a bidirectional path tracer would join
+    // a camera path to
+    fn light_subpath(&self, light_cdf: &[Float]) -> Vec<LightVertex> {
+        let mut vertices = Vec::new();
+        let (i, _) = sample_cdf(light_cdf, rand_f32());
+        let (mut ray, light) = match self.lights[i].sample_emission() {
+            Some(emission) => emission,
+            None => return vertices,
+        };
+        let mut beta = light / (light_cdf[i+1] - light_cdf[i]);
+        for _ in 0..self.camera.path_depth {
+            let hit = match self.intersect_ray(&ray, 0.001, self.camera.max_trace_dist) {
+                Some(hit) => hit,
+                None => break,
+            };
+            let (new_ray, brdf, pdf, _) = hit.material.scatter(&hit, &ray);
+            let dot_term = if hit.normal.magnitude2() > 0.0 { new_ray.direction.normalize().dot(hit.normal).abs() } else { 1.0 };
+            let new_beta = beta.mul_element_wise(brdf) * dot_term / pdf;
+            vertices.push(LightVertex { wi: -ray.direction.normalize(), time: ray.time, hit, beta });
+            // russian roulette, keeping the path with the share of its power that survived the bounce
+            let survival = Float::min(luminance(new_beta) / luminance(beta), 1.0);
+            if survival.is_nan() || survival <= 0.0 || rand_f32() >= survival { break }
+            beta = new_beta / survival;
+            ray = new_ray;
+        }
+        vertices
+    }
+    // the pixel a light path's vertex is seen in through a random point of the lens, and the light it sends there
+    // (None if the camera can't see it from there, or the vertex's surface can't be connected to, like glass)
+    // the thin lens sees the vertex where the line from the lens point through it crosses the plane in focus, so it
+    // lands in the image where the center of the lens sees that point. the light is weighted by the camera's
+    // importance: one over the image plane's area (at a distance of one) and cos^4 of the angle it's seen at, which is
+    // what makes splatting a path's light match the average radiance a pixel's camera rays find
+    fn connect_to_camera(&self, vertex: &LightVertex) -> Option<(u32, u32, Color)> {
+        let camera = &self.camera;
+        let lens_point = camera.lens_radius()*camera.aperture.sample(rand_vec2()).extend(0.0);
+        let to_vertex = camera.to_camera_space(vertex.hit.hitpoint - camera.eyepoint) - lens_point;
+        if to_vertex.z >= 0.0 { return None }
+        let distance = to_vertex.magnitude();
+        let direction = to_vertex / distance;
+        let normal = camera.focus_plane_normal();
+        let t = (-camera.focus_dist*normal.z - lens_point.dot(normal)) / direction.dot(normal);
+        if t <= 0.0 || !t.is_finite() { return None }
+        let focus_point = lens_point + t*direction;
+        if focus_point.z >= 0.0 { return None }
+        let image_point = focus_point * (-camera.focal_length / focus_point.z);
+        // (the inverse of generate_sample's mapping from pixels to the image plane)
+        let plane = camera.image_plane_size();
+        let pixel_size = plane.y / camera.screen_height as Float;
+        let position = vec2(
+            (image_point.x - camera.lens_shift.x) / pixel_size + 0.5*camera.screen_width as Float,
+            0.5*camera.screen_height as Float - (image_point.y - camera.lens_shift.y) / pixel_size,
+        );
+        if position.x < 0.0 || position.y < 0.0 || position.x >= camera.screen_width as Float || position.y >= camera.screen_height as Float { return None }
+        let wo = -(camera.rotation() * direction);
+        let (brdf, _) = vertex.hit.material.eval(&vertex.hit, wo, vertex.wi)?;
+        if brdf == Color::zero() { return None }
+        let shadow_ray = Ray { origin: vertex.hit.hitpoint, direction: wo, time: vertex.time };
+        let transmittance = self.transmittance(&shadow_ray, 0.001, distance - 0.001);
+        if transmittance <= 0.0 { return None }
+        let cos_theta = -direction.z;
+        let importance = camera.focal_length*camera.focal_length / (plane.x*plane.y*cos_theta.powi(4));
+        let dot_term = if vertex.hit.normal.magnitude2() > 0.0 { wo.dot(vertex.hit.normal).abs() } else { 1.0 };
+        let light = vertex.beta.mul_element_wise(brdf) * transmittance * dot_term * importance * cos_theta / (distance*distance);
+        Some((position.x as u32, position.y as u32, light))
     }
-}
 
-// SCENE
-pub struct Scene {
-    pub camera: Camera,
-    pub objects: Arc<Vec<Arc<dyn Intersectable + Send + Sync>>>,
-    pub point_light_pos: Vec3,  // point light only used for phong shading, which was just for debuging
-    pub ambient: Vec3,          // ambient light used for phong shading (and possibly when pathtracing stops recursing)
-}
-impl Scene {
-    // render scene to image
-    pub fn render_to_image(&self) -> RgbImage {
-        println!("Rendering...");
-        let progress_bar = ProgressBar::new((self.camera.screen_width*self.camera.screen_height) as u64);
-        progress_bar.set_style(ProgressStyle::default_bar().template("[{elapsed_precise}, {eta_precise}] {wide_bar:.green/blue} {pos:>7}/{len:7}").progress_chars("##-"));
-        // create image and thread channel
-        let mut img = RgbImage::new(self.camera.screen_width, self.camera.screen_height);
-        // iterate through pixels...
-        img.as_parallel_slice_mut().into_par_iter().chunks(self.camera.screen_width as usize * 3).enumerate().for_each(|(y, mut data)| {
-            for x in 0..self.camera.screen_width as usize {
-                // get rays, trace rays, and take average of outputs for AA
-                let cam_rays = self.camera.generate_rays(x as u32, y as u32);
-                let mut final_color = Vec3::zero();
-                for sample_idx in 0..cam_rays.len() {
-                    if matches!(self.camera.shading_mode, ShadingMode::Phong) {
-                        final_color += self.phong_shade_ray(&cam_rays[sample_idx]);
-                    }
-                    else {
-                        final_color += self.shade_ray(&cam_rays[sample_idx], 0);
-                    }
+    // GRADIENT-DOMAIN PATH TRACING (Kettunen et al. 2015) - besides path tracing each pixel, estimates the difference
+    // between it and each of its four neighbors by tracing the neighbor's paths with the pixel's own random numbers
+    // (a random replay shift, see ShiftedSampler). where the two paths take the same route, as they mostly do over
+    // diffuse surfaces, their noise cancels out of the difference, so the gradients are far less noisy than the pixels.
+    // the image is then solved for as the one that best matches both (a screened poisson problem, see screened_poisson),
+    // which spreads the pixels' noise out into low frequencies that are much harder to see. every sample
+    // traces five paths (each of which also counts towards the pixel it went through), so it takes about five times as
+    // long as a path traced one. the pixel filter isn't applied
+    pub fn render_gradient(&self, settings: &GradientSettings) -> HdrImage {
+        let (width, height) = self.camera.image_size();
+        let _span = info_span!("render", mode = "gradient domain", width, height, samples = self.camera.aa_sample_count).entered();
+        let (w, h) = (width as usize, height as usize);
+        let progress = RenderProgress::new(self.camera.aa_sample_count as u64, "passes", self.camera.progress_json);
+        // scenes that trace a pixel's paths through its neighbors to the right, left, below, and above
+        let offsets = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+        let shifted: Vec<Scene> = offsets.iter().map(|&offset| {
+            self.with_sampler(Arc::new(ShiftedSampler { sampler: self.camera.sampler.clone(), offset }))
+        }).collect();
+        // sums over the samples of each pixel (its own, and those traced from its neighbors), and of the differences to
+        // the right (x) and down (y) from each pixel, which are estimated from both of the pixels on either side
+        let mut primal = vec![Color::zero(); w*h];
+        let mut primal_counts = vec![0; w*h];
+        let mut gradient_x = vec![Color::zero(); w*h];
+        let mut gradient_y = vec![Color::zero(); w*h];
+        // (cancelling stops after the pass it's on, keeping the ones before it)
+        let mut passes = 0;
+        for i in 0..self.camera.aa_sample_count {
+            if self.camera.cancel.is_cancelled() {
+                warn!(finished = i, passes = self.camera.aa_sample_count, "render cancelled");
+                break;
+            }
+            passes += 1;
+            let samples: Vec<(Color, [Option<Color>; 4])> = (0..w*h).into_par_iter().map(|p| {
+                let (x, y) = ((p % w) as u32, (p / w) as u32);
+                let mut neighbors = [None; 4];
+                for (k, &(dx, dy)) in offsets.iter().enumerate() {
+                    let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 { continue }
+                    neighbors[k] = Some(shifted[k].gradient_sample(nx as u32, ny as u32, i));
                 }
-                final_color = final_color / cam_rays.len() as f32;
-                
-                // saturate colors towards white if they are excessively bright
-                let tmp = final_color.clone();
-                for i in 0..3 {
-                    let d = tmp[i] - 1.0;
-                    if d > 0.0 {
-                        final_color[(i+1)%3] += d;
-                        final_color[(i+2)%3] += d;
+                (self.gradient_sample(x, y, i), neighbors)
+            }).collect();
+            for (p, (base, neighbors)) in samples.into_iter().enumerate() {
+                primal[p] += base;
+                primal_counts[p] += 1;
+                // (a shifted path is a sample of the neighbor it went through, as much as its own pixel's is)
+                for (k, neighbor) in neighbors.into_iter().enumerate() {
+                    let neighbor = match neighbor {
+                        Some(neighbor) => neighbor,
+                        None => continue,
+                    };
+                    let q = [p+1, p.wrapping_sub(1), p+w, p.wrapping_sub(w)][k];
+                    primal[q] += neighbor;
+                    primal_counts[q] += 1;
+                    // (a difference towards the left or up is the one from that neighbor, backwards)
+                    match k {
+                        0 => gradient_x[p] += neighbor - base,
+                        1 => gradient_x[q] -= neighbor - base,
+                        2 => gradient_y[p] += neighbor - base,
+                        _ => gradient_y[q] -= neighbor - base,
                     }
                 }
-
-                // write to image
-                *(data[3*x])   = (f32::powf(final_color.x.clamp(0.0,1.0), 1.0/self.camera.gamma) * 255.9999) as u8;
-                *(data[3*x+1]) = (f32::powf(final_color.y.clamp(0.0,1.0), 1.0/self.camera.gamma) * 255.9999) as u8;
-                *(data[3*x+2]) = (f32::powf(final_color.z.clamp(0.0,1.0), 1.0/self.camera.gamma) * 255.9999) as u8;
-                progress_bar.inc(1);
             }
+            progress.inc(1);
+        }
+        progress.finish();
+        let passes = u32::max(passes, 1) as Float;
+        for (color, &count) in primal.iter_mut().zip(&primal_counts) {
+            *color /= u32::max(count, 1) as Float;
+        }
+        gradient_x.iter_mut().for_each(|c| *c /= 2.0*passes);
+        gradient_y.iter_mut().for_each(|c| *c /= 2.0*passes);
+        let solved = screened_poisson(&primal, &gradient_x, &gradient_y, w, h, settings);
+        let mut img = HdrImage::from_fn(width, height, |x, y| {
+            let color = solved[(y*width + x) as usize] * self.camera.exposure_scale() * self.camera.vignetting(x, y);
+            color_pixel(color)
         });
-        progress_bar.finish();
-        println!("Done.");
-        return img;
+        self.apply_image_effects(&mut img);
+        img
     }
-    
-    // defines background color in a given direction
-    fn background_color(_v: &Vec3) -> Color {
-        // used to use blue gradient from raytracing in one weekend
-        // let u = v.normalize();
-        // let t = 0.5*(u.y+1.0);
-        // (1.0-t)*vec3(1.0, 1.0, 1.0) + t*vec3(0.5, 0.7, 1.0)
-        
-        // now just use black void:
-        Vec3::zero()
+    // path traces one of a pixel's samples, returning the light it brings back (with nans and infinities, which
+    // would spread over the whole image in the solve, dropped)
+    fn gradient_sample(&self, x: u32, y: u32, i: u32) -> Color {
+        let ray = self.camera.generate_ray(x, y, i);
+        let hit = self.intersect_ray_kind(&ray, self.camera_t_min(), self.camera.max_trace_dist, RayKind::Camera);
+        let color = self.shade_sample(x, y, i, &ray, hit).0;
+        if color.x.is_finite() && color.y.is_finite() && color.z.is_finite() { color } else { Color::zero() }
+    }
+
+    // effects applied to the whole image once its pixels are rendered
+    fn apply_image_effects(&self, img: &mut HdrImage) {
+        if let ShadingMode::Toon(style) = self.camera.shading_mode {
+            if style.outlines { self.draw_outlines(img, &style) }
+        }
+        if self.camera.chromatic_aberration != 0.0 {
+            *img = lateral_chromatic_aberration(img, self.camera.chromatic_aberration, self.camera.screen_width, self.camera.screen_height);
+        }
+    }
+    // turns a rendered image into what's shown on screen, with 8 bits per channel
+    pub fn display_image(&self, hdr: &HdrImage) -> RgbImage {
+        RgbImage::from_fn(hdr.width(), hdr.height(), |x, y| {
+            let p = hdr.get_pixel(x, y);
+            let color = display_color(pixel_color(p), self.camera.tone_mapping, self.camera.gamma);
+            Rgb([(color.x*255.9999) as u8, (color.y*255.9999) as u8, (color.z*255.9999) as u8])
+        })
+    }
+    // turns a rendered image into what's shown on screen, with 16 bits per channel
+    pub fn display_image16(&self, hdr: &HdrImage) -> ImageBuffer<Rgb<u16>, Vec<u16>> {
+        ImageBuffer::from_fn(hdr.width(), hdr.height(), |x, y| {
+            let p = hdr.get_pixel(x, y);
+            let color = display_color(pixel_color(p), self.camera.tone_mapping, self.camera.gamma);
+            Rgb([(color.x*65535.9999) as u16, (color.y*65535.9999) as u16, (color.z*65535.9999) as u16])
+        })
+    }
+    // renders the scene through each of its named cameras after the main one, into files named after both the file
+    // name and the camera, like render_side.png (in any format save_image_with_aovs can write). the objects are only
+    // loaded once, and ctrl-c stops all the renders
+    pub fn render_cameras(&self, file_name: &str) {
+        let path = Path::new(file_name);
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        for (name, camera) in &self.cameras {
+            if self.camera.cancel.is_cancelled() { break }
+            let _span = info_span!("camera", name = %name).entered();
+            // (each camera keeps its own checkpoint, so a stopped render carries on with the right one)
+            let mut view = self.with_camera(Camera {
+                cancel: self.camera.cancel.clone(),
+                progress_json: self.camera.progress_json,
+                checkpoint_file: camera.checkpoint_file.as_ref().map(|file| format!("{}.{}", file, name)),
+                ..camera.clone()
+            });
+            view.autofocus();
+            let (img, aovs) = view.render_with_aovs();
+            let mattes = view.render_cryptomattes();
+            let camera_file = path.with_file_name(format!("{}_{}.{}", stem, name, extension));
+            view.save_image_with_aovs(&img, &aovs, &mattes, camera_file.to_str().unwrap());
+        }
+    }
+    // writes a rendered image to a file, in the format its extension asks for:
+    // .exr - linear radiance as 32-bit floats, for compositing
+    // .hdr - linear radiance in radiance's RGBE format, e.g. to use a render as an environment map elsewhere
+    // anything else - the image as it's displayed, in whatever format the image crate picks (png, tiff, jpg, ...),
+    //                 with 16 bits per channel if the camera's bit depth is 16 (which only png and tiff can hold)
+    pub fn save_image(&self, hdr: &HdrImage, file_name: &str) {
+        self.save_image_with_aovs(hdr, &[], &[], file_name);
+    }
+    // same, along with aovs: as more layers of an exr, or otherwise each in its own file named after the image's, like
+    // render_depth.png (visualized to fit, for 8 and 16-bit formats). cryptomattes are only kept in exr files
+    pub fn save_image_with_aovs(&self, hdr: &HdrImage, aovs: &[(Aov, HdrImage)], mattes: &[CryptomatteImage], file_name: &str) {
+        let _span = info_span!("encode", file = %file_name).entered();
+        let path = Path::new(file_name);
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        if extension != "exr" && !mattes.is_empty() {
+            warn!(file = %file_name, "cryptomattes can only be saved to exr files, so this one won't have them");
+        }
+        if extension != "exr" {
+            for (aov, img) in aovs {
+                let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+                let aov_file = path.with_file_name(format!("{}_{}.{}", stem, aov.name(), extension));
+                let aov_img = if extension == "hdr" { img.clone() } else { aov.visualize(img, self.camera.gamma) };
+                self.save_image(&aov_img, aov_file.to_str().unwrap());
+            }
+        }
+        match extension.as_str() {
+            "exr" => {
+                let mut layers = vec![("rgb", hdr)];
+                layers.extend(aovs.iter().map(|(aov, img)| (aov.name(), img)));
+                write_exr(file_name, &layers, mattes).expect("couldn't write exr file")
+            }
+            "hdr" => write_hdr(file_name, hdr).expect("couldn't write hdr file"),
+            _ if self.camera.bit_depth == 16 => self.display_image16(hdr).save(file_name).unwrap(),
+            _ => self.display_image(hdr).save(file_name).unwrap(),
+        }
+    }
+
+    // inks pixels on silhouettes and creases, found from jumps in depth or normal between neighboring pixels
+    // (each pixel's depth and normal are averaged over its camera rays' first hits, so lines follow the same antialiasing)
+    fn draw_outlines(&self, img: &mut HdrImage, style: &ToonStyle) {
+        let (width, height) = self.camera.image_size();
+        let surfaces: Vec<(Float, Vec3)> = (0..width*height).into_par_iter().map(|i| {
+            let cam_rays = self.camera.generate_rays(i % width, i / width);
+            let mut depth = 0.0;
+            let mut normal = Vec3::zero();
+            for hit in self.intersect_packet(&cam_rays, 0.001, self.camera.max_trace_dist) {
+                match hit {
+                    Some(hit) => { depth += hit.distance; normal += hit.normal; }
+                    None => depth += self.camera.max_trace_dist,
+                }
+            }
+            (depth / cam_rays.len() as Float, if normal.magnitude2() > 0.0 { normal.normalize() } else { normal })
+        }).collect();
+
+        // lines are only drawn on the nearer side of an edge, so they stay one pixel wide
+        let crease_cos = style.crease_angle.to_radians().cos();
+        let is_edge = |x: u32, y: u32| {
+            let (depth, normal) = surfaces[(y*width + x) as usize];
+            [(-1, 0), (1, 0), (0, -1), (0, 1)].iter().any(|(dx, dy)| {
+                let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 { return false }
+                let (neighbor_depth, neighbor_normal) = surfaces[(ny as u32*width + nx as u32) as usize];
+                let silhouette = neighbor_depth - depth > style.depth_threshold*depth;
+                let crease = neighbor_depth >= depth && normal.dot(neighbor_normal) < crease_cos;
+                silhouette || crease
+            })
+        };
+        let ink = color_pixel(style.ink_color);
+        for y in 0..height {
+            for x in 0..width {
+                if is_edge(x, y) { img.put_pixel(x, y, ink); }
+            }
+        }
     }
     
-    // computes phong shading for a given rayhit. usually just used for debugging
+    // debug render that colors each pixel by how many aabb or triangle tests its camera rays needed
+    // colors run from blue (cheapest pixel) through green and yellow to red (most expensive pixel)
+    pub fn render_traversal_heatmap(&self, metric: HeatmapMetric) -> RgbImage {
+        let _span = info_span!("render", mode = "heatmap", ?metric).entered();
+        let (width, height) = self.camera.image_size();
+        // count tests for each pixel's camera rays
+        let costs: Vec<Float> = (0..width*height).into_par_iter().map(|i| {
+            let cam_rays = self.camera.generate_rays(i % width, i / width);
+            take_ray_stats();
+            self.intersect_packet(&cam_rays, 0.001, self.camera.max_trace_dist);
+            let rays = take_ray_stats();
+            let count = match metric {
+                HeatmapMetric::AABBTests => rays.aabb_tests,
+                HeatmapMetric::TriangleTests => rays.triangle_tests,
+            };
+            count as Float / cam_rays.len() as Float
+        }).collect();
+        // normalize by the most expensive pixel
+        let max_cost = costs.iter().cloned().fold(0.0, Float::max);
+        info!(?metric, max_per_ray = max_cost, "heatmap scale");
+        heatmap(width, height, &costs, max_cost)
+    }
+
+    // computes phong shading for a given ray. usually just used for debugging
     fn phong_shade_ray(&self, ray: &Ray) -> Color {
-        // get hit
-        match self.intersect_ray(ray, 0.0, self.camera.max_trace_dist) {
-            None => Scene::background_color(&ray.direction),
+        self.phong_shade_hit(ray, self.intersect_ray(ray, 0.0, self.camera.max_trace_dist))
+    }
+    // computes phong shading for a ray's (already found) hit
+    fn phong_shade_hit(&self, ray: &Ray, hit: Option<RayHit>) -> Color {
+        match hit {
+            None => self.background.color(ray.direction),
             Some(hit) => {
                 // standard phong shading
                 let to_light = (self.point_light_pos - hit.hitpoint).normalize();
@@ -286,74 +2605,557 @@ impl Scene {
                 let diffuse_weight = (dot(hit.normal, to_light)).clamp(0.0, 1.0);
                 let specular_weight = dot(to_camera, reflected).clamp(0.0, 1.0).powf(40.0);
                 // cast shadow ray
-                let shadow_ray = Ray { origin: hit.hitpoint + 0.01*hit.normal, direction: to_light };
-                let shadow_weight = match self.intersect_ray(&shadow_ray, 0.0, (self.point_light_pos - hit.hitpoint).magnitude()) {
-                    None => 1.0,
-                    Some(hit) => if hit.distance*hit.distance > (self.point_light_pos - hit.hitpoint).magnitude2() { 1.0 } else { 0.3 }
-                };
+                let shadow_ray = Ray { origin: hit.hitpoint + 0.01*hit.normal, direction: to_light, time: ray.time };
+                let shadow_weight = if self.intersect_any(&shadow_ray, 0.0, (self.point_light_pos - hit.hitpoint).magnitude()) { 0.3 } else { 1.0 };
                 shadow_weight * (self.ambient + diffuse_weight*hit.material.scatter(&hit, ray).1 + specular_weight*vec3(0.4, 0.4, 0.4))
             }
         }
     }
     
+    // computes toon shading for a ray's (already found) hit: the surface's color in flat bands of light from the
+    // point light, plus a rim light around its silhouette
+    fn toon_shade_hit(&self, ray: &Ray, hit: Option<RayHit>, style: ToonStyle) -> Color {
+        match hit {
+            None => self.background.color(ray.direction),
+            Some(hit) => {
+                let style = hit.material.toon_style().unwrap_or(style);
+                // the surface's color is an estimate of how much light it reflects, so any material can be drawn
+                const COLOR_SAMPLES: u32 = 8;
+                let mut base_color = Color::zero();
+                for _ in 0..COLOR_SAMPLES {
+                    let (new_ray, brdf, pdf, _) = hit.material.scatter(&hit, ray);
+                    base_color += brdf * new_ray.direction.normalize().dot(hit.normal).abs() / pdf;
+                }
+                base_color /= COLOR_SAMPLES as Float;
+
+                // quantize diffuse lighting (and shadows) into bands
+                let to_light = (self.point_light_pos - hit.hitpoint).normalize();
+                let shadow_ray = Ray { origin: hit.hitpoint + 0.01*hit.normal, direction: to_light, time: ray.time };
+                let in_shadow = self.intersect_any(&shadow_ray, 0.0, (self.point_light_pos - hit.hitpoint).magnitude());
+                let lit = if in_shadow { 0.0 } else { hit.normal.dot(to_light).clamp(0.0, 1.0) };
+                let bands = u32::max(style.bands, 2);
+                let band = Float::min((lit*bands as Float).floor() / (bands-1) as Float, 1.0);
+                let light = lerpvec(self.ambient, vec3(1.0, 1.0, 1.0), band);
+
+                // hard-edged rim where the surface turns away from the camera
+                let facing = hit.normal.dot(-ray.direction.normalize()).clamp(0.0, 1.0);
+                let rim = if facing < style.rim_width { style.rim_color } else { Color::zero() };
+
+                hit.material.emission(&hit) + base_color.mul_element_wise(light) + rim
+            }
+        }
+    }
+
+    // light arriving straight from the scene's lights, found by sampling each light and casting a shadow ray towards it
+    // area and environment lights can also be found by scattered rays, so both ways of finding them are weighted with
+    // the power heuristic (multiple importance sampling) - each counts most where its pdf is highest
+    fn sample_lights(&self, hit: &RayHit, ray: &Ray) -> Color {
+        let wo = -ray.direction.normalize();
+        let links = hit.material.light_links();
+        let mut direct = Color::zero();
+        for light in self.linked_lights(links) {
+            let sample = match light.sample(hit.hitpoint) {
+                Some(sample) if sample.pdf > 0.0 => sample,
+                _ => continue,
+            };
+            let (brdf, bsdf_pdf) = match hit.material.eval(hit, wo, sample.direction) {
+                Some((brdf, pdf)) if brdf != Color::zero() => (brdf, pdf),
+                _ => continue,
+            };
+            let shadow_ray = Ray { origin: hit.hitpoint, direction: sample.direction, time: ray.time };
+            // (participating media along the way dim the light rather than blocking it)
+            let (weight, transmittance) = if sample.delta {
+                let transmittance = self.transmittance(&shadow_ray, 0.001, Float::min(sample.distance - 0.001, self.camera.max_trace_dist));
+                if transmittance <= 0.0 { continue }
+                (1.0, transmittance)
+            }
+            else if sample.distance.is_infinite() {
+                // lights infinitely far away are reached by escaping the scene
+                let transmittance = self.transmittance(&shadow_ray, 0.001, self.camera.max_trace_dist);
+                if transmittance <= 0.0 { continue }
+                let light_pdfs = Float::max(self.escaped_pdfs_squared(&shadow_ray, links), sample.pdf*sample.pdf);
+                (sample.pdf*sample.pdf / (light_pdfs + bsdf_pdf*bsdf_pdf), transmittance)
+            }
+            else {
+                // the light has to be the first surface along the shadow ray
+                // (which takes finding the closest hit, but it's still a shadow ray)
+                match self.shadow_hit(&shadow_ray, 0.001, sample.distance*1.001) {
+                    (Some(light_hit), transmittance) if light_hit.distance >= sample.distance*0.999 && transmittance > 0.0 => {
+                        let light_pdfs = Float::max(self.light_pdfs_squared(&shadow_ray, &light_hit, links), sample.pdf*sample.pdf);
+                        (sample.pdf*sample.pdf / (light_pdfs + bsdf_pdf*bsdf_pdf), transmittance)
+                    }
+                    _ => continue,
+                }
+            };
+            let dot_term = if hit.normal.magnitude2() > 0.0 {sample.direction.dot(hit.normal).abs()} else {1.0};
+            direct += weight*transmittance*dot_term*brdf.mul_element_wise(sample.radiance) / sample.pdf;
+        }
+        direct
+    }
+    // sum of the squared pdfs of every light that could have sampled the ray's direction towards hit
+    // (only counting lights linked to the surface the ray left from)
+    fn light_pdfs_squared(&self, ray: &Ray, hit: &RayHit, links: Option<&LightLinks>) -> Float {
+        self.linked_lights(links).map(|light| light.pdf(ray, hit).powi(2)).sum()
+    }
+    // same for a ray that escapes the scene
+    fn escaped_pdfs_squared(&self, ray: &Ray, links: Option<&LightLinks>) -> Float {
+        self.linked_lights(links).map(|light| light.escaped_pdf(ray).powi(2)).sum()
+    }
+    // alpha of a camera ray's sample (see Aov::Alpha)
+    fn sample_alpha(&self, ray: &Ray, hit: &Option<RayHit>) -> Float {
+        match hit {
+            None => 0.0,
+            Some(hit) if hit.material.is_shadow_catcher() => self.caught_shadow(ray, hit),
+            Some(_) => 1.0,
+        }
+    }
+    // how much of the light reaching a shadow catcher the scene blocks, from a sample of each light: their light is
+    // counted both as it arrives and as if nothing were in the way, and the shadow is the share that goes missing
+    // (taken from the same samples, so the shadow is much less noisy than either)
+    fn caught_shadow(&self, ray: &Ray, hit: &RayHit) -> Float {
+        let wo = -ray.direction.normalize();
+        let (mut arriving, mut unblocked) = (0.0, 0.0);
+        for light in self.linked_lights(hit.material.light_links()) {
+            let sample = match light.sample(hit.hitpoint) {
+                Some(sample) if sample.pdf > 0.0 => sample,
+                _ => continue,
+            };
+            let brdf = match hit.material.eval(hit, wo, sample.direction) {
+                Some((brdf, _)) => brdf,
+                None => continue,
+            };
+            let dot_term = if hit.normal.magnitude2() > 0.0 {sample.direction.dot(hit.normal).abs()} else {1.0};
+            let light = luminance(dot_term*brdf.mul_element_wise(sample.radiance)) / sample.pdf;
+            // (stopping just short of lights with a shape, so they don't block themselves)
+            let shadow_ray = Ray { origin: hit.hitpoint, direction: sample.direction, time: ray.time };
+            let t_max = if sample.delta { sample.distance - 0.001 } else { sample.distance*0.999 };
+            arriving += light*self.transmittance(&shadow_ray, 0.001, Float::min(t_max, self.camera.max_trace_dist));
+            unblocked += light;
+        }
+        if unblocked > 0.0 { (1.0 - arriving / unblocked).clamp(0.0, 1.0) } else { 0.0 }
+    }
+    // light a shadow catcher seen by the camera catches off the scene's other objects: what its bounces find where they
+    // hit something that isn't a shadow catcher, without anything given off there (the photo already has the light
+    // from the lights and its own surroundings)
+    fn caught_light(&self, ray: &Ray, hit: &RayHit) -> Color {
+        let mut caught = Color::zero();
+        for _i in 0..self.camera.path_samples {
+            let (new_ray, brdf_term, pdf, kind) = hit.material.scatter(hit, ray);
+            let dot_term = if hit.normal.magnitude2() > 0.0 {new_ray.direction.normalize().dot(hit.normal).abs().clamp(0.0,1.0)} else {1.0};
+            let next_hit = self.intersect_ray(&new_ray, 0.001, self.camera.max_trace_dist).filter(|next_hit| !next_hit.material.is_shadow_catcher());
+            if next_hit.is_some() {
+                let (_, direct, indirect) = self.shade_hit_parts(&new_ray, next_hit, Bounces::default().after(kind), None, hit.material.light_links());
+                caught += (dot_term*brdf_term / pdf).mul_element_wise(direct + indirect);
+            }
+        }
+        caught / Float::max(1.0, self.camera.path_samples as Float)
+    }
+    // the lights that shine on a surface with the given links (all of them if it has none)
+    fn linked_lights<'a>(&'a self, links: Option<&'a LightLinks>) -> impl Iterator<Item = &'a Arc<dyn Light + Send + Sync>> {
+        self.lights.iter().filter(move |light| links.is_none_or(|links| links.links(light)))
+    }
+    // light arriving along a ray that hits nothing: the background, plus any lights infinitely far away
+    // (weighted against light sampling the same way as emission, see sample_lights)
+    fn escaped_light(&self, ray: &Ray, bsdf_pdf: Option<Float>, links: Option<&LightLinks>) -> Color {
+        let direction = ray.direction.normalize();
+        let mut light: Color = self.linked_lights(links).map(|light| light.escaped_radiance(direction)).sum();
+        if let Some(bsdf_pdf) = bsdf_pdf {
+            if light != Color::zero() {
+                light *= bsdf_pdf*bsdf_pdf / (bsdf_pdf*bsdf_pdf + self.escaped_pdfs_squared(ray, links));
+            }
+        }
+        self.background.color(ray.direction) + light
+    }
+
+    // light emitted at a hit, as found by a ray scattered with bsdf_pdf from a surface with the given light links
+    // (weighted against light sampling the same way as escaped_light)
+    fn found_emission(&self, ray: &Ray, hit: &RayHit, bsdf_pdf: Option<Float>, links: Option<&LightLinks>) -> Color {
+        let mut emission = hit.material.emission(hit);
+        if emission != Color::zero() {
+            // lights that aren't linked to the surface the ray came from don't light it, even when a scattered ray finds them
+            if let Some(links) = links {
+                if self.lights.iter().any(|light| !links.links(light) && light.pdf(ray, hit) > 0.0) {
+                    emission = Color::zero();
+                }
+            }
+            // light sampling already found some of this emission, so weight it against that (see sample_lights)
+            if let Some(bsdf_pdf) = bsdf_pdf {
+                emission *= bsdf_pdf*bsdf_pdf / (bsdf_pdf*bsdf_pdf + self.light_pdfs_squared(ray, hit, links));
+            }
+        }
+        emission
+    }
+
     // computes shading for a ray hit according to the monte-carlo integrated rendering equation
-    fn shade_ray(&self, ray: &Ray, recursion_depth: u32) -> Color {
-        if recursion_depth >= self.camera.path_depth { 
-            return Scene::background_color(&ray.direction); // approximates the remaining infinite recursion results
+    // bsdf_pdf is the pdf the ray was scattered with, if lights were also sampled from where it started (None otherwise)
+    // links are the light links of the surface it started from (None for camera rays and surfaces without any)
+    fn shade_ray(&self, ray: &Ray, bounces: Bounces, bsdf_pdf: Option<Float>, links: Option<&LightLinks>) -> Color {
+        if bounces.total() >= self.camera.path_depth || !bounces.within(&self.camera.bounce_limits) { 
+            return self.background.color(ray.direction); // approximates the remaining infinite recursion results
         }
         // get hit
-        match self.intersect_ray(ray, 0.001, self.camera.max_trace_dist.clone()) {
-            None => Scene::background_color(&ray.direction),
-            Some(hit) => {
-                // accumulate integral
-                let mut integral = Color::zero();
+        self.shade_hit(ray, self.intersect_ray(ray, 0.001, self.camera.max_trace_dist), bounces, bsdf_pdf, links)
+    }
+    // light a hit reflects back along the ray that found it, path traced as if the ray came from the camera
+    // (for hits made up outside the scene, e.g. the points on a mesh that bake renders lightmaps from)
+    pub fn shade_first_hit(&self, ray: &Ray, hit: RayHit) -> Color {
+        clamp_radiance(self.shade_hit(ray, Some(hit), Bounces::default(), None, None), self.camera.firefly_clamp)
+    }
+    // light arriving back along a ray, path traced as if it came from the camera (e.g. at the light probes bake_probes
+    // renders from)
+    pub fn incoming_light(&self, ray: &Ray) -> Color {
+        clamp_radiance(self.shade_ray(ray, Bounces::default(), None, None), self.camera.firefly_clamp)
+    }
+    // computes shading for a ray's (already found) hit according to the rendering equation
+    fn shade_hit(&self, ray: &Ray, hit: Option<RayHit>, bounces: Bounces, bsdf_pdf: Option<Float>, links: Option<&LightLinks>) -> Color {
+        let (emitted, direct, indirect) = self.shade_hit_parts(ray, hit, bounces, bsdf_pdf, links);
+        emitted + direct + indirect
+    }
+    // same, split into the light emitted at the hit (or arriving from the background), the light it reflects straight
+    // from a light, and the light it reflects after more bounces, so the first hit can tell direct light from indirect
+    fn shade_hit_parts(&self, ray: &Ray, hit: Option<RayHit>, bounces: Bounces, bsdf_pdf: Option<Float>, links: Option<&LightLinks>) -> (Color, Color, Color) {
+        if bounces.total() >= self.camera.path_depth || !bounces.within(&self.camera.bounce_limits) { 
+            return (self.background.color(ray.direction), Color::zero(), Color::zero());
+        }
+        match hit {
+            None if bounces.total() == 0 && self.camera.transparent_background => (Color::zero(), Color::zero(), Color::zero()),
+            None => (self.escaped_light(ray, bsdf_pdf, links), Color::zero(), Color::zero()),
+            Some(mut hit) => {
+                if let Some(style) = hit.material.toon_style() {
+                    return (self.toon_shade_hit(ray, Some(hit), style), Color::zero(), Color::zero());
+                }
+                if bounces.total() == 0 && hit.material.is_shadow_catcher() {
+                    return (Color::zero(), Color::zero(), self.camera.indirect_light.apply(self.caught_light(ray, &hit)));
+                }
+                if let Some(strength) = self.camera.roughness_regularization {
+                    hit.min_roughness = bounces.min_roughness(strength);
+                }
+                let emission = self.found_emission(ray, &hit, bsdf_pdf, links);
+                // accumulate integral (of light found by scattered rays on their next hit, and after that)
+                let (mut integral_direct, mut integral_indirect) = (Color::zero(), Color::zero());
                 for _i in 0..self.camera.path_samples {
                     // pick new direction, generate ray, and recurse
-                    let (new_ray, brdf_term, pdf) = hit.material.scatter(&hit, ray);
-                    let dot_term = if hit.normal.magnitude2() > 0.0 {new_ray.direction.dot(hit.normal).abs().clamp(0.0,1.0)} else {1.0};
-                    let incoming_light = self.shade_ray(&new_ray, recursion_depth+1);
+                    let (new_ray, brdf_term, pdf, kind) = hit.material.scatter(&hit, ray);
+                    // (scattered directions aren't always unit length, e.g. hemisphere samples come from points in a ball)
+                    let dot_term = if hit.normal.magnitude2() > 0.0 {new_ray.direction.normalize().dot(hit.normal).abs().clamp(0.0,1.0)} else {1.0};
+                    let bsdf_pdf = if self.lights.is_empty() { None } else {
+                        // (a pdf of zero means the direction is one the material doesn't reflect into; the ray carries no light
+                        // back anyway, and weighting against it would divide by zero)
+                        hit.material.eval(&hit, -ray.direction.normalize(), new_ray.direction.normalize()).map(|(_, pdf)| pdf).filter(|&pdf| pdf > 0.0)
+                    };
+                    let weight = dot_term*brdf_term / pdf;
                     // accumulate into integral
-                    integral += (dot_term*(brdf_term.mul_element_wise(incoming_light))) / pdf;
+                    if bounces.total() == 0 {
+                        // at the first hit, whatever the scattered ray finds on its next hit is direct light, and anything
+                        // more is indirect
+                        let next_hit = self.intersect_ray(&new_ray, 0.001, self.camera.max_trace_dist);
+                        let (emitted, direct, indirect) = self.shade_hit_parts(&new_ray, next_hit, bounces.after(kind), bsdf_pdf, hit.material.light_links());
+                        integral_direct += self.camera.direct_light.apply(weight.mul_element_wise(emitted));
+                        integral_indirect += self.camera.indirect_light.apply(weight.mul_element_wise(direct + indirect));
+                    } else {
+                        integral_indirect += weight.mul_element_wise(self.shade_ray(&new_ray, bounces.after(kind), bsdf_pdf, hit.material.light_links()));
+                    }
+                }
+                integral_direct /= self.camera.path_samples as Float;
+                integral_indirect /= self.camera.path_samples as Float;
+                let mut direct = self.sample_lights(&hit, ray);
+                if bounces.total() == 0 {
+                    direct = self.camera.direct_light.apply(direct);
                 }
-                integral /= self.camera.path_samples as f32; 
         
-                // total light = integrated + emitted light
-                hit.material.emission() + integral
+                // total light = emitted + (directly sampled + integrated light)
+                (emission, direct + integral_direct, integral_indirect)
             }
         }        
     }
-}
-impl Intersectable for Scene {
-    fn intersect_ray(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
-        // iterate over all objects in the list and return the closest intersection
-        let mut best_hit = None;
-        for object in self.objects.iter() {
-            if let Some(hit) = object.intersect_ray(ray, t_min, t_max) {
-                best_hit = match best_hit {
-                    None => Some(hit),
-                    Some(current_best) => {
-                        if hit.distance < current_best.distance {
-                            Some(hit)
-                        }
-                        else {
-                            Some(current_best)
+
+    // path traces a pixel's samples from their camera rays' hits like shade_sample does, but rather than following each
+    // path to its end before starting the next, keeps every path that's still going in a queue and moves them all along
+    // together, a stage at a time:
+    //   intersect - finds each path's next hit (camera rays come with theirs)
+    //   shade - adds the light emitted where each path landed (or escaping it), and scatters it into new paths
+    //   next event estimation - samples the lights from each hit
+    //   compaction - keeps only the paths that are still going for the next round
+    // there's no recursion, so paths can be as long as they need to be, and paths are shaded sorted by material, so
+    // the same code and textures are used back to back. returns each sample's light and its direct and indirect parts
+    fn shade_wavefront(&self, x: u32, y: u32, rays: &[Ray], hits: &[Option<RayHit>]) -> Vec<(Color, Color, Color)> {
+        let path_samples = self.camera.path_samples;
+        let continues = |bounces: &Bounces| bounces.total() < self.camera.path_depth && bounces.within(&self.camera.bounce_limits);
+        // adds light a path found to its sample (emitted is whether it was found on the path's own hit, rather than
+        // sampled from a light there)
+        let deposit = |samples: &mut [WavefrontSample], path: &WavefrontPath, light: Color, emitted: bool| {
+            let sample = &mut samples[path.sample];
+            match path.branch {
+                None if emitted => sample.emitted += light,
+                None => sample.direct += self.camera.direct_light.apply(light),
+                Some(b) if emitted && path.bounces.total() == 1 => sample.branches[b].0 += path.throughput.mul_element_wise(light),
+                Some(b) => sample.branches[b].1 += path.throughput.mul_element_wise(light),
+            }
+        };
+        let empty = WavefrontSample { emitted: Color::zero(), direct: Color::zero(), branches: Vec::new(), next_dimension: WAVEFRONT_BRANCH_DIMENSIONS };
+        let mut samples = vec![empty; rays.len()];
+        let mut queue: Vec<WavefrontPath> = rays.iter().zip(hits).enumerate().map(|(i, (ray, hit))| {
+            // shading continues the sample its camera ray was generated from
+            start_sample(&self.camera.sampler, (x, y), i as u32, CAMERA_DIMENSIONS);
+            WavefrontPath {
+                ray: Ray { origin: ray.origin, direction: ray.direction, time: ray.time },
+                hit: hit.clone(),
+                sample: i,
+                branch: None,
+                throughput: vec3(1.0, 1.0, 1.0),
+                bounces: Bounces::default(),
+                bsdf_pdf: None,
+                from: None,
+                cursor: save_sample(),
+            }
+        }).collect();
+        let mut first_round = true;
+        while !queue.is_empty() {
+            // intersect
+            if !first_round {
+                for path in queue.iter_mut().filter(|path| continues(&path.bounces)) {
+                    // (volumes draw random numbers to find where rays scatter inside them)
+                    resume_sample(&self.camera.sampler, path.cursor);
+                    path.hit = self.intersect_ray(&path.ray, 0.001, self.camera.max_trace_dist);
+                    path.cursor = save_sample();
+                }
+            }
+            first_round = false;
+
+            // shade, grouped by material (paths that missed first)
+            queue.sort_by_key(|path| path.hit.as_ref().map(|hit| Arc::as_ptr(&hit.material) as *const () as usize));
+            let mut next: Vec<WavefrontPath> = Vec::with_capacity(queue.len());
+            // hits to sample lights from, and which new path carries on with their random numbers afterwards
+            let mut lit: Vec<(WavefrontPath, RayHit, Option<usize>)> = Vec::new();
+            for mut path in queue {
+                if !continues(&path.bounces) {
+                    // approximates the rest of the path (see shade_ray)
+                    deposit(&mut samples, &path, self.background.color(path.ray.direction), true);
+                    continue;
+                }
+                let mut hit = match path.hit.take() {
+                    Some(hit) => hit,
+                    None if path.branch.is_none() && self.camera.transparent_background => continue,
+                    None => {
+                        deposit(&mut samples, &path, self.escaped_light(&path.ray, path.bsdf_pdf, path.links()), true);
+                        continue;
+                    }
+                };
+                if let Some(style) = hit.material.toon_style() {
+                    deposit(&mut samples, &path, self.toon_shade_hit(&path.ray, Some(hit), style), true);
+                    continue;
+                }
+                if path.branch.is_none() && hit.material.is_shadow_catcher() {
+                    // (shaded on the spot, see shade_hit_parts. each of the hit's bounces would have been a branch)
+                    resume_sample(&self.camera.sampler, path.cursor);
+                    let caught = self.caught_light(&path.ray, &hit);
+                    samples[path.sample].branches.extend((0..path_samples).map(|_| (Color::zero(), caught)));
+                    continue;
+                }
+                if let Some(strength) = self.camera.roughness_regularization {
+                    hit.min_roughness = path.bounces.min_roughness(strength);
+                }
+                deposit(&mut samples, &path, self.found_emission(&path.ray, &hit, path.bsdf_pdf, path.links()), true);
+                resume_sample(&self.camera.sampler, path.cursor);
+                let first_child = next.len();
+                for k in 0..path_samples {
+                    let (new_ray, brdf_term, pdf, kind) = hit.material.scatter(&hit, &path.ray);
+                    // (see shade_hit_parts)
+                    let dot_term = if hit.normal.magnitude2() > 0.0 {new_ray.direction.normalize().dot(hit.normal).abs().clamp(0.0,1.0)} else {1.0};
+                    let bsdf_pdf = if self.lights.is_empty() { None } else {
+                        hit.material.eval(&hit, -path.ray.direction.normalize(), new_ray.direction.normalize()).map(|(_, pdf)| pdf).filter(|&pdf| pdf > 0.0)
+                    };
+                    let weight = dot_term*brdf_term / pdf;
+                    // the first hit's bounces each start a branch of their own, deeper ones share their path's
+                    let (branch, throughput) = match path.branch {
+                        None => {
+                            samples[path.sample].branches.push((Color::zero(), Color::zero()));
+                            (samples[path.sample].branches.len() - 1, weight)
                         }
+                        Some(b) => (b, path.throughput.mul_element_wise(weight) / path_samples as Float),
+                    };
+                    // every new path after the first takes its random numbers from a stretch of the sample's sequence of
+                    // its own, so paths split off from the same hit don't repeat each other
+                    let mut cursor = path.cursor;
+                    if k > 0 {
+                        cursor.dimension = samples[path.sample].next_dimension;
+                        samples[path.sample].next_dimension += WAVEFRONT_BRANCH_DIMENSIONS;
                     }
+                    next.push(WavefrontPath {
+                        ray: new_ray,
+                        hit: None,
+                        sample: path.sample,
+                        branch: Some(branch),
+                        throughput,
+                        bounces: path.bounces.after(kind),
+                        bsdf_pdf,
+                        from: Some(hit.material.clone()),
+                        cursor,
+                    });
+                }
+                path.cursor = save_sample();
+                lit.push((path, hit, if path_samples > 0 { Some(first_child) } else { None }));
+            }
+
+            // next event estimation
+            for (path, hit, first_child) in lit {
+                resume_sample(&self.camera.sampler, path.cursor);
+                deposit(&mut samples, &path, self.sample_lights(&hit, &path.ray), false);
+                if let Some(i) = first_child {
+                    next[i].cursor = save_sample();
                 }
             }
+
+            // compaction (paths that ended weren't carried over)
+            queue = next;
         }
-        return best_hit;
+        samples.into_iter().map(|sample| {
+            let mut direct = sample.direct;
+            let mut indirect = Color::zero();
+            for (next_hit, after) in sample.branches {
+                direct += self.camera.direct_light.apply(next_hit) / path_samples as Float;
+                indirect += self.camera.indirect_light.apply(after) / path_samples as Float;
+            }
+            (clamp_radiance(sample.emitted + direct + indirect, self.camera.firefly_clamp), direct, indirect)
+        }).collect()
+    }
+}
+impl Intersectable for Scene {
+    fn intersect_ray(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<RayHit> {
+        self.intersect_ray_kind(ray, t_min, t_max, RayKind::Indirect)
     }
     fn bounding_box(&self) -> Option<AABB> {
         None    // we don't really need a bounding box for the entire scene right now
     }
+    // (the scene's packets are all of camera rays)
+    fn intersect_packet(&self, rays: &[Ray], t_min: Float, t_max: Float) -> Vec<Option<RayHit>> {
+        // intersect the packet with each object and keep the closest hit per ray
+        let mut best_hits: Vec<Option<RayHit>> = vec![None; rays.len()];
+        for (i, object) in self.objects.iter().enumerate().filter(|(_, object)| object.visibility().camera) {
+            for (best, hit) in best_hits.iter_mut().zip(object.intersect_packet(rays, t_min, t_max)) {
+                if let Some(mut hit) = hit {
+                    if best.as_ref().is_none_or(|b| hit.distance < b.distance) {
+                        hit.object_id = i as u32 + 1;
+                        *best = Some(hit);
+                    }
+                }
+            }
+        }
+        // rays that landed on a transparent part of a material are traced again on their own
+        for (best, ray) in best_hits.iter_mut().zip(rays) {
+            if best.as_ref().is_some_and(|hit| hit.material.alpha(hit) < ALPHA_CUTOFF) {
+                *best = self.intersect_ray_kind(ray, t_min, t_max, RayKind::Camera);
+            }
+            else {
+                if let Some(fog_hit) = self.fog_hit(ray, t_min, best.as_ref().map_or(t_max, |hit| hit.distance)) {
+                    *best = Some(fog_hit);
+                }
+                if let Some(hit) = best.as_mut() {
+                    hit.uv_footprint *= self.camera.pixel_spread();
+                    self.apply_clay(hit);
+                }
+            }
+        }
+        count_ray_stats(|stats| stats.closest_hit_rays += rays.len() as u64);
+        best_hits
+    }
+    fn intersect_any(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        count_ray_stats(|stats| stats.shadow_rays += 1);
+        // any-hit can't see materials, so look for an opaque hit instead
+        // (in one query: objects like volumes find a different hit each time they're asked, so asking twice would skew them)
+        self.objects.iter().filter(|object| object.visibility().shadow).any(|object| Scene::intersect_opaque(object.as_ref(), ray, t_min, t_max).is_some())
+    }
 }
 
 
-// runs ray tracer
-pub fn run() {
+// logs what the renderer does to stdout, with how long each part of it takes (loading, building acceleration structures,
+// rendering, encoding) when it finishes, at the level RUST_LOG asks for (info by default, e.g. RUST_LOG=warn for only
+// problems, or RUST_LOG=info,cs397_ray_tracing_sp22::util::geometry=warn to leave out mesh loading)
+fn init_logging() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt().with_env_filter(filter).with_target(false).with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
+        .with_ansi(std::io::stdout().is_terminal()).init();
+}
+
+// reads the render seed from `--seed <n>` on the command line
+fn seed_arg() -> Option<u64> {
+    let args: Vec<String> = std::env::args().collect();
+    let i = args.iter().position(|arg| arg == "--seed")?;
+    match args.get(i+1).map(|arg| arg.parse::<u64>()) {
+        Some(Ok(seed)) => Some(seed),
+        _ => {
+            warn!("--seed expects a whole number, using a random seed instead");
+            None
+        }
+    }
+}
+
+// whether a flag like `--progress-json` was given on the command line
+fn flag_arg(flag: &str) -> bool {
+    std::env::args().any(|arg| arg == flag)
+}
+
+// reads the batch manifest's file name from `--batch <file>` on the command line
+fn batch_arg() -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    let i = args.iter().position(|arg| arg == "--batch")?;
+    match args.get(i+1) {
+        Some(file_name) => Some(file_name.clone()),
+        None => {
+            warn!("--batch expects a manifest file, rendering the default scene instead");
+            None
+        }
+    }
+}
+
+// reads the file to keep a checkpoint in from `--checkpoint <file>` on the command line
+fn checkpoint_arg() -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    let i = args.iter().position(|arg| arg == "--checkpoint")?;
+    match args.get(i+1) {
+        Some(file_name) => Some(file_name.clone()),
+        None => {
+            warn!("--checkpoint expects a file, rendering without one instead");
+            None
+        }
+    }
+}
+
+// scenes a batch manifest can ask for by name
+pub fn scene_by_name(name: &str, seed: u64) -> Option<Scene> {
+    match name {
+        "default" => Some(default_scene(seed)),
+        _ => None,
+    }
+}
+
+// the scene run() renders: a drone, a few rows of spheres and some volumes, lit from the ceiling
+pub fn default_scene(seed: u64) -> Scene {
+    // ceiling light, shared between the objects (so rays can hit it) and the lights (so it can be sampled directly)
+    let ceiling_light: [Arc<dyn Intersectable + Send + Sync>; 2] = [
+        Arc::new(Triangle {
+            a: vec3(-2.5, 7.5, -0.5),
+            b: vec3(2.5, 7.5,  -0.5),
+            c: vec3(2.5, 7.5, 3.5),
+            material: Arc::new(Lambertian { albedo: vec3(0.0,0.6,0.0), emission: vec3(7.0,7.0,7.0) }),
+        }),
+        Arc::new(Triangle {
+            a: vec3(-2.5, 7.5, -0.5),
+            b: vec3(-2.5, 7.5,  3.5),
+            c: vec3(2.5, 7.5, 3.5),
+            material: Arc::new(Lambertian { albedo: vec3(0.0,0.6,0.0), emission: vec3(7.0,7.0,7.0) }),
+        }),
+    ];
+
+    // glowing sphere, shared the same way
+    let glowing_sphere = Arc::new(Sphere {
+        center: vec3(2.3,2.0,2.0),
+        radius: 0.4,
+        material: Arc::new(Lambertian { albedo: vec3(0.3,0.3,0.3), emission: vec3(0.0,1.0,1.0),}),
+    });
+
     // initialize scene
-    let scene = Scene {
+    Scene {
         camera: Camera {
             eyepoint: vec3(0.0, 2.0, 5.5),
             view_dir: -Vec3::unit_z(),
@@ -361,16 +3163,56 @@ pub fn run() {
             focal_length: 0.6,  // distance from eyepoint to image plane
             focus_dist: 5.0,    // distance from eyepoint to focus plane
             lens_radius: 0.0,   // radius of thin-lens approximation
+            exposure: None,     // e.g. Some(PhysicalExposure { iso: 100.0, shutter_speed: 1.0/125.0, f_number: 2.8 }) for physically lit scenes
+            exposure_compensation: 0.0, // e.g. 1.0 to brighten the image a stop
+            aperture: Aperture::Disk,   // e.g. Aperture::Blades { count: 6, rotation: 0.0 } for hexagonal bokeh
+            pixel_filter: PixelFilter::default(),   // e.g. PixelFilter::mitchell() for sharper edges
+            vignetting: None,   // e.g. Some(Vignetting { natural: 1.0, barrel_length: 2.0 }) to match a real lens
+            chromatic_aberration: 0.0,  // e.g. 0.005 for the color fringes of a cheap lens
+            lens_shift: Vec2::zero(),
+            lens_tilt: Vec2::zero(),    // e.g. vec2(-20.0, 0.0) with a wide lens_radius to focus along the floor
+            autofocus: None,    // e.g. Some(vec2(0.5, 0.5)) to focus on whatever is in the middle of the image
+            shutter_open: 0.0,
+            shutter_close: 0.0, // e.g. 1.0 to blur objects that move (see Moving)
             projection_mode: CameraProjectionMode::Perspective,
-            shading_mode: ShadingMode::PathTrace,
+            ortho_height: 1.0,  // e.g. 8.0 with CameraProjectionMode::Orthographic to fit the whole scene in view
+            shading_mode: ShadingMode::PathTrace,  // e.g. ShadingMode::Sppm(SppmSettings::default()) for clean caustics from the glass, ShadingMode::Mlt(MltSettings::default()), ShadingMode::LightTrace(LightTraceSettings::default()), or ShadingMode::Gradient(GradientSettings::default()) for less noise on diffuse surfaces
             screen_width: 100,
             screen_height: 100,
+            sensor_fit: SensorFit::Vertical,    // e.g. SensorFit::Auto to keep the framing when switching to portrait
+            stereo: None,       // e.g. Some(Stereo { ipd: 0.064, layout: StereoLayout::SideBySide }) to view in a VR headset
             aa_sample_count: 100,
+            tile_size: 16,      // smaller tiles share expensive parts of the image out more evenly between threads
             path_depth: 10,     // path-tracing recursion depth
+            bounce_limits: BounceLimits { diffuse: 4, glossy: 8, transmission: 10, volume: 10 },
             path_samples: 1,    // sub-rays cast per recursion (slow if more than 1)
             max_trace_dist: 100.0,
+            tone_mapping: ToneMapping::AgX,   // e.g. ToneMapping::Aces for more contrast, or ToneMapping::Clamp to leave colors exact
             gamma: 2.0,
+            bit_depth: 8,       // e.g. 16 to leave room for grading without banding (png and tiff only)
+            firefly_clamp: None,        // e.g. Some(10.0) to cut fireflies from light focused through the glass
+            direct_light: LightAdjustment::none(),
+            indirect_light: LightAdjustment::none(),   // e.g. LightAdjustment::clamp(10.0) to only clamp light that has bounced around
+            roughness_regularization: None,     // e.g. Some(0.1) to tame caustic fireflies from the glass spheres on the floor
+            aovs: vec![],       // e.g. vec![Aov::Depth, Aov::WorldNormal, Aov::Albedo] for compositing or denoising
+            cryptomattes: vec![],   // e.g. vec![Cryptomatte::Object, Cryptomatte::Material] to pick anything out when compositing an exr
+            transparent_background: false,  // e.g. true, with Aov::Alpha and the floor a ShadowCatcher, to put the spheres into a photo
+            observer: None,     // e.g. Some(Arc::new(sender)) with a channel's sender to get each tile on another thread as it finishes
+            progress_json: false,       // e.g. true (or --progress-json on the command line) to show the render's progress in another program
+            terminal_preview: None,     // e.g. Some(TerminalPreview::HalfBlocks { columns: 60 }) to check on a progressive render over ssh
+                                        // (or TerminalPreview::Sixel { width: 300 } in terminals that can show images)
+            ray_stats_regions: None,    // e.g. Some(4) to see which parts of the image cost the most
+            denoiser: None,     // e.g. Some(Denoiser::default()) to clean up renders with few samples per pixel
+            clay: None,         // e.g. Some(vec3(0.5,0.5,0.5)) for a gray clay render
+            sampler: Arc::new(RandomSampler { seed }),   // (HaltonSampler::new(HaltonScrambling::Faure) or SobolSampler::new(SobolScrambling::Owen(seed))
+                                                // for less noise at the same sample count, wrapped in a BlueNoiseSampler to make the noise that
+                                                // remains finer grained)
+            cancel: CancelToken::new(),
+            checkpoint_file: None,      // e.g. Some("render.checkpoint".to_string()) (or --checkpoint <file> on the command line) so a render
+                                        // stopped with ctrl-c carries on where it left off
         },
+        cameras: vec![],    // e.g. vec![("side".to_string(), Camera { aa_sample_count: 100, ..Camera::look_at(vec3(7.0,2.0,1.5), vec3(0.0,1.5,1.5), Vec3::unit_y(), 60.0, 1.0) })]
+                            // to also render render_side.png from the side
         objects: Arc::new(vec![
             Arc::new(StaticMesh::load_from_file(
                 "./obj/drone.obj",
@@ -403,146 +3245,280 @@ pub fn run() {
                 Matrix4::from_translation(vec3(1.7,0.5,2.7))*Matrix4::from_angle_y(Deg(45.0))*Matrix4::from_scale(0.6),
             )),     
             
-            // DEMO OF PARAMETERIZED MATERIAL
-            Arc::new(Sphere {
-                center: vec3(-2.6,3.3,0.0),
-                radius: 0.5,
-                material: Arc::new(ParameterizedMaterial{albedo: vec3(0.01,0.02,0.5), emission: Vec3::zero(), roughness: 0.0, metallic: 0.0})
-            }),
-            Arc::new(Sphere {
-                center: vec3(-1.3,3.3,0.0),
-                radius: 0.5,
-                material: Arc::new(ParameterizedMaterial{albedo: vec3(0.01,0.02,0.5), emission: Vec3::zero(), roughness: 0.25, metallic: 0.0})
-            }),
-            Arc::new(Sphere {
-                center: vec3(0.0,3.3,0.0),
-                radius: 0.5,
-                material: Arc::new(ParameterizedMaterial{albedo: vec3(0.01,0.02,0.5), emission: Vec3::zero(), roughness: 0.5, metallic: 0.0})
-            }),
-            Arc::new(Sphere {
-                center: vec3(1.3,3.3,0.0),
-                radius: 0.5,
-                material: Arc::new(ParameterizedMaterial{albedo: vec3(0.01,0.02,0.5), emission: Vec3::zero(), roughness: 0.75, metallic: 0.0})
-            }),
-            Arc::new(Sphere {
-                center: vec3(2.6,3.3,0.0),
-                radius: 0.5,
-                material: Arc::new(ParameterizedMaterial{albedo: vec3(0.01,0.02,0.5), emission: Vec3::zero(), roughness: 1.0, metallic: 0.0})
-            }),
-            
-            Arc::new(Sphere {
-                center: vec3(-2.6,4.4,0.0),
-                radius: 0.5,
-                material: Arc::new(ParameterizedMaterial{albedo: vec3(0.01,0.02,0.5), emission: Vec3::zero(), roughness: 0.0, metallic: 0.5})
-            }),
-            Arc::new(Sphere {
-                center: vec3(-1.3,4.4,0.0),
-                radius: 0.5,
-                material: Arc::new(ParameterizedMaterial{albedo: vec3(0.01,0.02,0.5), emission: Vec3::zero(), roughness: 0.25, metallic: 0.5})
-            }),
-            Arc::new(Sphere {
-                center: vec3(0.0,4.4,0.0),
-                radius: 0.5,
-                material: Arc::new(ParameterizedMaterial{albedo: vec3(0.01,0.02,0.5), emission: Vec3::zero(), roughness: 0.5, metallic: 0.5})
-            }),
-            Arc::new(Sphere {
-                center: vec3(1.3,4.4,0.0),
-                radius: 0.5,
-                material: Arc::new(ParameterizedMaterial{albedo: vec3(0.01,0.02,0.5), emission: Vec3::zero(), roughness: 0.75, metallic: 0.5})
-            }),
-            Arc::new(Sphere {
-                center: vec3(2.6,4.4,0.0),
-                radius: 0.5,
-                material: Arc::new(ParameterizedMaterial{albedo: vec3(0.01,0.02,0.5), emission: Vec3::zero(), roughness: 1.0, metallic: 0.5})
-            }),
+            // DEMO OF PARAMETERIZED MATERIAL (spheres share a bvh rather than being tested one by one)
+            Arc::new(BVHNode::build(vec![
+                Sphere {
+                    center: vec3(-2.6,3.3,0.0),
+                    radius: 0.5,
+                    material: Arc::new(ParameterizedMaterial{albedo: solid_color(vec3(0.01,0.02,0.5)), emission: solid_color(Vec3::zero()), roughness: solid_value(0.0), metallic: solid_value(0.0), ..Default::default() })
+                },
+                Sphere {
+                    center: vec3(-1.3,3.3,0.0),
+                    radius: 0.5,
+                    material: Arc::new(ParameterizedMaterial{albedo: solid_color(vec3(0.01,0.02,0.5)), emission: solid_color(Vec3::zero()), roughness: solid_value(0.25), metallic: solid_value(0.0), ..Default::default() })
+                },
+                Sphere {
+                    center: vec3(0.0,3.3,0.0),
+                    radius: 0.5,
+                    material: Arc::new(ParameterizedMaterial{albedo: solid_color(vec3(0.01,0.02,0.5)), emission: solid_color(Vec3::zero()), roughness: solid_value(0.5), metallic: solid_value(0.0), ..Default::default() })
+                },
+                Sphere {
+                    center: vec3(1.3,3.3,0.0),
+                    radius: 0.5,
+                    material: Arc::new(ParameterizedMaterial{albedo: solid_color(vec3(0.01,0.02,0.5)), emission: solid_color(Vec3::zero()), roughness: solid_value(0.75), metallic: solid_value(0.0), ..Default::default() })
+                },
+                Sphere {
+                    center: vec3(2.6,3.3,0.0),
+                    radius: 0.5,
+                    material: Arc::new(ParameterizedMaterial{albedo: solid_color(vec3(0.01,0.02,0.5)), emission: solid_color(Vec3::zero()), roughness: solid_value(1.0), metallic: solid_value(0.0), ..Default::default() })
+                },
+                Sphere {
+                    center: vec3(-2.6,4.4,0.0),
+                    radius: 0.5,
+                    material: Arc::new(ParameterizedMaterial{albedo: solid_color(vec3(0.01,0.02,0.5)), emission: solid_color(Vec3::zero()), roughness: solid_value(0.0), metallic: solid_value(0.5), ..Default::default() })
+                },
+                Sphere {
+                    center: vec3(-1.3,4.4,0.0),
+                    radius: 0.5,
+                    material: Arc::new(ParameterizedMaterial{albedo: solid_color(vec3(0.01,0.02,0.5)), emission: solid_color(Vec3::zero()), roughness: solid_value(0.25), metallic: solid_value(0.5), ..Default::default() })
+                },
+                Sphere {
+                    center: vec3(0.0,4.4,0.0),
+                    radius: 0.5,
+                    material: Arc::new(ParameterizedMaterial{albedo: solid_color(vec3(0.01,0.02,0.5)), emission: solid_color(Vec3::zero()), roughness: solid_value(0.5), metallic: solid_value(0.5), ..Default::default() })
+                },
+                Sphere {
+                    center: vec3(1.3,4.4,0.0),
+                    radius: 0.5,
+                    material: Arc::new(ParameterizedMaterial{albedo: solid_color(vec3(0.01,0.02,0.5)), emission: solid_color(Vec3::zero()), roughness: solid_value(0.75), metallic: solid_value(0.5), ..Default::default() })
+                },
+                Sphere {
+                    center: vec3(2.6,4.4,0.0),
+                    radius: 0.5,
+                    material: Arc::new(ParameterizedMaterial{albedo: solid_color(vec3(0.01,0.02,0.5)), emission: solid_color(Vec3::zero()), roughness: solid_value(1.0), metallic: solid_value(0.5), ..Default::default() })
+                },
+                Sphere {
+                    center: vec3(-2.6,5.5,0.0),
+                    radius: 0.5,
+                    material: Arc::new(ParameterizedMaterial{albedo: solid_color(vec3(0.01,0.02,0.5)), emission: solid_color(Vec3::zero()), roughness: solid_value(0.0), metallic: solid_value(1.0), ..Default::default() })
+                },
+                Sphere {
+                    center: vec3(-1.3,5.5,0.0),
+                    radius: 0.5,
+                    material: Arc::new(ParameterizedMaterial{albedo: solid_color(vec3(0.01,0.02,0.5)), emission: solid_color(Vec3::zero()), roughness: solid_value(0.25), metallic: solid_value(1.0), ..Default::default() })
+                },
+                Sphere {
+                    center: vec3(0.0,5.5,0.0),
+                    radius: 0.5,
+                    material: Arc::new(ParameterizedMaterial{albedo: solid_color(vec3(0.01,0.02,0.5)), emission: solid_color(Vec3::zero()), roughness: solid_value(0.5), metallic: solid_value(1.0), ..Default::default() })
+                },
+                Sphere {
+                    center: vec3(1.3,5.5,0.0),
+                    radius: 0.5,
+                    material: Arc::new(ParameterizedMaterial{albedo: solid_color(vec3(0.01,0.02,0.5)), emission: solid_color(Vec3::zero()), roughness: solid_value(0.75), metallic: solid_value(1.0), ..Default::default() })
+                },
+                Sphere {
+                    center: vec3(2.6,5.5,0.0),
+                    radius: 0.5,
+                    material: Arc::new(ParameterizedMaterial{albedo: solid_color(vec3(0.01,0.02,0.5)), emission: solid_color(Vec3::zero()), roughness: solid_value(1.0), metallic: solid_value(1.0), ..Default::default() })
+                },
+            ]).unwrap()),
+
 
-            Arc::new(Sphere {
-                center: vec3(-2.6,5.5,0.0),
-                radius: 0.5,
-                material: Arc::new(ParameterizedMaterial{albedo: vec3(0.01,0.02,0.5), emission: Vec3::zero(), roughness: 0.0, metallic: 1.0})
-            }),
-            Arc::new(Sphere {
-                center: vec3(-1.3,5.5,0.0),
-                radius: 0.5,
-                material: Arc::new(ParameterizedMaterial{albedo: vec3(0.01,0.02,0.5), emission: Vec3::zero(), roughness: 0.25, metallic: 1.0})
-            }),
-            Arc::new(Sphere {
-                center: vec3(0.0,5.5,0.0),
-                radius: 0.5,
-                material: Arc::new(ParameterizedMaterial{albedo: vec3(0.01,0.02,0.5), emission: Vec3::zero(), roughness: 0.5, metallic: 1.0})
-            }),
-            Arc::new(Sphere {
-                center: vec3(1.3,5.5,0.0),
-                radius: 0.5,
-                material: Arc::new(ParameterizedMaterial{albedo: vec3(0.01,0.02,0.5), emission: Vec3::zero(), roughness: 0.75, metallic: 1.0})
-            }),
-            Arc::new(Sphere {
-                center: vec3(2.6,5.5,0.0),
-                radius: 0.5,
-                material: Arc::new(ParameterizedMaterial{albedo: vec3(0.01,0.02,0.5), emission: Vec3::zero(), roughness: 1.0, metallic: 1.0})
-            }),
-            
-            
-            
             // VARIOUS OTHER OBJECTS
 
             Arc::new(Sphere {
                 center: vec3(-2.3,2.0,2.0),
                 radius: 0.4,
-                material: Arc::new(Dielectric { idx_of_refraction: 2.5 })
-            }),
-            Arc::new(Sphere {
-                center: vec3(2.3,2.0,2.0),
-                radius: 0.4,
-                material: Arc::new(Lambertian { albedo: vec3(0.3,0.3,0.3), emission: vec3(0.0,1.0,1.0),}),
+                material: Arc::new(Dielectric { idx_of_refraction: 2.5, dispersion: None })  // e.g. Some(Dispersion::diamond()) for rainbow fire
             }),
+            glowing_sphere.clone(),
             Arc::new(ConvexVolume {
                 boundary: Arc::new(Sphere {
                     center: vec3(-3.0,1.0,1.0),
                     radius: 1.0,
-                    material: Arc::new(Dielectric { idx_of_refraction: 1.5 }) /* arbitrary */,
+                    material: Arc::new(Dielectric { idx_of_refraction: 1.5, dispersion: None }) /* arbitrary */,
                 }),
                 phase_function: Arc::new(Isotropic { albedo: vec3(1.0,1.0,1.0), emission: Vec3::zero() }),
                 density: 0.6,
+                density_field: None,    // e.g. Some(Arc::new(NoiseDensity { scale: 0.5, octaves: 4, threshold: 0.4, seed: 0 })) with a higher density for a cloud
             }),
             Arc::new(ConvexVolume {
                 boundary: Arc::new(Sphere {
                     center: vec3(3.0,1.0,1.0),
                     radius: 1.0,
-                    material: Arc::new(Dielectric { idx_of_refraction: 1.5 }) /* arbitrary */,
+                    material: Arc::new(Dielectric { idx_of_refraction: 1.5, dispersion: None }) /* arbitrary */,
                 }),
                 phase_function: Arc::new(Isotropic { albedo: vec3(0.0,0.0,0.0), emission: Vec3::zero() }),
                 density: 0.8,
+                density_field: None,
             }),
 
-            // Floor
+            // Floor (wrap its material in a ShadowCatcher to composite the rest onto a photo of a floor)
             Arc::new(Plane {
                 point: vec3(0.0, 0.0, 0.0),
                 normal: Vec3::unit_y(),
                 // material: Arc::new(Lambertian { albedo: vec3(0.33,0.33,0.33), ..Default::default() }),
-                material: Arc::new(ParameterizedMaterial { albedo: vec3(0.33,0.33,0.33), emission: Vec3::zero(), metallic: 0.3, roughness: 0.7 }),
+                material: Arc::new(ParameterizedMaterial { albedo: solid_color(vec3(0.33,0.33,0.33)), emission: solid_color(Vec3::zero()), metallic: solid_value(0.3), roughness: solid_value(0.7), ..Default::default() }),
             }),  
             
-            // LIGHT
-            Arc::new(Triangle {
-                a: vec3(-2.5, 7.5, -0.5),
-                b: vec3(2.5, 7.5,  -0.5),
-                c: vec3(2.5, 7.5, 3.5),
-                material: Arc::new(Lambertian { albedo: vec3(0.0,0.6,0.0), emission: vec3(7.0,7.0,7.0), ..Default::default() }),
-            }),
-            Arc::new(Triangle {
-                a: vec3(-2.5, 7.5, -0.5),
-                b: vec3(-2.5, 7.5,  3.5),
-                c: vec3(2.5, 7.5, 3.5),
-                material: Arc::new(Lambertian { albedo: vec3(0.0,0.6,0.0), emission: vec3(7.0,7.0,7.0), ..Default::default() }),
-            }),
+            // LIGHT (wrap any object like Visibility { object: ..., rays: RayVisibility { camera: false, ..Default::default() } }
+            // to hide it from the camera, or set shadow: false so it casts no shadows)
+            ceiling_light[0].clone(),
+            ceiling_light[1].clone(),
 
         ]),
-        point_light_pos: vec3(0.0,1.0,5.0), // for phong shading only
-        ambient: vec3(0.1,0.1,0.1), // for phong shading only
-    };
+        lights: vec![
+            Arc::new(AreaLight { shape: ceiling_light[0].clone() }),
+            Arc::new(AreaLight { shape: ceiling_light[1].clone() }),
+            Arc::new(SphereLight { sphere: glowing_sphere.clone() }),
+            // Arc::new(EnvironmentLight::load_from_file("./texture/sky.hdr", 1.0).unwrap()),
+            // Arc::new(SpotLight { position: vec3(0.0, 6.0, 4.0), direction: vec3(0.0, -1.0, -0.6), intensity: vec3(20.0,20.0,20.0), inner_angle: 20.0, outer_angle: 30.0, gobo: None }),
+        ],
+        background: Arc::new(SolidColor { color: Color::zero() }), // black void (VerticalGradient::sky() for the blue sky gradient)
+        point_light_pos: vec3(0.0,1.0,5.0), // for phong and toon shading only
+        ambient: vec3(0.1,0.1,0.1), // for phong and toon shading only
+        object_names: vec![],   // e.g. vec!["bunny".to_string(), "teapot".to_string()] (the rest are numbered)
+        fog: None,  // e.g. Some(Fog { density: 0.05, phase_function: Arc::new(Isotropic { albedo: vec3(0.9,0.9,0.9), emission: Vec3::zero() }),
+                    // height_falloff: Some(HeightDensity { base: 0.0, falloff: 2.0 }) }) for shafts of light from the ceiling
+    }
+}
+
+// runs ray tracer
+pub fn run() {
+    init_logging();
+    // the same seed always renders the exact same image, so log it to be able to repeat a run
+    let seed = seed_arg().unwrap_or_else(rand::random);
+    info!(seed, "render seed");
+
+    // (or, given --batch <manifest>, render the jobs it lists instead)
+    let progress_json = flag_arg("--progress-json");
+    let checkpoint = checkpoint_arg();
+    if let Some(manifest) = batch_arg() {
+        if checkpoint.is_some() {
+            warn!("--checkpoint is for single renders, give batch jobs a checkpoint=<file> setting instead");
+        }
+        render_manifest(&manifest, seed, progress_json);
+        return;
+    }
+    let mut scene = default_scene(seed);
+    scene.camera.progress_json |= progress_json;
+    // (given --checkpoint <file>, a render stopped with ctrl-c saves what it finished there, and running it again with
+    // the same file carries on from it)
+    if checkpoint.is_some() {
+        scene.camera.checkpoint_file = checkpoint;
+    }
 
     // render and write output
-    scene.render_to_image().save_with_format("render.png", ImageFormat::Png).unwrap();
+    // (ctrl-c stops the render and writes what's finished, a second ctrl-c quits straight away)
+    cancel_on_ctrl_c(&scene.camera.cancel);
+    scene.autofocus();
+    // (for an animation, make the scene mutable and move the camera along keyframes instead, e.g.
+    // animation::render_frames(&mut scene, &CameraPath { keyframes: vec![...] }, 0..48, 24.0, "frame"), or
+    // animation::render_frames_accumulated(..., &TemporalAccumulation::default()) with fewer samples a frame)
+    // (or to light a mesh in a game engine, keep an Arc of it and bake its lighting instead, e.g.
+    // scene.save_image(&bake::bake(&scene, &drone, &BakeSettings::default()).unwrap(), "lightmap.exr"), or bake light
+    // probes for the objects moving through it, e.g. bake::write_probes("probes.txt", &bake::bake_probes(&scene, &points,
+    // &ProbeSettings::default()), ProbeFormat::SphericalHarmonics))
+    if let Some(save_interval) = PROGRESSIVE_SAVE_INTERVAL {
+        scene.render_progressive(OUTPUT_FILE, save_interval);
+    } else if RENDER_SAMPLE_STATS {
+        let (img, variance, sample_count) = scene.render_with_sample_stats();
+        img.save_with_format("render.png", ImageFormat::Png).unwrap();
+        variance.save_with_format("variance.png", ImageFormat::Png).unwrap();
+        sample_count.save_with_format("sample_count.png", ImageFormat::Png).unwrap();
+    } else {
+        let (img, aovs) = scene.render_with_aovs();
+        let mattes = scene.render_cryptomattes();
+        scene.save_image_with_aovs(&img, &aovs, &mattes, OUTPUT_FILE);
+        scene.render_cameras(OUTPUT_FILE);
+    }
+    if RENDER_TRAVERSAL_HEATMAPS {
+        scene.render_traversal_heatmap(HeatmapMetric::AABBTests).save_with_format("heatmap_aabb.png", ImageFormat::Png).unwrap();
+        scene.render_traversal_heatmap(HeatmapMetric::TriangleTests).save_with_format("heatmap_triangle.png", ImageFormat::Png).unwrap();
+    }
+
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // angle between two directions, in degrees
+    fn angle(a: Vec3, b: Vec3) -> Float {
+        a.normalize().dot(b.normalize()).clamp(-1.0, 1.0).acos().to_degrees()
+    }
+    fn assert_close(a: Float, b: Float, tolerance: Float) {
+        assert!((a - b).abs() <= tolerance, "{} isn't within {} of {}", a, tolerance, b);
+    }
+
+    // a camera looking down -z with a 60 degree vertical field of view, with 16 jittered samples per pixel
+    fn camera(width: u32, height: u32) -> Camera {
+        Camera::builder().resolution(width, height).vfov(60.0).aa_sample_count(16).build().unwrap()
+    }
+
+    // checks the edges of the image are the field of view apart across and down it, and that every sample lands in the
+    // pixel it was taken for, which only holds if the pixels are as wide as they are tall
+    fn check_field_of_view(width: u32, height: u32) {
+        let camera = camera(width, height);
+        assert_eq!(camera.image_size(), (width, height));
+        let aspect = width as Float / height as Float;
+        let hfov = 2.0*(aspect*(30.0 as Float).to_radians().tan()).atan().to_degrees();
+        assert_close(camera.vfov(), 60.0, 1e-3);
+        assert_close(angle(camera.center_ray(vec2(0.5, 0.0)).direction, camera.center_ray(vec2(0.5, 1.0)).direction), 60.0, 1e-3);
+        assert_close(angle(camera.center_ray(vec2(0.0, 0.5)).direction, camera.center_ray(vec2(1.0, 0.5)).direction), hfov, 1e-3);
+        assert_close(angle(camera.center_ray(vec2(0.5, 0.5)).direction, camera.view_dir), 0.0, 1e-2);
+
+        // corner and center pixels
+        for (x, y) in [(0, 0), (width - 1, 0), (0, height - 1), (width - 1, height - 1), (width/2, height/2)] {
+            for i in 0..camera.aa_sample_count {
+                let (ray, position) = camera.generate_sample(x, y, i);
+                assert!(position.x >= x as Float && position.x <= (x + 1) as Float, "sample {:?} isn't in pixel {:?}", position, (x, y));
+                assert!(position.y >= y as Float && position.y <= (y + 1) as Float, "sample {:?} isn't in pixel {:?}", position, (x, y));
+                let point = camera.center_ray_point(ray.direction).unwrap();
+                assert_close(point.x*width as Float, position.x, 1e-2);
+                assert_close(point.y*height as Float, position.y, 1e-2);
+            }
+        }
+    }
 
-}
\ No newline at end of file
+    // checks light paths splat into the pixel whose camera rays see where they end, by connecting the points the corner
+    // and center pixels' samples hit on a wall back to the camera
+    fn check_connect_to_camera(width: u32, height: u32) {
+        let scene = Scene {
+            camera: camera(width, height),
+            cameras: vec![],
+            objects: Arc::new(vec![Arc::new(Plane {
+                point: vec3(0.0, 0.0, -5.0),
+                normal: Vec3::unit_z(),
+                material: Arc::new(Lambertian { albedo: vec3(0.5, 0.5, 0.5), emission: Vec3::zero() }),
+            })]),
+            lights: vec![],
+            background: Arc::new(SolidColor { color: Color::zero() }),
+            point_light_pos: Vec3::zero(),
+            ambient: Vec3::zero(),
+            object_names: vec![],
+            fog: None,
+        };
+        for (x, y) in [(0, 0), (width - 1, 0), (0, height - 1), (width - 1, height - 1), (width/2, height/2)] {
+            for i in 0..scene.camera.aa_sample_count {
+                let (ray, position) = scene.camera.generate_sample(x, y, i);
+                let hit = scene.intersect_ray(&ray, 0.001, scene.camera.max_trace_dist).unwrap();
+                let vertex = LightVertex { wi: hit.normal, time: ray.time, hit, beta: Color::new(1.0, 1.0, 1.0) };
+                let (splat_x, splat_y, _) = scene.connect_to_camera(&vertex).unwrap();
+                assert_eq!((splat_x, splat_y), (position.x as u32, position.y as u32), "sample {} of pixel {:?} splats elsewhere", i, (x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn widescreen_field_of_view() {
+        check_field_of_view(320, 180);
+        check_connect_to_camera(320, 180);
+    }
+
+    #[test]
+    fn portrait_field_of_view() {
+        check_field_of_view(180, 320);
+        check_connect_to_camera(180, 320);
+    }
+}